@@ -1,5 +1,5 @@
 use bumpalo::Bump;
-use roc_load::{ExecutionMode, LoadConfig, LoadedModule, Threading};
+use roc_load::{ExecutionMode, LoadConfig, LoadedModule, MonoPasses, Threading};
 use roc_packaging::cache::RocCacheDir;
 use roc_reporting::report::DEFAULT_PALETTE;
 use roc_target::TargetInfo;
@@ -16,6 +16,7 @@ pub fn load_module(
         palette: DEFAULT_PALETTE,
         threading,
         exec_mode: ExecutionMode::Check,
+        mono_passes: MonoPasses::All,
     };
 
     let arena = Bump::new();
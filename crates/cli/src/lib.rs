@@ -351,6 +351,28 @@ pub enum FormatMode {
     CheckOnly,
 }
 
+/// Warn about what the `expect`s and `dbg`s in `loaded` will report, before they run.
+#[cfg(not(windows))]
+fn warn_about_expect_frames(loaded: &roc_load::MonomorphizedModule) {
+    // Values too big for an expect frame are better caught before the program runs
+    let frame_limit = roc_repl_expect::run::ExpectMemory::default_frame_limit();
+    for frame in roc_repl_expect::frame_size::large_frames(loaded, frame_limit) {
+        if let Some((path, source)) = loaded.sources.get(&frame.module_id) {
+            eprintln!("{}", frame.warning(path, source, frame_limit));
+        }
+    }
+
+    // A function in a reported value can only be shown as <function>
+    for lookup in roc_repl_expect::unshowable::unshowable_lookups(loaded) {
+        if let Some((path, source)) = loaded.sources.get(&lookup.module_id) {
+            eprintln!("{}", lookup.warning(path, source, &loaded.interns));
+        }
+    }
+}
+
+#[cfg(windows)]
+fn warn_about_expect_frames(_loaded: &roc_load::MonomorphizedModule) {}
+
 #[cfg(windows)]
 pub fn test(_matches: &ArgMatches, _triple: Triple) -> io::Result<i32> {
     todo!("running tests does not work on windows right now")
@@ -360,7 +382,7 @@ pub fn test(_matches: &ArgMatches, _triple: Triple) -> io::Result<i32> {
 pub fn test(matches: &ArgMatches, triple: Triple) -> io::Result<i32> {
     use roc_build::program::report_problems_monomorphized;
     use roc_gen_llvm::llvm::build::LlvmBackendMode;
-    use roc_load::{ExecutionMode, LoadConfig, LoadMonomorphizedError};
+    use roc_load::{ExecutionMode, LoadConfig, LoadMonomorphizedError, MonoPasses};
     use roc_packaging::cache;
     use roc_target::TargetInfo;
 
@@ -422,6 +444,7 @@ pub fn test(matches: &ArgMatches, triple: Triple) -> io::Result<i32> {
         palette: roc_reporting::report::DEFAULT_PALETTE,
         threading,
         exec_mode: ExecutionMode::Test,
+        mono_passes: MonoPasses::All,
     };
     let load_result = roc_load::load_and_monomorphize(
         arena,
@@ -441,6 +464,8 @@ pub fn test(matches: &ArgMatches, triple: Triple) -> io::Result<i32> {
     };
     let problems = report_problems_monomorphized(&mut loaded);
 
+    warn_about_expect_frames(&loaded);
+
     let mut expectations = std::mem::take(&mut loaded.expectations);
 
     let interns = loaded.interns.clone();
@@ -451,6 +476,8 @@ pub fn test(matches: &ArgMatches, triple: Triple) -> io::Result<i32> {
         loaded,
         opt_level,
         LlvmBackendMode::CliTest,
+        // failures are reported with the text of their condition, not just its region
+        roc_repl_expect::frames::FrameFormat::WithSource,
     )
     .unwrap();
 
@@ -519,7 +546,7 @@ pub fn build(
     roc_cache_dir: RocCacheDir<'_>,
     link_type: LinkType,
 ) -> io::Result<i32> {
-    use roc_build::program::build_file;
+    use roc_build::program::build_loaded_file;
     use BuildConfig::*;
 
     let filename = matches.value_of_os(ROC_FILE).unwrap();
@@ -672,19 +699,31 @@ pub fn build(
 
     let load_config = standard_load_config(&triple, build_ordering, threading);
 
-    let res_binary_path = build_file(
-        &arena,
-        &triple,
-        path_buf,
-        code_gen_options,
-        emit_timings,
-        link_type,
-        linking_strategy,
-        prebuilt,
-        wasm_dev_stack_bytes,
-        roc_cache_dir,
-        load_config,
-    );
+    let compilation_start = Instant::now();
+
+    let res_binary_path =
+        roc_load::load_and_monomorphize(&arena, path_buf.clone(), roc_cache_dir, load_config)
+            .map_err(|e| BuildFileError::from_mono_error(e, compilation_start))
+            .and_then(|loaded| {
+                // `roc dev` runs the expects and dbgs, so warn about them before it builds
+                if let OptLevel::Development = opt_level {
+                    warn_about_expect_frames(&loaded);
+                }
+
+                build_loaded_file(
+                    &arena,
+                    &triple,
+                    path_buf,
+                    code_gen_options,
+                    emit_timings,
+                    link_type,
+                    linking_strategy,
+                    prebuilt,
+                    wasm_dev_stack_bytes,
+                    loaded,
+                    compilation_start,
+                )
+            });
 
     match res_binary_path {
         Ok(BuiltFile {
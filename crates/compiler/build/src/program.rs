@@ -8,7 +8,7 @@ use roc_gen_llvm::llvm::build::{module_from_builtins, LlvmBackendMode};
 use roc_gen_llvm::llvm::externs::add_default_roc_externs;
 use roc_load::{
     EntryPoint, ExecutionMode, ExpectMetadata, LoadConfig, LoadMonomorphizedError, LoadedModule,
-    LoadingProblem, MonomorphizedModule, Threading,
+    LoadingProblem, MonoPasses, MonomorphizedModule, Threading,
 };
 use roc_mono::ir::{OptLevel, SingleEntryPoint};
 use roc_packaging::cache::RocCacheDir;
@@ -197,6 +197,7 @@ fn gen_from_mono_module_llvm<'a>(
         },
 
         exposed_to_host: loaded.exposed_to_host.values.keys().copied().collect(),
+        expect_sources: None,
     };
 
     // does not add any externs for this mode (we have a host) but cleans up some functions around
@@ -510,6 +511,7 @@ fn gen_from_mono_module_dev_wasm32<'a>(
         module_id,
         exposed_to_host,
         stack_bytes: wasm_dev_stack_bytes.unwrap_or(roc_gen_wasm::Env::DEFAULT_STACK_BYTES),
+        trace_refcounts: false,
     };
 
     let host_bytes = std::fs::read(preprocessed_host_path).unwrap_or_else(|_| {
@@ -633,7 +635,7 @@ pub enum BuildFileError<'a> {
 }
 
 impl<'a> BuildFileError<'a> {
-    fn from_mono_error(error: LoadMonomorphizedError<'a>, compilation_start: Instant) -> Self {
+    pub fn from_mono_error(error: LoadMonomorphizedError<'a>, compilation_start: Instant) -> Self {
         match error {
             LoadMonomorphizedError::LoadingProblem(problem) => {
                 BuildFileError::LoadingProblem(problem)
@@ -664,6 +666,7 @@ pub fn standard_load_config(
         palette: DEFAULT_PALETTE,
         threading,
         exec_mode,
+        mono_passes: MonoPasses::All,
     }
 }
 
@@ -704,7 +707,7 @@ pub fn build_file<'a>(
 }
 
 #[allow(clippy::too_many_arguments)]
-fn build_loaded_file<'a>(
+pub fn build_loaded_file<'a>(
     arena: &'a Bump,
     target: &Triple,
     app_module_path: PathBuf,
@@ -1117,6 +1120,7 @@ pub fn check_file<'a>(
         palette: DEFAULT_PALETTE,
         threading,
         exec_mode: ExecutionMode::Check,
+        mono_passes: MonoPasses::All,
     };
     let mut loaded =
         roc_load::load_and_typecheck(arena, roc_file_path, roc_cache_dir, load_config)?;
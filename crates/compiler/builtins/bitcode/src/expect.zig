@@ -13,6 +13,13 @@ pub const MAP_SHARED: c_int = 0x0001;
 var SHARED_BUFFER: []u8 = undefined;
 
 pub fn setSharedBuffer(ptr: [*]u8, length: usize) callconv(.C) usize {
+    // The generated code reads and writes the header a word at a time (`read_state` and
+    // `write_state`), so the buffer must be aligned like a pointer. An mmap-ed buffer always is.
+    // This is checked in every build mode, because a misaligned buffer fails in confusing ways.
+    if (@ptrToInt(ptr) % @alignOf(usize) != 0) {
+        @panic("the shared buffer must be aligned to the pointer size");
+    }
+
     SHARED_BUFFER = ptr[0..length];
 
     // the rust side expects that a pointer is returned
@@ -79,6 +86,52 @@ pub fn readSharedBufferEnv() callconv(.C) void {
     }
 }
 
+// The pointers that were already cloned into the current expect frame, and the word that was
+// written into the frame for each of them. When a value is shared between several places in
+// the lookups, only the first place gets a copy, and the others point to that same copy.
+//
+// A copy is made with the recursion budget that was left where it was made, and anything
+// deeper than that is elided. So it is only reused where the budget is no bigger, or the
+// place that reuses it would show less of the value than it could.
+//
+// Like SHARED_BUFFER, these are plain globals: expects only ever run on one thread.
+const CLONE_SEEN_CAPACITY = 64;
+var CLONE_SEEN_POINTERS: [CLONE_SEEN_CAPACITY]usize = undefined;
+var CLONE_SEEN_WORDS: [CLONE_SEEN_CAPACITY]u64 = undefined;
+var CLONE_SEEN_BUDGETS: [CLONE_SEEN_CAPACITY]usize = undefined;
+var CLONE_SEEN_COUNT: usize = 0;
+
+pub fn expectCloneSeenReset() callconv(.C) void {
+    CLONE_SEEN_COUNT = 0;
+}
+
+// Returns 0 if the pointer has not been cloned yet with at least `recursion_budget`;
+// a written word is never 0. A null pointer is never looked up.
+pub fn expectCloneSeenFind(pointer: usize, recursion_budget: usize) callconv(.C) u64 {
+    if (pointer == 0) {
+        return 0;
+    }
+
+    var i: usize = 0;
+    while (i < CLONE_SEEN_COUNT) : (i += 1) {
+        if (CLONE_SEEN_POINTERS[i] == pointer and CLONE_SEEN_BUDGETS[i] >= recursion_budget) {
+            return CLONE_SEEN_WORDS[i];
+        }
+    }
+
+    return 0;
+}
+
+// Once the table is full, further pointers are just cloned again.
+pub fn expectCloneSeenInsert(pointer: usize, word: u64, recursion_budget: usize) callconv(.C) void {
+    if (pointer != 0 and CLONE_SEEN_COUNT < CLONE_SEEN_CAPACITY) {
+        CLONE_SEEN_POINTERS[CLONE_SEEN_COUNT] = pointer;
+        CLONE_SEEN_WORDS[CLONE_SEEN_COUNT] = word;
+        CLONE_SEEN_BUDGETS[CLONE_SEEN_COUNT] = recursion_budget;
+        CLONE_SEEN_COUNT += 1;
+    }
+}
+
 pub fn notifyParent(shared_buffer: [*]u8, tag: u32) callconv(.C) void {
     if (builtin.os.tag == .macos or builtin.os.tag == .linux) {
         const usize_ptr = @ptrCast([*]u32, @alignCast(@alignOf(usize), shared_buffer));
@@ -181,6 +181,9 @@ comptime {
         exportUtilsFn(expect.expectFailedStartSharedFile, "expect_failed_start_shared_file");
         exportUtilsFn(expect.notifyParentExpect, "notify_parent_expect");
         exportUtilsFn(expect.notifyParentDbg, "notify_parent_dbg");
+        exportUtilsFn(expect.expectCloneSeenReset, "expect_clone_seen_reset");
+        exportUtilsFn(expect.expectCloneSeenFind, "expect_clone_seen_find");
+        exportUtilsFn(expect.expectCloneSeenInsert, "expect_clone_seen_insert");
 
         // sets the buffer used for expect failures
         @export(expect.setSharedBuffer, .{ .name = "set_shared_buffer", .linkage = .Weak });
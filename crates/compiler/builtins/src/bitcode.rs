@@ -383,6 +383,9 @@ pub const UTILS_EXPECT_FAILED_START_SHARED_FILE: &str =
 pub const UTILS_EXPECT_READ_ENV_SHARED_BUFFER: &str = "roc_builtins.utils.read_env_shared_buffer";
 pub const NOTIFY_PARENT_EXPECT: &str = "roc_builtins.utils.notify_parent_expect";
 pub const NOTIFY_PARENT_DBG: &str = "roc_builtins.utils.notify_parent_dbg";
+pub const UTILS_EXPECT_CLONE_SEEN_RESET: &str = "roc_builtins.utils.expect_clone_seen_reset";
+pub const UTILS_EXPECT_CLONE_SEEN_FIND: &str = "roc_builtins.utils.expect_clone_seen_find";
+pub const UTILS_EXPECT_CLONE_SEEN_INSERT: &str = "roc_builtins.utils.expect_clone_seen_insert";
 
 pub const UTILS_LONGJMP: &str = "longjmp";
 pub const UTILS_SETJMP: &str = "setjmp";
@@ -133,6 +133,10 @@ flags! {
     /// instructions.
     ROC_PRINT_IR_AFTER_REFCOUNT
 
+    /// Makes the dev backends call the host's `roc_refcount_trace` before every refcount
+    /// change in their refcounting helper procs. See `CodeGenHelp::with_refcount_trace`.
+    ROC_TRACE_REFCOUNTS
+
     /// Prints debug information during the alias analysis pass.
     ROC_DEBUG_ALIAS_ANALYSIS
 
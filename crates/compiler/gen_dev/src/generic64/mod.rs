@@ -524,7 +524,10 @@ pub fn new_backend_64bit<
         env,
         interns,
         layout_interner,
-        helper_proc_gen: CodeGenHelp::new(env.arena, target_info, env.module_id),
+        helper_proc_gen: CodeGenHelp::new(env.arena, target_info, env.module_id)
+            .with_refcount_trace(roc_debug_flags::dbg_set!(
+                roc_debug_flags::ROC_TRACE_REFCOUNTS
+            )),
         helper_proc_symbols: bumpalo::vec![in env.arena],
         proc_name: None,
         is_self_recursive: None,
@@ -1042,6 +1042,14 @@ trait Backend<'a> {
                 arg_layouts,
                 ret_layout,
             ),
+            // provided by the host, see `CodeGenHelp::with_refcount_trace`
+            LowLevel::RefCountTrace => self.build_fn_call(
+                sym,
+                "roc_refcount_trace".to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
             x => todo!("low level, {:?}", x),
         }
     }
@@ -46,10 +46,11 @@ use roc_mono::layout::{
     Builtin, InLayout, LambdaName, LambdaSet, Layout, LayoutIds, LayoutInterner, Niche,
     RawFunctionLayout, STLayoutInterner, TagIdIntType, UnionLayout,
 };
+use roc_region::all::Region;
 use roc_std::RocDec;
 use roc_target::{PtrWidth, TargetInfo};
 use std::convert::TryInto;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use target_lexicon::{Architecture, OperatingSystem, Triple};
 
 use super::convert::{struct_type_from_union_layout, RocUnion};
@@ -238,6 +239,9 @@ impl LlvmBackendMode {
     }
 }
 
+/// The path and source of each module, like `MonomorphizedModule::sources`
+pub type ExpectSources = MutMap<ModuleId, (PathBuf, Box<str>)>;
+
 pub struct Env<'a, 'ctx, 'env> {
     pub arena: &'a Bump,
     pub context: &'ctx Context,
@@ -249,9 +253,31 @@ pub struct Env<'a, 'ctx, 'env> {
     pub target_info: TargetInfo,
     pub mode: LlvmBackendMode,
     pub exposed_to_host: MutSet<Symbol>,
+    /// When given, each expect frame also holds the source text of the expect's condition,
+    /// so that it can be reported on without the module's source.
+    pub expect_sources: Option<&'env ExpectSources>,
 }
 
 impl<'a, 'ctx, 'env> Env<'a, 'ctx, 'env> {
+    /// The source text that goes in the frame of a failed `expect`, if `expect_sources` was given.
+    /// It is empty when the condition's module has no source.
+    pub(crate) fn expect_condition_source(
+        &self,
+        condition: Symbol,
+        region: Region,
+    ) -> Option<&'env str> {
+        let sources = self.expect_sources?;
+
+        let text = sources
+            .get(&condition.module_id())
+            .and_then(|(_, source)| {
+                source.get(region.start().offset as usize..region.end().offset as usize)
+            })
+            .unwrap_or("");
+
+        Some(text)
+    }
+
     /// The integer type representing a pointer
     ///
     /// on 64-bit systems, this is i64
@@ -2853,6 +2879,8 @@ pub fn build_exp_stmt<'a, 'ctx, 'env>(
                     &shared_memory,
                     *symbol,
                     region,
+                    // a dbg has no condition, and its region is really its symbol
+                    env.expect_sources.map(|_| ""),
                     &[*symbol],
                     &[*specialized_var],
                 );
@@ -2910,6 +2938,7 @@ pub fn build_exp_stmt<'a, 'ctx, 'env>(
                             &shared_memory,
                             *cond_symbol,
                             *region,
+                            env.expect_condition_source(*cond_symbol, *region),
                             lookups,
                             variables,
                         );
@@ -2982,6 +3011,7 @@ pub fn build_exp_stmt<'a, 'ctx, 'env>(
                             &shared_memory,
                             *cond_symbol,
                             *region,
+                            env.expect_condition_source(*cond_symbol, *region),
                             lookups,
                             variables,
                         );
@@ -5963,7 +5993,7 @@ fn build_foreign_symbol<'a, 'ctx, 'env>(
     )
 }
 
-fn define_global_str_literal_ptr<'a, 'ctx, 'env>(
+pub(crate) fn define_global_str_literal_ptr<'a, 'ctx, 'env>(
     env: &Env<'a, 'ctx, 'env>,
     message: &str,
 ) -> PointerValue<'ctx> {
@@ -1,13 +1,16 @@
 use crate::debug_info_init;
-use crate::llvm::bitcode::call_str_bitcode_fn;
-use crate::llvm::build::{get_tag_id, store_roc_value, tag_pointer_clear_tag_id, Env};
+use crate::llvm::bitcode::{call_bitcode_fn, call_str_bitcode_fn, call_void_bitcode_fn};
+use crate::llvm::build::{
+    define_global_str_literal_ptr, get_tag_id, store_roc_value, tag_pointer_clear_tag_id,
+    throw_internal_exception, Env,
+};
 use crate::llvm::build_list::{self, incrementing_elem_loop};
-use crate::llvm::convert::{basic_type_from_layout, RocUnion};
+use crate::llvm::convert::{basic_type_from_layout, zig_list_type, RocUnion};
 use inkwell::builder::Builder;
 use inkwell::module::Linkage;
-use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, IntType};
 use inkwell::values::{BasicValueEnum, FunctionValue, IntValue, PointerValue};
-use inkwell::AddressSpace;
+use inkwell::{AddressSpace, IntPredicate};
 use roc_builtins::bitcode;
 use roc_error_macros::internal_error;
 use roc_module::symbol::Symbol;
@@ -54,6 +57,10 @@ impl<'ctx> SharedMemoryPointer<'ctx> {
 struct Cursors<'ctx> {
     offset: IntValue<'ctx>,
     extra_offset: IntValue<'ctx>,
+    /// How many more recursive pointers the clone of the current lookup may follow
+    recursion_budget: IntValue<'ctx>,
+    /// Whether the host asked for the native layout (an `i1`), read once per frame
+    native_layout: IntValue<'ctx>,
 }
 
 fn pointer_at_offset<'ctx>(
@@ -93,8 +100,46 @@ fn write_header<'a, 'ctx, 'env>(
     offset
 }
 
+/// Like `CONDITION_SOURCE_ALIGNMENT` in `roc_repl_expect`: the lookups after the condition's
+/// text start at a multiple of this, so they are aligned like a pointer on every target
+const CONDITION_SOURCE_ALIGNMENT: u64 = 8;
+
+/// Writes the source text of the condition into the buffer, after its length, and pads it to
+/// `CONDITION_SOURCE_ALIGNMENT`
+fn write_condition_source<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    ptr: PointerValue<'ctx>,
+    offset: IntValue<'ctx>,
+    text: &str,
+) -> IntValue<'ctx> {
+    let len = env.context.i32_type().const_int(text.len() as _, false);
+    let mut offset = build_copy(env, ptr, offset, len.into());
+
+    let bd = env.builder;
+
+    if !text.is_empty() {
+        let text_width = env.ptr_int().const_int(text.len() as _, false);
+
+        let dest = pointer_at_offset(bd, env.context.i8_type(), ptr, offset);
+        let src = define_global_str_literal_ptr(env, text);
+        bd.build_memcpy(dest, 1, src, 1, text_width).unwrap();
+
+        offset = bd.build_int_add(offset, text_width, "after_condition_source");
+    }
+
+    // the offset is absolute, and the buffer is aligned, so this aligns the lookups themselves
+    let ptr_int = env.ptr_int();
+    let bump = ptr_int.const_int(CONDITION_SOURCE_ALIGNMENT - 1, false);
+    let mask = ptr_int.const_int(!(CONDITION_SOURCE_ALIGNMENT - 1), false);
+    let bumped = bd.build_int_add(offset, bump, "bump_to_alignment");
+    bd.build_and(bumped, mask, "condition_source_padded")
+}
+
 /// Read the first two 32-bit values from the shared memory,
 /// representing the total number of expect frames and the next free position
+///
+/// Like all accesses to the header, this assumes that the shared memory is aligned to the
+/// pointer size. The host's buffer is mmap-ed, and `set_shared_buffer` asserts the alignment.
 fn read_state<'a, 'ctx, 'env>(
     env: &Env<'a, 'ctx, 'env>,
     ptr: PointerValue<'ctx>,
@@ -129,6 +174,84 @@ fn write_state<'a, 'ctx, 'env>(
     env.builder.build_store(offset_ptr, offset);
 }
 
+// The words of the shared memory's header that the host sets before running the expects, at the
+// same indices as in `ExpectSequence` in `roc_repl_expect`
+
+/// The number of bytes that the host allows frames to grow to before we stop cloning
+/// recursive values
+const MAX_FRAME_BYTES_INDEX: u64 = 3;
+/// When not zero, the host wants each value at an offset that is a multiple of its alignment,
+/// so that it can be used where it is
+const NATIVE_LAYOUT_INDEX: u64 = 4;
+/// How many recursive pointers the clone of each lookup may follow before the rest of its value
+/// is elided
+const RECURSION_BUDGET_INDEX: u64 = 5;
+
+/// Read the word at `index` from the shared memory's header.
+///
+/// The host writes these words into 8-byte slots whatever its pointer size (the frames start
+/// at `FRAMES_START` in `roc_repl_expect`), so we read the whole slot and truncate it.
+fn read_header_word<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    ptr: PointerValue<'ctx>,
+    index: u64,
+) -> IntValue<'ctx> {
+    let bd = env.builder;
+    let slot_type = env.context.i64_type();
+
+    let ptr = bd.build_pointer_cast(ptr, slot_type.ptr_type(AddressSpace::default()), "");
+
+    let index = env.ptr_int().const_int(index, false);
+    let slot_ptr = pointer_at_offset(bd, slot_type, ptr, index);
+
+    let slot = bd
+        .new_build_load(slot_type, slot_ptr, "load_header_slot")
+        .into_int_value();
+
+    bd.build_int_truncate_or_bit_cast(slot, env.ptr_int(), "header_word")
+}
+
+/// Whether the host asked for the native layout, as an `i1` for `align_extra_offset`
+fn read_native_layout<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    ptr: PointerValue<'ctx>,
+) -> IntValue<'ctx> {
+    env.builder.build_int_compare(
+        IntPredicate::NE,
+        read_header_word(env, ptr, NATIVE_LAYOUT_INDEX),
+        env.ptr_int().const_zero(),
+        "is_native_layout",
+    )
+}
+
+/// Where a value with the given alignment goes when it would go at `extra_offset`.
+///
+/// By default values are packed, and this is just `extra_offset`. When the host asked for the
+/// native layout (see `read_native_layout`), it is rounded up to a multiple of `alignment`.
+/// The frame itself starts at an offset that the host keeps aligned to the pointer size.
+fn align_extra_offset<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    native_layout: IntValue<'ctx>,
+    extra_offset: IntValue<'ctx>,
+    alignment: u32,
+) -> IntValue<'ctx> {
+    if alignment <= 1 {
+        return extra_offset;
+    }
+
+    let bd = env.builder;
+    let mask = env.ptr_int().const_int(alignment as u64 - 1, false);
+
+    let aligned = bd.build_and(
+        bd.build_int_add(extra_offset, mask, "add_alignment_mask"),
+        bd.build_not(mask, "alignment_mask"),
+        "aligned_extra_offset",
+    );
+
+    bd.build_select(native_layout, aligned, extra_offset, "extra_offset")
+        .into_int_value()
+}
+
 pub(crate) fn notify_parent_expect(env: &Env, shared_memory: &SharedMemoryPointer) {
     let func = env
         .module
@@ -157,6 +280,9 @@ pub(crate) fn notify_parent_dbg(env: &Env, shared_memory: &SharedMemoryPointer)
 //     ===
 //     Fixed-size header
 //     ===
+//     source_len    (u32, only with `expect_sources`)
+//     source_bytes  (source_len)
+//     padding       (to a multiple of 8)
 // /-- ptr_lookup_1  (ptr_size)
 // |   var_lookup_1  (u32)
 // |   ..
@@ -174,6 +300,7 @@ pub(crate) fn clone_to_shared_memory<'a, 'ctx, 'env>(
     shared_memory: &SharedMemoryPointer<'ctx>,
     condition: Symbol,
     region: Region,
+    condition_source: Option<&str>,
     lookups: &[Symbol],
     lookup_variables: &[LookupType],
 ) {
@@ -181,8 +308,15 @@ pub(crate) fn clone_to_shared_memory<'a, 'ctx, 'env>(
 
     let (count, mut offset) = read_state(env, original_ptr);
 
+    // values are only shared within a frame
+    call_void_bitcode_fn(env, &[], bitcode::UTILS_EXPECT_CLONE_SEEN_RESET);
+
     offset = write_header(env, original_ptr, offset, condition, region);
 
+    if let Some(text) = condition_source {
+        offset = write_condition_source(env, original_ptr, offset, text);
+    }
+
     let after_header = offset;
 
     let space_for_offsets = env.ptr_int().const_int(
@@ -193,15 +327,25 @@ pub(crate) fn clone_to_shared_memory<'a, 'ctx, 'env>(
 
     let mut lookup_starts = bumpalo::collections::Vec::with_capacity_in(lookups.len(), env.arena);
 
+    // each lookup gets the whole budget
+    let recursion_budget = read_header_word(env, original_ptr, RECURSION_BUDGET_INDEX);
+    let native_layout = read_native_layout(env, original_ptr);
+
     offset = env
         .builder
         .build_int_add(offset, space_for_offsets, "offset");
 
     for lookup in lookups.iter() {
-        lookup_starts.push(offset);
-
         let (value, layout) = load_symbol_and_layout(scope, lookup);
 
+        offset = align_extra_offset(
+            env,
+            native_layout,
+            offset,
+            layout_interner.alignment_bytes(layout),
+        );
+        lookup_starts.push(offset);
+
         let stack_size = env
             .ptr_int()
             .const_int(layout_interner.stack_size(layout) as u64, false);
@@ -211,6 +355,8 @@ pub(crate) fn clone_to_shared_memory<'a, 'ctx, 'env>(
         let cursors = Cursors {
             offset,
             extra_offset,
+            recursion_budget,
+            native_layout,
         };
 
         extra_offset = build_clone(
@@ -278,6 +424,9 @@ pub(crate) fn clone_to_shared_memory<'a, 'ctx, 'env>(
     write_state(env, original_ptr, new_count, offset)
 }
 
+/// Writes `value` into the frame at `cursors.offset`, and what it points to at
+/// `cursors.extra_offset`. This is the only code that writes frames: the dev and wasm backends
+/// don't implement `dbg` or `expect`, so there is no mono IR version of it for them to share.
 fn build_clone<'a, 'ctx, 'env>(
     env: &Env<'a, 'ctx, 'env>,
     layout_interner: &mut STLayoutInterner<'a>,
@@ -308,9 +457,17 @@ fn build_clone<'a, 'ctx, 'env>(
             field_layouts,
         ),
 
-        // Since we will never actually display functions (and hence lambda sets)
-        // we just write nothing to the buffer
-        Layout::LambdaSet(_) => cursors.extra_offset,
+        // Functions are never displayed, but their captures are cloned like any other value,
+        // so that the frame holds everything the stack bytes of the value point to.
+        Layout::LambdaSet(lambda_set) => build_clone(
+            env,
+            layout_interner,
+            layout_ids,
+            ptr,
+            cursors,
+            value,
+            lambda_set.runtime_representation(),
+        ),
 
         Layout::Union(union_layout) => {
             if layout_interner.safe_to_memcpy(layout) {
@@ -331,7 +488,7 @@ fn build_clone<'a, 'ctx, 'env>(
                 store_roc_value(env, layout_interner, layout, ptr, value);
 
                 cursors.extra_offset
-            } else {
+            } else if let UnionLayout::NonRecursive(_) = union_layout {
                 build_clone_tag(
                     env,
                     layout_interner,
@@ -341,38 +498,42 @@ fn build_clone<'a, 'ctx, 'env>(
                     value,
                     union_layout,
                 )
+            } else {
+                build_clone_shared(
+                    env,
+                    ptr,
+                    cursors,
+                    value.into_pointer_value(),
+                    union_pointer_word_type(env, union_layout),
+                    || {
+                        build_clone_tag(
+                            env,
+                            layout_interner,
+                            layout_ids,
+                            ptr,
+                            cursors,
+                            value,
+                            union_layout,
+                        )
+                    },
+                )
             }
         }
 
         Layout::Boxed(inner_layout) => {
-            // write the offset
-            build_copy(env, ptr, cursors.offset, cursors.extra_offset.into());
-
             let source = value.into_pointer_value();
-            let value = load_roc_value(env, layout_interner, inner_layout, source, "inner");
 
-            let inner_width = env
-                .ptr_int()
-                .const_int(layout_interner.stack_size(inner_layout) as u64, false);
-
-            let new_extra = env
-                .builder
-                .build_int_add(cursors.offset, inner_width, "new_extra");
-
-            let cursors = Cursors {
-                offset: cursors.extra_offset,
-                extra_offset: new_extra,
-            };
-
-            build_clone(
-                env,
-                layout_interner,
-                layout_ids,
-                ptr,
-                cursors,
-                value,
-                inner_layout,
-            )
+            build_clone_shared(env, ptr, cursors, source, env.ptr_int(), || {
+                build_clone_boxed(
+                    env,
+                    layout_interner,
+                    layout_ids,
+                    ptr,
+                    cursors,
+                    source,
+                    inner_layout,
+                )
+            })
         }
 
         Layout::RecursivePointer(rec_layout) => {
@@ -395,19 +556,287 @@ fn build_clone<'a, 'ctx, 'env>(
                 _ => internal_error!(),
             };
 
-            build_clone_tag(
+            build_clone_shared(
                 env,
-                layout_interner,
-                layout_ids,
                 ptr,
                 cursors,
-                field1_cast.into(),
-                union_layout,
+                field1_cast,
+                union_pointer_word_type(env, union_layout),
+                || {
+                    build_clone_recursive(
+                        env,
+                        layout_interner,
+                        layout_ids,
+                        ptr,
+                        cursors,
+                        field1_cast.into(),
+                        union_layout,
+                    )
+                },
             )
         }
     }
 }
 
+fn build_clone_boxed<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    layout_ids: &mut LayoutIds<'a>,
+    ptr: PointerValue<'ctx>,
+    cursors: Cursors<'ctx>,
+    source: PointerValue<'ctx>,
+    inner_layout: InLayout<'a>,
+) -> IntValue<'ctx> {
+    let cursors = Cursors {
+        offset: cursors.offset,
+        extra_offset: align_extra_offset(
+            env,
+            cursors.native_layout,
+            cursors.extra_offset,
+            layout_interner.alignment_bytes(inner_layout),
+        ),
+        ..cursors
+    };
+
+    // write the offset
+    build_copy(env, ptr, cursors.offset, cursors.extra_offset.into());
+
+    let inner_width = env
+        .ptr_int()
+        .const_int(layout_interner.stack_size(inner_layout) as u64, false);
+
+    let new_extra = env
+        .builder
+        .build_int_add(cursors.extra_offset, inner_width, "new_extra");
+
+    if layout_interner.safe_to_memcpy(inner_layout) {
+        // the inner value has no pointers, so its bytes can be copied as they are
+        let bd = env.builder;
+        let dest = pointer_at_offset(bd, env.context.i8_type(), ptr, cursors.extra_offset);
+        let src = bd.build_pointer_cast(
+            source,
+            env.context.i8_type().ptr_type(AddressSpace::default()),
+            "to_bytes_pointer",
+        );
+        bd.build_memcpy(dest, 1, src, 1, inner_width).unwrap();
+
+        return new_extra;
+    }
+
+    let value = load_roc_value(env, layout_interner, inner_layout, source, "inner");
+
+    let cursors = Cursors {
+        offset: cursors.extra_offset,
+        extra_offset: new_extra,
+        ..cursors
+    };
+
+    build_clone(
+        env,
+        layout_interner,
+        layout_ids,
+        ptr,
+        cursors,
+        value,
+        inner_layout,
+    )
+}
+
+/// Clone the value behind `source` with `clone`, unless the same pointer was already cloned
+/// into this frame. Then we write the word that was written for it back then, so a value that
+/// is shared between several places in the lookups is cloned (and stored) only once.
+///
+/// A copy is only reused with a recursion budget no bigger than the one it was made with, and
+/// an elided value is never reused, so sharing never shows less of a value than cloning would.
+///
+/// `word_type` is the type of what `clone` writes at `cursors.offset`.
+fn build_clone_shared<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    ptr: PointerValue<'ctx>,
+    cursors: Cursors<'ctx>,
+    source: PointerValue<'ctx>,
+    word_type: IntType<'ctx>,
+    clone: impl FnOnce() -> IntValue<'ctx>,
+) -> IntValue<'ctx> {
+    let bd = env.builder;
+    let i64_type = env.context.i64_type();
+
+    let parent = bd.get_insert_block().and_then(|b| b.get_parent()).unwrap();
+
+    let source_addr = bd.build_ptr_to_int(source, env.ptr_int(), "source_addr");
+    let seen_word = call_bitcode_fn(
+        env,
+        &[source_addr.into(), cursors.recursion_budget.into()],
+        bitcode::UTILS_EXPECT_CLONE_SEEN_FIND,
+    )
+    .into_int_value();
+    let is_seen = bd.build_int_compare(
+        IntPredicate::NE,
+        seen_word,
+        i64_type.const_zero(),
+        "is_seen",
+    );
+
+    let seen_block = env.context.append_basic_block(parent, "seen_block");
+    let clone_block = env.context.append_basic_block(parent, "clone_block");
+    let insert_block = env.context.append_basic_block(parent, "insert_block");
+    let cont_block = env.context.append_basic_block(parent, "cont_block");
+
+    bd.build_conditional_branch(is_seen, seen_block, clone_block);
+
+    bd.position_at_end(seen_block);
+    let word = bd.build_int_truncate_or_bit_cast(seen_word, word_type, "seen_word");
+    build_copy(env, ptr, cursors.offset, word.into());
+    bd.build_unconditional_branch(cont_block);
+
+    bd.position_at_end(clone_block);
+    let cloned_extra_offset = clone();
+    let written_ptr = pointer_at_offset(bd, env.context.i8_type(), ptr, cursors.offset);
+
+    // an elided value was cut short where it was, and must be cloned again elsewhere
+    let marker_ptr = bd.build_pointer_cast(
+        written_ptr,
+        env.ptr_int().ptr_type(AddressSpace::default()),
+        "marker_ptr",
+    );
+    let marker = bd
+        .new_build_load(env.ptr_int(), marker_ptr, "written_marker")
+        .into_int_value();
+    let is_elided = bd.build_int_compare(IntPredicate::EQ, marker, elided_marker(env), "is_elided");
+    let clone_end_block = bd.get_insert_block().unwrap();
+    bd.build_conditional_branch(is_elided, cont_block, insert_block);
+
+    bd.position_at_end(insert_block);
+    let word_ptr = bd.build_pointer_cast(
+        written_ptr,
+        word_type.ptr_type(AddressSpace::default()),
+        "word_ptr",
+    );
+    let word = bd
+        .new_build_load(word_type, word_ptr, "written_word")
+        .into_int_value();
+    let word = bd.build_int_z_extend_or_bit_cast(word, i64_type, "written_word");
+    call_void_bitcode_fn(
+        env,
+        &[
+            source_addr.into(),
+            word.into(),
+            cursors.recursion_budget.into(),
+        ],
+        bitcode::UTILS_EXPECT_CLONE_SEEN_INSERT,
+    );
+    bd.build_unconditional_branch(cont_block);
+
+    bd.position_at_end(cont_block);
+    let phi = bd.build_phi(env.ptr_int(), "extra_offset");
+    phi.add_incoming(&[
+        (&cursors.extra_offset, seen_block),
+        (&cloned_extra_offset, clone_end_block),
+        (&cloned_extra_offset, insert_block),
+    ]);
+
+    phi.as_basic_value().into_int_value()
+}
+
+/// Whether a pointer into this union is written as a u32 tag id followed by a u32 offset.
+///
+/// This only depends on how many tags the union has, so it is the same for every value of the
+/// union, in every clone proc that handles it, and in the host that reads the frame.
+fn clone_tag_id_in_pointer(env: &Env<'_, '_, '_>, union_layout: UnionLayout<'_>) -> bool {
+    union_layout.stores_tag_id_in_pointer(env.target_info)
+}
+
+/// The type of what `write_pointer_with_tag_id` writes for a pointer into this union
+fn union_pointer_word_type<'ctx>(
+    env: &Env<'_, 'ctx, '_>,
+    union_layout: UnionLayout<'_>,
+) -> IntType<'ctx> {
+    if clone_tag_id_in_pointer(env, union_layout) {
+        // a u32 tag id followed by a u32 offset
+        env.context.i64_type()
+    } else {
+        env.ptr_int()
+    }
+}
+
+/// The marker that is written instead of a pointer when a recursive value is elided
+fn elided_marker<'ctx>(env: &Env<'_, 'ctx, '_>) -> IntValue<'ctx> {
+    env.ptr_int().const_all_ones()
+}
+
+/// Clone the value behind a recursive pointer, unless the frame has already grown past
+/// the limit that the host put in the shared memory header, or the lookup has used up its
+/// recursion budget. In that case, we write a marker instead of the pointer, and the host
+/// renders the rest of the value as `...`.
+fn build_clone_recursive<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    layout_ids: &mut LayoutIds<'a>,
+    ptr: PointerValue<'ctx>,
+    cursors: Cursors<'ctx>,
+    value: BasicValueEnum<'ctx>,
+    union_layout: UnionLayout<'a>,
+) -> IntValue<'ctx> {
+    let bd = env.builder;
+
+    let parent = bd.get_insert_block().and_then(|b| b.get_parent()).unwrap();
+
+    let max_frame_bytes = read_header_word(env, ptr, MAX_FRAME_BYTES_INDEX);
+    let is_too_big = bd.build_int_compare(
+        IntPredicate::UGE,
+        cursors.extra_offset,
+        max_frame_bytes,
+        "is_too_big",
+    );
+    let is_too_deep = bd.build_int_compare(
+        IntPredicate::EQ,
+        cursors.recursion_budget,
+        env.ptr_int().const_zero(),
+        "is_too_deep",
+    );
+    let should_elide = bd.build_or(is_too_big, is_too_deep, "should_elide");
+
+    let elide_block = env.context.append_basic_block(parent, "elide_block");
+    let clone_block = env.context.append_basic_block(parent, "clone_block");
+    let cont_block = env.context.append_basic_block(parent, "cont_block");
+
+    bd.build_conditional_branch(should_elide, elide_block, clone_block);
+
+    bd.position_at_end(elide_block);
+    build_copy(env, ptr, cursors.offset, elided_marker(env).into());
+    bd.build_unconditional_branch(cont_block);
+
+    bd.position_at_end(clone_block);
+    let cursors = Cursors {
+        recursion_budget: bd.build_int_sub(
+            cursors.recursion_budget,
+            env.ptr_int().const_int(1, false),
+            "recursion_budget",
+        ),
+        ..cursors
+    };
+    let cloned_extra_offset = build_clone_tag(
+        env,
+        layout_interner,
+        layout_ids,
+        ptr,
+        cursors,
+        value,
+        union_layout,
+    );
+    let clone_end_block = bd.get_insert_block().unwrap();
+    bd.build_unconditional_branch(cont_block);
+
+    bd.position_at_end(cont_block);
+    let phi = bd.build_phi(env.ptr_int(), "extra_offset");
+    phi.add_incoming(&[
+        (&cursors.extra_offset, elide_block),
+        (&cloned_extra_offset, clone_end_block),
+    ]);
+
+    phi.as_basic_value().into_int_value()
+}
+
 fn build_clone_struct<'a, 'ctx, 'env>(
     env: &Env<'a, 'ctx, 'env>,
     layout_interner: &mut STLayoutInterner<'a>,
@@ -421,6 +850,24 @@ fn build_clone_struct<'a, 'ctx, 'env>(
 
     if layout.safe_to_memcpy(layout_interner) {
         build_copy(env, ptr, cursors.offset, value)
+    } else if let [field_layout] = field_layouts {
+        // a single-field struct has the same layout as its field; clone the field directly
+        let field = env
+            .builder
+            .build_extract_value(value.into_struct_value(), 0, "extract")
+            .unwrap();
+
+        let field = use_roc_value(env, layout_interner, *field_layout, field, "field");
+
+        build_clone(
+            env,
+            layout_interner,
+            layout_ids,
+            ptr,
+            cursors,
+            field,
+            *field_layout,
+        )
     } else {
         let mut cursors = cursors;
 
@@ -468,10 +915,34 @@ fn build_clone_tag<'a, 'ctx, 'env>(
     union_layout: UnionLayout<'a>,
 ) -> IntValue<'ctx> {
     let layout = layout_interner.insert(Layout::Union(union_layout));
-    let layout_id = layout_ids.get(Symbol::CLONE, &layout);
-    let fn_name = layout_id.to_symbol_string(Symbol::CLONE, &env.interns);
+    // Named after what the union is rather than when it was interned, so that the same program
+    // always gets the same names
+    let base_name = format!(
+        "{}_{:016x}",
+        Symbol::CLONE.as_str(&env.interns),
+        layout_interner.content_hash(layout)
+    );
 
-    let function = match env.module.get_function(fn_name.as_str()) {
+    // Another union can have the same hash. Its function takes another type of value, so keep
+    // adding a suffix until the name is free, or has a function for this type of value.
+    let mut fn_name = base_name.clone();
+    let mut collisions = 0;
+    let existing = loop {
+        match env.module.get_function(fn_name.as_str()) {
+            Some(function_value)
+                if function_value.get_type().get_param_types()[3] == value.get_type() =>
+            {
+                break Some(function_value);
+            }
+            Some(_) => {
+                collisions += 1;
+                fn_name = format!("{}_{}", base_name, collisions);
+            }
+            None => break None,
+        }
+    };
+
+    let function = match existing {
         Some(function_value) => function_value,
         None => {
             let block = env.builder.get_insert_block().expect("to be in a function");
@@ -486,6 +957,8 @@ fn build_clone_tag<'a, 'ctx, 'env>(
                     env.ptr_int().into(),
                     env.ptr_int().into(),
                     BasicMetadataTypeEnum::from(value.get_type()),
+                    env.ptr_int().into(),
+                    env.context.bool_type().into(),
                 ],
                 false,
             );
@@ -503,6 +976,8 @@ fn build_clone_tag<'a, 'ctx, 'env>(
 
             env.dibuilder.finalize();
 
+            // The function is declared before its body is built, so a union that contains
+            // itself (directly, or through another union) calls it rather than building it again
             build_clone_tag_help(
                 env,
                 layout_interner,
@@ -525,6 +1000,8 @@ fn build_clone_tag<'a, 'ctx, 'env>(
             cursors.offset.into(),
             cursors.extra_offset.into(),
             value.into(),
+            cursors.recursion_budget.into(),
+            cursors.native_layout.into(),
         ],
         "build_clone_tag",
     );
@@ -564,6 +1041,9 @@ fn load_tag_data<'a, 'ctx, 'env>(
     env.builder.new_build_load(tag_type, data_ptr, "load_data")
 }
 
+/// What a program that clones an empty tag union into an expect frame panics with.
+const CLONED_EMPTY_UNION: &str = "internal error: cloned empty tag union";
+
 fn build_clone_tag_help<'a, 'ctx, 'env>(
     env: &Env<'a, 'ctx, 'env>,
     layout_interner: &mut STLayoutInterner<'a>,
@@ -591,12 +1071,23 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
 
     let ptr = it.next().unwrap().into_pointer_value();
     let offset = it.next().unwrap().into_int_value();
-    let extra_offset = it.next().unwrap().into_int_value();
+    let mut extra_offset = it.next().unwrap().into_int_value();
     let tag_value = it.next().unwrap();
+    let recursion_budget = it.next().unwrap().into_int_value();
+    let native_layout = it.next().unwrap().into_int_value();
+
+    // the data of a recursive union is behind a pointer, and goes at the extra offset
+    if !matches!(union_layout, UnionLayout::NonRecursive(_)) {
+        let (_, data_align) =
+            union_layout.data_size_and_alignment(layout_interner, env.target_info);
+        extra_offset = align_extra_offset(env, native_layout, extra_offset, data_align);
+    }
 
     let cursors = Cursors {
         offset,
         extra_offset,
+        recursion_budget,
+        native_layout,
     };
 
     let parent = fn_val;
@@ -607,8 +1098,8 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
 
     match union_layout {
         NonRecursive(&[]) => {
-            // we're comparing empty tag unions; this code is effectively unreachable
-            env.builder.build_unreachable();
+            // a value of an empty tag union can't exist, so getting here is a compiler bug
+            throw_internal_exception(env, parent, CLONED_EMPTY_UNION);
         }
         NonRecursive(tags) => {
             let id = get_tag_id(env, layout_interner, parent, &union_layout, tag_value);
@@ -623,9 +1114,6 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                 env.builder.position_at_end(block);
 
                 let layout = layout_interner.insert(Layout::struct_no_name_order(field_layouts));
-                let layout = layout_interner.insert(Layout::struct_no_name_order(
-                    env.arena.alloc([layout, union_layout.tag_id_layout()]),
-                ));
 
                 let basic_type = basic_type_from_layout(env, layout_interner, layout);
                 let data = load_tag_data(
@@ -636,6 +1124,19 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                     basic_type,
                 );
 
+                // The tag id goes after the biggest payload, where the host reads it, and not
+                // right after this tag's payload. This matters for a tag with a smaller payload,
+                // like `A` in a `List [A I64, B Str]`, where the elements are just as wide anyway.
+                let tag_id_offset = union_layout
+                    .tag_id_offset(layout_interner, env.target_info)
+                    .unwrap();
+                let tag_id_offset = env.builder.build_int_add(
+                    offset,
+                    env.ptr_int().const_int(tag_id_offset as _, false),
+                    "tag_id_offset",
+                );
+                build_copy(env, ptr, tag_id_offset, id.into());
+
                 let answer =
                     build_clone(env, layout_interner, layout_ids, ptr, cursors, data, layout);
 
@@ -651,8 +1152,8 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                     env.builder.build_switch(id, default, &cases);
                 }
                 None => {
-                    // we're serializing an empty tag union; this code is effectively unreachable
-                    env.builder.build_unreachable();
+                    // a value of an empty tag union can't exist, so getting here is a compiler bug
+                    throw_internal_exception(env, parent, CLONED_EMPTY_UNION);
                 }
             }
         }
@@ -674,7 +1175,7 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                 let tag_value = tag_pointer_clear_tag_id(env, tag_value.into_pointer_value());
 
                 let layout = layout_interner.insert(Layout::struct_no_name_order(field_layouts));
-                let layout = if union_layout.stores_tag_id_in_pointer(env.target_info) {
+                let layout = if clone_tag_id_in_pointer(env, union_layout) {
                     layout
                 } else {
                     layout_interner.insert(Layout::struct_no_name_order(
@@ -695,6 +1196,7 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                         env.ptr_int().const_int(width as _, false),
                         "new_offset",
                     ),
+                    ..cursors
                 };
 
                 let answer =
@@ -712,8 +1214,8 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                     env.builder.build_switch(id, default, &cases);
                 }
                 None => {
-                    // we're serializing an empty tag union; this code is effectively unreachable
-                    env.builder.build_unreachable();
+                    // a value of an empty tag union can't exist, so getting here is a compiler bug
+                    throw_internal_exception(env, parent, CLONED_EMPTY_UNION);
                 }
             }
         }
@@ -722,7 +1224,11 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
 
             build_copy(env, ptr, offset, extra_offset.into());
 
-            let layout = layout_interner.insert(Layout::struct_no_name_order(fields));
+            // a single field is laid out just like a struct of it, so it is cloned on its own
+            let layout = match fields {
+                [field] => *field,
+                _ => layout_interner.insert(Layout::struct_no_name_order(fields)),
+            };
             let basic_type = basic_type_from_layout(env, layout_interner, layout);
 
             let (width, _) = union_layout.data_size_and_alignment(layout_interner, env.target_info);
@@ -734,6 +1240,7 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                     env.ptr_int().const_int(width as _, false),
                     "new_offset",
                 ),
+                ..cursors
             };
 
             let data = load_tag_data(env, layout_interner, union_layout, tag_value, basic_type);
@@ -778,7 +1285,16 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                         other_tags[i]
                     };
 
+                    // like for `Recursive`, the host reads the tag id from the data when it
+                    // does not fit in the pointer, so it must be cloned along with the fields
                     let layout = layout_interner.insert(Layout::struct_no_name_order(fields));
+                    let layout = if clone_tag_id_in_pointer(env, union_layout) {
+                        layout
+                    } else {
+                        layout_interner.insert(Layout::struct_no_name_order(
+                            env.arena.alloc([layout, union_layout.tag_id_layout()]),
+                        ))
+                    };
                     let basic_type = basic_type_from_layout(env, layout_interner, layout);
 
                     let (width, _) =
@@ -791,6 +1307,7 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                             env.ptr_int().const_int(width as _, false),
                             "new_offset",
                         ),
+                        ..cursors
                     };
 
                     let tag_value = tag_pointer_clear_tag_id(env, tag_value.into_pointer_value());
@@ -812,8 +1329,8 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                         env.builder.build_switch(id, default, &cases);
                     }
                     None => {
-                        // we're serializing an empty tag union; this code is effectively unreachable
-                        env.builder.build_unreachable();
+                        // a value of an empty tag union can't exist, so getting here is a compiler bug
+                        throw_internal_exception(env, parent, CLONED_EMPTY_UNION);
                     }
                 }
             }
@@ -864,6 +1381,7 @@ fn build_clone_tag_help<'a, 'ctx, 'env>(
                             .const_int(layout_interner.stack_size(layout) as _, false),
                         "new_offset",
                     ),
+                    ..cursors
                 };
 
                 let data = load_tag_data(
@@ -891,7 +1409,7 @@ fn write_pointer_with_tag_id<'a, 'ctx, 'env>(
     union_layout: UnionLayout<'a>,
     tag_id: usize,
 ) {
-    if union_layout.stores_tag_id_in_pointer(env.target_info) {
+    if clone_tag_id_in_pointer(env, union_layout) {
         // first, store tag id as u32
         let tag_id_intval = env.context.i32_type().const_int(tag_id as _, false);
         build_copy(env, ptr, offset, tag_id_intval.into());
@@ -949,6 +1467,10 @@ fn build_clone_builtin<'a, 'ctx, 'env>(
     use Builtin::*;
 
     match builtin {
+        // Floats are only ever copied as bits: no frame format has a formatted string next to
+        // them, because a float's value takes the same bytes as in the program, and the reader
+        // finds values by their layout. The host prints the bits with the shortest digits that
+        // round-trip, like `Num.toStr` does.
         Int(_) | Float(_) | Bool | Decimal => {
             build_copy(env, ptr, cursors.offset, value);
 
@@ -971,109 +1493,253 @@ fn build_clone_builtin<'a, 'ctx, 'env>(
             )
             .into_int_value()
         }
+        // `Dict` and `Set` are opaque records of lists by the time they get here, so copying
+        // every list's elements in storage order is what preserves their iteration order.
         Builtin::List(elem) => {
-            let bd = env.builder;
-
             let list = value.into_struct_value();
             let (elements, len, _cap) = build_list::destructure(env.builder, list);
 
             let mut offset = cursors.offset;
+            let elements_start = align_extra_offset(
+                env,
+                cursors.native_layout,
+                cursors.extra_offset,
+                layout_interner.alignment_bytes(elem),
+            );
 
             // we only copy the elements we actually have (and skip extra capacity)
-            offset = build_copy(env, ptr, offset, cursors.extra_offset.into());
+            // the length is written even when the elements are zero-sized, like in a `List {}`
+            offset = build_copy(env, ptr, offset, elements_start.into());
             offset = build_copy(env, ptr, offset, len.into());
             offset = build_copy(env, ptr, offset, len.into());
 
-            let (element_width, _element_align) = layout_interner.stack_size_and_alignment(elem);
-            let element_width = env.ptr_int().const_int(element_width as _, false);
-
-            let elements_width = bd.build_int_mul(element_width, len, "elements_width");
-
             // We clone the elements into the extra_offset address.
             let _ = offset;
-            let elements_start_offset = cursors.extra_offset;
-
-            if layout_interner.safe_to_memcpy(elem) {
-                // NOTE we are not actually sure the dest is properly aligned
-                let dest = pointer_at_offset(bd, env.context.i8_type(), ptr, elements_start_offset);
-                let src = bd.build_pointer_cast(
-                    elements,
-                    env.context.i8_type().ptr_type(AddressSpace::default()),
-                    "to_bytes_pointer",
-                );
-                bd.build_memcpy(dest, 1, src, 1, elements_width).unwrap();
 
-                bd.build_int_add(elements_start_offset, elements_width, "new_offset")
-            } else {
-                let element_type = basic_type_from_layout(env, layout_interner, elem);
-                let elements = bd.build_pointer_cast(
-                    elements,
-                    element_type.ptr_type(AddressSpace::default()),
-                    "elements",
-                );
+            build_clone_elements(
+                env,
+                layout_interner,
+                layout_ids,
+                ptr,
+                elements,
+                len,
+                elements_start,
+                elem,
+                cursors.recursion_budget,
+                cursors.native_layout,
+            )
+        }
+    }
+}
+
+/// Clone `len` elements of layout `elem` from `elements` to `elements_start_offset` in the frame,
+/// followed by whatever they point to, and return the offset after all of it.
+///
+/// This is what cloning a list does after writing its header. The length can be any value,
+/// so a fixed-size array (if `Layout` ever gets one) could pass a constant for it.
+#[allow(clippy::too_many_arguments)]
+fn build_clone_elements<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    layout_ids: &mut LayoutIds<'a>,
+    ptr: PointerValue<'ctx>,
+    elements: PointerValue<'ctx>,
+    len: IntValue<'ctx>,
+    elements_start_offset: IntValue<'ctx>,
+    elem: InLayout<'a>,
+    recursion_budget: IntValue<'ctx>,
+    native_layout: IntValue<'ctx>,
+) -> IntValue<'ctx> {
+    let bd = env.builder;
 
-                // if the element has any pointers, we clone them to this offset
-                let rest_offset = bd.build_alloca(env.ptr_int(), "rest_offset");
+    let (element_width, _element_align) = layout_interner.stack_size_and_alignment(elem);
+    let element_width = env.ptr_int().const_int(element_width as _, false);
 
-                let element_stack_size = env
-                    .ptr_int()
-                    .const_int(layout_interner.stack_size(elem) as u64, false);
-                let rest_start_offset = bd.build_int_add(
-                    cursors.extra_offset,
-                    bd.build_int_mul(len, element_stack_size, "elements_width"),
-                    "rest_start_offset",
-                );
-                bd.build_store(rest_offset, rest_start_offset);
+    let elements_width = bd.build_int_mul(element_width, len, "elements_width");
 
-                let body = |layout_interner, index, element| {
-                    let current_offset =
-                        bd.build_int_mul(element_stack_size, index, "current_offset");
-                    let current_offset =
-                        bd.build_int_add(elements_start_offset, current_offset, "current_offset");
-                    let current_extra_offset =
-                        bd.new_build_load(env.ptr_int(), rest_offset, "element_offset");
+    let flat_inner = match layout_interner.get(elem) {
+        Layout::Builtin(Builtin::List(inner)) if layout_interner.safe_to_memcpy(inner) => {
+            Some(inner)
+        }
+        _ => None,
+    };
 
-                    let offset = current_offset;
-                    let extra_offset = current_extra_offset.into_int_value();
+    if layout_interner.safe_to_memcpy(elem) {
+        // only aligned when the host asked for the native layout, so copy byte by byte
+        let dest = pointer_at_offset(bd, env.context.i8_type(), ptr, elements_start_offset);
+        let src = bd.build_pointer_cast(
+            elements,
+            env.context.i8_type().ptr_type(AddressSpace::default()),
+            "to_bytes_pointer",
+        );
+        bd.build_memcpy(dest, 1, src, 1, elements_width).unwrap();
 
-                    let cursors = Cursors {
-                        offset,
-                        extra_offset,
-                    };
+        bd.build_int_add(elements_start_offset, elements_width, "new_offset")
+    } else if let Some(inner) = flat_inner {
+        build_clone_flat_lists(
+            env,
+            layout_interner,
+            ptr,
+            elements,
+            len,
+            elements_start_offset,
+            element_width,
+            inner,
+            native_layout,
+        )
+    } else {
+        let element_type = basic_type_from_layout(env, layout_interner, elem);
+        let elements = bd.build_pointer_cast(
+            elements,
+            element_type.ptr_type(AddressSpace::default()),
+            "elements",
+        );
 
-                    let new_offset = build_clone(
-                        env,
-                        layout_interner,
-                        layout_ids,
-                        ptr,
-                        cursors,
-                        element,
-                        elem,
-                    );
+        // if the element has any pointers, we clone them to this offset
+        let rest_offset = bd.build_alloca(env.ptr_int(), "rest_offset");
 
-                    bd.build_store(rest_offset, new_offset);
-                };
+        let element_stack_size = env
+            .ptr_int()
+            .const_int(layout_interner.stack_size(elem) as u64, false);
+        let rest_start_offset = bd.build_int_add(
+            elements_start_offset,
+            bd.build_int_mul(len, element_stack_size, "elements_width"),
+            "rest_start_offset",
+        );
+        bd.build_store(rest_offset, rest_start_offset);
 
-                let parent = env
-                    .builder
-                    .get_insert_block()
-                    .and_then(|b| b.get_parent())
-                    .unwrap();
+        let body = |layout_interner, index, element| {
+            let current_offset = bd.build_int_mul(element_stack_size, index, "current_offset");
+            let current_offset =
+                bd.build_int_add(elements_start_offset, current_offset, "current_offset");
+            let current_extra_offset =
+                bd.new_build_load(env.ptr_int(), rest_offset, "element_offset");
 
-                incrementing_elem_loop(
-                    env,
-                    layout_interner,
-                    parent,
-                    elem,
-                    elements,
-                    len,
-                    "index",
-                    body,
-                );
+            let offset = current_offset;
+            let extra_offset = current_extra_offset.into_int_value();
 
-                bd.new_build_load(env.ptr_int(), rest_offset, "rest_start_offset")
-                    .into_int_value()
-            }
-        }
+            let cursors = Cursors {
+                offset,
+                extra_offset,
+                recursion_budget,
+                native_layout,
+            };
+
+            let new_offset = build_clone(
+                env,
+                layout_interner,
+                layout_ids,
+                ptr,
+                cursors,
+                element,
+                elem,
+            );
+
+            bd.build_store(rest_offset, new_offset);
+        };
+
+        let parent = env
+            .builder
+            .get_insert_block()
+            .and_then(|b| b.get_parent())
+            .unwrap();
+
+        incrementing_elem_loop(
+            env,
+            layout_interner,
+            parent,
+            elem,
+            elements,
+            len,
+            "index",
+            body,
+        );
+
+        bd.new_build_load(env.ptr_int(), rest_offset, "rest_start_offset")
+            .into_int_value()
     }
 }
+
+/// Clone the elements of a list of lists whose elements have no pointers, like a `List (List U8)`.
+///
+/// This writes the same as cloning each inner list with `build_clone`: a header for each inner
+/// list, and then the bytes of each inner list in turn. But each header is written from just the
+/// pointer and length of its inner list, without loading the whole list and dispatching on its
+/// layout, which makes for a lot less IR per element.
+#[allow(clippy::too_many_arguments)]
+fn build_clone_flat_lists<'a, 'ctx, 'env>(
+    env: &Env<'a, 'ctx, 'env>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    ptr: PointerValue<'ctx>,
+    lists: PointerValue<'ctx>,
+    len: IntValue<'ctx>,
+    headers_start_offset: IntValue<'ctx>,
+    header_width: IntValue<'ctx>,
+    inner: InLayout<'a>,
+    native_layout: IntValue<'ctx>,
+) -> IntValue<'ctx> {
+    let bd = env.builder;
+
+    let list_type = zig_list_type(env);
+    let lists = bd.build_pointer_cast(lists, list_type.ptr_type(AddressSpace::default()), "lists");
+
+    let (inner_width, inner_align) = layout_interner.stack_size_and_alignment(inner);
+    let inner_width = env.ptr_int().const_int(inner_width as u64, false);
+
+    // the bytes of the inner lists follow all of their headers
+    let rest_offset = bd.build_alloca(env.ptr_int(), "rest_offset");
+    let rest_start_offset = bd.build_int_add(
+        headers_start_offset,
+        bd.build_int_mul(len, header_width, "headers_width"),
+        "rest_start_offset",
+    );
+    bd.build_store(rest_offset, rest_start_offset);
+
+    let parent = bd.get_insert_block().and_then(|b| b.get_parent()).unwrap();
+
+    build_list::incrementing_index_loop(env, layout_interner, parent, len, "index", |_, index| {
+        let list = unsafe { bd.new_build_in_bounds_gep(list_type, lists, &[index], "list") };
+
+        let bytes_ptr = bd
+            .new_build_struct_gep(list_type, list, 0, "bytes_ptr")
+            .unwrap();
+        let bytes = bd
+            .new_build_load(
+                env.context.i8_type().ptr_type(AddressSpace::default()),
+                bytes_ptr,
+                "bytes",
+            )
+            .into_pointer_value();
+        let len_ptr = bd
+            .new_build_struct_gep(list_type, list, 1, "len_ptr")
+            .unwrap();
+        let inner_len = bd
+            .new_build_load(env.ptr_int(), len_ptr, "inner_len")
+            .into_int_value();
+
+        let extra_offset = bd
+            .new_build_load(env.ptr_int(), rest_offset, "extra_offset")
+            .into_int_value();
+        let extra_offset = align_extra_offset(env, native_layout, extra_offset, inner_align);
+        let header_offset = bd.build_int_add(
+            headers_start_offset,
+            bd.build_int_mul(index, header_width, "header_offset"),
+            "header_offset",
+        );
+
+        // like any list: where its bytes are, its length, and its length again as the capacity
+        let offset = build_copy(env, ptr, header_offset, extra_offset.into());
+        let offset = build_copy(env, ptr, offset, inner_len.into());
+        build_copy(env, ptr, offset, inner_len.into());
+
+        let bytes_width = bd.build_int_mul(inner_width, inner_len, "bytes_width");
+        let dest = pointer_at_offset(bd, env.context.i8_type(), ptr, extra_offset);
+        bd.build_memcpy(dest, 1, bytes, 1, bytes_width).unwrap();
+
+        let next_offset = bd.build_int_add(extra_offset, bytes_width, "next_offset");
+        bd.build_store(rest_offset, next_offset);
+    });
+
+    bd.new_build_load(env.ptr_int(), rest_offset, "rest_offset")
+        .into_int_value()
+}
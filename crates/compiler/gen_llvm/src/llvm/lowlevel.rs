@@ -1226,7 +1226,7 @@ pub(crate) fn run_low_level<'a, 'ctx, 'env>(
             unreachable!("The {:?} operation is turned into mono Expr", op)
         }
 
-        PtrCast | RefCountInc | RefCountDec => {
+        PtrCast | RefCountInc | RefCountDec | RefCountTrace => {
             unreachable!("Not used in LLVM backend: {:?}", op);
         }
 
@@ -967,6 +967,11 @@ impl<'a, 'r> WasmBackend<'a, 'r> {
     }
 
     fn stmt_refcounting(&mut self, modify: &ModifyRc, following: &'a Stmt<'a>) {
+        if let Some(following) = self.helper_proc_gen.try_elide_rc_pair(modify, following) {
+            self.stmt(following);
+            return;
+        }
+
         let value = modify.get_symbol();
         let layout = self.storage.symbol_layouts[&value];
 
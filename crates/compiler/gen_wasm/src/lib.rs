@@ -46,6 +46,9 @@ pub struct Env<'a> {
     pub module_id: ModuleId,
     pub exposed_to_host: MutSet<Symbol>,
     pub stack_bytes: u32,
+    /// Call the host's `roc_refcount_trace` before every refcount change in the refcounting
+    /// helpers, as the ROC_TRACE_REFCOUNTS debug flag does. See `CodeGenHelp::with_refcount_trace`.
+    pub trace_refcounts: bool,
 }
 
 impl Env<'_> {
@@ -135,7 +138,11 @@ pub fn build_app_module<'a, 'r>(
         host_to_app_map,
         host_module,
         fn_index_offset,
-        CodeGenHelp::new(env.arena, TargetInfo::default_wasm32(), env.module_id),
+        CodeGenHelp::new(env.arena, TargetInfo::default_wasm32(), env.module_id)
+            .with_refcount_trace(
+                env.trace_refcounts
+                    || roc_debug_flags::dbg_set!(roc_debug_flags::ROC_TRACE_REFCOUNTS),
+            ),
     );
 
     if DEBUG_SETTINGS.user_procs_ir {
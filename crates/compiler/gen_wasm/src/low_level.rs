@@ -1906,6 +1906,8 @@ impl<'a> LowLevelCall<'a> {
             }
             RefCountInc => self.load_args_and_call_zig(backend, bitcode::UTILS_INCREF),
             RefCountDec => self.load_args_and_call_zig(backend, bitcode::UTILS_DECREF),
+            // provided by the host, see `CodeGenHelp::with_refcount_trace`
+            RefCountTrace => self.load_args_and_call_zig(backend, "roc_refcount_trace"),
 
             PtrCast => {
                 let code_builder = &mut backend.code_builder;
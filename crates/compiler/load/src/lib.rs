@@ -19,7 +19,7 @@ const SKIP_SUBS_CACHE: bool = {
 pub use roc_load_internal::docs;
 pub use roc_load_internal::file::{
     EntryPoint, ExecutionMode, ExpectMetadata, Expectations, LoadConfig, LoadResult, LoadStart,
-    LoadedModule, LoadingProblem, MonomorphizedModule, Phase, Threading,
+    LoadedModule, LoadingProblem, MonoPasses, MonomorphizedModule, Phase, Threading,
 };
 
 #[allow(clippy::too_many_arguments)]
@@ -65,6 +65,7 @@ pub fn load_single_threaded<'a>(
         render,
         palette,
         exec_mode,
+        MonoPasses::All,
         roc_cache_dir,
     )
 }
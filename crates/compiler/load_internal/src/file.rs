@@ -101,6 +101,7 @@ pub struct LoadConfig {
     pub palette: Palette,
     pub threading: Threading,
     pub exec_mode: ExecutionMode,
+    pub mono_passes: MonoPasses,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -114,6 +115,16 @@ pub enum ExecutionMode {
     Test,
 }
 
+/// Which passes run over the mono IR once everything is specialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonoPasses {
+    /// Insert reset/reuse and refcounting operations, like code gen needs.
+    All,
+    /// Leave the IR as specialization made it. It has no refcounting, so it can't be
+    /// used for code gen, but it is what tests of specialization want to look at.
+    SpecializationOnly,
+}
+
 impl ExecutionMode {
     fn goal_phase(&self) -> Phase {
         match self {
@@ -1008,6 +1019,7 @@ struct State<'a> {
     pub render: RenderTarget,
     pub palette: Palette,
     pub exec_mode: ExecutionMode,
+    pub mono_passes: MonoPasses,
 
     /// All abilities across all modules.
     pub world_abilities: WorldAbilities,
@@ -1039,6 +1051,7 @@ impl<'a> State<'a> {
         palette: Palette,
         number_of_workers: usize,
         exec_mode: ExecutionMode,
+        mono_passes: MonoPasses,
     ) -> Self {
         let arc_shorthands = Arc::new(Mutex::new(MutMap::default()));
         let cache_dir = roc_packaging::cache::roc_cache_dir();
@@ -1073,6 +1086,7 @@ impl<'a> State<'a> {
             render,
             palette,
             exec_mode,
+            mono_passes,
             make_specializations_pass: MakeSpecializationsPass::Pass(1),
             world_abilities: Default::default(),
             layout_interner: GlobalLayoutInterner::with_capacity(128, target_info),
@@ -1349,6 +1363,7 @@ pub fn load_and_typecheck_str<'a>(
         palette,
         threading,
         exec_mode: ExecutionMode::Check,
+        mono_passes: MonoPasses::All,
     };
 
     match load(
@@ -1608,6 +1623,7 @@ pub fn load<'a>(
             load_config.render,
             load_config.palette,
             load_config.exec_mode,
+            load_config.mono_passes,
             roc_cache_dir,
         ),
         Threads::Many(threads) => load_multi_threaded(
@@ -1620,6 +1636,7 @@ pub fn load<'a>(
             load_config.palette,
             threads,
             load_config.exec_mode,
+            load_config.mono_passes,
             roc_cache_dir,
         ),
     }
@@ -1635,6 +1652,7 @@ pub fn load_single_threaded<'a>(
     render: RenderTarget,
     palette: Palette,
     exec_mode: ExecutionMode,
+    mono_passes: MonoPasses,
     roc_cache_dir: RocCacheDir<'_>,
 ) -> Result<LoadResult<'a>, LoadingProblem<'a>> {
     let LoadStart {
@@ -1666,6 +1684,7 @@ pub fn load_single_threaded<'a>(
         palette,
         number_of_workers,
         exec_mode,
+        mono_passes,
     );
 
     // We'll add tasks to this, and then worker threads will take tasks from it.
@@ -1948,6 +1967,7 @@ fn load_multi_threaded<'a>(
     palette: Palette,
     available_threads: usize,
     exec_mode: ExecutionMode,
+    mono_passes: MonoPasses,
     roc_cache_dir: RocCacheDir<'_>,
 ) -> Result<LoadResult<'a>, LoadingProblem<'a>> {
     let LoadStart {
@@ -1994,6 +2014,7 @@ fn load_multi_threaded<'a>(
         palette,
         num_workers,
         exec_mode,
+        mono_passes,
     );
 
     // an arena for every worker, stored in an arena-allocated bumpalo vec to make the lifetimes work
@@ -3090,33 +3111,35 @@ fn update<'a>(
 
                     let ident_ids = state.constrained_ident_ids.get_mut(&module_id).unwrap();
 
-                    Proc::insert_reset_reuse_operations(
-                        arena,
-                        &mut layout_interner,
-                        module_id,
-                        ident_ids,
-                        &mut update_mode_ids,
-                        &mut state.procedures,
-                    );
+                    if state.mono_passes == MonoPasses::All {
+                        Proc::insert_reset_reuse_operations(
+                            arena,
+                            &mut layout_interner,
+                            module_id,
+                            ident_ids,
+                            &mut update_mode_ids,
+                            &mut state.procedures,
+                        );
 
-                    debug_print_ir!(state, &layout_interner, ROC_PRINT_IR_AFTER_RESET_REUSE);
+                        debug_print_ir!(state, &layout_interner, ROC_PRINT_IR_AFTER_RESET_REUSE);
 
-                    let host_exposed_procs = bumpalo::collections::Vec::from_iter_in(
-                        state.exposed_to_host.values.keys().copied(),
-                        arena,
-                    );
+                        let host_exposed_procs = bumpalo::collections::Vec::from_iter_in(
+                            state.exposed_to_host.values.keys().copied(),
+                            arena,
+                        );
 
-                    Proc::insert_refcount_operations(
-                        arena,
-                        &layout_interner,
-                        module_id,
-                        ident_ids,
-                        &mut update_mode_ids,
-                        &mut state.procedures,
-                        &host_exposed_procs,
-                    );
+                        Proc::insert_refcount_operations(
+                            arena,
+                            &layout_interner,
+                            module_id,
+                            ident_ids,
+                            &mut update_mode_ids,
+                            &mut state.procedures,
+                            &host_exposed_procs,
+                        );
 
-                    debug_print_ir!(state, &layout_interner, ROC_PRINT_IR_AFTER_REFCOUNT);
+                        debug_print_ir!(state, &layout_interner, ROC_PRINT_IR_AFTER_REFCOUNT);
+                    }
 
                     // This is not safe with the new non-recursive RC updates that we do for tag unions
                     //
@@ -17,7 +17,7 @@ mod helpers;
 use crate::helpers::fixtures_dir;
 use bumpalo::Bump;
 use roc_can::module::ExposedByModule;
-use roc_load_internal::file::{ExecutionMode, LoadConfig, Threading};
+use roc_load_internal::file::{ExecutionMode, LoadConfig, MonoPasses, Threading};
 use roc_load_internal::file::{LoadResult, LoadStart, LoadedModule, LoadingProblem};
 use roc_module::ident::ModuleName;
 use roc_module::symbol::{Interns, ModuleId};
@@ -54,6 +54,7 @@ fn load_and_typecheck(
         palette: DEFAULT_PALETTE,
         threading: Threading::Single,
         exec_mode: ExecutionMode::Check,
+        mono_passes: MonoPasses::All,
     };
 
     match roc_load_internal::file::load(
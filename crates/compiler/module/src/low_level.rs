@@ -115,6 +115,7 @@ pub enum LowLevel {
     PtrCast,
     RefCountInc,
     RefCountDec,
+    RefCountTrace,
     BoxExpr,
     UnboxExpr,
     Unreachable,
@@ -219,6 +220,7 @@ macro_rules! map_symbol_to_lowlevel {
                 LowLevel::PtrCast => unimplemented!(),
                 LowLevel::RefCountInc => unimplemented!(),
                 LowLevel::RefCountDec => unimplemented!(),
+                LowLevel::RefCountTrace => unimplemented!(),
 
                 // these are not implemented, not sure why
                 LowLevel::StrFromInt => unimplemented!(),
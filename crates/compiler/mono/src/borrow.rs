@@ -1067,7 +1067,7 @@ pub fn lowlevel_borrow_signature(arena: &Bump, op: LowLevel) -> &[bool] {
             unreachable!("These lowlevel operations are turned into mono Expr's")
         }
 
-        PtrCast | RefCountInc | RefCountDec => {
+        PtrCast | RefCountInc | RefCountDec | RefCountTrace => {
             unreachable!("Only inserted *after* borrow checking: {:?}", op);
         }
     }
@@ -1,8 +1,12 @@
 use bumpalo::collections::vec::Vec;
 use bumpalo::Bump;
+use roc_collections::all::{MutMap, MutSet};
+use roc_error_macros::internal_error;
 use roc_module::low_level::LowLevel;
 use roc_module::symbol::{IdentIds, ModuleId, Symbol};
+use roc_region::all::Region;
 use roc_target::TargetInfo;
+use std::cell::RefCell;
 
 use crate::ir::{
     Call, CallSpecId, CallType, Expr, HostExposedLayouts, JoinPointId, ModifyRc, Proc, ProcLayout,
@@ -38,21 +42,121 @@ impl HelperOp {
     fn is_decref(&self) -> bool {
         matches!(self, Self::DecRef(_))
     }
+
+    fn name(&self) -> &'static str {
+        match self {
+            HelperOp::Inc => "Inc",
+            HelperOp::Dec => "Dec",
+            HelperOp::DecRef(_) => "DecRef",
+            HelperOp::Reset => "Reset",
+            HelperOp::Eq => "Eq",
+        }
+    }
 }
 
+/// A helper proc, looked up by `op` and `layout` only.
+/// `Inc` procs take the amount as an argument, so increments of a layout
+/// by different amounts all share the same proc.
 #[derive(Debug)]
 struct Specialization<'a> {
     op: HelperOp,
     layout: InLayout<'a>,
     symbol: Symbol,
+    /// The name of the proc, from `unique_helper_proc_name`
+    name: String,
     proc: Option<Proc<'a>>,
 }
 
+/// How the refcount procs of a recursive union get to the nodes below the first one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecursiveRefcount {
+    /// Loop along one recursive field of each node, and call the proc for the others.
+    /// This keeps the stack flat for linear structures like a cons-list, however long.
+    TailLoop,
+    /// Call the proc for every recursive field.
+    SelfRecursive,
+    /// Keep the nodes that are still to do in a list on the heap, and loop until it is empty,
+    /// so that the stack stays flat for any shape of data, like a deep tree. A recursive field
+    /// nested in another value, like a `List`, is still refcounted by calling that value's proc.
+    Worklist,
+}
+
+/// Helper procs that have already been emitted, shared between several `CodeGenHelp`s.
+///
+/// Procs are keyed by `CodeGenHelp::helper_proc_name`, which hashes the structure of the
+/// layout rather than its interned index, so the key means the same thing to every user
+/// of the cache even when their layout interners differ. The key also has the options that
+/// change what a proc does, like registered finalizers, so that a proc is only reused by
+/// `CodeGenHelp`s that would have generated the same one. Since hashes can collide, each
+/// proc is stored with its layout's `content_bytes`, and only reused for the same layout.
+///
+/// A cached symbol is called directly, without generating a new proc. So every `CodeGenHelp`
+/// sharing a cache must be able to call the procs the others emit, which in practice means
+/// they all create their helpers in the same shared module.
+#[derive(Debug, Default)]
+pub struct HelperProcCache {
+    procs: std::sync::Mutex<MutMap<String, CachedProc>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedProc {
+    /// The `content_bytes` of the proc's layout
+    layout: std::vec::Vec<u8>,
+    symbol: Symbol,
+}
+
+impl HelperProcCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The proc cached under `key`, if it is for the layout whose `content_bytes` are `layout`
+    fn get(&self, key: &str, layout: &[u8]) -> Option<Symbol> {
+        let procs = self.procs.lock().unwrap();
+        let cached = procs.get(key)?;
+
+        (cached.layout == layout).then_some(cached.symbol)
+    }
+
+    /// Whether `key` is taken by a proc for another layout than the one whose `content_bytes`
+    /// are `layout`
+    fn is_taken(&self, key: &str, layout: &[u8]) -> bool {
+        let procs = self.procs.lock().unwrap();
+
+        matches!(procs.get(key), Some(cached) if cached.layout != layout)
+    }
+
+    fn insert(&self, key: String, layout: std::vec::Vec<u8>, symbol: Symbol) {
+        self.procs
+            .lock()
+            .unwrap()
+            .insert(key, CachedProc { layout, symbol });
+    }
+}
+
+/// How many `Refcounting` nodes of one layout `CodeGenHelp::expand_refcount_stmt` expanded.
+///
+/// These count the nodes in the IR, not how many times they run: an `Inc` in a loop is one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RefcountStats {
+    pub inc: usize,
+    pub dec: usize,
+    pub decref: usize,
+}
+
+impl RefcountStats {
+    pub fn total(&self) -> usize {
+        self.inc + self.dec + self.decref
+    }
+}
+
 #[derive(Debug)]
 pub struct Context<'a> {
     new_linker_data: Vec<'a, (Symbol, ProcLayout<'a>)>,
     recursive_union: Option<UnionLayout<'a>>,
     op: HelperOp,
+    /// What the refcount changes in the helper proc being generated are traced as, if they are
+    trace_name: Option<&'a str>,
 }
 
 /// Generate specialized helper procs for code gen
@@ -80,7 +184,15 @@ pub struct CodeGenHelp<'a> {
     layout_isize: InLayout<'a>,
     union_refcount: UnionLayout<'a>,
     specializations: Vec<'a, Specialization<'a>>,
+    finalizers: Vec<'a, (InLayout<'a>, Symbol)>,
+    shared_cache: Option<&'a HelperProcCache>,
+    recursive_refcount: RecursiveRefcount,
     debug_recursion_depth: usize,
+    refcount_stats: Option<MutMap<InLayout<'a>, RefcountStats>>,
+    refcount_trace: bool,
+    /// The debug names used so far in the helper proc being generated, each with the next
+    /// suffix to try for it. `None` outside of one.
+    proc_debug_names: RefCell<Option<MutMap<String, usize>>>,
 }
 
 impl<'a> CodeGenHelp<'a> {
@@ -97,10 +209,95 @@ impl<'a> CodeGenHelp<'a> {
             layout_isize,
             union_refcount,
             specializations: Vec::with_capacity_in(16, arena),
+            finalizers: Vec::new_in(arena),
+            shared_cache: None,
+            recursive_refcount: RecursiveRefcount::TailLoop,
             debug_recursion_depth: 0,
+            refcount_stats: None,
+            refcount_trace: false,
+            proc_debug_names: RefCell::new(None),
         }
     }
 
+    /// How to refcount recursive unions, `RecursiveRefcount::TailLoop` by default.
+    ///
+    /// Only use `SelfRecursive` for backends that handle deep recursion well, and `Worklist`
+    /// where even a deep tree must not overflow the stack. Equality helpers always use a loop.
+    pub fn with_recursive_refcount(mut self, recursive_refcount: RecursiveRefcount) -> Self {
+        self.recursive_refcount = recursive_refcount;
+        self
+    }
+
+    /// Count the `Refcounting` nodes that are expanded, per layout, for `stats`.
+    pub fn with_refcount_stats(mut self, enabled: bool) -> Self {
+        self.refcount_stats = enabled.then(MutMap::default);
+        self
+    }
+
+    /// The layouts whose `Refcounting` nodes were expanded so far, with how many of each kind,
+    /// most expanded first. Empty unless counting was turned on with `with_refcount_stats`.
+    ///
+    /// Layouts that are never refcounted, like integers, are left out.
+    pub fn stats(&self) -> std::vec::Vec<(InLayout<'a>, RefcountStats)> {
+        let mut stats: std::vec::Vec<_> = match &self.refcount_stats {
+            Some(refcount_stats) => refcount_stats
+                .iter()
+                .map(|(layout, counts)| (*layout, *counts))
+                .collect(),
+            None => return std::vec::Vec::new(),
+        };
+
+        stats.sort_by(|(layout1, counts1), (layout2, counts2)| {
+            counts2
+                .total()
+                .cmp(&counts1.total())
+                .then(layout1.cmp(layout2))
+        });
+
+        stats
+    }
+
+    /// Call the host's `roc_refcount_trace` just before every refcount change in a generated
+    /// helper proc, with a `Str` naming the op and the layout (like `Dec List Str`) and the
+    /// pointer to the refcount, so that the host can log them while it looks for a leak.
+    ///
+    /// The host must provide `roc_refcount_trace` when this is on.
+    pub fn with_refcount_trace(mut self, enabled: bool) -> Self {
+        self.refcount_trace = enabled;
+        self
+    }
+
+    /// Reuse helper procs already emitted by other `CodeGenHelp`s that share `cache`,
+    /// and record the ones emitted here for them to reuse in turn.
+    pub fn with_shared_cache(mut self, cache: &'a HelperProcCache) -> Self {
+        self.shared_cache = Some(cache);
+        self
+    }
+
+    /// Call `finalizer` from the Dec proc of a `Box` layout, when the box is about to be freed.
+    /// The finalizer takes the box as its only argument and returns `{}`. It runs before the
+    /// contents of the box are decremented, so it can still use them.
+    ///
+    /// This must happen before the Dec proc for the layout is generated.
+    pub fn register_finalizer(&mut self, layout: InLayout<'a>, finalizer: Symbol) {
+        debug_assert!(
+            !self
+                .specializations
+                .iter()
+                .any(|spec| spec.op == HelperOp::Dec && spec.layout == layout),
+            "registered a finalizer after its Dec proc was generated"
+        );
+
+        self.finalizers.push((layout, finalizer));
+    }
+
+    fn finalizer(&self, layout: InLayout<'a>) -> Option<Symbol> {
+        self.finalizers
+            .iter()
+            .find(|(finalizer_layout, _)| *finalizer_layout == layout)
+            .map(|(_, finalizer)| *finalizer)
+    }
+
     pub fn take_procs(&mut self) -> Vec<'a, Proc<'a>> {
         let procs_iter = self
             .specializations
@@ -115,6 +312,16 @@ impl<'a> CodeGenHelp<'a> {
     //
     // ============================================================================
 
+    /// Whether refcounting a value of this layout does anything at all.
+    /// If not, the backend can skip the `Refcounting` node instead of expanding it.
+    pub fn needs_refcount_helper(
+        &self,
+        layout_interner: &STLayoutInterner<'a>,
+        layout: InLayout<'a>,
+    ) -> bool {
+        layout_interner.contains_refcounted(layout)
+    }
+
     /// Expand a `Refcounting` node to a `Let` node that calls a specialized helper proc.
     /// The helper procs themselves are to be generated later with `generate_procs`
     pub fn expand_refcount_stmt(
@@ -125,6 +332,46 @@ impl<'a> CodeGenHelp<'a> {
         modify: &ModifyRc,
         following: &'a Stmt<'a>,
     ) -> (&'a Stmt<'a>, Vec<'a, (Symbol, ProcLayout<'a>)>) {
+        self.expand_refcount_stmt_at(ident_ids, layout_interner, layout, modify, following, None)
+    }
+
+    /// Like `expand_refcount_stmt`, for a backend that knows the region of the refcounted
+    /// value in the user's source. An error about the value's layout then says where it is.
+    pub fn expand_refcount_stmt_at(
+        &mut self,
+        ident_ids: &mut IdentIds,
+        layout_interner: &mut STLayoutInterner<'a>,
+        layout: InLayout<'a>,
+        modify: &ModifyRc,
+        following: &'a Stmt<'a>,
+        region: Option<Region>,
+    ) -> (&'a Stmt<'a>, Vec<'a, (Symbol, ProcLayout<'a>)>) {
+        // A recursive pointer is only refcounted by the procs of the union it points into
+        if let Layout::RecursivePointer(_) = layout_interner.get(layout) {
+            internal_error!(
+                "Cannot refcount {}: it is a recursive pointer, which is only refcounted as part \
+                 of its union",
+                describe_value(modify.get_symbol(), region),
+            );
+        }
+
+        // Primitives are not refcounted, so there is no need to call a helper at all
+        if let Layout::Builtin(
+            Builtin::Int(_) | Builtin::Float(_) | Builtin::Bool | Builtin::Decimal,
+        ) = layout_interner.get(layout)
+        {
+            return (following, Vec::new_in(self.arena));
+        }
+
+        if let Some(refcount_stats) = self.refcount_stats.as_mut() {
+            let counts = refcount_stats.entry(layout).or_default();
+            match modify {
+                ModifyRc::Inc(..) => counts.inc += 1,
+                ModifyRc::Dec(_) => counts.dec += 1,
+                ModifyRc::DecRef(_) => counts.decref += 1,
+            }
+        }
+
         let op = match modify {
             ModifyRc::Inc(..) => HelperOp::Inc,
             ModifyRc::Dec(_) => HelperOp::Dec,
@@ -138,6 +385,7 @@ impl<'a> CodeGenHelp<'a> {
             new_linker_data: Vec::new_in(self.arena),
             recursive_union: None,
             op,
+            trace_name: self.trace_name(layout_interner, op, layout),
         };
 
         let rc_stmt = refcount::refcount_stmt(
@@ -152,6 +400,50 @@ impl<'a> CodeGenHelp<'a> {
         (rc_stmt, ctx.new_linker_data)
     }
 
+    /// If `modify` is an `Inc(x, 1)` that is undone by a `Dec(x)` further down, return
+    /// `following` without that `Dec`, so that the backend can generate neither of them.
+    ///
+    /// Only `Let`s that neither define nor use `x` are looked through. Anything else between
+    /// the two, like a branch, a join point, a return or refcounting of another value, could
+    /// use `x` or let it escape, so the pair is kept.
+    ///
+    /// The `Let`s in between are rebuilt, so a backend that keys anything on the addresses
+    /// of the statements it was given should not use this.
+    ///
+    /// With `with_refcount_trace`, an elided pair is not traced either: it changes no refcount.
+    pub fn try_elide_rc_pair(
+        &self,
+        modify: &ModifyRc,
+        following: &'a Stmt<'a>,
+    ) -> Option<&'a Stmt<'a>> {
+        match modify {
+            ModifyRc::Inc(symbol, 1) => self.without_dec(*symbol, following),
+            _ => None,
+        }
+    }
+
+    fn without_dec(&self, symbol: Symbol, stmt: &'a Stmt<'a>) -> Option<&'a Stmt<'a>> {
+        match stmt {
+            Stmt::Refcounting(ModifyRc::Dec(decremented), rest) if *decremented == symbol => {
+                Some(rest)
+            }
+            Stmt::Let(defined, expr, layout, rest) if *defined != symbol => {
+                let mut used = MutSet::default();
+                crate::inc_dec::occurring_variables_expr(expr, &mut used);
+
+                if used.contains(&symbol) {
+                    return None;
+                }
+
+                let rest = self.without_dec(symbol, rest)?;
+                let stmt = Stmt::Let(*defined, expr.clone(), *layout, rest);
+
+                Some(self.arena.alloc(stmt))
+            }
+            _ => None,
+        }
+    }
+
     pub fn call_reset_refcount(
         &mut self,
         ident_ids: &mut IdentIds,
@@ -159,10 +451,19 @@ impl<'a> CodeGenHelp<'a> {
         layout: InLayout<'a>,
         argument: Symbol,
     ) -> (Expr<'a>, Vec<'a, (Symbol, ProcLayout<'a>)>) {
+        if !matches!(layout_interner.get(layout), Layout::Union(_)) {
+            internal_error!(
+                "Cannot reset {}: reset is only implemented for unions, but it has layout {}",
+                describe_value(argument, None),
+                layout_interner.dbg(layout)
+            );
+        }
+
         let mut ctx = Context {
             new_linker_data: Vec::new_in(self.arena),
             recursive_union: None,
             op: HelperOp::Reset,
+            trace_name: None,
         };
 
         let proc_name = self.find_or_create_proc(ident_ids, &mut ctx, layout_interner, layout);
@@ -197,6 +498,7 @@ impl<'a> CodeGenHelp<'a> {
             new_linker_data: Vec::new_in(self.arena),
             recursive_union: None,
             op,
+            trace_name: None,
         };
 
         let proc_name = self.find_or_create_proc(ident_ids, &mut ctx, layout_interner, layout);
@@ -217,6 +519,7 @@ impl<'a> CodeGenHelp<'a> {
             new_linker_data: Vec::new_in(self.arena),
             recursive_union: None,
             op: HelperOp::Eq,
+            trace_name: None,
         };
 
         let expr = self
@@ -299,6 +602,19 @@ impl<'a> CodeGenHelp<'a> {
     ) -> Symbol {
         use HelperOp::*;
 
+        // Str has no children, so Dec is the same as DecRef. Both use the Dec proc.
+        if let (DecRef(_), Layout::Builtin(Builtin::Str)) =
+            (ctx.op, layout_interner.get(orig_layout))
+        {
+            let decref = ctx.op;
+            ctx.op = Dec;
+            let proc_symbol =
+                self.find_or_create_proc(ident_ids, ctx, layout_interner, orig_layout);
+            ctx.op = decref;
+
+            return proc_symbol;
+        }
+
         let layout = self.replace_rec_ptr(ctx, layout_interner, orig_layout);
 
         let found = self
@@ -310,19 +626,46 @@ impl<'a> CodeGenHelp<'a> {
             return spec.symbol;
         }
 
+        let content = self
+            .shared_cache
+            .map(|_| layout_interner.content_bytes(layout));
+
+        let debug_name =
+            self.unique_helper_proc_name(layout_interner, ctx.op, layout, content.as_deref());
+
+        let cache_key = self
+            .shared_cache
+            .map(|_| self.cache_key(layout_interner, &debug_name));
+
+        if let (Some(cache), Some(key), Some(content)) = (self.shared_cache, &cache_key, &content) {
+            if let Some(symbol) = cache.get(key, content) {
+                return symbol;
+            }
+        }
+
         // Procs can be recursive, so we need to create the symbol before the body is complete
         // But with nested recursion, that means Symbols and Procs can end up in different orders.
         // We want the same order, especially for function indices in Wasm. So create an empty slot and fill it in later.
-        let (proc_symbol, proc_layout) = self.create_proc_symbol(ident_ids, ctx, layout);
+        let (proc_symbol, proc_layout) =
+            self.create_proc_symbol(ident_ids, ctx, layout, &debug_name);
         ctx.new_linker_data.push((proc_symbol, proc_layout));
+
+        if let (Some(cache), Some(key), Some(content)) = (self.shared_cache, cache_key, content) {
+            cache.insert(key, content, proc_symbol);
+        }
         let spec_index = self.specializations.len();
         self.specializations.push(Specialization {
             op: ctx.op,
             layout,
             symbol: proc_symbol,
+            name: debug_name,
             proc: None,
         });
 
+        let trace_name = self.trace_name(layout_interner, ctx.op, layout);
+        let outer_trace_name = std::mem::replace(&mut ctx.trace_name, trace_name);
+        let outer_debug_names = self.proc_debug_names.replace(Some(MutMap::default()));
+
         // Recursively generate the body of the Proc and sub-procs
         let (ret_layout, body) = match ctx.op {
             Inc | Dec | DecRef(_) => (
@@ -353,6 +696,9 @@ impl<'a> CodeGenHelp<'a> {
             ),
         };
 
+        ctx.trace_name = outer_trace_name;
+        self.proc_debug_names.replace(outer_debug_names);
+
         let args: &'a [(InLayout<'a>, Symbol)] = {
             let roc_value = (layout, ARG_1);
             match ctx.op {
@@ -379,20 +725,101 @@ impl<'a> CodeGenHelp<'a> {
         proc_symbol
     }
 
+    /// The name of the helper proc for `op` on `layout`.
+    ///
+    /// The name is derived from the layout's `content_hash` rather than from the order
+    /// in which layouts were interned or specializations created, so the same layout always
+    /// gets the same name. This keeps the names stable across builds, which helps linkers and caches.
+    pub fn helper_proc_name(
+        layout_interner: &STLayoutInterner<'a>,
+        op: HelperOp,
+        layout: InLayout<'a>,
+    ) -> String {
+        format!(
+            "#help_{}_{:016x}",
+            op.name(),
+            layout_interner.content_hash(layout)
+        )
+    }
+
+    /// The name of the helper proc for `op` on `layout` here: `helper_proc_name`, or that with
+    /// a `_1`, `_2` and so on if another layout whose content hash is the same already has a
+    /// proc by that name. Hashes can collide, and two layouts must never share a proc.
+    ///
+    /// With a shared cache, `content` is the layout's `content_bytes`, and names taken by the
+    /// procs of other layouts in the cache are skipped too.
+    fn unique_helper_proc_name(
+        &self,
+        layout_interner: &STLayoutInterner<'a>,
+        op: HelperOp,
+        layout: InLayout<'a>,
+        content: Option<&[u8]>,
+    ) -> String {
+        let is_taken = |name: &str| {
+            let taken_here = self
+                .specializations
+                .iter()
+                .any(|spec| spec.name == name && !layout_interner.equiv(spec.layout, layout));
+
+            let taken_in_cache = match (self.shared_cache, content) {
+                (Some(cache), Some(content)) => {
+                    cache.is_taken(&self.cache_key(layout_interner, name), content)
+                }
+                _ => false,
+            };
+
+            taken_here || taken_in_cache
+        };
+
+        let name = Self::helper_proc_name(layout_interner, op, layout);
+        if !is_taken(&name) {
+            return name;
+        }
+
+        (1..)
+            .map(|suffix| format!("{}_{}", name, suffix))
+            .find(|name| !is_taken(name))
+            .unwrap()
+    }
+
+    /// The key of the proc named `debug_name` in the shared cache. A proc's body depends on the
+    /// options of this `CodeGenHelp` as well as on its layout, and so do the bodies of the
+    /// procs it calls, so all of the options go into the key.
+    fn cache_key(&self, layout_interner: &STLayoutInterner<'a>, debug_name: &str) -> String {
+        let mut finalizers: std::vec::Vec<(std::vec::Vec<u8>, u64)> = self
+            .finalizers
+            .iter()
+            .map(|(layout, finalizer)| (layout_interner.content_bytes(*layout), finalizer.as_u64()))
+            .collect();
+        finalizers.sort_unstable();
+
+        format!(
+            "{} recursion={:?} trace={} finalizers={:?}",
+            debug_name, self.recursive_refcount, self.refcount_trace, finalizers
+        )
+    }
+
+    /// With `with_refcount_trace`, what the refcount changes made by `op` on `layout` are traced as
+    fn trace_name(
+        &self,
+        layout_interner: &STLayoutInterner<'a>,
+        op: HelperOp,
+        layout: InLayout<'a>,
+    ) -> Option<&'a str> {
+        self.refcount_trace.then(|| {
+            let name = format!("{} {}", op.name(), layout_interner.dbg(layout));
+            &*self.arena.alloc_str(&name)
+        })
+    }
+
     fn create_proc_symbol(
         &self,
         ident_ids: &mut IdentIds,
         ctx: &mut Context<'a>,
         layout: InLayout<'a>,
+        debug_name: &str,
     ) -> (Symbol, ProcLayout<'a>) {
-        let debug_name = format!(
-            "#help{}_{:?}_{:?}",
-            self.specializations.len(),
-            ctx.op,
-            layout
-        )
-        .replace("Builtin", "");
-        let proc_symbol: Symbol = self.create_symbol(ident_ids, &debug_name);
+        let proc_symbol: Symbol = self.create_symbol(ident_ids, debug_name);
 
         let proc_layout = match ctx.op {
             HelperOp::Inc => ProcLayout {
@@ -421,9 +848,35 @@ impl<'a> CodeGenHelp<'a> {
         (proc_symbol, proc_layout)
     }
 
+    /// A new symbol named `debug_name`, or `debug_name_1`, `debug_name_2` and so on if the
+    /// helper proc being generated already has one by that name, so that IR dumps can tell
+    /// them apart. Symbols for the caller's proc keep their name, since its others aren't known.
     fn create_symbol(&self, ident_ids: &mut IdentIds, debug_name: &str) -> Symbol {
-        let ident_id = ident_ids.add_str(debug_name);
-        Symbol::new(self.home, ident_id)
+        let name = match self.proc_debug_names.borrow_mut().as_mut() {
+            None => debug_name.to_string(),
+            Some(used) => match used.get_mut(debug_name) {
+                None => {
+                    used.insert(debug_name.to_string(), 1);
+                    debug_name.to_string()
+                }
+                Some(next_suffix) => {
+                    let mut suffix = *next_suffix;
+                    // `field_1` may have been asked for by name before a second `field`
+                    let name = loop {
+                        let name = format!("{}_{}", debug_name, suffix);
+                        suffix += 1;
+                        if !used.contains_key(&name) {
+                            break name;
+                        }
+                    };
+                    used.insert(debug_name.to_string(), suffix);
+                    used.insert(name.clone(), 1);
+                    name
+                }
+            },
+        };
+
+        Symbol::new(self.home, ident_ids.add_str(&name))
     }
 
     // When creating or looking up Specializations, we need to replace RecursivePointer
@@ -537,6 +990,14 @@ impl<'a> CodeGenHelp<'a> {
     }
 }
 
+/// How an error message refers to the value `symbol`, with where it is if that is known
+fn describe_value(symbol: Symbol, region: Option<Region>) -> String {
+    match region {
+        Some(region) => format!("{:?} at {:?}", symbol, region),
+        None => format!("{:?}", symbol),
+    }
+}
+
 fn let_lowlevel<'a>(
     arena: &'a Bump,
     result_layout: InLayout<'a>,
@@ -583,3 +1044,739 @@ fn layout_needs_helper_proc<'a>(
         Layout::Boxed(_) => true,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bumpalo::Bump;
+    use roc_module::low_level::LowLevel;
+    use roc_module::symbol::{IdentIds, ModuleId, Symbol};
+    use roc_target::TargetInfo;
+
+    use super::{CodeGenHelp, HelperOp, HelperProcCache, RecursiveRefcount, RefcountStats};
+    use crate::ir::{CallType, Expr, JoinPointId, Literal, ModifyRc, Stmt};
+    use crate::layout::{Builtin, InLayout, Layout, LayoutInterner, STLayoutInterner};
+
+    const TARGET_INFO: TargetInfo = TargetInfo::default_x86_64();
+
+    fn proc_name(ident_ids: &IdentIds, symbol: Symbol) -> String {
+        ident_ids.get_name(symbol.ident_id()).unwrap().to_string()
+    }
+
+    fn gen_proc<'a>(
+        arena: &'a Bump,
+        interner: &mut STLayoutInterner<'a>,
+        ident_ids: &mut IdentIds,
+        layout: InLayout<'a>,
+        op: HelperOp,
+    ) -> Symbol {
+        let mut help = CodeGenHelp::new(arena, TARGET_INFO, ModuleId::ATTR);
+        let (symbol, _) = help.gen_refcount_proc(ident_ids, interner, layout, op);
+        symbol
+    }
+
+    #[test]
+    fn helper_proc_name_is_stable() {
+        let arena = Bump::new();
+
+        // `unrelated` is interned first, so that the layout's index differs between builds
+        let name_in_fresh_build = |unrelated: &[Layout<'static>], layout: Layout<'static>| {
+            let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+            let mut ident_ids = IdentIds::default();
+
+            for unrelated in unrelated {
+                let unrelated = interner.insert(*unrelated);
+                gen_proc(
+                    &arena,
+                    &mut interner,
+                    &mut ident_ids,
+                    unrelated,
+                    HelperOp::Inc,
+                );
+            }
+
+            let layout = interner.insert(layout);
+            let symbol = gen_proc(&arena, &mut interner, &mut ident_ids, layout, HelperOp::Inc);
+            proc_name(&ident_ids, symbol)
+        };
+
+        let list_of_str = Layout::Builtin(Builtin::List(Layout::STR));
+        let first = name_in_fresh_build(&[], list_of_str);
+        let second = name_in_fresh_build(
+            &[
+                Layout::Builtin(Builtin::List(Layout::U8)),
+                Layout::Builtin(Builtin::List(Layout::I64)),
+                Layout::Boxed(Layout::STR),
+            ],
+            list_of_str,
+        );
+        assert_eq!(first, second);
+
+        let other = name_in_fresh_build(&[], Layout::Builtin(Builtin::List(Layout::I64)));
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn colliding_helper_proc_names_get_a_suffix() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let list_of_i64 = interner.insert(Layout::Builtin(Builtin::List(Layout::I64)));
+        let list_of_str = interner.insert(Layout::Builtin(Builtin::List(Layout::STR)));
+
+        // Give the List I64 proc the name of the List Str one, as if their hashes collided
+        let (i64_symbol, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, list_of_i64, HelperOp::Inc);
+        let name = CodeGenHelp::helper_proc_name(&interner, HelperOp::Inc, list_of_str);
+        help.specializations[0].name = name.clone();
+
+        let (str_symbol, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, list_of_str, HelperOp::Inc);
+
+        assert_ne!(str_symbol, i64_symbol);
+        assert_eq!(proc_name(&ident_ids, str_symbol), format!("{}_1", name));
+    }
+
+    #[test]
+    fn inc_by_any_amount_shares_one_proc() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let layout = interner.insert(Layout::Builtin(Builtin::List(Layout::STR)));
+        let structure = Symbol::ATTR_ATTR;
+        let following = arena.alloc(Stmt::Ret(structure));
+
+        let mut inc_by = |amount| {
+            let (stmt, new_procs) = help.expand_refcount_stmt(
+                &mut ident_ids,
+                &mut interner,
+                layout,
+                &ModifyRc::Inc(structure, amount),
+                following,
+            );
+
+            // let amount = ...; let call_result_empty = CallByName(proc, structure, amount)
+            let called = match stmt {
+                Stmt::Let(_, _, _, Stmt::Let(_, Expr::Call(call), _, _)) => match &call.call_type {
+                    CallType::ByName { name, .. } => name.name(),
+                    _ => panic!("expected a call by name, got {:?}", call),
+                },
+                _ => panic!("expected an amount and a call, got {:?}", stmt),
+            };
+
+            (called, new_procs.len())
+        };
+
+        let (by_one, new_procs_by_one) = inc_by(1);
+        let (by_three, new_procs_by_three) = inc_by(3);
+
+        assert_eq!(by_one, by_three);
+        assert!(new_procs_by_one > 0);
+        assert_eq!(new_procs_by_three, 0);
+    }
+
+    #[test]
+    fn refcount_stats_count_expansions_per_layout() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help =
+            CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR).with_refcount_stats(true);
+
+        let list_of_str = interner.insert(Layout::Builtin(Builtin::List(Layout::STR)));
+        let structure = Symbol::ATTR_ATTR;
+        let following: &Stmt = arena.alloc(Stmt::Ret(structure));
+
+        for (layout, modify) in [
+            (list_of_str, ModifyRc::Inc(structure, 1)),
+            (Layout::STR, ModifyRc::DecRef(structure)),
+            (list_of_str, ModifyRc::Inc(structure, 5)),
+            (Layout::I64, ModifyRc::Inc(structure, 1)),
+            (list_of_str, ModifyRc::Dec(structure)),
+        ] {
+            help.expand_refcount_stmt(&mut ident_ids, &mut interner, layout, &modify, following);
+        }
+
+        let stats = |inc, dec, decref| RefcountStats { inc, dec, decref };
+        assert_eq!(
+            help.stats(),
+            [(list_of_str, stats(2, 1, 0)), (Layout::STR, stats(0, 0, 1)),]
+        );
+
+        let uncounted = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+        assert!(uncounted.stats().is_empty());
+    }
+
+    #[test]
+    fn refcount_trace_is_called_before_refcount_changes() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+
+        let list_of_str = interner.insert(Layout::Builtin(Builtin::List(Layout::STR)));
+
+        let mut traces_in_procs = |trace| {
+            let mut help =
+                CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR).with_refcount_trace(trace);
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, list_of_str, HelperOp::Dec);
+
+            let mut traces = std::vec::Vec::new();
+            for proc in help.take_procs() {
+                collect_traces(&proc.body, &mut traces);
+            }
+            traces.sort();
+            traces
+        };
+
+        // the list's own refcount, and each string's
+        assert_eq!(traces_in_procs(true), ["Dec List Str", "Dec Str"]);
+        assert!(traces_in_procs(false).is_empty());
+    }
+
+    #[test]
+    fn str_decref_is_traced_as_the_dec_it_becomes() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help =
+            CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR).with_refcount_trace(true);
+
+        let structure = Symbol::ATTR_ATTR;
+        let following: &Stmt = arena.alloc(Stmt::Ret(structure));
+        help.expand_refcount_stmt(
+            &mut ident_ids,
+            &mut interner,
+            Layout::STR,
+            &ModifyRc::DecRef(structure),
+            following,
+        );
+
+        let mut traces = std::vec::Vec::new();
+        for proc in help.take_procs() {
+            collect_traces(&proc.body, &mut traces);
+        }
+        assert_eq!(traces, ["Dec Str"]);
+    }
+
+    /// The names passed to each `RefCountTrace` call in `stmt`, in every branch
+    fn collect_traces(stmt: &Stmt, traces: &mut std::vec::Vec<String>) {
+        match stmt {
+            Stmt::Let(name, Expr::Literal(Literal::Str(text)), _, next) => {
+                let is_traced = matches!(
+                    next,
+                    Stmt::Let(_, Expr::Call(call), _, _) if matches!(
+                        call.call_type,
+                        CallType::LowLevel { op: LowLevel::RefCountTrace, .. }
+                    ) && call.arguments.first() == Some(name)
+                );
+                if is_traced {
+                    traces.push(text.to_string());
+                }
+
+                collect_traces(next, traces);
+            }
+            Stmt::Let(_, _, _, next) => collect_traces(next, traces),
+            Stmt::Switch {
+                branches,
+                default_branch,
+                ..
+            } => {
+                for (_, _, branch) in branches.iter() {
+                    collect_traces(branch, traces);
+                }
+                collect_traces(default_branch.1, traces);
+            }
+            Stmt::Join {
+                body, remainder, ..
+            } => {
+                collect_traces(body, traces);
+                collect_traces(remainder, traces);
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn symbols_in_a_helper_proc_have_unique_names() {
+        use crate::layout::UnionLayout;
+
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        // [A Str Str, B (List Str)], whose procs handle several fields and tags
+        let list_of_str = interner.insert(Layout::Builtin(Builtin::List(Layout::STR)));
+        let tags: &[&[InLayout]] = arena.alloc([
+            &*arena.alloc([Layout::STR, Layout::STR]) as &[_],
+            arena.alloc([list_of_str]),
+        ]);
+        let union = interner.insert(Layout::Union(UnionLayout::NonRecursive(tags)));
+
+        for op in [HelperOp::Inc, HelperOp::Dec, HelperOp::Eq] {
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, union, op);
+        }
+
+        for proc in help.take_procs() {
+            // A statement can be shared by several branches, binding the same symbol in each
+            let mut symbols = std::vec::Vec::new();
+            collect_let_symbols(&proc.body, &mut symbols);
+            symbols.sort();
+            symbols.dedup();
+
+            let mut names: std::vec::Vec<_> = symbols
+                .iter()
+                .map(|symbol| proc_name(&ident_ids, *symbol))
+                .collect();
+            names.sort();
+
+            let mut unique = names.clone();
+            unique.dedup();
+            assert_eq!(
+                names,
+                unique,
+                "in {}",
+                proc_name(&ident_ids, proc.name.name())
+            );
+        }
+    }
+
+    /// The symbols bound by each `Let` in `stmt`, in every branch
+    fn collect_let_symbols(stmt: &Stmt, symbols: &mut std::vec::Vec<Symbol>) {
+        match stmt {
+            Stmt::Let(symbol, _, _, next) => {
+                symbols.push(*symbol);
+                collect_let_symbols(next, symbols);
+            }
+            Stmt::Switch {
+                branches,
+                default_branch,
+                ..
+            } => {
+                for (_, _, branch) in branches.iter() {
+                    collect_let_symbols(branch, symbols);
+                }
+                collect_let_symbols(default_branch.1, symbols);
+            }
+            Stmt::Join {
+                body, remainder, ..
+            } => {
+                collect_let_symbols(body, symbols);
+                collect_let_symbols(remainder, symbols);
+            }
+            Stmt::Refcounting(_, next) => collect_let_symbols(next, symbols),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn unsupported_layout_error_says_where_the_value_is() {
+        use roc_region::all::{Position, Region};
+
+        // registers the names of the builtin modules, so that the error can print the symbol
+        let _ = roc_module::symbol::ModuleIds::default();
+
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let structure = Symbol::ATTR_ATTR;
+        let following: &Stmt = arena.alloc(Stmt::Ret(structure));
+        let layout = interner.insert(Layout::RecursivePointer(Layout::VOID));
+        let region = Region::new(Position::new(4), Position::new(9));
+
+        let error = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            help.expand_refcount_stmt_at(
+                &mut ident_ids,
+                &mut interner,
+                layout,
+                &ModifyRc::Dec(structure),
+                following,
+                Some(region),
+            )
+        }))
+        .unwrap_err();
+
+        let message = error.downcast_ref::<String>().unwrap();
+        assert!(
+            message.contains(&format!("{:?} at @4-9", structure)),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn dec_refcount_is_a_no_op() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let structure = Symbol::ATTR_ATTR;
+        let following: &Stmt = arena.alloc(Stmt::Ret(structure));
+
+        for modify in [
+            ModifyRc::Inc(structure, 1),
+            ModifyRc::Dec(structure),
+            ModifyRc::DecRef(structure),
+        ] {
+            let (stmt, new_procs) = help.expand_refcount_stmt(
+                &mut ident_ids,
+                &mut interner,
+                Layout::DEC,
+                &modify,
+                following,
+            );
+
+            assert!(
+                std::ptr::eq(stmt, following),
+                "{:?} of a Dec: {:?}",
+                modify,
+                stmt
+            );
+            assert!(new_procs.is_empty());
+        }
+
+        assert!(help.take_procs().is_empty());
+    }
+
+    #[test]
+    fn str_dec_and_decref_share_one_proc() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let jp_decref = JoinPointId(help.create_symbol(&mut ident_ids, "jp_decref"));
+
+        let (dec, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, Layout::STR, HelperOp::Dec);
+        let (decref, new_procs) = help.gen_refcount_proc(
+            &mut ident_ids,
+            &mut interner,
+            Layout::STR,
+            HelperOp::DecRef(jp_decref),
+        );
+
+        assert_eq!(dec, decref);
+        assert!(new_procs.is_empty());
+        assert_eq!(help.take_procs().len(), 1);
+    }
+
+    #[test]
+    fn inc_dec_pair_around_a_no_op_is_elided() {
+        let arena = Bump::new();
+        let mut ident_ids = IdentIds::default();
+        let help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let list = Symbol::ATTR_ATTR;
+        let answer = help.create_symbol(&mut ident_ids, "answer");
+        let inc = ModifyRc::Inc(list, 1);
+
+        // let answer = 42; dec list; ret answer
+        let around = |expr| {
+            let ret = arena.alloc(Stmt::Ret(answer));
+            let dec = arena.alloc(Stmt::Refcounting(ModifyRc::Dec(list), ret));
+            &*arena.alloc(Stmt::Let(answer, expr, Layout::I64, dec))
+        };
+
+        let no_op = around(Expr::Literal(Literal::Int(42i128.to_ne_bytes())));
+        match help.try_elide_rc_pair(&inc, no_op) {
+            Some(Stmt::Let(symbol, _, _, Stmt::Ret(returned))) => {
+                assert_eq!((*symbol, *returned), (answer, answer))
+            }
+            other => panic!("expected the dec to be elided, got {:?}", other),
+        }
+
+        let uses_list = around(Expr::Struct(arena.alloc([list])));
+        assert_eq!(help.try_elide_rc_pair(&inc, uses_list), None);
+
+        let inc_by_two = ModifyRc::Inc(list, 2);
+        assert_eq!(help.try_elide_rc_pair(&inc_by_two, no_op), None);
+    }
+
+    fn calls_by_name(stmt: &Stmt, callee: Symbol) -> bool {
+        count_calls_by_name(stmt, callee) > 0
+    }
+
+    /// How many calls to `callee` there are in `stmt`, counting every branch
+    fn count_calls_by_name(stmt: &Stmt, callee: Symbol) -> usize {
+        match stmt {
+            Stmt::Let(_, expr, _, next) => {
+                let is_call = matches!(
+                    expr,
+                    Expr::Call(call) if matches!(
+                        call.call_type,
+                        CallType::ByName { name, .. } if name.name() == callee
+                    )
+                );
+
+                is_call as usize + count_calls_by_name(next, callee)
+            }
+            Stmt::Switch {
+                branches,
+                default_branch,
+                ..
+            } => {
+                branches
+                    .iter()
+                    .map(|(_, _, branch)| count_calls_by_name(branch, callee))
+                    .sum::<usize>()
+                    + count_calls_by_name(default_branch.1, callee)
+            }
+            Stmt::Join {
+                body, remainder, ..
+            } => count_calls_by_name(body, callee) + count_calls_by_name(remainder, callee),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn dec_calls_registered_finalizer() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let resource = interner.insert(Layout::Boxed(Layout::STR));
+        let finalizer = Symbol::new(ModuleId::ATTR, ident_ids.add_str("close_resource"));
+        help.register_finalizer(resource, finalizer);
+
+        let (dec_symbol, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, resource, HelperOp::Dec);
+        let (inc_symbol, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, resource, HelperOp::Inc);
+
+        let procs = help.take_procs();
+        let body_of = |symbol| {
+            &procs
+                .iter()
+                .find(|proc| proc.name.name() == symbol)
+                .unwrap()
+                .body
+        };
+
+        assert!(calls_by_name(body_of(dec_symbol), finalizer));
+        assert!(!calls_by_name(body_of(inc_symbol), finalizer));
+    }
+
+    #[test]
+    fn shared_cache_emits_each_proc_once() {
+        let arena = Bump::new();
+        let cache = HelperProcCache::new();
+        let mut ident_ids = IdentIds::default();
+
+        let mut gen_str_dec = || {
+            // each instance has its own interner, as it would when compiling a separate module
+            let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+            let mut help =
+                CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR).with_shared_cache(&cache);
+
+            let (symbol, new_procs) =
+                help.gen_refcount_proc(&mut ident_ids, &mut interner, Layout::STR, HelperOp::Dec);
+
+            (symbol, new_procs.len(), help.take_procs().len())
+        };
+
+        let (first, first_linker_data, first_procs) = gen_str_dec();
+        let (second, second_linker_data, second_procs) = gen_str_dec();
+
+        assert_eq!(first, second);
+        assert_eq!((first_linker_data, first_procs), (1, 1));
+        assert_eq!((second_linker_data, second_procs), (0, 0));
+    }
+
+    #[test]
+    fn shared_cache_keeps_apart_procs_with_different_finalizers() {
+        let arena = Bump::new();
+        let cache = HelperProcCache::new();
+        let mut ident_ids = IdentIds::default();
+        let finalizer = Symbol::new(ModuleId::ATTR, ident_ids.add_str("close_resource"));
+
+        let mut gen_list_dec = |with_finalizer| {
+            let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+            let resource = interner.insert(Layout::Boxed(Layout::STR));
+            let resources = interner.insert(Layout::Builtin(Builtin::List(resource)));
+
+            let mut help =
+                CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR).with_shared_cache(&cache);
+            if with_finalizer {
+                help.register_finalizer(resource, finalizer);
+            }
+
+            // the finalizer is called by the Box's proc, which the List's proc calls
+            let (symbol, _) =
+                help.gen_refcount_proc(&mut ident_ids, &mut interner, resources, HelperOp::Dec);
+
+            (symbol, help.take_procs().len())
+        };
+
+        let (finalized, finalized_procs) = gen_list_dec(true);
+        let (plain, plain_procs) = gen_list_dec(false);
+
+        assert_ne!(finalized, plain);
+        assert!(finalized_procs > 0 && plain_procs > 0);
+    }
+
+    #[test]
+    fn shared_cache_keeps_apart_layouts_whose_hashes_collide() {
+        let arena = Bump::new();
+        let cache = HelperProcCache::new();
+        let mut ident_ids = IdentIds::default();
+
+        let mut gen_list_dec = |element| {
+            let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+            let layout = interner.insert(Layout::Builtin(Builtin::List(element)));
+            let mut help =
+                CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR).with_shared_cache(&cache);
+
+            let (symbol, _) =
+                help.gen_refcount_proc(&mut ident_ids, &mut interner, layout, HelperOp::Dec);
+            let name = CodeGenHelp::helper_proc_name(&interner, HelperOp::Dec, layout);
+
+            (symbol, help.cache_key(&interner, &name))
+        };
+
+        let (i64_symbol, i64_key) = gen_list_dec(Layout::I64);
+
+        // Put the List I64 proc where the List Str one goes, as if their hashes collided
+        let (_, str_key) = gen_list_dec(Layout::STR);
+        {
+            let mut procs = cache.procs.lock().unwrap();
+            let i64_proc = procs[&i64_key].clone();
+            procs.insert(str_key, i64_proc);
+        }
+
+        let (str_symbol, _) = gen_list_dec(Layout::STR);
+
+        assert_ne!(str_symbol, i64_symbol);
+        assert!(proc_name(&ident_ids, str_symbol).ends_with("_1"));
+    }
+
+    #[test]
+    fn cons_list_dec_loops_unless_told_to_recurse() {
+        use crate::layout::UnionLayout;
+
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+
+        // ConsList a : [Nil, Cons a (ConsList a)]
+        let naked_recursive_ptr = interner.insert(Layout::RecursivePointer(Layout::VOID));
+        let cons_list = interner.insert_recursive(
+            &arena,
+            Layout::Union(UnionLayout::NullableUnwrapped {
+                nullable_id: false,
+                other_fields: arena.alloc([Layout::STR, naked_recursive_ptr]),
+            }),
+        );
+
+        let mut dec_calls_itself = |recursive_refcount| {
+            let mut ident_ids = IdentIds::default();
+            let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR)
+                .with_recursive_refcount(recursive_refcount);
+
+            let (dec, _) =
+                help.gen_refcount_proc(&mut ident_ids, &mut interner, cons_list, HelperOp::Dec);
+
+            let procs = help.take_procs();
+            let proc = procs.iter().find(|proc| proc.name.name() == dec).unwrap();
+
+            calls_by_name(&proc.body, dec)
+        };
+
+        assert!(!dec_calls_itself(RecursiveRefcount::TailLoop));
+        assert!(dec_calls_itself(RecursiveRefcount::SelfRecursive));
+        assert!(!dec_calls_itself(RecursiveRefcount::Worklist));
+    }
+
+    #[test]
+    fn binary_tree_dec_follows_each_recursive_pointer() {
+        use crate::layout::UnionLayout;
+
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+
+        // Tree : [Empty, Node Tree Str Tree]
+        let naked_recursive_ptr = interner.insert(Layout::RecursivePointer(Layout::VOID));
+        let tree = interner.insert_recursive(
+            &arena,
+            Layout::Union(UnionLayout::NullableUnwrapped {
+                nullable_id: false,
+                other_fields: arena.alloc([naked_recursive_ptr, Layout::STR, naked_recursive_ptr]),
+            }),
+        );
+
+        let mut ident_ids = IdentIds::default();
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR)
+            .with_recursive_refcount(RecursiveRefcount::SelfRecursive);
+
+        let (dec, _) = help.gen_refcount_proc(&mut ident_ids, &mut interner, tree, HelperOp::Dec);
+
+        // Both pointers resolve to the tree itself, so there is one helper for the
+        // tree, and it decrements each child subtree once by calling itself
+        let procs = help.take_procs();
+        let tree_procs: Vec<_> = procs
+            .iter()
+            .filter(|proc| proc.name.name() == dec)
+            .collect();
+        assert_eq!(tree_procs.len(), 1);
+        assert_eq!(count_calls_by_name(&tree_procs[0].body, dec), 2);
+    }
+
+    #[test]
+    fn binary_tree_dec_with_a_worklist_never_calls_itself() {
+        use crate::layout::UnionLayout;
+
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let mut ident_ids = IdentIds::default();
+
+        // Tree : [Empty, Node Tree Str Tree]
+        let naked_recursive_ptr = interner.insert(Layout::RecursivePointer(Layout::VOID));
+        let tree = interner.insert_recursive(
+            &arena,
+            Layout::Union(UnionLayout::NullableUnwrapped {
+                nullable_id: false,
+                other_fields: arena.alloc([naked_recursive_ptr, Layout::STR, naked_recursive_ptr]),
+            }),
+        );
+
+        let mut help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR)
+            .with_recursive_refcount(RecursiveRefcount::Worklist);
+
+        let (dec, _) = help.gen_refcount_proc(&mut ident_ids, &mut interner, tree, HelperOp::Dec);
+        let (str_dec, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, Layout::STR, HelperOp::Dec);
+        let worklist = interner.insert(Layout::Builtin(Builtin::List(Layout::isize(TARGET_INFO))));
+        let (worklist_dec, _) =
+            help.gen_refcount_proc(&mut ident_ids, &mut interner, worklist, HelperOp::Dec);
+
+        // Both subtrees go on the worklist, so the stack stays flat however deep the tree
+        // is. The string in each node is still decremented, and the worklist freed at the end.
+        let procs = help.take_procs();
+        let proc = procs.iter().find(|proc| proc.name.name() == dec).unwrap();
+        assert!(!calls_by_name(&proc.body, dec));
+        assert_eq!(count_calls_by_name(&proc.body, str_dec), 1);
+        assert_eq!(count_calls_by_name(&proc.body, worklist_dec), 1);
+    }
+
+    #[test]
+    fn only_refcounted_layouts_need_a_helper() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let help = CodeGenHelp::new(&arena, TARGET_INFO, ModuleId::ATTR);
+
+        let list_of_str = interner.insert(Layout::Builtin(Builtin::List(Layout::STR)));
+        let plain_struct = interner.insert(Layout::struct_no_name_order(
+            arena.alloc([Layout::I64, Layout::F64]),
+        ));
+
+        assert!(!help.needs_refcount_helper(&interner, Layout::I64));
+        assert!(!help.needs_refcount_helper(&interner, plain_struct));
+        assert!(help.needs_refcount_helper(&interner, list_of_str));
+    }
+}
@@ -1,6 +1,6 @@
 #![allow(clippy::too_many_arguments)]
 
-use bumpalo::collections::vec::Vec;
+use bumpalo::collections::{CollectIn, Vec};
 use roc_module::low_level::{LowLevel, LowLevel::*};
 use roc_module::symbol::{IdentIds, Symbol};
 use roc_target::PtrWidth;
@@ -8,13 +8,15 @@ use roc_target::PtrWidth;
 use crate::borrow::Ownership;
 use crate::code_gen_help::let_lowlevel;
 use crate::ir::{
-    BranchInfo, Call, CallType, Expr, JoinPointId, Literal, ModifyRc, Param, Stmt, UpdateModeId,
+    BranchInfo, Call, CallSpecId, CallType, Expr, JoinPointId, Literal, ModifyRc, Param, Stmt,
+    UpdateModeId,
 };
 use crate::layout::{
-    Builtin, InLayout, Layout, LayoutInterner, STLayoutInterner, TagIdIntType, UnionLayout,
+    Builtin, InLayout, LambdaName, Layout, LayoutInterner, STLayoutInterner, TagIdIntType,
+    UnionLayout,
 };
 
-use super::{CodeGenHelp, Context, HelperOp};
+use super::{CodeGenHelp, Context, HelperOp, RecursiveRefcount};
 
 const LAYOUT_BOOL: InLayout = Layout::BOOL;
 const LAYOUT_UNIT: InLayout = Layout::UNIT;
@@ -77,13 +79,14 @@ pub fn refcount_stmt<'a>(
                 // Str has no children, so Dec is the same as DecRef.
                 Layout::Builtin(Builtin::Str) => {
                     ctx.op = HelperOp::Dec;
+                    ctx.trace_name = root.trace_name(layout_interner, ctx.op, layout);
                     refcount_stmt(
                         root,
                         ident_ids,
                         ctx,
                         layout_interner,
                         layout,
-                        modify,
+                        &ModifyRc::Dec(*structure),
                         following,
                     )
                 }
@@ -369,12 +372,7 @@ pub fn refcount_reset_proc_body<'a>(
     };
 
     // Constant for unique refcount
-    let refcount_1_encoded = match root.target_info.ptr_width() {
-        PtrWidth::Bytes4 => i32::MIN as i128,
-        PtrWidth::Bytes8 => i64::MIN as i128,
-    }
-    .to_ne_bytes();
-    let refcount_1_expr = Expr::Literal(Literal::Int(refcount_1_encoded));
+    let refcount_1_expr = refcount_1_literal(root);
     let refcount_1_stmt = Stmt::Let(
         refcount_1,
         refcount_1_expr,
@@ -413,6 +411,17 @@ pub fn refcount_reset_proc_body<'a>(
     rc_ptr_stmt
 }
 
+/// The refcount of a unique allocation
+fn refcount_1_literal<'a>(root: &CodeGenHelp<'a>) -> Expr<'a> {
+    let refcount_1_encoded = match root.target_info.ptr_width() {
+        PtrWidth::Bytes4 => i32::MIN as i128,
+        PtrWidth::Bytes8 => i64::MIN as i128,
+    }
+    .to_ne_bytes();
+
+    Expr::Literal(Literal::Int(refcount_1_encoded))
+}
+
 fn rc_return_stmt<'a>(
     root: &CodeGenHelp<'a>,
     ident_ids: &mut IdentIds,
@@ -578,6 +587,44 @@ fn modify_refcount<'a>(
     rc_ptr: Symbol,
     alignment: u32,
     following: &'a Stmt<'a>,
+) -> Stmt<'a> {
+    let modify_stmt = modify_refcount_help(root, ident_ids, ctx, rc_ptr, alignment, following);
+
+    match ctx.trace_name {
+        Some(name) => {
+            // Tell the host about the change before it happens, in case it frees the value
+            let trace_name = root.create_symbol(ident_ids, "trace_name");
+            let trace_name_expr = Expr::Literal(Literal::Str(name));
+            let trace_result = root.create_symbol(ident_ids, "trace_result");
+            let trace_stmt = |next| {
+                let_lowlevel(
+                    root.arena,
+                    LAYOUT_UNIT,
+                    trace_result,
+                    RefCountTrace,
+                    &[trace_name, rc_ptr],
+                    next,
+                )
+            };
+
+            Stmt::Let(
+                trace_name,
+                trace_name_expr,
+                Layout::STR,
+                root.arena.alloc(trace_stmt(root.arena.alloc(modify_stmt))),
+            )
+        }
+        None => modify_stmt,
+    }
+}
+
+fn modify_refcount_help<'a>(
+    root: &CodeGenHelp<'a>,
+    ident_ids: &mut IdentIds,
+    ctx: &mut Context<'a>,
+    rc_ptr: Symbol,
+    alignment: u32,
+    following: &'a Stmt<'a>,
 ) -> Stmt<'a> {
     // Call the relevant Zig lowlevel to actually modify the refcount
     let zig_call_result = root.create_symbol(ident_ids, "zig_call_result");
@@ -740,19 +787,30 @@ fn refcount_list<'a>(
     let len = root.create_symbol(ident_ids, "len");
     let len_stmt = |next| let_lowlevel(arena, layout_isize, len, ListLen, &[structure], next);
 
+    // The capacity, or for a seamless slice, a pointer to the elements of the list it is a slice
+    // of, shifted right by one, with the high bit set. That list has the refcount we modify.
+    let cap_or_ref = root.create_symbol(ident_ids, "cap_or_ref");
+    let cap_or_ref_expr = Expr::StructAtIndex {
+        index: 2,
+        field_layouts: arena.alloc([box_layout, layout_isize, layout_isize]),
+        structure,
+    };
+    let cap_or_ref_stmt = |next| Stmt::Let(cap_or_ref, cap_or_ref_expr, layout_isize, next);
+
     // Zero
     let zero = root.create_symbol(ident_ids, "zero");
     let zero_expr = Expr::Literal(Literal::Int(0i128.to_ne_bytes()));
     let zero_stmt = |next| Stmt::Let(zero, zero_expr, layout_isize, next);
 
-    // let is_empty = lowlevel Eq len zero
+    // let is_empty = lowlevel Eq cap_or_ref zero
+    // (not the length: an empty seamless slice still holds on to the list it is a slice of)
     let is_empty = root.create_symbol(ident_ids, "is_empty");
     let is_empty_expr = Expr::Call(Call {
         call_type: CallType::LowLevel {
             op: LowLevel::Eq,
             update_mode: UpdateModeId::BACKEND_DUMMY,
         },
-        arguments: root.arena.alloc([len, zero]),
+        arguments: root.arena.alloc([cap_or_ref, zero]),
     });
     let is_empty_stmt = |next| Stmt::Let(is_empty, is_empty_expr, LAYOUT_BOOL, next);
 
@@ -783,16 +841,75 @@ fn refcount_list<'a>(
         arena.alloc(ret_stmt),
     );
 
-    let get_rc_and_modify_list = rc_ptr_from_data_ptr(
+    let jp_modify_list = JoinPointId(root.create_symbol(ident_ids, "jp_modify_list"));
+
+    // A list's refcount is just before its elements
+    let list_rc_ptr = root.create_symbol(ident_ids, "list_rc_ptr");
+    let list_branch = rc_ptr_from_data_ptr(
         root,
         ident_ids,
         elements,
-        rc_ptr,
+        list_rc_ptr,
         false,
-        arena.alloc(modify_list),
+        arena.alloc(Stmt::Jump(jp_modify_list, arena.alloc([list_rc_ptr]))),
         Layout::OPAQUE_PTR,
     );
 
+    // A seamless slice's refcount is just before the elements of the list it is a slice of.
+    // Only the slice's own elements are modified, even when this frees the source list:
+    // the source elements outside the slice were already released when the slice was made.
+    let one = root.create_symbol(ident_ids, "one");
+    let one_expr = Expr::Literal(Literal::Int(1i128.to_ne_bytes()));
+    let source_elements = root.create_symbol(ident_ids, "source_elements");
+    let slice_rc_ptr = root.create_symbol(ident_ids, "slice_rc_ptr");
+    let slice_branch = Stmt::Let(
+        one,
+        one_expr,
+        Layout::U8,
+        arena.alloc(let_lowlevel(
+            arena,
+            layout_isize,
+            source_elements,
+            NumShiftLeftBy,
+            &[cap_or_ref, one],
+            arena.alloc(rc_ptr_from_data_ptr(
+                root,
+                ident_ids,
+                source_elements,
+                slice_rc_ptr,
+                false,
+                arena.alloc(Stmt::Jump(jp_modify_list, arena.alloc([slice_rc_ptr]))),
+                Layout::OPAQUE_PTR,
+            )),
+        )),
+    );
+
+    // The high bit of a seamless slice's `cap_or_ref` is set, so it is negative
+    let is_slice = root.create_symbol(ident_ids, "is_slice");
+    let get_rc_and_modify_list = let_lowlevel(
+        arena,
+        LAYOUT_BOOL,
+        is_slice,
+        NumLt,
+        &[cap_or_ref, zero],
+        arena.alloc(Stmt::Join {
+            id: jp_modify_list,
+            parameters: arena.alloc([Param {
+                symbol: rc_ptr,
+                ownership: Ownership::Borrowed,
+                layout: Layout::OPAQUE_PTR,
+            }]),
+            body: arena.alloc(modify_list),
+            remainder: arena.alloc(Stmt::Switch {
+                cond_symbol: is_slice,
+                cond_layout: LAYOUT_BOOL,
+                branches: arena.alloc([(1, BranchInfo::None, slice_branch)]),
+                default_branch: (BranchInfo::None, arena.alloc(list_branch)),
+                ret_layout: LAYOUT_UNIT,
+            }),
+        }),
+    );
+
     let modify_elems_and_list =
         if layout_interner.get(elem_layout).is_refcounted() && !ctx.op.is_decref() {
             refcount_list_elems(
@@ -835,11 +952,14 @@ fn refcount_list<'a>(
 
     len_stmt(arena.alloc(
         //
-        zero_stmt(arena.alloc(
+        cap_or_ref_stmt(arena.alloc(
             //
-            is_empty_stmt(arena.alloc(
+            zero_stmt(arena.alloc(
                 //
-                if_stmt,
+                is_empty_stmt(arena.alloc(
+                    //
+                    if_stmt,
+                )),
             )),
         )),
     ))
@@ -1072,35 +1192,17 @@ fn refcount_union<'a>(
             structure,
         ),
 
-        Recursive(tags) => {
-            let (is_tailrec, tail_idx) = root.union_tail_recursion_fields(layout_interner, union);
-            if is_tailrec && !ctx.op.is_decref() {
-                refcount_union_tailrec(
-                    root,
-                    ident_ids,
-                    ctx,
-                    layout_interner,
-                    union,
-                    tags,
-                    None,
-                    tail_idx,
-                    structure,
-                )
-            } else {
-                let recursive_ptr = layout_interner.insert(Layout::RecursivePointer(layout));
-                refcount_union_rec(
-                    root,
-                    ident_ids,
-                    ctx,
-                    layout_interner,
-                    union,
-                    tags,
-                    None,
-                    recursive_ptr,
-                    structure,
-                )
-            }
-        }
+        Recursive(tags) => refcount_union_recursive(
+            root,
+            ident_ids,
+            ctx,
+            layout_interner,
+            layout,
+            union,
+            tags,
+            None,
+            structure,
+        ),
 
         NonNullableUnwrapped(field_layouts) => {
             // We don't do tail recursion on NonNullableUnwrapped.
@@ -1127,33 +1229,17 @@ fn refcount_union<'a>(
             nullable_id,
         } => {
             let null_id = Some(nullable_id);
-            let (is_tailrec, tail_idx) = root.union_tail_recursion_fields(layout_interner, union);
-            if is_tailrec && !ctx.op.is_decref() {
-                refcount_union_tailrec(
-                    root,
-                    ident_ids,
-                    ctx,
-                    layout_interner,
-                    union,
-                    tags,
-                    null_id,
-                    tail_idx,
-                    structure,
-                )
-            } else {
-                let recursive_ptr = layout_interner.insert(Layout::RecursivePointer(layout));
-                refcount_union_rec(
-                    root,
-                    ident_ids,
-                    ctx,
-                    layout_interner,
-                    union,
-                    tags,
-                    null_id,
-                    recursive_ptr,
-                    structure,
-                )
-            }
+            refcount_union_recursive(
+                root,
+                ident_ids,
+                ctx,
+                layout_interner,
+                layout,
+                union,
+                tags,
+                null_id,
+                structure,
+            )
         }
 
         NullableUnwrapped {
@@ -1162,33 +1248,17 @@ fn refcount_union<'a>(
         } => {
             let null_id = Some(nullable_id as TagIdIntType);
             let tags = root.arena.alloc([other_fields]);
-            let (is_tailrec, tail_idx) = root.union_tail_recursion_fields(layout_interner, union);
-            if is_tailrec && !ctx.op.is_decref() {
-                refcount_union_tailrec(
-                    root,
-                    ident_ids,
-                    ctx,
-                    layout_interner,
-                    union,
-                    tags,
-                    null_id,
-                    tail_idx,
-                    structure,
-                )
-            } else {
-                let recursive_ptr = layout_interner.insert(Layout::RecursivePointer(layout));
-                refcount_union_rec(
-                    root,
-                    ident_ids,
-                    ctx,
-                    layout_interner,
-                    union,
-                    tags,
-                    null_id,
-                    recursive_ptr,
-                    structure,
-                )
-            }
+            refcount_union_recursive(
+                root,
+                ident_ids,
+                ctx,
+                layout_interner,
+                layout,
+                union,
+                tags,
+                null_id,
+                structure,
+            )
         }
     };
 
@@ -1197,6 +1267,62 @@ fn refcount_union<'a>(
     body
 }
 
+/// Refcount a recursive union the way `root.recursive_refcount` asks for. A `DecRef`, or a
+/// union without a recursive field of its own, is always done by calling the proc itself.
+fn refcount_union_recursive<'a>(
+    root: &mut CodeGenHelp<'a>,
+    ident_ids: &mut IdentIds,
+    ctx: &mut Context<'a>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    layout: InLayout<'a>,
+    union: UnionLayout<'a>,
+    tags: &'a [&'a [InLayout<'a>]],
+    null_id: Option<TagIdIntType>,
+    structure: Symbol,
+) -> Stmt<'a> {
+    let (is_tailrec, tail_idx) = root.union_tail_recursion_fields(layout_interner, union);
+
+    match root.recursive_refcount {
+        RecursiveRefcount::TailLoop if is_tailrec && !ctx.op.is_decref() => refcount_union_tailrec(
+            root,
+            ident_ids,
+            ctx,
+            layout_interner,
+            union,
+            tags,
+            null_id,
+            tail_idx,
+            structure,
+        ),
+        RecursiveRefcount::Worklist if is_tailrec && !ctx.op.is_decref() => {
+            refcount_union_worklist(
+                root,
+                ident_ids,
+                ctx,
+                layout_interner,
+                union,
+                tags,
+                null_id,
+                structure,
+            )
+        }
+        _ => {
+            let recursive_ptr = layout_interner.insert(Layout::RecursivePointer(layout));
+            refcount_union_rec(
+                root,
+                ident_ids,
+                ctx,
+                layout_interner,
+                union,
+                tags,
+                null_id,
+                recursive_ptr,
+                structure,
+            )
+        }
+    }
+}
+
 fn refcount_union_nonrec<'a>(
     root: &mut CodeGenHelp<'a>,
     ident_ids: &mut IdentIds,
@@ -1600,6 +1726,472 @@ fn refcount_union_tailrec<'a>(
     }
 }
 
+// Refcount a recursive union in a loop that takes the next node from a worklist on the heap,
+// and pushes each of its recursive fields there, so that the stack stays flat however the
+// nodes are linked. The worklist is a stack of the nodes' addresses, as integers, so that
+// there is nothing in it to refcount. Like the other procs, each node's fields are modified
+// before the node itself.
+fn refcount_union_worklist<'a>(
+    root: &mut CodeGenHelp<'a>,
+    ident_ids: &mut IdentIds,
+    ctx: &mut Context<'a>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    union_layout: UnionLayout<'a>,
+    tag_layouts: &'a [&'a [InLayout<'a>]],
+    null_id: Option<TagIdIntType>,
+    initial_structure: Symbol,
+) -> Stmt<'a> {
+    let arena = root.arena;
+    let layout_isize = root.layout_isize;
+    let layout = layout_interner.insert(Layout::Union(union_layout));
+    let recursion_ptr = layout_interner.insert(Layout::RecursivePointer(layout));
+    let stack_layout = layout_interner.insert(Layout::Builtin(Builtin::List(layout_isize)));
+
+    let worklist_loop = JoinPointId(root.create_symbol(ident_ids, "worklist_loop"));
+    let stack = root.create_symbol(ident_ids, "stack");
+    let top = root.create_symbol(ident_ids, "top");
+    let current = root.create_symbol(ident_ids, "current");
+    let popped_top = root.create_symbol(ident_ids, "popped_top");
+
+    let int_stmt = |symbol, value: i128, next| {
+        Stmt::Let(
+            symbol,
+            Expr::Literal(Literal::Int(value.to_ne_bytes())),
+            layout_isize,
+            next,
+        )
+    };
+
+    // Modify the refcount of the current node, once its fields are done, and loop
+    let jp_modify_union = JoinPointId(root.create_symbol(ident_ids, "jp_modify_union"));
+    let pushed_stack = root.create_symbol(ident_ids, "pushed_stack");
+    let pushed_top = root.create_symbol(ident_ids, "pushed_top");
+    let rc_structure_stmt = {
+        let rc_ptr = root.create_symbol(ident_ids, "rc_ptr");
+        let jump_to_loop = Stmt::Jump(worklist_loop, arena.alloc([pushed_stack, pushed_top]));
+
+        let alignment = layout_interner.alignment_bytes(layout);
+        let modify_structure_stmt = modify_refcount(
+            root,
+            ident_ids,
+            ctx,
+            rc_ptr,
+            alignment,
+            arena.alloc(jump_to_loop),
+        );
+
+        rc_ptr_from_data_ptr(
+            root,
+            ident_ids,
+            current,
+            rc_ptr,
+            union_layout.stores_tag_id_in_pointer(root.target_info),
+            arena.alloc(modify_structure_stmt),
+            recursion_ptr,
+        )
+    };
+
+    let tag_id_layout = union_layout.tag_id_layout();
+    let tag_id_sym = root.create_symbol(ident_ids, "tag_id");
+    let mut tag_branches = Vec::with_capacity_in(tag_layouts.len() + 1, arena);
+
+    // A null node has no refcount and no fields. Just go on with the next one.
+    if let Some(id) = null_id {
+        let next_node = Stmt::Jump(worklist_loop, arena.alloc([stack, popped_top]));
+        tag_branches.push((id as u64, BranchInfo::None, next_node));
+    }
+
+    let mut tag_id: TagIdIntType = 0;
+    for field_layouts in tag_layouts.iter() {
+        match null_id {
+            Some(id) if id == tag_id => {
+                tag_id += 1;
+            }
+            _ => {}
+        }
+
+        let is_recursive: std::vec::Vec<bool> = field_layouts
+            .iter()
+            .map(|field| matches!(layout_interner.get(*field), Layout::RecursivePointer(_)))
+            .collect();
+
+        // Push the recursive fields, each push taking the stack and its top from the one
+        // before it, and then modify the node itself
+        let recursive_fields: std::vec::Vec<(usize, InLayout<'a>)> = field_layouts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| is_recursive[*i])
+            .map(|(i, field)| (i, *field))
+            .collect();
+        let mut stacks_and_tops = std::vec::Vec::with_capacity(recursive_fields.len() + 1);
+        stacks_and_tops.push((stack, popped_top));
+        for _ in &recursive_fields {
+            stacks_and_tops.push((
+                root.create_symbol(ident_ids, "pushed_stack"),
+                root.create_symbol(ident_ids, "pushed_top"),
+            ));
+        }
+
+        let (last_stack, last_top) = stacks_and_tops[recursive_fields.len()];
+        let mut push_stmt = Stmt::Jump(jp_modify_union, arena.alloc([last_stack, last_top]));
+        for (n, (i, field_layout)) in recursive_fields.into_iter().enumerate().rev() {
+            let field_val = root.create_symbol(ident_ids, &format!("field_{}_{}", tag_id, i));
+            let field_val_expr = Expr::UnionAtIndex {
+                union_layout,
+                tag_id,
+                index: i as u64,
+                structure: current,
+            };
+
+            let push = push_address(
+                root,
+                ident_ids,
+                layout_interner,
+                stack_layout,
+                stacks_and_tops[n],
+                field_val,
+                stacks_and_tops[n + 1],
+                push_stmt,
+            );
+            push_stmt = Stmt::Let(field_val, field_val_expr, field_layout, arena.alloc(push));
+        }
+
+        let non_recursive_fields: Vec<'a, InLayout<'a>> = field_layouts
+            .iter()
+            .zip(&is_recursive)
+            .map(
+                |(field, is_recursive)| {
+                    if *is_recursive {
+                        Layout::UNIT
+                    } else {
+                        *field
+                    }
+                },
+            )
+            .collect_in(arena);
+
+        let fields_stmt = refcount_tag_fields(
+            root,
+            ident_ids,
+            ctx,
+            layout_interner,
+            union_layout,
+            non_recursive_fields.into_bump_slice(),
+            current,
+            tag_id,
+            push_stmt,
+        );
+
+        tag_branches.push((tag_id as u64, BranchInfo::None, fields_stmt));
+
+        tag_id += 1;
+    }
+
+    let default_stmt: Stmt<'a> = tag_branches.pop().unwrap().2;
+
+    let tag_id_switch = Stmt::Switch {
+        cond_symbol: tag_id_sym,
+        cond_layout: tag_id_layout,
+        branches: tag_branches.into_bump_slice(),
+        default_branch: (BranchInfo::None, arena.alloc(default_stmt)),
+        ret_layout: LAYOUT_UNIT,
+    };
+
+    let modify_union_params = arena.alloc([
+        Param {
+            symbol: pushed_stack,
+            ownership: Ownership::Owned,
+            layout: stack_layout,
+        },
+        Param {
+            symbol: pushed_top,
+            ownership: Ownership::Borrowed,
+            layout: layout_isize,
+        },
+    ]);
+    let do_node = Stmt::Join {
+        id: jp_modify_union,
+        parameters: modify_union_params,
+        body: arena.alloc(rc_structure_stmt),
+        remainder: arena.alloc(Stmt::Let(
+            tag_id_sym,
+            Expr::GetTagId {
+                structure: current,
+                union_layout,
+            },
+            tag_id_layout,
+            arena.alloc(tag_id_switch),
+        )),
+    };
+
+    // Pop the next node
+    let one = root.create_symbol(ident_ids, "one");
+    let addr = root.create_symbol(ident_ids, "addr");
+    let pop_stmt = int_stmt(
+        one,
+        1,
+        arena.alloc(let_lowlevel(
+            arena,
+            layout_isize,
+            popped_top,
+            NumSubWrap,
+            &[top, one],
+            arena.alloc(let_lowlevel(
+                arena,
+                layout_isize,
+                addr,
+                ListGetUnsafe,
+                &[stack, popped_top],
+                arena.alloc(let_lowlevel(
+                    arena,
+                    layout,
+                    current,
+                    PtrCast,
+                    &[addr],
+                    arena.alloc(do_node),
+                )),
+            )),
+        )),
+    );
+
+    // Once the stack is empty, free it. It holds integers, so that is all a Dec of it does.
+    let done_stmt = {
+        let freed = root.create_symbol(ident_ids, "freed");
+        let outer_op = std::mem::replace(&mut ctx.op, HelperOp::Dec);
+        let free_expr = root
+            .call_specialized_op(
+                ident_ids,
+                ctx,
+                layout_interner,
+                stack_layout,
+                arena.alloc([stack]),
+            )
+            .unwrap();
+        ctx.op = outer_op;
+
+        let ret_stmt = rc_return_stmt(root, ident_ids, ctx);
+        Stmt::Let(freed, free_expr, LAYOUT_UNIT, arena.alloc(ret_stmt))
+    };
+
+    let zero = root.create_symbol(ident_ids, "zero");
+    let is_empty = root.create_symbol(ident_ids, "is_empty");
+    let loop_body = int_stmt(
+        zero,
+        0,
+        arena.alloc(let_lowlevel(
+            arena,
+            LAYOUT_BOOL,
+            is_empty,
+            Eq,
+            &[top, zero],
+            arena.alloc(Stmt::Switch {
+                cond_symbol: is_empty,
+                cond_layout: LAYOUT_BOOL,
+                branches: arena.alloc([(1, BranchInfo::None, done_stmt)]),
+                default_branch: (BranchInfo::None, arena.alloc(pop_stmt)),
+                ret_layout: LAYOUT_UNIT,
+            }),
+        )),
+    );
+
+    // Start with a stack that holds just the initial node
+    let capacity = root.create_symbol(ident_ids, "capacity");
+    let empty_stack = root.create_symbol(ident_ids, "empty_stack");
+    let initial_addr = root.create_symbol(ident_ids, "initial_addr");
+    let initial_stack = root.create_symbol(ident_ids, "initial_stack");
+    let initial_top = root.create_symbol(ident_ids, "initial_top");
+    let loop_init = int_stmt(
+        capacity,
+        16,
+        arena.alloc(let_lowlevel(
+            arena,
+            stack_layout,
+            empty_stack,
+            ListWithCapacity,
+            &[capacity],
+            arena.alloc(let_lowlevel(
+                arena,
+                layout_isize,
+                initial_addr,
+                PtrCast,
+                &[initial_structure],
+                arena.alloc(let_lowlevel(
+                    arena,
+                    stack_layout,
+                    initial_stack,
+                    ListAppendUnsafe,
+                    &[empty_stack, initial_addr],
+                    arena.alloc(int_stmt(
+                        initial_top,
+                        1,
+                        arena.alloc(Stmt::Jump(
+                            worklist_loop,
+                            arena.alloc([initial_stack, initial_top]),
+                        )),
+                    )),
+                )),
+            )),
+        )),
+    );
+
+    let loop_params = arena.alloc([
+        Param {
+            symbol: stack,
+            ownership: Ownership::Owned,
+            layout: stack_layout,
+        },
+        Param {
+            symbol: top,
+            ownership: Ownership::Borrowed,
+            layout: layout_isize,
+        },
+    ]);
+
+    Stmt::Join {
+        id: worklist_loop,
+        parameters: loop_params,
+        body: arena.alloc(loop_body),
+        remainder: arena.alloc(loop_init),
+    }
+}
+
+/// Push the address of `value` onto the worklist `stack`, whose elements below `top` are the
+/// nodes still to do, and go on with `following`, in which `pushed` are the new stack and top.
+///
+/// The stack never shrinks, so where there is a popped element above the top, it is replaced.
+fn push_address<'a>(
+    root: &mut CodeGenHelp<'a>,
+    ident_ids: &mut IdentIds,
+    layout_interner: &mut STLayoutInterner<'a>,
+    stack_layout: InLayout<'a>,
+    (stack, top): (Symbol, Symbol),
+    value: Symbol,
+    (pushed_stack, pushed_top): (Symbol, Symbol),
+    following: Stmt<'a>,
+) -> Stmt<'a> {
+    let arena = root.arena;
+    let layout_isize = root.layout_isize;
+    let one_stmt = |one, next| {
+        Stmt::Let(
+            one,
+            Expr::Literal(Literal::Int(1i128.to_ne_bytes())),
+            layout_isize,
+            next,
+        )
+    };
+
+    let jp_pushed = JoinPointId(root.create_symbol(ident_ids, "jp_pushed"));
+    let addr = root.create_symbol(ident_ids, "addr");
+
+    let replace_stmt = {
+        let replaced = root.create_symbol(ident_ids, "replaced");
+        let replaced_layout = layout_interner.insert(Layout::struct_no_name_order(
+            arena.alloc([stack_layout, layout_isize]),
+        ));
+        let replaced_stack = root.create_symbol(ident_ids, "replaced_stack");
+        let replaced_stack_expr = Expr::StructAtIndex {
+            index: 0,
+            field_layouts: arena.alloc([stack_layout, layout_isize]),
+            structure: replaced,
+        };
+
+        let_lowlevel(
+            arena,
+            replaced_layout,
+            replaced,
+            ListReplaceUnsafe,
+            &[stack, top, addr],
+            arena.alloc(Stmt::Let(
+                replaced_stack,
+                replaced_stack_expr,
+                stack_layout,
+                arena.alloc(Stmt::Jump(jp_pushed, arena.alloc([replaced_stack]))),
+            )),
+        )
+    };
+
+    let append_stmt = {
+        let one = root.create_symbol(ident_ids, "one");
+        let reserved = root.create_symbol(ident_ids, "reserved");
+        let appended = root.create_symbol(ident_ids, "appended");
+
+        one_stmt(
+            one,
+            arena.alloc(let_lowlevel(
+                arena,
+                stack_layout,
+                reserved,
+                ListReserve,
+                &[stack, one],
+                arena.alloc(let_lowlevel(
+                    arena,
+                    stack_layout,
+                    appended,
+                    ListAppendUnsafe,
+                    &[reserved, addr],
+                    arena.alloc(Stmt::Jump(jp_pushed, arena.alloc([appended]))),
+                )),
+            )),
+        )
+    };
+
+    let len = root.create_symbol(ident_ids, "len");
+    let has_slot = root.create_symbol(ident_ids, "has_slot");
+    let push_stmt = let_lowlevel(
+        arena,
+        layout_isize,
+        len,
+        ListLen,
+        &[stack],
+        arena.alloc(let_lowlevel(
+            arena,
+            LAYOUT_BOOL,
+            has_slot,
+            NumLt,
+            &[top, len],
+            arena.alloc(Stmt::Switch {
+                cond_symbol: has_slot,
+                cond_layout: LAYOUT_BOOL,
+                branches: arena.alloc([(1, BranchInfo::None, replace_stmt)]),
+                default_branch: (BranchInfo::None, arena.alloc(append_stmt)),
+                ret_layout: LAYOUT_UNIT,
+            }),
+        )),
+    );
+    let addr_stmt = let_lowlevel(
+        arena,
+        layout_isize,
+        addr,
+        PtrCast,
+        &[value],
+        arena.alloc(push_stmt),
+    );
+
+    let one = root.create_symbol(ident_ids, "one");
+    let pushed_body = one_stmt(
+        one,
+        arena.alloc(let_lowlevel(
+            arena,
+            layout_isize,
+            pushed_top,
+            NumAddWrap,
+            &[top, one],
+            arena.alloc(following),
+        )),
+    );
+
+    Stmt::Join {
+        id: jp_pushed,
+        parameters: arena.alloc([Param {
+            symbol: pushed_stack,
+            ownership: Ownership::Owned,
+            layout: stack_layout,
+        }]),
+        body: arena.alloc(pushed_body),
+        remainder: arena.alloc(addr_stmt),
+    }
+}
+
 fn refcount_tag_fields<'a>(
     root: &mut CodeGenHelp<'a>,
     ident_ids: &mut IdentIds,
@@ -1652,6 +2244,34 @@ fn refcount_boxed<'a>(
     layout: InLayout<'a>,
     inner_layout: InLayout<'a>,
     outer: Symbol,
+) -> Stmt<'a> {
+    let modify_stmt = modify_boxed(
+        root,
+        ident_ids,
+        ctx,
+        layout_interner,
+        layout,
+        inner_layout,
+        outer,
+    );
+
+    match root.finalizer(layout) {
+        Some(finalizer) if ctx.op == HelperOp::Dec => {
+            let following = root.arena.alloc(modify_stmt);
+            finalize_if_unique(root, ident_ids, layout, outer, finalizer, following)
+        }
+        _ => modify_stmt,
+    }
+}
+
+fn modify_boxed<'a>(
+    root: &mut CodeGenHelp<'a>,
+    ident_ids: &mut IdentIds,
+    ctx: &mut Context<'a>,
+    layout_interner: &mut STLayoutInterner<'a>,
+    layout: InLayout<'a>,
+    inner_layout: InLayout<'a>,
+    outer: Symbol,
 ) -> Stmt<'a> {
     let arena = root.arena;
 
@@ -1714,3 +2334,87 @@ fn refcount_boxed<'a>(
         get_rc_and_modify_outer
     }
 }
+
+/// If `outer` is unique, the Dec that follows will free it, so call its finalizer first.
+fn finalize_if_unique<'a>(
+    root: &mut CodeGenHelp<'a>,
+    ident_ids: &mut IdentIds,
+    layout: InLayout<'a>,
+    outer: Symbol,
+    finalizer: Symbol,
+    following: &'a Stmt<'a>,
+) -> Stmt<'a> {
+    let arena = root.arena;
+
+    let rc_ptr = root.create_symbol(ident_ids, "rc_ptr");
+    let rc = root.create_symbol(ident_ids, "rc");
+    let refcount_1 = root.create_symbol(ident_ids, "refcount_1");
+    let is_unique = root.create_symbol(ident_ids, "is_unique");
+    let finalizer_unit = root.create_symbol(ident_ids, "finalizer_unit");
+    let jp_finalized = JoinPointId(root.create_symbol(ident_ids, "jp_finalized"));
+
+    let finalizer_expr = Expr::Call(Call {
+        call_type: CallType::ByName {
+            name: LambdaName::no_niche(finalizer),
+            ret_layout: LAYOUT_UNIT,
+            arg_layouts: arena.alloc([layout]),
+            specialization_id: CallSpecId::BACKEND_DUMMY,
+        },
+        arguments: arena.alloc([outer]),
+    });
+    let finalize_stmt = Stmt::Let(
+        finalizer_unit,
+        finalizer_expr,
+        LAYOUT_UNIT,
+        arena.alloc(Stmt::Jump(jp_finalized, &[])),
+    );
+
+    let if_unique_stmt = Stmt::Switch {
+        cond_symbol: is_unique,
+        cond_layout: LAYOUT_BOOL,
+        branches: arena.alloc([(1, BranchInfo::None, finalize_stmt)]),
+        default_branch: (BranchInfo::None, arena.alloc(Stmt::Jump(jp_finalized, &[]))),
+        ret_layout: LAYOUT_UNIT,
+    };
+
+    let join_stmt = Stmt::Join {
+        id: jp_finalized,
+        parameters: &[],
+        body: following,
+        remainder: arena.alloc(if_unique_stmt),
+    };
+
+    let is_unique_stmt = let_lowlevel(
+        arena,
+        LAYOUT_BOOL,
+        is_unique,
+        Eq,
+        &[rc, refcount_1],
+        arena.alloc(join_stmt),
+    );
+
+    let refcount_1_stmt = Stmt::Let(
+        refcount_1,
+        refcount_1_literal(root),
+        root.layout_isize,
+        arena.alloc(is_unique_stmt),
+    );
+
+    let rc_expr = Expr::UnionAtIndex {
+        structure: rc_ptr,
+        tag_id: 0,
+        union_layout: root.union_refcount,
+        index: 0,
+    };
+    let rc_stmt = Stmt::Let(rc, rc_expr, root.layout_isize, arena.alloc(refcount_1_stmt));
+
+    rc_ptr_from_data_ptr(
+        root,
+        ident_ids,
+        outer,
+        rc_ptr,
+        false,
+        arena.alloc(rc_stmt),
+        Layout::OPAQUE_PTR,
+    )
+}
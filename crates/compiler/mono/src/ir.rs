@@ -1965,7 +1965,8 @@ impl<'a> Literal<'a> {
     }
 }
 
-pub(crate) fn symbol_to_doc_string(symbol: Symbol, force_pretty: bool) -> String {
+/// How a symbol is written in pretty-printed IR.
+pub fn symbol_to_doc_string(symbol: Symbol, force_pretty: bool) -> String {
     use roc_module::ident::ModuleName;
 
     if pretty_print_ir_symbols() || force_pretty {
@@ -1955,7 +1955,7 @@ impl<'a> LambdaSet<'a> {
     where
         I: LayoutInterner<'a>,
     {
-        interner.get(self.representation).safe_to_memcpy(interner)
+        interner.safe_to_memcpy(self.representation)
     }
 
     pub fn alignment_bytes<I>(&self, interner: &I, target_info: TargetInfo) -> u32
@@ -2454,7 +2454,7 @@ impl<'a> Layout<'a> {
             Builtin(builtin) => builtin.safe_to_memcpy(),
             Struct { field_layouts, .. } => field_layouts
                 .iter()
-                .all(|field_layout| interner.get(*field_layout).safe_to_memcpy(interner)),
+                .all(|field_layout| interner.safe_to_memcpy(*field_layout)),
             Union(variant) => {
                 use UnionLayout::*;
 
@@ -2462,7 +2462,7 @@ impl<'a> Layout<'a> {
                     NonRecursive(tags) => tags.iter().all(|tag_layout| {
                         tag_layout
                             .iter()
-                            .all(|field| interner.get(*field).safe_to_memcpy(interner))
+                            .all(|field| interner.safe_to_memcpy(*field))
                     }),
                     Recursive(_)
                     | NullableWrapped { .. }
@@ -2473,9 +2473,7 @@ impl<'a> Layout<'a> {
                     }
                 }
             }
-            LambdaSet(lambda_set) => interner
-                .get(lambda_set.runtime_representation())
-                .safe_to_memcpy(interner),
+            LambdaSet(lambda_set) => interner.safe_to_memcpy(lambda_set.runtime_representation()),
             Boxed(_) | RecursivePointer(_) => {
                 // We cannot memcpy pointers, because then we would have the same pointer in multiple places!
                 false
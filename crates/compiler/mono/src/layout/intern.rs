@@ -259,6 +259,24 @@ pub trait LayoutInterner<'a>: Sized {
         })
     }
 
+    /// A hash of what the layout is made of, rather than of where it happens to be interned.
+    ///
+    /// An [InLayout], and so a [LayoutId][crate::layout::LayoutId], depends on the order in
+    /// which layouts were interned. This doesn't, so it can name generated code reproducibly.
+    fn content_hash(&self, layout: InLayout<'a>) -> u64 {
+        let mut state = roc_collections::all::BuildHasher::default().build_hasher();
+        content_hash::hash_layout(self, &mut Vec::new(), layout, &mut state);
+        state.finish()
+    }
+
+    /// What `content_hash` hashes, as bytes. Unlike the hash, these are the same for two
+    /// layouts only if the layouts are, so they tell apart layouts whose hashes collide.
+    fn content_bytes(&self, layout: InLayout<'a>) -> Vec<u8> {
+        let mut bytes = content_hash::ContentBytes::default();
+        content_hash::hash_layout(self, &mut Vec::new(), layout, &mut bytes);
+        bytes.0
+    }
+
     fn to_doc<'b, D, A>(
         &self,
         layout: InLayout<'a>,
@@ -464,6 +482,9 @@ pub struct STLayoutInterner<'a> {
     normalized_lambda_set_map: BumpMap<LambdaSet<'a>, LambdaSet<'a>>,
     vec: Vec<Layout<'a>>,
     target_info: TargetInfo,
+    /// Whether each layout is safe to memcpy, by index, once it has been asked.
+    /// Cloning a big value asks this of the same nested layouts over and over.
+    safe_to_memcpy_cache: RefCell<Vec<Option<bool>>>,
 }
 
 /// Interner constructed with an exclusive lock over [GlobalLayoutInterner]
@@ -537,6 +558,7 @@ impl<'a> GlobalLayoutInterner<'a> {
             normalized_lambda_set_map,
             vec,
             target_info,
+            safe_to_memcpy_cache: RefCell::new(Vec::new()),
         })
     }
 
@@ -824,6 +846,7 @@ impl<'a> STLayoutInterner<'a> {
             normalized_lambda_set_map: BumpMap::with_capacity_and_hasher(cap, default_hasher()),
             vec: Vec::with_capacity(cap),
             target_info,
+            safe_to_memcpy_cache: RefCell::new(Vec::new()),
         };
         fill_reserved_layouts(&mut interner);
         interner
@@ -839,6 +862,7 @@ impl<'a> STLayoutInterner<'a> {
             normalized_lambda_set_map,
             vec,
             target_info,
+            safe_to_memcpy_cache: _,
         } = self;
         GlobalLayoutInterner(Arc::new(GlobalLayoutInternerInner {
             map: Mutex::new(map),
@@ -851,6 +875,32 @@ impl<'a> STLayoutInterner<'a> {
     pub fn is_empty(&self) -> bool {
         self.vec.is_empty()
     }
+
+    fn memoized_safe_to_memcpy(&self, layout: InLayout<'a>) -> bool {
+        let InLayout(index, _) = layout;
+
+        if let Some(Some(safe)) = self.safe_to_memcpy_cache.borrow().get(index) {
+            return *safe;
+        }
+
+        // not borrowed while the fields are asked, which fills in their entries too
+        let safe = self.get(layout).safe_to_memcpy(self);
+
+        let mut cache = self.safe_to_memcpy_cache.borrow_mut();
+        if cache.len() <= index {
+            cache.resize(self.vec.len(), None);
+        }
+        cache[index] = Some(safe);
+
+        safe
+    }
+}
+
+impl<'a, 'r> LockedGlobalInterner<'a, 'r> {
+    /// Only locked for a moment, so there is nothing to remember this for.
+    fn memoized_safe_to_memcpy(&self, layout: InLayout<'a>) -> bool {
+        self.get(layout).safe_to_memcpy(self)
+    }
 }
 
 macro_rules! st_impl {
@@ -955,6 +1005,10 @@ macro_rules! st_impl {
             fn target_info(&self) -> TargetInfo {
                 self.target_info
             }
+
+            fn safe_to_memcpy(&self, layout: InLayout<'a>) -> bool {
+                self.memoized_safe_to_memcpy(layout)
+            }
         }
     };
 }
@@ -1278,6 +1332,137 @@ mod equiv {
     }
 }
 
+mod content_hash {
+    use std::hash::{Hash, Hasher};
+
+    use crate::layout::{Builtin, LambdaSet, Layout, UnionLayout};
+
+    use super::{InLayout, LayoutInterner};
+
+    /// A `Hasher` that keeps everything that is hashed, rather than a hash of it
+    #[derive(Default)]
+    pub struct ContentBytes(pub Vec<u8>);
+
+    impl Hasher for ContentBytes {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.extend_from_slice(bytes);
+        }
+
+        fn finish(&self) -> u64 {
+            unreachable!("the bytes are used, not a hash of them")
+        }
+    }
+
+    /// `recursive` holds the recursive unions that `layout` is in, innermost last. A recursive
+    /// pointer is hashed as how far out its union is, since its own index is an interned one.
+    pub fn hash_layout<'a>(
+        interner: &impl LayoutInterner<'a>,
+        recursive: &mut Vec<InLayout<'a>>,
+        layout: InLayout<'a>,
+        state: &mut impl Hasher,
+    ) {
+        let hash_fields =
+            |recursive: &mut Vec<InLayout<'a>>, fields: &[InLayout<'a>], state: &mut _| {
+                fields.len().hash(state);
+                for field in fields {
+                    hash_layout(interner, recursive, *field, state);
+                }
+            };
+
+        match interner.get(layout) {
+            Layout::Builtin(builtin) => {
+                state.write_u8(0);
+                match builtin {
+                    Builtin::Int(width) => (0u8, width).hash(state),
+                    Builtin::Float(width) => (1u8, width).hash(state),
+                    Builtin::Bool => state.write_u8(2),
+                    Builtin::Decimal => state.write_u8(3),
+                    Builtin::Str => state.write_u8(4),
+                    Builtin::List(element) => {
+                        state.write_u8(5);
+                        hash_layout(interner, recursive, element, state);
+                    }
+                }
+            }
+            Layout::Struct {
+                field_order_hash,
+                field_layouts,
+            } => {
+                state.write_u8(1);
+                field_order_hash.hash(state);
+                hash_fields(recursive, field_layouts, state);
+            }
+            Layout::Boxed(inner) => {
+                state.write_u8(2);
+                hash_layout(interner, recursive, inner, state);
+            }
+            Layout::Union(union_layout) => {
+                state.write_u8(3);
+
+                let is_recursive = !matches!(union_layout, UnionLayout::NonRecursive(_));
+                if is_recursive {
+                    recursive.push(layout);
+                }
+
+                match union_layout {
+                    UnionLayout::NonRecursive(tags) | UnionLayout::Recursive(tags) => {
+                        (is_recursive as u8, tags.len()).hash(state);
+                        for fields in tags.iter() {
+                            hash_fields(recursive, fields, state);
+                        }
+                    }
+                    UnionLayout::NonNullableUnwrapped(fields) => {
+                        state.write_u8(2);
+                        hash_fields(recursive, fields, state);
+                    }
+                    UnionLayout::NullableWrapped {
+                        nullable_id,
+                        other_tags,
+                    } => {
+                        (3u8, nullable_id, other_tags.len()).hash(state);
+                        for fields in other_tags.iter() {
+                            hash_fields(recursive, fields, state);
+                        }
+                    }
+                    UnionLayout::NullableUnwrapped {
+                        nullable_id,
+                        other_fields,
+                    } => {
+                        (4u8, nullable_id).hash(state);
+                        hash_fields(recursive, other_fields, state);
+                    }
+                }
+
+                if is_recursive {
+                    recursive.pop();
+                }
+            }
+            Layout::LambdaSet(LambdaSet {
+                args,
+                ret,
+                set,
+                representation,
+                full_layout: _,
+            }) => {
+                state.write_u8(4);
+                hash_fields(recursive, args, state);
+                hash_layout(interner, recursive, ret, state);
+                set.len().hash(state);
+                for (function, captures) in set.iter() {
+                    function.hash(state);
+                    hash_fields(recursive, captures, state);
+                }
+                hash_layout(interner, recursive, representation, state);
+            }
+            Layout::RecursivePointer(union) => {
+                state.write_u8(5);
+                let depth = recursive.iter().rev().position(|outer| *outer == union);
+                depth.hash(state);
+            }
+        }
+    }
+}
+
 pub mod dbg {
     use roc_module::symbol::Symbol;
 
@@ -1694,3 +1879,92 @@ mod insert_recursive_layout {
         assert_eq!(in1, in2);
     }
 }
+
+#[cfg(test)]
+mod content_hash_test {
+    use bumpalo::Bump;
+    use roc_target::TargetInfo;
+
+    use crate::layout::{Builtin, Layout, UnionLayout};
+
+    use super::{InLayout, LayoutInterner, STLayoutInterner};
+
+    const TARGET_INFO: TargetInfo = TargetInfo::default_x86_64();
+
+    /// `Tree : [Node Str (List Tree), Leaf I64]`
+    fn tree<'a>(arena: &'a Bump, interner: &mut STLayoutInterner<'a>) -> InLayout<'a> {
+        let children = interner.insert(Layout::Builtin(Builtin::List(Layout::NAKED_RECURSIVE_PTR)));
+        let layout = Layout::Union(UnionLayout::Recursive(arena.alloc([
+            &*arena.alloc([Layout::STR, children]) as &[_],
+            &*arena.alloc([Layout::I64]),
+        ])));
+
+        interner.insert_recursive(arena, layout)
+    }
+
+    #[test]
+    fn same_in_any_interning_order() {
+        let arena = Bump::new();
+
+        let mut first = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let tree1 = tree(&arena, &mut first);
+        let pair1 = first.insert(Layout::struct_no_name_order(
+            arena.alloc([Layout::STR, tree1]),
+        ));
+
+        // other layouts first, so that the tree is interned at different indices
+        let mut second = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        second.insert(Layout::struct_no_name_order(
+            arena.alloc([Layout::U8, Layout::F32]),
+        ));
+        second.insert(Layout::Builtin(Builtin::List(Layout::DEC)));
+        let tree2 = tree(&arena, &mut second);
+        let pair2 = second.insert(Layout::struct_no_name_order(
+            arena.alloc([Layout::STR, tree2]),
+        ));
+
+        assert_ne!(tree1, tree2);
+        assert_eq!(first.content_hash(tree1), second.content_hash(tree2));
+        assert_eq!(first.content_hash(pair1), second.content_hash(pair2));
+        assert_ne!(first.content_hash(tree1), first.content_hash(pair1));
+    }
+}
+
+#[cfg(test)]
+mod safe_to_memcpy {
+    use bumpalo::Bump;
+    use roc_target::TargetInfo;
+
+    use crate::layout::Layout;
+
+    use super::{InLayout, LayoutInterner, STLayoutInterner};
+
+    const TARGET_INFO: TargetInfo = TargetInfo::default_x86_64();
+
+    /// A struct of two of the struct one level down, `depth` times. Without remembering what
+    /// each level is, asking about the outermost one would ask about `leaf` 2^depth times.
+    fn nested_pairs<'a>(
+        arena: &'a Bump,
+        interner: &mut STLayoutInterner<'a>,
+        leaf: InLayout<'a>,
+        depth: usize,
+    ) -> InLayout<'a> {
+        (0..depth).fold(leaf, |inner, _| {
+            interner.insert(Layout::struct_no_name_order(arena.alloc([inner, inner])))
+        })
+    }
+
+    #[test]
+    fn deeply_nested_struct_is_quick() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+
+        let flat = nested_pairs(&arena, &mut interner, Layout::I64, 64);
+        let with_str = nested_pairs(&arena, &mut interner, Layout::STR, 64);
+
+        assert!(interner.safe_to_memcpy(flat));
+        assert!(!interner.safe_to_memcpy(with_str));
+        // asked again, from the cache
+        assert!(interner.safe_to_memcpy(flat));
+    }
+}
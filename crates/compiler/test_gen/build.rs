@@ -7,6 +7,7 @@ use std::path::PathBuf;
 use wasi_libc_sys::{WASI_COMPILER_RT_PATH, WASI_LIBC_PATH};
 
 const PLATFORM_FILENAME: &str = "wasm_test_platform";
+const DEV_PLATFORM_FILENAME: &str = "dev_test_platform";
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
@@ -14,6 +15,9 @@ fn main() {
         build_wasm_test_host();
         build_wasm_linking_test_host();
     }
+    if feature_is_enabled("gen-dev") {
+        build_dev_test_host();
+    }
 }
 
 const fn object_file_extension() -> &'static str {
@@ -135,6 +139,30 @@ fn build_wasm_platform(out_dir: &str, source_path: &str) -> PathBuf {
     outfile
 }
 
+/// The dev backend's refcount tests link the same C platform natively,
+/// so that they can track allocations the way the wasm tests do.
+fn build_dev_test_host() {
+    let mut source_path = PathBuf::new()
+        .join("src")
+        .join("helpers")
+        .join(PLATFORM_FILENAME);
+    source_path.set_extension("c");
+    println!("cargo:rerun-if-changed={}", source_path.to_str().unwrap());
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    // Should manually match helpers/dev.rs
+    let mut outfile = PathBuf::from(&out_dir).join(DEV_PLATFORM_FILENAME);
+    outfile.set_extension("o");
+
+    run_zig(&[
+        "build-obj",
+        "-lc",
+        "-fPIC",
+        source_path.to_str().unwrap(),
+        &format!("-femit-bin={}", outfile.to_str().unwrap()),
+    ]);
+}
+
 fn feature_is_enabled(feature_name: &str) -> bool {
     let cargo_env_var = format!(
         "CARGO_FEATURE_{}",
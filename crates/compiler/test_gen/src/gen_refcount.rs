@@ -1,5 +1,11 @@
 #[cfg(feature = "gen-wasm")]
-use crate::helpers::{wasm::assert_refcounts, RefCount::*};
+use crate::helpers::wasm::assert_refcounts;
+
+#[cfg(feature = "gen-dev")]
+use crate::helpers::dev::assert_refcounts;
+
+#[cfg(any(feature = "gen-wasm", feature = "gen-dev"))]
+use crate::helpers::RefCount::*;
 
 #[allow(unused_imports)]
 use indoc::indoc;
@@ -123,6 +129,52 @@ fn list_str_dealloc() {
     );
 }
 
+#[test]
+#[cfg(any(feature = "gen-wasm", feature = "gen-dev"))]
+fn list_str_slice_dealloc() {
+    assert_refcounts!(
+        indoc!(
+            r#"
+                s = Str.concat "A long enough string " "to be heap-allocated"
+                list = [s, s, s]
+                slice = List.dropFirst list
+                List.len slice
+            "#
+        ),
+        usize,
+        &[
+            Deallocated, // s
+            Deallocated  // list, through the slice
+        ]
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-wasm", feature = "gen-dev"))]
+fn list_str_middle_slice_dealloc() {
+    // The elements on either side of the slice are released when it is made,
+    // so freeing the source list through the slice must not leak them
+    assert_refcounts!(
+        indoc!(
+            r#"
+                a = Str.concat "A long enough string " "to be heap-allocated"
+                b = Str.concat "Another long enough string " "to be heap-allocated"
+                c = Str.concat "Yet another long enough string " "to be heap-allocated"
+                list = [a, b, c]
+                slice = List.sublist list { start: 1, len: 1 }
+                List.len slice
+            "#
+        ),
+        usize,
+        &[
+            Deallocated, // a
+            Deallocated, // b
+            Deallocated, // c
+            Deallocated  // list, through the slice
+        ]
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-wasm"))]
 fn struct_inc() {
@@ -454,6 +506,27 @@ fn union_linked_list_long_dec() {
     );
 }
 
+#[test]
+#[cfg(any(feature = "gen-wasm", feature = "gen-dev"))]
+fn boxed_int_dec() {
+    assert_refcounts!(
+        indoc!(
+            r#"
+                b = Box.box 42i64
+
+                if Bool.false then
+                    ReturnTheBox b
+                else
+                    DeallocateEverything
+            "#
+        ),
+        (Pointer, u8),
+        &[
+            Deallocated, // b
+        ]
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-wasm"))]
 fn boxed_str_inc() {
@@ -496,3 +569,27 @@ fn boxed_str_dec() {
         ]
     );
 }
+
+#[test]
+#[cfg(feature = "gen-wasm")]
+fn refcount_trace_is_called() {
+    let traces = crate::helpers::wasm::refcount_traces_help::<usize>(
+        indoc!(
+            r#"
+                s = Str.concat "A long enough string " "to be heap-allocated"
+                list = [s, s, s]
+                List.len list
+            "#
+        ),
+        std::marker::PhantomData,
+    )
+    .unwrap();
+
+    // the list's own refcount, and each string's
+    assert!(
+        traces.iter().any(|name| name == "Dec List Str"),
+        "{:?}",
+        traces
+    );
+    assert!(traces.iter().any(|name| name == "Dec Str"), "{:?}", traces);
+}
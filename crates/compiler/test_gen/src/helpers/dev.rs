@@ -1,7 +1,8 @@
+use super::RefCount;
 use libloading::Library;
 use roc_build::link::{link, LinkType};
 use roc_builtins::bitcode;
-use roc_load::{EntryPoint, ExecutionMode, LoadConfig, Threading};
+use roc_load::{EntryPoint, ExecutionMode, LoadConfig, MonoPasses, Threading};
 use roc_mono::ir::SingleEntryPoint;
 use roc_packaging::cache::RocCacheDir;
 use roc_region::all::LineInfo;
@@ -13,6 +14,15 @@ use roc_collections::all::MutMap;
 #[allow(unused_imports)]
 use roc_mono::ir::pretty_print_ir_symbols;
 
+const INIT_REFCOUNT_NAME: &str = "init_refcount_test";
+
+macro_rules! host_object_path {
+    () => {
+        // Should manually match build.rs
+        concat!(env!("OUT_DIR"), "/dev_test_platform.o")
+    };
+}
+
 #[allow(dead_code)]
 fn promote_expr_to_module(src: &str) -> String {
     let mut buffer = String::from("app \"test\" provides [main] to \"./platform\"\n\nmain =\n");
@@ -33,6 +43,7 @@ pub fn helper(
     src: &str,
     _leak: bool,
     lazy_literals: bool,
+    track_refcounts: bool,
 ) -> (String, Vec<roc_problem::can::Problem>, Library) {
     use std::path::PathBuf;
 
@@ -58,6 +69,7 @@ pub fn helper(
         palette: roc_reporting::report::DEFAULT_PALETTE,
         threading: Threading::Single,
         exec_mode: ExecutionMode::Executable,
+        mono_passes: MonoPasses::All,
     };
     let loaded = roc_load::load_and_monomorphize_from_str(
         arena,
@@ -190,7 +202,9 @@ pub fn helper(
         module_id,
         exposed_to_host: exposed_to_host.values.keys().copied().collect(),
         lazy_literals,
-        generate_allocators: true, // Needed for testing, since we don't have a platform
+        // Needed for testing, since we don't have a platform.
+        // Refcount tests link the C test platform instead, which tracks every allocation.
+        generate_allocators: !track_refcounts,
     };
 
     let target = target_lexicon::Triple::host();
@@ -214,18 +228,18 @@ pub fn helper(
         std::fs::copy(&app_o_file, "/tmp/app.o").unwrap();
     }
 
-    let (mut child, dylib_path) = link(
-        &target,
-        app_o_file.clone(),
-        // Long term we probably want a smarter way to link in zig builtins.
-        // With the current method all methods are kept and it adds about 100k to all outputs.
-        &[
-            app_o_file.to_str().unwrap(),
-            builtins_host_tempfile.path().to_str().unwrap(),
-        ],
-        LinkType::Dylib,
-    )
-    .expect("failed to link dynamic library");
+    // Long term we probably want a smarter way to link in zig builtins.
+    // With the current method all methods are kept and it adds about 100k to all outputs.
+    let mut input_paths = vec![
+        app_o_file.to_str().unwrap(),
+        builtins_host_tempfile.path().to_str().unwrap(),
+    ];
+    if track_refcounts {
+        input_paths.push(host_object_path!());
+    }
+
+    let (mut child, dylib_path) = link(&target, app_o_file.clone(), &input_paths, LinkType::Dylib)
+        .expect("failed to link dynamic library");
 
     child.wait().unwrap();
 
@@ -269,7 +283,7 @@ macro_rules! assert_evals_to {
 
         let arena = Bump::new();
         let (main_fn_name, errors, lib) =
-            $crate::helpers::dev::helper(&arena, $src, $leak, $lazy_literals);
+            $crate::helpers::dev::helper(&arena, $src, $leak, $lazy_literals, false);
 
         let transform = |success| {
             let expected = $expected;
@@ -281,5 +295,83 @@ macro_rules! assert_evals_to {
     };
 }
 
+/// Ask the test platform to track `num_refcounts` allocations.
+/// Returns the vector it writes their refcount pointers into: a length, then the pointers.
+#[allow(dead_code)]
+pub fn init_refcount_test(lib: &Library, num_refcounts: usize) -> *const usize {
+    unsafe {
+        let init: libloading::Symbol<unsafe extern "C" fn(usize) -> *const usize> =
+            lib.get(INIT_REFCOUNT_NAME.as_bytes()).unwrap_or_else(|_| {
+                panic!("The test platform does not define `{}`", INIT_REFCOUNT_NAME)
+            });
+
+        init(num_refcounts)
+    }
+}
+
+#[allow(dead_code)]
+pub fn read_refcounts(
+    refcount_vector: *const usize,
+    num_refcounts: usize,
+) -> Result<Vec<RefCount>, String> {
+    // The test platform allocated room for `num_refcounts` pointers after the length,
+    // and nulls out the pointer of every allocation it frees.
+    let actual_num_refcounts = unsafe { *refcount_vector };
+    if actual_num_refcounts != num_refcounts {
+        return Err(format!(
+            "Expected {} refcounts but got {}",
+            num_refcounts, actual_num_refcounts
+        ));
+    }
+
+    let mut refcounts = Vec::with_capacity(num_refcounts);
+    for index in 0..num_refcounts {
+        let rc_ptr = unsafe { *refcount_vector.add(1 + index) } as *const isize;
+        let rc = if rc_ptr.is_null() {
+            RefCount::Deallocated
+        } else {
+            // Decode the refcount from the negative number format
+            let rc_encoded = unsafe { *rc_ptr };
+            if rc_encoded == 0 {
+                RefCount::Constant
+            } else {
+                let rc = rc_encoded - isize::MIN + 1;
+                RefCount::Live(rc as u32)
+            }
+        };
+        refcounts.push(rc);
+    }
+    Ok(refcounts)
+}
+
+#[allow(unused_macros)]
+macro_rules! assert_refcounts {
+    // We need the result type to call `main`, even though we ignore the value!
+    ($src: expr, $ty: ty, $expected_refcounts: expr) => {{
+        use bumpalo::Bump;
+        use roc_gen_dev::run_jit_function_raw;
+
+        let arena = Bump::new();
+        let num_refcounts = $expected_refcounts.len();
+        let (main_fn_name, errors, lib) =
+            $crate::helpers::dev::helper(&arena, $src, false, false, true);
+
+        let refcount_vector = $crate::helpers::dev::init_refcount_test(&lib, num_refcounts);
+
+        // Dropping the result would change the refcounts we are about to read
+        run_jit_function_raw!(lib, main_fn_name, $ty, std::mem::forget, errors);
+
+        match $crate::helpers::dev::read_refcounts(refcount_vector, num_refcounts) {
+            Err(msg) => panic!("{:?}", msg),
+            Ok(actual_refcounts) => {
+                assert_eq!(&actual_refcounts, $expected_refcounts)
+            }
+        }
+    }};
+}
+
 #[allow(unused_imports)]
 pub(crate) use assert_evals_to;
+
+#[allow(unused_imports)]
+pub(crate) use assert_refcounts;
@@ -8,7 +8,9 @@ use roc_collections::all::MutSet;
 use roc_command_utils::zig;
 use roc_gen_llvm::llvm::externs::add_default_roc_externs;
 use roc_gen_llvm::{llvm::build::LlvmBackendMode, run_roc::RocCallResult};
-use roc_load::{EntryPoint, ExecutionMode, LoadConfig, LoadMonomorphizedError, Threading};
+use roc_load::{
+    EntryPoint, ExecutionMode, LoadConfig, LoadMonomorphizedError, MonoPasses, Threading,
+};
 use roc_mono::ir::{CrashTag, OptLevel, SingleEntryPoint};
 use roc_packaging::cache::RocCacheDir;
 use roc_region::all::LineInfo;
@@ -74,6 +76,7 @@ fn create_llvm_module<'a>(
         palette: DEFAULT_PALETTE,
         threading: Threading::Single,
         exec_mode: ExecutionMode::Executable,
+        mono_passes: MonoPasses::All,
     };
     let loaded = roc_load::load_and_monomorphize_from_str(
         arena,
@@ -226,6 +229,7 @@ fn create_llvm_module<'a>(
         mode: config.mode,
         // important! we don't want any procedures to get the C calling convention
         exposed_to_host: MutSet::default(),
+        expect_sources: None,
     };
 
     // strip Zig debug stuff
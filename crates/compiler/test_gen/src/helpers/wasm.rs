@@ -4,7 +4,7 @@ use bumpalo::Bump;
 use roc_collections::all::MutSet;
 use roc_gen_wasm::wasm32_result::Wasm32Result;
 use roc_gen_wasm::DEBUG_SETTINGS;
-use roc_load::{ExecutionMode, LoadConfig, Threading};
+use roc_load::{ExecutionMode, LoadConfig, MonoPasses, Threading};
 use roc_packaging::cache::RocCacheDir;
 use roc_reporting::report::DEFAULT_PALETTE_HTML;
 use roc_std::RocStr;
@@ -51,12 +51,18 @@ pub fn compile_to_wasm_bytes<'a, T: Wasm32Result>(
     arena: &'a bumpalo::Bump,
     src: &str,
     test_wrapper_type_info: PhantomData<T>,
+    trace_refcounts: bool,
 ) -> Vec<u8> {
     let platform_bytes = include_bytes!(host_bytes_path!());
     println!("Loading test host {}", host_bytes_path!());
 
-    let compiled_bytes =
-        compile_roc_to_wasm_bytes(arena, platform_bytes, src, test_wrapper_type_info);
+    let compiled_bytes = compile_roc_to_wasm_bytes(
+        arena,
+        platform_bytes,
+        src,
+        test_wrapper_type_info,
+        trace_refcounts,
+    );
 
     if write_final_wasm() {
         let build_dir_hash = crate::helpers::src_hash(src);
@@ -71,6 +77,7 @@ fn compile_roc_to_wasm_bytes<'a, T: Wasm32Result>(
     host_bytes: &[u8],
     src: &str,
     _test_wrapper_type_info: PhantomData<T>,
+    trace_refcounts: bool,
 ) -> Vec<u8> {
     let filename = PathBuf::from("Test.roc");
     let src_dir = PathBuf::from("fake/test/path");
@@ -92,6 +99,7 @@ fn compile_roc_to_wasm_bytes<'a, T: Wasm32Result>(
         palette: DEFAULT_PALETTE_HTML,
         threading: Threading::Single,
         exec_mode: ExecutionMode::Executable,
+        mono_passes: MonoPasses::All,
     };
     let loaded = roc_load::load_and_monomorphize_from_str(
         arena,
@@ -127,6 +135,7 @@ fn compile_roc_to_wasm_bytes<'a, T: Wasm32Result>(
         module_id,
         exposed_to_host,
         stack_bytes: roc_gen_wasm::Env::DEFAULT_STACK_BYTES,
+        trace_refcounts,
     };
 
     let host_module = roc_gen_wasm::parse_host(env.arena, host_bytes).unwrap_or_else(|e| {
@@ -179,13 +188,15 @@ where
 {
     let arena = bumpalo::Bump::new();
 
-    let wasm_bytes = crate::helpers::wasm::compile_to_wasm_bytes(&arena, src, phantom);
+    let wasm_bytes = crate::helpers::wasm::compile_to_wasm_bytes(&arena, src, phantom, false);
 
     run_wasm_test_bytes::<T>(TEST_WRAPPER_NAME, wasm_bytes)
 }
 
 struct TestDispatcher<'a> {
     wasi: WasiDispatcher<'a>,
+    /// The name passed to each call of the test host's `roc_refcount_trace`
+    refcount_traces: Vec<String>,
 }
 
 impl<'a> ImportDispatcher for TestDispatcher<'a> {
@@ -208,6 +219,11 @@ impl<'a> ImportDispatcher for TestDispatcher<'a> {
                 tag => format!(r#"Got an invald panic tag: "{}""#, tag),
             };
             panic!("{}", msg)
+        } else if module_name == "env" && function_name == "send_refcount_trace_to_rust" {
+            let name_ptr = arguments[0].expect_i32().unwrap();
+            let name = RocStr::decode(memory, name_ptr as _);
+            self.refcount_traces.push(name.to_string());
+            None
         } else {
             panic!(
                 "TestDispatcher does not implement {}.{}",
@@ -241,6 +257,7 @@ where
 {
     let dispatcher = TestDispatcher {
         wasi: wasi::WasiDispatcher::default(),
+        refcount_traces: Vec::new(),
     };
     let is_debug_mode = roc_debug_flags::dbg_set!(roc_debug_flags::ROC_LOG_WASM_INTERP);
     let mut inst = Instance::for_module(&arena, &module, dispatcher, is_debug_mode)?;
@@ -262,7 +279,7 @@ where
 {
     let arena = bumpalo::Bump::new();
 
-    let wasm_bytes = crate::helpers::wasm::compile_to_wasm_bytes(&arena, src, phantom);
+    let wasm_bytes = crate::helpers::wasm::compile_to_wasm_bytes(&arena, src, phantom, false);
 
     let require_relocatable = false;
     let module = WasmModule::preload(&arena, &wasm_bytes, require_relocatable)
@@ -270,6 +287,7 @@ where
 
     let dispatcher = TestDispatcher {
         wasi: wasi::WasiDispatcher::default(),
+        refcount_traces: Vec::new(),
     };
     let is_debug_mode = roc_debug_flags::dbg_set!(roc_debug_flags::ROC_LOG_WASM_INTERP);
     let mut inst = Instance::for_module(&arena, &module, dispatcher, is_debug_mode)?;
@@ -320,6 +338,34 @@ where
     Ok(refcounts)
 }
 
+/// Run `src` built with refcount tracing, and return the name of each refcount change it made
+#[allow(dead_code)]
+pub fn refcount_traces_help<T>(src: &str, phantom: PhantomData<T>) -> Result<Vec<String>, String>
+where
+    T: FromWasm32Memory + Wasm32Result,
+{
+    let arena = bumpalo::Bump::new();
+
+    let wasm_bytes = crate::helpers::wasm::compile_to_wasm_bytes(&arena, src, phantom, true);
+
+    let require_relocatable = false;
+    let module = WasmModule::preload(&arena, &wasm_bytes, require_relocatable)
+        .map_err(|e| format!("{:?}", e))?;
+
+    let dispatcher = TestDispatcher {
+        wasi: wasi::WasiDispatcher::default(),
+        refcount_traces: Vec::new(),
+    };
+    let is_debug_mode = roc_debug_flags::dbg_set!(roc_debug_flags::ROC_LOG_WASM_INTERP);
+    let mut inst = Instance::for_module(&arena, &module, dispatcher, is_debug_mode)?;
+
+    // Run the test, ignoring the result
+    inst.call_export(TEST_WRAPPER_NAME, [])?
+        .ok_or_else(|| format!("No return address from {}", TEST_WRAPPER_NAME))?;
+
+    Ok(inst.import_dispatcher.refcount_traces)
+}
+
 fn read_i32(memory: &[u8], addr: i32) -> i32 {
     let index = addr as usize;
     let mut bytes = [0; 4];
@@ -124,16 +124,35 @@ void roc_dealloc(void *ptr, unsigned int alignment)
 
 //--------------------------
 
+// The dev backend's refcount tests link this file natively, where there is no Rust import to call
+#ifdef __wasm__
 extern void send_panic_msg_to_rust(void* msg, uint32_t tag_id);
+#endif
 
 void roc_panic(void* msg, unsigned int tag_id)
 {
+#ifdef __wasm__
     send_panic_msg_to_rust(msg, tag_id);
+#else
+    fprintf(stderr, "Roc panicked with tag %u\n", tag_id);
+#endif
     exit(101);
 }
 
 //--------------------------
 
+#ifdef __wasm__
+extern void send_refcount_trace_to_rust(void *name, size_t *rc_ptr);
+
+// Only called by apps built with refcount tracing. See `CodeGenHelp::with_refcount_trace`.
+void roc_refcount_trace(void *name, size_t *rc_ptr)
+{
+    send_refcount_trace_to_rust(name, rc_ptr);
+}
+#endif
+
+//--------------------------
+
 void roc_memcpy(void *dest, const void *src, size_t n)
 {
     memcpy(dest, src, n);
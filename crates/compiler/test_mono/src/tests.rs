@@ -18,8 +18,10 @@ use roc_collections::all::MutMap;
 use roc_load::ExecutionMode;
 use roc_load::LoadConfig;
 use roc_load::LoadMonomorphizedError;
+use roc_load::MonoPasses;
 use roc_load::Threading;
 use roc_module::symbol::Interns;
+use roc_module::symbol::ModuleId;
 use roc_module::symbol::Symbol;
 use roc_mono::ir::Proc;
 use roc_mono::ir::ProcLayout;
@@ -76,12 +78,31 @@ fn promote_expr_to_module(src: &str) -> String {
     buffer
 }
 
-fn compiles_to_ir(test_name: &str, src: &str, mode: &str, no_check: bool) {
+/// Check the IR for `src` against the snapshot in `generated/`, or, if `contains` is not
+/// empty, only check that the IR contains every one of those substrings.
+///
+/// With `derive` set, also check whether any of the procs is an implementation that the
+/// compiler derived for an ability. With `proc_count` set, also check how many procs there are.
+///
+/// With `type_error` set, the program is expected not to typecheck: one of its type errors
+/// must contain that substring, and there is no IR to check.
+#[allow(clippy::too_many_arguments)]
+fn compiles_to_ir(
+    test_name: &str,
+    src: &str,
+    mode: &str,
+    no_check: bool,
+    optimized: bool,
+    derive: Option<bool>,
+    proc_count: Option<usize>,
+    type_error: Option<&str>,
+    contains: &[&str],
+) {
     use roc_packaging::cache::RocCacheDir;
     use std::path::PathBuf;
 
     let exec_mode = match mode {
-        "exec" => ExecutionMode::Executable,
+        "exec" | "json" => ExecutionMode::Executable,
         "test" => ExecutionMode::Test,
         _ => panic!("Invalid test_mono exec mode {mode}"),
     };
@@ -108,6 +129,12 @@ fn compiles_to_ir(test_name: &str, src: &str, mode: &str, no_check: bool) {
         render: roc_reporting::report::RenderTarget::Generic,
         palette: roc_reporting::report::DEFAULT_PALETTE,
         exec_mode,
+        // Without the passes after specialization, the snapshot is of the raw specialized procs
+        mono_passes: if optimized {
+            MonoPasses::All
+        } else {
+            MonoPasses::SpecializationOnly
+        },
     };
     let loaded = roc_load::load_and_monomorphize_from_str(
         arena,
@@ -146,6 +173,19 @@ fn compiles_to_ir(test_name: &str, src: &str, mode: &str, no_check: bool) {
         println!("Ignoring {} canonicalization problems", can_problems.len());
     }
 
+    if let Some(expected) = type_error {
+        let (module_path, src) = &loaded.sources[&home];
+        let reports = render_type_problems(home, &interns, module_path, src, type_problems);
+
+        assert!(
+            reports.iter().any(|report| report.contains(expected)),
+            "expected a type error containing {expected:?}, but the type errors were:\n\n{}",
+            reports.join("\n")
+        );
+
+        return;
+    }
+
     assert!(type_problems.is_empty());
 
     let main_fn_symbol = exposed_to_host.values.keys().copied().next();
@@ -154,7 +194,56 @@ fn compiles_to_ir(test_name: &str, src: &str, mode: &str, no_check: bool) {
         check_procedures(arena, &interns, &mut layout_interner, &procedures);
     }
 
-    verify_procedures(test_name, layout_interner, procedures, main_fn_symbol);
+    if let Some(derive) = derive {
+        check_derived_procedures(&procedures, derive);
+    }
+
+    if let Some(proc_count) = proc_count {
+        assert_eq!(
+            procedures.len(),
+            proc_count,
+            "expected {proc_count} procs in the IR, but there are {}",
+            procedures.len()
+        );
+    }
+
+    if mode == "json" {
+        verify_procedures_json(test_name, layout_interner, procedures, contains);
+    } else {
+        verify_procedures(
+            test_name,
+            layout_interner,
+            procedures,
+            main_fn_symbol,
+            contains,
+        );
+    }
+}
+
+fn render_type_problems(
+    home: ModuleId,
+    interns: &Interns,
+    module_path: &std::path::Path,
+    src: &str,
+    type_problems: Vec<roc_solve_problem::TypeError>,
+) -> Vec<String> {
+    use roc_region::all::LineInfo;
+    use roc_reporting::report::{type_problem, RocDocAllocator};
+
+    let line_info = LineInfo::new(src);
+    let src_lines: Vec<&str> = src.split('\n').collect();
+    let alloc = RocDocAllocator::new(&src_lines, home, interns);
+
+    type_problems
+        .into_iter()
+        .filter_map(|problem| {
+            let report = type_problem(&alloc, &line_info, module_path.to_path_buf(), problem)?;
+            let mut buf = String::new();
+            report.render_ci(&mut buf, &alloc);
+
+            Some(buf)
+        })
+        .collect()
 }
 
 fn check_procedures<'a>(
@@ -172,11 +261,27 @@ fn check_procedures<'a>(
     panic!("IR problems found:\n{formatted}");
 }
 
+/// Derived implementations are specialized into procs of their own module, whatever
+/// module asked for them.
+fn check_derived_procedures(procedures: &MutMap<(Symbol, ProcLayout), Proc>, derive: bool) {
+    let derived = procedures
+        .keys()
+        .filter(|(symbol, _)| symbol.module_id() == ModuleId::DERIVED_SYNTH)
+        .count();
+
+    if derive {
+        assert!(derived > 0, "expected a derived implementation in the IR");
+    } else {
+        assert_eq!(derived, 0, "expected no derived implementations in the IR");
+    }
+}
+
 fn verify_procedures<'a>(
     test_name: &str,
     interner: STLayoutInterner<'a>,
     procedures: MutMap<(Symbol, ProcLayout<'a>), Proc<'a>>,
     opt_main_fn_symbol: Option<Symbol>,
+    contains: &[&str],
 ) {
     let mut procs_string = procedures
         .values()
@@ -199,7 +304,77 @@ fn verify_procedures<'a>(
 
     let result = procs_string.join("\n");
 
-    let path = format!("generated/{}.txt", test_name);
+    verify_output(format!("generated/{}.txt", test_name), result, contains);
+}
+
+/// Like `verify_procedures`, but the procs are written as JSON for tools that read the IR.
+/// Each proc has its name, its arguments and return layout, and its pretty-printed body.
+fn verify_procedures_json<'a>(
+    test_name: &str,
+    interner: STLayoutInterner<'a>,
+    procedures: MutMap<(Symbol, ProcLayout<'a>), Proc<'a>>,
+    contains: &[&str],
+) {
+    use roc_mono::ir::symbol_to_doc_string;
+    use roc_mono::layout::LayoutInterner;
+    use serde_json::{json, Value};
+
+    let mut procs = procedures
+        .values()
+        .map(|proc| {
+            let args = proc
+                .args
+                .iter()
+                .map(|(layout, symbol)| {
+                    json!({
+                        "symbol": symbol_to_doc_string(*symbol, false),
+                        "layout": interner.dbg(*layout),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            json!({
+                "name": symbol_to_doc_string(proc.name.name(), false),
+                "args": args,
+                "ret_layout": interner.dbg(proc.ret_layout),
+                "body": proc.body.to_pretty(&interner, 200, false),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    procs.sort_by_key(|proc| (proc["name"].to_string(), proc.to_string()));
+
+    let result = serde_json::to_string_pretty(&procs).unwrap();
+
+    // Tools only get the names back if the JSON reads back as it was written
+    let names = |procs: &[Value]| {
+        procs
+            .iter()
+            .map(|proc| proc["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let read_back: Vec<Value> = serde_json::from_str(&result).unwrap();
+    assert_eq!(names(&read_back), names(&procs));
+
+    verify_output(format!("generated/{}.json", test_name), result, contains);
+}
+
+/// Check that the output contains each of `contains`, or if there are none,
+/// that it matches the snapshot at `path`.
+fn verify_output(path: String, result: String, contains: &[&str]) {
+    if !contains.is_empty() {
+        for needle in contains {
+            assert!(
+                result.contains(needle),
+                "The IR does not contain {:?}:\n\n{}",
+                needle,
+                result
+            );
+        }
+
+        return;
+    }
+
     std::fs::create_dir_all("generated").unwrap();
     std::fs::write(&path, result).unwrap();
 
@@ -248,6 +423,15 @@ fn ir_int_add() {
     "#
 }
 
+#[mono_test(optimized = false)]
+fn ir_int_add_unoptimized() {
+    // Like `ir_int_add`, but without the passes after specialization, so `x` is never decremented
+    r#"
+    x = [1,2]
+    5 + 4 + 3 + List.len x
+    "#
+}
+
 #[mono_test]
 fn ir_assignment() {
     r#"
@@ -398,6 +582,16 @@ fn list_append_closure() {
     "#
 }
 
+#[mono_test(
+    contains = "lowlevel ListReserve",
+    contains = "lowlevel ListAppendUnsafe"
+)]
+fn list_append_reserves_then_appends() {
+    r#"
+    List.append [1, 2] 3
+    "#
+}
+
 #[mono_test]
 fn list_append() {
     // TODO this leaks at the moment
@@ -2148,6 +2342,32 @@ fn function_pointer_lambda_set() {
     )
 }
 
+#[mono_test(proc_count = 3, contains = "procedure Test.2 (Test.3):")]
+fn function_pointer_lambda_set_proc_count() {
+    indoc!(
+        r#"
+        app "test" provides [main] to "./platform"
+
+        number = \{} -> 1u64
+
+        parse = \parser -> parser {}
+
+        main =
+            parser = number
+            parse parser
+        "#
+    )
+}
+
+#[mono_test(type_error = "This 2nd argument to `add` has an unexpected type")]
+fn adding_a_string_to_a_number() {
+    indoc!(
+        r#"
+        1 + "one"
+        "#
+    )
+}
+
 #[mono_test]
 fn anonymous_closure_lifted_to_named_issue_2403() {
     indoc!(
@@ -2768,3 +2988,59 @@ fn inline_return_joinpoints_in_union_lambda_set() {
         "#
     )
 }
+
+#[mono_test(derive = true, contains = "procedure #Derived")]
+fn encode_opaque_with_derived_encoding() {
+    indoc!(
+        r#"
+        app "test"
+            imports [Json]
+            provides [main] to "./platform"
+
+        HelloWorld := { a: Str } has [Encoding]
+
+        main =
+            result = Str.fromUtf8 (Encode.toBytes (@HelloWorld { a: "Hello, World!" }) Json.toUtf8)
+            when result is
+                Ok s -> s
+                _ -> "<bad>"
+        "#
+    )
+}
+
+#[mono_test(derive = false, contains = "procedure Test.0 ()")]
+fn encode_opaque_with_explicit_encoding() {
+    indoc!(
+        r#"
+        app "test"
+            imports [Encode, Json]
+            provides [main] to "./platform"
+
+        HelloWorld := { a: Str } has [Encoding {toEncoder}]
+
+        toEncoder = \@HelloWorld { a } ->
+            Encode.custom \bytes, fmt ->
+                bytes
+                    |> Encode.appendWith (Encode.string a) fmt
+
+        main =
+            result = Str.fromUtf8 (Encode.toBytes (@HelloWorld { a: "Hello, World!" }) Json.toUtf8)
+            when result is
+                Ok s -> s
+                _ -> "<bad>"
+        "#
+    )
+}
+
+#[mono_test(
+    mode = "json",
+    contains = r#""name": "List.6""#,
+    contains = r#""name": "Test.0""#,
+    contains = r#""ret_layout": "U64""#
+)]
+fn ir_json_dump() {
+    r#"
+    x = [1,2]
+    List.len x
+    "#
+}
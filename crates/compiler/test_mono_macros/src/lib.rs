@@ -7,21 +7,56 @@ use quote::quote;
 #[proc_macro_attribute]
 pub fn mono_test(args: TokenStream, item: TokenStream) -> TokenStream {
     let mut no_check = false;
+    let mut optimized = true;
+    let mut derive = quote!(None);
+    let mut proc_count = quote!(None);
+    let mut type_error = quote!(None);
     let mut mode = "exec".to_owned();
+    let mut contains = Vec::new();
     for arg in syn::parse_macro_input!(args as syn::AttributeArgs) {
         use syn::{Lit, Meta, MetaNameValue, NestedMeta};
-        if let NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-            path,
-            eq_token: _,
-            lit: Lit::Str(s),
-        })) = arg
-        {
-            if path.is_ident("mode") {
-                mode = s.value();
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                eq_token: _,
+                lit: Lit::Str(s),
+            })) => {
+                if path.is_ident("mode") {
+                    mode = s.value();
+                }
+                if path.is_ident("no_check") {
+                    no_check = true;
+                }
+                if path.is_ident("contains") {
+                    contains.push(s.value());
+                }
+                if path.is_ident("type_error") {
+                    let expected = s.value();
+                    type_error = quote!(Some(#expected));
+                }
             }
-            if path.is_ident("no_check") {
-                no_check = true;
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                eq_token: _,
+                lit: Lit::Bool(b),
+            })) => {
+                if path.is_ident("optimized") {
+                    optimized = b.value;
+                }
+                if path.is_ident("derive") {
+                    let derived = b.value;
+                    derive = quote!(Some(#derived));
+                }
             }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                eq_token: _,
+                lit: Lit::Int(i),
+            })) if path.is_ident("proc_count") => {
+                let count: usize = i.base10_parse().unwrap();
+                proc_count = quote!(Some(#count));
+            }
+            _ => {}
         }
     }
 
@@ -40,7 +75,7 @@ pub fn mono_test(args: TokenStream, item: TokenStream) -> TokenStream {
         #[test]
         #(#attributes)*
         #visibility fn #name(#args) {
-            compiles_to_ir(#name_str, #body, &#mode, #no_check);
+            compiles_to_ir(#name_str, #body, &#mode, #no_check, #optimized, #derive, #proc_count, #type_error, &[#(#contains),*]);
 
         }
     };
@@ -7,7 +7,7 @@ use roc_can::scope::Scope;
 use roc_collections::VecSet;
 use roc_load::docs::{DocEntry, TypeAnnotation};
 use roc_load::docs::{ModuleDocumentation, RecordField};
-use roc_load::{ExecutionMode, LoadConfig, LoadedModule, LoadingProblem, Threading};
+use roc_load::{ExecutionMode, LoadConfig, LoadedModule, LoadingProblem, MonoPasses, Threading};
 use roc_module::symbol::{Interns, Symbol};
 use roc_packaging::cache::{self, RocCacheDir};
 use roc_parse::ident::{parse_ident, Accessor, Ident};
@@ -354,6 +354,7 @@ pub fn load_module_for_docs(filename: PathBuf) -> LoadedModule {
         palette: roc_reporting::report::DEFAULT_PALETTE,
         threading: Threading::AllAvailable,
         exec_mode: ExecutionMode::Check,
+        mono_passes: MonoPasses::All,
     };
     match roc_load::load_and_typecheck(
         &arena,
@@ -1,7 +1,7 @@
 use crate::rust_glue;
 use crate::types::{Env, Types};
 use bumpalo::Bump;
-use roc_load::{ExecutionMode, LoadConfig, LoadedModule, LoadingProblem, Threading};
+use roc_load::{ExecutionMode, LoadConfig, LoadedModule, LoadingProblem, MonoPasses, Threading};
 use roc_mono::layout::GlobalLayoutInterner;
 use roc_packaging::cache::{self, RocCacheDir};
 use roc_reporting::report::{RenderTarget, DEFAULT_PALETTE};
@@ -102,6 +102,7 @@ pub fn load_types(
             palette: DEFAULT_PALETTE,
             threading,
             exec_mode: ExecutionMode::Check,
+            mono_passes: MonoPasses::All,
         },
     )
     .unwrap_or_else(|problem| match problem {
@@ -1,5 +1,6 @@
 use roc_parse::highlight::Token;
 use roc_region::all::Loc;
+use std::ops::Range;
 
 pub fn highlight_roc_code(code: &str) -> String {
     let buf = highlight(code);
@@ -13,18 +14,48 @@ pub fn highlight_roc_code_inline(code: &str) -> String {
     format!("<code>{}</code>", buf.join(""))
 }
 
+/// Like `highlight_roc_code`, but each token's span has `data-start` and `data-end`
+/// attributes with the byte range of the token in `code`, so that tools can map the
+/// markup back to the source.
+pub fn highlight_roc_code_with_ranges(code: &str) -> String {
+    let buf = highlight_help(code, true);
+
+    format!("<pre><samp>{}</samp></pre>", buf.join(""))
+}
+
+/// Like `highlight_roc_code_inline`, with the byte ranges of `highlight_roc_code_with_ranges`.
+pub fn highlight_roc_code_inline_with_ranges(code: &str) -> String {
+    let buf = highlight_help(code, true);
+
+    format!("<code>{}</code>", buf.join(""))
+}
+
 pub fn highlight(code: &str) -> Vec<String> {
+    highlight_help(code, false)
+}
+
+fn highlight_help(code: &str, with_ranges: bool) -> Vec<String> {
     let locations: Vec<Loc<Token>> = roc_parse::highlight::highlight(code);
     let mut buf: Vec<String> = Vec::new();
     let mut offset = 0;
 
     for location in locations {
-        let current_text = &code[offset..location.byte_range().end];
+        let range = location.byte_range();
+
+        // Without ranges, the whitespace before a token goes in its span. With them, it goes
+        // before the span, so that the span's text is exactly what its range says it is.
+        let current_text = if with_ranges {
+            buf = push_html(buf, &code[offset..range.start]);
+            &code[range.start..range.end]
+        } else {
+            &code[offset..range.end]
+        };
+        let range = if with_ranges { Some(range) } else { None };
 
         match location.value {
             // Comments `#` and Documentation comments `##`
             Token::LineComment | Token::DocComment => {
-                buf = push_html_span(buf, current_text, "comment");
+                buf = push_html_span(buf, current_text, "comment", range);
             }
             // Number, String, Tag, Type literals
             Token::SingleQuote
@@ -33,7 +64,7 @@ pub fn highlight(code: &str) -> Vec<String> {
             | Token::EscapedChar
             | Token::Interpolated
             | Token::Number => {
-                buf = push_html_span(buf, current_text, "literal");
+                buf = push_html_span(buf, current_text, "literal", range);
             }
             // Keywords and punctuation
             Token::Keyword
@@ -47,7 +78,7 @@ pub fn highlight(code: &str) -> Vec<String> {
             | Token::And
             | Token::AtSign
             | Token::QuestionMark => {
-                buf = push_html_span(buf, current_text, "kw");
+                buf = push_html_span(buf, current_text, "kw", range);
             }
             // Operators
             Token::Percent
@@ -67,7 +98,7 @@ pub fn highlight(code: &str) -> Vec<String> {
             | Token::Multiply
             | Token::Plus
             | Token::DoubleAnd => {
-                buf = push_html_span(buf, current_text, "op");
+                buf = push_html_span(buf, current_text, "op", range);
             }
             // Delimieters
             Token::Paren
@@ -76,15 +107,15 @@ pub fn highlight(code: &str) -> Vec<String> {
             | Token::Comma
             | Token::Bar
             | Token::Decimal => {
-                buf = push_html_span(buf, current_text, "delimeter");
+                buf = push_html_span(buf, current_text, "delimeter", range);
             }
             // Types, Tags, and Modules
             Token::UpperIdent => {
-                buf = push_html_span(buf, current_text, "upperident");
+                buf = push_html_span(buf, current_text, "upperident", range);
             }
             // Variables modules and field names
             Token::LowerIdent | Token::Underscore => {
-                buf = push_html_span(buf, current_text, "lowerident");
+                buf = push_html_span(buf, current_text, "lowerident", range);
             }
             // Anyting else that wasn't tokenised
             Token::Error | Token::Other => {
@@ -98,11 +129,22 @@ pub fn highlight(code: &str) -> Vec<String> {
     buf
 }
 
-fn push_html_span(mut buf: Vec<String>, curr: &str, class: &str) -> Vec<String> {
+fn push_html_span(
+    mut buf: Vec<String>,
+    curr: &str,
+    class: &str,
+    range: Option<Range<usize>>,
+) -> Vec<String> {
     // html escape strings from source code
     let escaped = html_escape::encode_text(curr);
 
-    buf.push(format!("<span class=\"{}\">{}</span>", class, escaped));
+    match range {
+        Some(range) => buf.push(format!(
+            "<span class=\"{}\" data-start=\"{}\" data-end=\"{}\">{}</span>",
+            class, range.start, range.end, escaped
+        )),
+        None => buf.push(format!("<span class=\"{}\">{}</span>", class, escaped)),
+    }
 
     buf
 }
@@ -6,7 +6,7 @@
 use memmap2::{Mmap, MmapMut};
 use object::Object;
 use roc_error_macros::internal_error;
-use roc_load::{EntryPoint, ExecutionMode, LoadConfig, Threading};
+use roc_load::{EntryPoint, ExecutionMode, LoadConfig, MonoPasses, Threading};
 use roc_packaging::cache::RocCacheDir;
 use roc_reporting::report::{RenderTarget, DEFAULT_PALETTE};
 use roc_target::get_target_triple_str;
@@ -105,6 +105,7 @@ pub fn generate_stub_lib(
             palette: DEFAULT_PALETTE,
             threading: Threading::AllAvailable,
             exec_mode: ExecutionMode::Executable,
+            mono_passes: MonoPasses::All,
         },
     )
     .unwrap_or_else(|problem| todo!("{:?}", problem));
@@ -18,7 +18,7 @@ use roc_region::all::{Loc, Region};
 use roc_std::RocDec;
 use roc_target::TargetInfo;
 use roc_types::subs::{
-    Content, FlatType, GetSubsSlice, RecordFields, Subs, TagExt, UnionTags, Variable,
+    Content, FlatType, GetSubsSlice, RecordFields, Subs, TagExt, TupleElems, UnionTags, Variable,
 };
 
 use crate::{ReplApp, ReplAppMemory};
@@ -336,6 +336,16 @@ const OPAQUE_FUNCTION: Expr = Expr::Var {
     ident: "<function>",
 };
 
+/// Stands in for the part of a recursive value that was too big to be written into an
+/// `expect` or `dbg` frame
+const ELIDED_VALUE: Expr = Expr::Var {
+    module_name: "",
+    ident: "...",
+};
+
+/// Written instead of a recursive pointer when the rest of the value was elided
+const ELIDED_POINTER: usize = usize::MAX;
+
 fn jit_to_ast_help<'a, A: ReplApp<'a>>(
     env: &mut Env<'a, '_>,
     app: &mut A,
@@ -432,6 +442,9 @@ fn jit_to_ast_help<'a, A: ReplApp<'a>>(
                 Content::Structure(FlatType::EmptyRecord) => {
                     struct_to_ast(env, mem, addr, RecordFields::empty())
                 }
+                Content::Structure(FlatType::Tuple(elems, _)) => {
+                    tuple_to_ast(env, mem, addr, *elems)
+                }
                 Content::Structure(FlatType::TagUnion(tags, _)) => {
                     let (tag_name, payload_vars) = unpack_single_element_tag_union(env.subs, *tags);
 
@@ -612,6 +625,7 @@ fn addr_to_ast<'a, M: ReplAppMemory>(
             Content::Structure(FlatType::Record(fields, _)) => {
                 struct_to_ast(env, mem, addr, *fields)
             }
+            Content::Structure(FlatType::Tuple(elems, _)) => tuple_to_ast(env, mem, addr, *elems),
             Content::Structure(FlatType::TagUnion(tags, _)) => {
                 debug_assert_eq!(tags.len(), 1);
 
@@ -632,6 +646,9 @@ fn addr_to_ast<'a, M: ReplAppMemory>(
                 );
             }
         },
+        (_, Layout::RecursivePointer(_)) if mem.deref_usize(addr) == ELIDED_POINTER => {
+            ELIDED_VALUE
+        }
         (_, Layout::RecursivePointer(_)) => match (raw_content, when_recursive) {
             (
                 Content::RecursionVar {
@@ -1127,6 +1144,75 @@ fn struct_to_ast<'a, 'env, M: ReplAppMemory>(
     }
 }
 
+fn tuple_to_ast<'a, 'env, M: ReplAppMemory>(
+    env: &mut Env<'a, 'env>,
+    mem: &'a M,
+    addr: usize,
+    tuple_elems: TupleElems,
+) -> Expr<'a> {
+    let arena = env.arena;
+    let subs = env.subs;
+    let mut output = Vec::with_capacity_in(tuple_elems.len(), arena);
+
+    // We'll advance this as we iterate through the elems
+    let mut elem_addr = addr;
+
+    // the type checker stores tuple elems in source order
+    let source_order_elems: Vec<_> = tuple_elems
+        .sorted_iterator(subs, Variable::EMPTY_TUPLE)
+        .map(|(index, elem_var)| {
+            let layout = env
+                .layout_cache
+                .from_var(arena, elem_var, env.subs)
+                .unwrap();
+
+            (index, elem_var, layout)
+        })
+        .collect_in(arena);
+
+    // but the memory representation sorts first by size (and uses the index as a tie breaker)
+    let mut in_memory_elems = source_order_elems;
+    in_memory_elems.sort_by(|(index1, _, layout1), (index2, _, layout2)| {
+        cmp_fields(
+            &env.layout_cache.interner,
+            index1,
+            *layout1,
+            index2,
+            *layout2,
+            env.target_info,
+        )
+    });
+
+    for (index, elem_var, elem_layout) in in_memory_elems {
+        let loc_expr = &*arena.alloc(Loc {
+            value: addr_to_ast(
+                env,
+                mem,
+                elem_addr,
+                elem_layout,
+                WhenRecursive::Unreachable,
+                elem_var,
+            ),
+            region: Region::zero(),
+        });
+
+        output.push((index, loc_expr));
+
+        // Advance the elem pointer to the next elem.
+        elem_addr += env.layout_cache.interner.stack_size(elem_layout) as usize;
+    }
+
+    // to the user we want to present the elems in source order again, so re-sort
+    output.sort_by_key(|(index, _)| *index);
+    let output = output
+        .into_iter()
+        .map(|(_, loc_expr)| loc_expr)
+        .collect_in::<Vec<_>>(arena)
+        .into_bump_slice();
+
+    Expr::Tuple(Collection::with_items(output))
+}
+
 fn unpack_single_element_tag_union(subs: &Subs, tags: UnionTags) -> (&TagName, &[Variable]) {
     let (tag_name_index, payload_vars_index) = tags.iter_all().next().unwrap();
 
@@ -1,5 +1,5 @@
 use bumpalo::Bump;
-use roc_load::{ExecutionMode, LoadConfig, LoadMonomorphizedError, Threading};
+use roc_load::{ExecutionMode, LoadConfig, LoadMonomorphizedError, MonoPasses, Threading};
 use roc_packaging::cache::{self, RocCacheDir};
 use roc_problem::Severity;
 use roc_reporting::report::Palette;
@@ -66,6 +66,7 @@ pub fn compile_to_mono<'a, 'i, I: Iterator<Item = &'i str>>(
             palette,
             threading: Threading::Single,
             exec_mode: ExecutionMode::Executable,
+            mono_passes: MonoPasses::All,
         },
     );
 
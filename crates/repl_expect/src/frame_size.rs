@@ -0,0 +1,201 @@
+//! A compile-time estimate of how many bytes an `expect` or `dbg` writes into the shared memory.
+//!
+//! Only what the layouts of its values pin down is counted: the frame's header, each value's
+//! stack size, and whatever a `Box` points to. Lists and strings can make a frame bigger still,
+//! but their size is only known at runtime, and recursive values stop being cloned once a frame
+//! reaches the host's limit. So the estimate is a lower bound, and a frame whose estimate is over
+//! the limit will not fit however small its lists are.
+use std::path::Path;
+
+use roc_collections::MutMap;
+use roc_load::MonomorphizedModule;
+use roc_module::symbol::{ModuleId, Symbol};
+use roc_mono::ir::{Proc, Stmt};
+use roc_mono::layout::{InLayout, Layout, LayoutInterner, STLayoutInterner, UnionLayout};
+use roc_region::all::{LineInfo, Region};
+
+/// An `expect` or `dbg` whose frame is estimated to be bigger than the limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeFrame {
+    pub module_id: ModuleId,
+    pub region: Region,
+    pub is_dbg: bool,
+    pub bytes: usize,
+}
+
+impl LargeFrame {
+    /// A warning to print before the tests run, pointing at the `expect` or `dbg`.
+    pub fn warning(&self, path: &Path, source: &str, limit: usize) -> String {
+        let line = LineInfo::new(source).convert_pos(self.region.start()).line + 1;
+
+        format!(
+            "Warning: the {} at {}:{} reports on values that take at least {} bytes, but a frame \
+             only holds {} bytes. Consider checking a smaller value.",
+            if self.is_dbg { "dbg" } else { "expect" },
+            path.display(),
+            line,
+            self.bytes,
+            limit
+        )
+    }
+}
+
+/// The `expect`s and `dbg`s in `loaded` whose frames will take more than `limit` bytes.
+pub fn large_frames(loaded: &MonomorphizedModule, limit: usize) -> Vec<LargeFrame> {
+    let mut frames = Vec::new();
+
+    visit_frames(loaded, &mut |module_id,
+                               region,
+                               is_dbg,
+                               lookups,
+                               layouts| {
+        let bytes = fixed_frame_bytes(
+            &loaded.layout_interner,
+            lookups
+                .iter()
+                .filter_map(|symbol| layouts.get(symbol).copied()),
+        );
+
+        if bytes > limit {
+            frames.push(LargeFrame {
+                module_id,
+                region,
+                is_dbg,
+                bytes,
+            });
+        }
+    });
+
+    // The same expect can end up in several specializations of a function
+    frames.sort_by_key(|frame| (frame.region.start().offset, frame.region.end().offset));
+    frames.dedup();
+
+    frames
+}
+
+/// The bytes that a frame reporting on values of these layouts takes, at the least.
+pub fn fixed_frame_bytes<'a>(
+    interner: &STLayoutInterner<'a>,
+    lookups: impl IntoIterator<Item = InLayout<'a>>,
+) -> usize {
+    // the region's start and end, and the module id
+    let header = 3 * std::mem::size_of::<u32>();
+
+    lookups.into_iter().fold(header, |bytes, layout| {
+        // an offset and a variable for each value
+        let lookup = interner.target_info().ptr_size() + std::mem::size_of::<u32>();
+
+        bytes + lookup + interner.stack_size(layout) + boxed_bytes(interner, layout)
+    })
+}
+
+/// The bytes behind the `Box`es in a value of this layout.
+fn boxed_bytes<'a>(interner: &STLayoutInterner<'a>, layout: InLayout<'a>) -> usize {
+    match interner.get(layout) {
+        Layout::Boxed(inner) => interner.stack_size(inner) + boxed_bytes(interner, inner),
+        Layout::Struct { field_layouts, .. } => field_layouts
+            .iter()
+            .map(|field| boxed_bytes(interner, *field))
+            .sum(),
+        Layout::Union(UnionLayout::NonRecursive(tags)) => tags
+            .iter()
+            .map(|fields| fields.iter().map(|f| boxed_bytes(interner, *f)).sum())
+            .max()
+            .unwrap_or(0),
+        Layout::LambdaSet(lambda_set) => boxed_bytes(interner, lambda_set.runtime_representation()),
+        // lists and strings are only known at runtime, and recursive values are cut off
+        Layout::Builtin(_) | Layout::Union(_) | Layout::RecursivePointer(_) => 0,
+    }
+}
+
+fn proc_arg_layouts<'a>(proc: &Proc<'a>) -> MutMap<Symbol, InLayout<'a>> {
+    proc.args
+        .iter()
+        .map(|(layout, symbol)| (*symbol, *layout))
+        .collect()
+}
+
+/// Call `visit` with the module, region, whether it is a `dbg`, and the lookups of every
+/// `expect` and `dbg` in `loaded`, along with the layouts of the symbols in scope there.
+pub(crate) fn visit_frames<'a>(
+    loaded: &MonomorphizedModule<'a>,
+    visit: &mut impl FnMut(ModuleId, Region, bool, &[Symbol], &MutMap<Symbol, InLayout<'a>>),
+) {
+    for proc in loaded.procedures.values() {
+        let mut layouts = proc_arg_layouts(proc);
+
+        visit_frames_help(loaded, &proc.body, &mut layouts, visit);
+    }
+}
+
+fn visit_frames_help<'a>(
+    loaded: &MonomorphizedModule<'a>,
+    stmt: &Stmt<'a>,
+    layouts: &mut MutMap<Symbol, InLayout<'a>>,
+    visit: &mut impl FnMut(ModuleId, Region, bool, &[Symbol], &MutMap<Symbol, InLayout<'a>>),
+) {
+    match stmt {
+        Stmt::Let(symbol, _, layout, next) => {
+            layouts.insert(*symbol, *layout);
+            visit_frames_help(loaded, next, layouts, visit);
+        }
+        Stmt::Switch {
+            branches,
+            default_branch,
+            ..
+        } => {
+            for (_, _, branch) in branches.iter() {
+                visit_frames_help(loaded, branch, layouts, visit);
+            }
+            visit_frames_help(loaded, default_branch.1, layouts, visit);
+        }
+        Stmt::Expect {
+            condition,
+            region,
+            lookups,
+            remainder,
+            ..
+        }
+        | Stmt::ExpectFx {
+            condition,
+            region,
+            lookups,
+            remainder,
+            ..
+        } => {
+            visit(condition.module_id(), *region, false, lookups, layouts);
+            visit_frames_help(loaded, remainder, layouts, visit);
+        }
+        Stmt::Dbg {
+            symbol, remainder, ..
+        } => {
+            // A dbg's frame is keyed by the symbol of the value it shows
+            let region = loaded
+                .expectations
+                .get(&symbol.module_id())
+                .and_then(|expectations| expectations.dbgs.get(symbol))
+                .map(|dbg| dbg.region);
+
+            if let Some(region) = region {
+                visit(symbol.module_id(), region, true, &[*symbol], layouts);
+            }
+            visit_frames_help(loaded, remainder, layouts, visit);
+        }
+        Stmt::Join {
+            parameters,
+            body,
+            remainder,
+            ..
+        } => {
+            for param in parameters.iter() {
+                layouts.insert(param.symbol, param.layout);
+            }
+            visit_frames_help(loaded, body, layouts, visit);
+            visit_frames_help(loaded, remainder, layouts, visit);
+        }
+        Stmt::Refcounting(_, next) => {
+            visit_frames_help(loaded, next, layouts, visit);
+        }
+        Stmt::Ret(_) | Stmt::Jump(_, _) | Stmt::Crash(_, _) => {}
+    }
+}
@@ -0,0 +1,241 @@
+//! Reads the header of the shared memory that `expect` frames are written to.
+//!
+//! The memory starts with a few words that the generated code maintains (see `read_state` and
+//! `write_state` in `llvm/expect.rs`): the number of frames written so far, and the offset where
+//! the next frame will go. The frames themselves follow back to back from `FRAMES_START`.
+//!
+//! The header does not record where one frame ends and the next begins: that is only known by
+//! reading a frame's values (which is what `get_values` does). So `FrameReader::frames` asks the
+//! caller for the length of each frame.
+//!
+//! Each frame starts with the region and module of its `expect`, and, in the `WithSource`
+//! format, the source text of the condition.
+use std::ops::Range;
+
+use roc_mono::layout::round_up_to_alignment;
+use roc_target::TargetInfo;
+
+/// Where the first frame starts, after the header words (the count, the next offset, the lock,
+/// the maximum frame size, whether values are written with their native alignment, and the
+/// recursion budget of each lookup).
+pub const FRAMES_START: usize = 8 + 8 + 8 + 8 + 8 + 8;
+
+const COUNT_INDEX: usize = 0;
+const OFFSET_INDEX: usize = 1;
+
+/// The region's start and end, and the module id, which every frame starts with
+pub const FRAME_HEADER_BYTES: usize = 3 * std::mem::size_of::<u32>();
+
+/// The source text of a `WithSource` frame is padded to this, so that the lookups after it are
+/// aligned like a pointer on every target. `write_condition_source` in `llvm/expect.rs` pads the same.
+pub const CONDITION_SOURCE_ALIGNMENT: u32 = 8;
+
+/// What comes after the header of each frame. This is decided when the program is compiled:
+/// frames have the source text when `roc_gen_llvm`'s `Env::expect_sources` is given, which
+/// `roc test` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameFormat {
+    /// Nothing: the condition is found in the module's source by the region.
+    #[default]
+    Regions,
+    /// The source text of the condition, as a `u32` length and then its bytes, padded to
+    /// `CONDITION_SOURCE_ALIGNMENT`, so that the frame can be reported on without the module's
+    /// source. A `dbg`'s text is empty.
+    WithSource,
+}
+
+impl FrameFormat {
+    /// Where the lookups of the frame at `offset` in `buffer` start
+    pub fn lookups_offset(self, buffer: &[u8], offset: usize) -> usize {
+        let after_header = offset + FRAME_HEADER_BYTES;
+
+        match self {
+            FrameFormat::Regions => after_header,
+            FrameFormat::WithSource => {
+                after_condition_source(after_header, read_u32(buffer, after_header))
+            }
+        }
+    }
+
+    /// The source text of the condition of the frame at `offset` in `buffer`,
+    /// if frames of this format have it
+    pub fn condition_source(self, buffer: &[u8], offset: usize) -> Option<&str> {
+        match self {
+            FrameFormat::Regions => None,
+            FrameFormat::WithSource => {
+                let len_offset = offset + FRAME_HEADER_BYTES;
+                let bytes = &buffer[len_offset + 4..][..read_u32(buffer, len_offset)];
+
+                Some(std::str::from_utf8(bytes).expect("the condition's source is not UTF-8"))
+            }
+        }
+    }
+}
+
+/// Where the lookups start, after a condition's text of `len` bytes whose length is at `len_offset`
+pub fn after_condition_source(len_offset: usize, len: usize) -> usize {
+    let end = len_offset + std::mem::size_of::<u32>() + len;
+
+    round_up_to_alignment(end as u32, CONDITION_SOURCE_ALIGNMENT) as usize
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> usize {
+    let bytes: [u8; 4] = buffer[offset..][..4].try_into().unwrap();
+
+    u32::from_le_bytes(bytes) as usize
+}
+
+pub struct FrameReader<'b> {
+    buffer: &'b [u8],
+    target_info: TargetInfo,
+}
+
+impl<'b> FrameReader<'b> {
+    pub fn new(buffer: &'b [u8], target_info: TargetInfo) -> Self {
+        Self {
+            buffer,
+            target_info,
+        }
+    }
+
+    /// The number of frames written so far
+    pub fn count(&self) -> usize {
+        self.read_word(COUNT_INDEX)
+    }
+
+    /// The bytes that hold all of the frames
+    pub fn written(&self) -> Range<usize> {
+        FRAMES_START..self.read_word(OFFSET_INDEX)
+    }
+
+    /// The byte range of each frame, in the order they were written.
+    ///
+    /// `frame_len` gets the offset of a frame, and returns how many bytes it takes up.
+    pub fn frames<F>(&self, mut frame_len: F) -> impl Iterator<Item = Range<usize>>
+    where
+        F: FnMut(usize) -> usize,
+    {
+        let written = self.written();
+        let mut offset = written.start;
+
+        (0..self.count()).map(move |_| {
+            let start = offset;
+            offset += frame_len(start);
+
+            debug_assert!(
+                offset <= written.end,
+                "frame at {} runs past the written bytes",
+                start
+            );
+
+            start..offset
+        })
+    }
+
+    fn read_word(&self, index: usize) -> usize {
+        let ptr_size = self.target_info.ptr_size();
+        let bytes = &self.buffer[index * ptr_size..][..ptr_size];
+
+        let mut word = [0u8; 8];
+        word[..ptr_size].copy_from_slice(bytes);
+
+        u64::from_le_bytes(word) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bumpalo::Bump;
+    use roc_module::symbol::ModuleId;
+    use roc_mono::layout::{Layout, STLayoutInterner};
+    use roc_region::all::{Position, Region};
+    use roc_target::TargetInfo;
+    use roc_types::subs::Variable;
+
+    use super::{
+        FrameFormat, FrameReader, COUNT_INDEX, FRAMES_START, FRAME_HEADER_BYTES, OFFSET_INDEX,
+    };
+    use crate::serialize::{Lookup, Serializer};
+
+    const TARGET_INFO: TargetInfo = TargetInfo::default_x86_64();
+
+    #[test]
+    fn three_frames_are_counted() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let source = 42i64.to_le_bytes();
+
+        let lookups = [Lookup {
+            addr: 0,
+            layout: Layout::I64,
+            variable: Variable::EMPTY_RECORD,
+        }];
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let mut starts = Vec::new();
+        let mut offset = FRAMES_START;
+
+        for _ in 0..3 {
+            starts.push(offset);
+            offset = serializer.write_frame(offset, ModuleId::ATTR, Region::zero(), &lookups);
+        }
+
+        let mut buffer = serializer.into_frame();
+        buffer[COUNT_INDEX * 8..][..8].copy_from_slice(&3u64.to_le_bytes());
+        buffer[OFFSET_INDEX * 8..][..8].copy_from_slice(&(offset as u64).to_le_bytes());
+
+        let reader = FrameReader::new(&buffer, TARGET_INFO);
+        assert_eq!(reader.count(), 3);
+        assert_eq!(reader.written(), FRAMES_START..offset);
+
+        let frame_len = offset - starts[2];
+        let frames: Vec<_> = reader.frames(|_| frame_len).collect();
+        assert_eq!(
+            frames,
+            vec![
+                starts[0]..starts[1],
+                starts[1]..starts[2],
+                starts[2]..offset
+            ]
+        );
+    }
+
+    #[test]
+    fn condition_source_is_the_text_of_the_region() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let source = 42i64.to_le_bytes();
+
+        let module_source = "main =\n    expect answer == 42\n";
+        let region = Region::new(Position::new(18), Position::new(30));
+
+        let lookups = [Lookup {
+            addr: 0,
+            layout: Layout::I64,
+            variable: Variable::EMPTY_RECORD,
+        }];
+
+        let mut serializer =
+            Serializer::new(&arena, &mut interner, &source).with_module_source(module_source);
+        let end = serializer.write_frame(0, ModuleId::ATTR, region, &lookups);
+        let frame = serializer.into_frame();
+
+        let text = FrameFormat::WithSource.condition_source(&frame, 0);
+        assert_eq!(text, Some(&module_source[18..30]));
+        assert_eq!(text, Some("answer == 42"));
+        assert_eq!(FrameFormat::Regions.condition_source(&frame, 0), None);
+
+        // the lookup table comes after the text, padded to 8 bytes, and points at the value after it
+        let lookups_offset = FrameFormat::WithSource.lookups_offset(&frame, 0);
+        assert_eq!(FRAME_HEADER_BYTES + 4 + "answer == 42".len(), 28);
+        assert_eq!(lookups_offset, 32);
+        assert_eq!(frame[28..32], [0; 4]);
+
+        let value_offset = lookups_offset + 8 + 4;
+        assert_eq!(
+            frame[lookups_offset..][..8],
+            (value_offset as u64).to_le_bytes()
+        );
+        assert_eq!(end, value_offset + 8);
+    }
+}
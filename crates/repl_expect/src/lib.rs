@@ -14,8 +14,14 @@ use {
 
 #[cfg(not(windows))]
 mod app;
+pub mod frame_size;
+pub mod frames;
+#[cfg(not(windows))]
+pub mod render;
 #[cfg(not(windows))]
 pub mod run;
+pub mod serialize;
+pub mod unshowable;
 
 #[cfg(not(windows))]
 use app::{ExpectMemory, ExpectReplApp};
@@ -44,15 +50,21 @@ pub fn get_values<'a>(
 
     let app = arena.alloc(app);
 
+    // The offsets in the lookup table are words of the target the frame was written for,
+    // which need not be the host: a `Nat` is read as wide as the target's `Nat` is too.
+    let ptr_size = target_info.ptr_size();
+
     for i in 0..number_of_lookups {
-        let size_of_lookup_header = 8 /* pointer to value */ + 4 /* type variable */;
+        let size_of_lookup_header = ptr_size /* offset of the value */ + 4 /* type variable */;
+        let lookup_header = start_offset + i * size_of_lookup_header;
 
-        let start = app
+        let start = match ptr_size {
+            4 => app.memory.deref_u32(lookup_header) as usize,
+            _ => app.memory.deref_usize(lookup_header),
+        };
+        let variable = app
             .memory
-            .deref_usize(start_offset + i * size_of_lookup_header);
-        let variable = app.memory.deref_u32(
-            start_offset + i * size_of_lookup_header + 8, /* skip the pointer */
-        );
+            .deref_u32(lookup_header + ptr_size /* skip the offset */);
         let variable = unsafe { Variable::from_index(variable) };
 
         app.offset = start;
@@ -94,26 +106,30 @@ mod test {
     use indoc::indoc;
     use pretty_assertions::assert_eq;
     use roc_gen_llvm::{llvm::build::LlvmBackendMode, run_roc::RocCallResult, run_roc_dylib};
-    use roc_load::{ExecutionMode, LoadConfig, LoadMonomorphizedError, Threading};
+    use roc_load::{ExecutionMode, LoadConfig, LoadMonomorphizedError, MonoPasses, Threading};
     use roc_packaging::cache::RocCacheDir;
     use roc_reporting::report::{RenderTarget, DEFAULT_PALETTE};
     use target_lexicon::Triple;
 
+    use crate::frames::{FrameFormat, FRAMES_START};
     use crate::run::expect_mono_module_to_dylib;
 
     use super::*;
 
-    fn run_expect_test(source: &str, expected: &str) {
-        let arena = bumpalo::Bump::new();
-        let arena = &arena;
+    /// The shared memory is read and written a word at a time, so it must be aligned like one
+    #[repr(C, align(8))]
+    struct SharedBuffer<const N: usize>([u8; N]);
 
-        let triple = Triple::host();
-        let target = &triple;
+    fn run_expect_test(source: &str, expected: &str) {
+        run_expect_test_help(source, expected, |_| {})
+    }
 
-        let opt_level = roc_mono::ir::OptLevel::Normal;
-        let target_info = TargetInfo::from(target);
+    fn load_test_module<'a>(
+        arena: &'a bumpalo::Bump,
+        source: &str,
+    ) -> roc_load::MonomorphizedModule<'a> {
+        let target_info = TargetInfo::from(&Triple::host());
 
-        // Step 1: compile the app and generate the .o file
         let src_dir = tempfile::tempdir().unwrap();
         let filename = src_dir.path().join("Test.roc");
 
@@ -125,8 +141,9 @@ mod test {
             palette: DEFAULT_PALETTE,
             threading: Threading::Single,
             exec_mode: ExecutionMode::Test,
+            mono_passes: MonoPasses::All,
         };
-        let loaded = match roc_load::load_and_monomorphize_from_str(
+        match roc_load::load_and_monomorphize_from_str(
             arena,
             filename,
             source,
@@ -139,20 +156,92 @@ mod test {
                 panic!("{:?}", (m.can_problems, m.type_problems))
             }
             Err(e) => panic!("{e:?}"),
-        };
+        }
+    }
+
+    fn run_expect_test_help(
+        source: &str,
+        expected: &str,
+        set_up: impl FnOnce(&mut crate::run::ExpectMemory),
+    ) {
+        let actual = run_expect_test_with_frame(source, set_up, |_| {});
+        let expected = expected.trim_end();
+
+        if actual != expected {
+            println!("{}", actual);
+        }
+
+        assert_eq!(actual, expected);
+    }
+
+    /// The first frame that a test's expects wrote into the shared memory.
+    struct WrittenFrame<'a, 'r> {
+        /// The whole shared memory, after the expects ran
+        buffer: &'r [u8],
+        /// Where the frame ends
+        end: usize,
+        module_id: roc_module::symbol::ModuleId,
+        region: roc_region::all::Region,
+        /// Where each lookup's value is in `buffer`, with its layout and type variable
+        lookups: Vec<crate::serialize::Lookup<'a>>,
+        layout_interner: &'r mut roc_mono::layout::STLayoutInterner<'a>,
+        /// The arena of `layout_interner`
+        arena: &'a bumpalo::Bump,
+    }
+
+    /// Run the expects in `source`, and return what was rendered (without its first line, which
+    /// has a tempdir in it). If an expect failed, `check_frame` is given the first frame.
+    fn run_expect_test_with_frame(
+        source: &str,
+        set_up: impl FnOnce(&mut crate::run::ExpectMemory),
+        check_frame: impl FnOnce(WrittenFrame),
+    ) -> String {
+        run_expect_test_in_format(source, FrameFormat::Regions, set_up, check_frame)
+    }
+
+    /// Like `run_expect_test_with_frame`, with the frames written in `frame_format`
+    fn run_expect_test_in_format(
+        source: &str,
+        frame_format: FrameFormat,
+        set_up: impl FnOnce(&mut crate::run::ExpectMemory),
+        check_frame: impl FnOnce(WrittenFrame),
+    ) -> String {
+        let arena = bumpalo::Bump::new();
+        let arena = &arena;
+        let build_arena = arena;
+
+        let triple = Triple::host();
+        let target = &triple;
+
+        let opt_level = roc_mono::ir::OptLevel::Normal;
 
-        let mut loaded = loaded;
+        // Step 1: compile the app and generate the .o file
+        let mut loaded = load_test_module(arena, source);
         let mut expectations = std::mem::take(&mut loaded.expectations);
         let loaded = loaded;
 
         let interns = loaded.interns.clone();
 
+        // the layouts of each expect's lookups, to find those of the frame that gets written
+        let mut frame_layouts = Vec::new();
+        crate::frame_size::visit_frames(
+            &loaded,
+            &mut |module_id, region, is_dbg, lookups, layouts| {
+                if !is_dbg {
+                    let lookup_layouts: Vec<_> =
+                        lookups.iter().map(|symbol| layouts[symbol]).collect();
+                    frame_layouts.push((module_id, region, lookup_layouts));
+                }
+            },
+        );
+
         let (lib, expects, layout_interner) = expect_mono_module_to_dylib(
             arena,
             target.clone(),
             loaded,
             opt_level,
             LlvmBackendMode::CliTest,
+            frame_format,
         )
         .unwrap();
 
@@ -161,21 +250,25 @@ mod test {
 
         const BUFFER_SIZE: usize = 1024;
 
-        let mut shared_buffer = [0u8; BUFFER_SIZE];
-        let mut memory = crate::run::ExpectMemory::from_slice(&mut shared_buffer);
+        let mut shared_buffer = SharedBuffer([0u8; BUFFER_SIZE]);
+        let mut memory = crate::run::ExpectMemory::from_slice(&mut shared_buffer.0);
+
+        set_up(&mut memory);
 
         // communicate the mmapped name to zig/roc
         let set_shared_buffer = run_roc_dylib!(lib, "set_shared_buffer", (*mut u8, usize), ());
         let mut result = RocCallResult::default();
-        unsafe { set_shared_buffer((shared_buffer.as_mut_ptr(), BUFFER_SIZE), &mut result) };
+        unsafe { set_shared_buffer((shared_buffer.0.as_mut_ptr(), BUFFER_SIZE), &mut result) };
+
+        let layout_interner = layout_interner.into_global();
 
         let mut writer = Vec::with_capacity(1024);
-        let (_failed, _passed) = crate::run::run_expects_with_memory(
+        let (failed, _passed) = crate::run::run_expects_with_memory(
             &mut writer,
             RenderTarget::ColorTerminal,
             arena,
             interns,
-            &layout_interner.into_global(),
+            &layout_interner,
             &lib,
             &mut expectations,
             expects,
@@ -183,6 +276,53 @@ mod test {
         )
         .unwrap();
 
+        if failed > 0 {
+            let mut layout_interner = layout_interner
+                .unwrap()
+                .unwrap_or_else(|_| panic!("the layout interner is still shared"));
+
+            let buffer = &shared_buffer.0[..];
+            let reader = crate::frames::FrameReader::new(buffer, TargetInfo::from(target));
+            let start = FRAMES_START;
+            let read_u32 =
+                |offset: usize| u32::from_le_bytes(buffer[offset..][..4].try_into().unwrap());
+
+            let region = roc_region::all::Region::new(
+                roc_region::all::Position::new(read_u32(start)),
+                roc_region::all::Position::new(read_u32(start + 4)),
+            );
+            let (module_id, _, layouts) = frame_layouts
+                .iter()
+                .find(|(_, frame_region, _)| *frame_region == region)
+                .expect("no expect has the region of the frame");
+
+            // the offset of each lookup's value, and its type variable
+            let table = frame_format.lookups_offset(buffer, start);
+            let ptr_size = std::mem::size_of::<usize>();
+            let lookups = layouts
+                .iter()
+                .enumerate()
+                .map(|(i, layout)| {
+                    let entry = table + i * (ptr_size + 4);
+                    crate::serialize::Lookup {
+                        addr: usize::from_le_bytes(buffer[entry..][..ptr_size].try_into().unwrap()),
+                        layout: *layout,
+                        variable: unsafe { Variable::from_index(read_u32(entry + ptr_size)) },
+                    }
+                })
+                .collect();
+
+            check_frame(WrittenFrame {
+                buffer,
+                end: reader.written().end,
+                module_id: *module_id,
+                region,
+                lookups,
+                layout_interner: &mut layout_interner,
+                arena: build_arena,
+            });
+        }
+
         // Remove ANSI escape codes from the answer - for example:
         //
         //     Before: "42 \u{1b}[35m:\u{1b}[0m Num *"
@@ -195,17 +335,246 @@ mod test {
             // changes between test runs
             let p = actual.bytes().position(|c| c == b'\n').unwrap();
             let (_, x) = actual.split_at(p);
-            let x = x.trim();
-            let expected = expected.trim_end();
-
-            if x != expected {
-                println!("{}", x);
-            }
 
-            assert_eq!(x, expected);
+            x.trim().to_string()
         } else {
-            assert_eq!(actual, expected);
+            actual
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "aligned")]
+    fn misaligned_shared_buffer_is_rejected() {
+        let mut shared_buffer = SharedBuffer([0u8; 64]);
+
+        crate::run::ExpectMemory::from_slice(&mut shared_buffer.0[1..]);
+    }
+
+    #[test]
+    fn serializer_writes_the_same_frame_as_the_generated_code() {
+        use crate::serialize::Serializer;
+
+        let mut checked = false;
+        run_expect_test_with_frame(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Shape : [Circle F64, Label Str, Group (List Shape)]
+
+                expect
+                    name = "Astra mortemque praestare gradatim"
+                    boxed = Box.box { name, count: 3u8 }
+                    shapes : List Shape
+                    shapes = [Label name, Group [Circle 1.5, Label "small"]]
+                    pairs = [(1u16, "a"), (2u16, "Profundum et fundamentum")]
+
+                    List.len shapes + List.len pairs == 0 && Box.unbox boxed == { name: "", count: 3u8 }
+                "#
+            ),
+            |_| {},
+            |frame| {
+                // A pointer in the frame is an offset into the shared memory, so the frame's
+                // values can be read by the serializer like any other source. Writing them again
+                // must give the same bytes, or the two writers have drifted apart.
+                let mut serializer =
+                    Serializer::new(frame.arena, frame.layout_interner, frame.buffer);
+                let end = serializer.write_frame(
+                    FRAMES_START,
+                    frame.module_id,
+                    frame.region,
+                    &frame.lookups,
+                );
+
+                assert_eq!(end, frame.end);
+                assert_eq!(
+                    &serializer.frame()[FRAMES_START..end],
+                    &frame.buffer[FRAMES_START..end]
+                );
+                checked = true;
+            },
+        );
+
+        assert!(checked, "the expect did not fail");
+    }
+
+    #[test]
+    fn clone_functions_have_the_same_names_in_every_build() {
+        let source = indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            main = 0
+
+            Tree : [Leaf, Node Tree I64 Tree]
+
+            expect
+                tree : Tree
+                tree = Node Leaf 1 (Node Leaf 2 Leaf)
+                shapes = [A 1i64, B "b"]
+
+                List.len shapes == 0 && tree == Leaf
+            "#
+        );
+
+        let clone_function_names = || {
+            let arena = bumpalo::Bump::new();
+            let loaded = load_test_module(&arena, source);
+            let prefix = roc_module::symbol::Symbol::CLONE
+                .as_str(&loaded.interns)
+                .to_string();
+
+            let context = inkwell::context::Context::create();
+            let (module, _, _) = crate::run::expect_mono_module_to_llvm(
+                &arena,
+                &context,
+                &Triple::host(),
+                loaded,
+                roc_mono::ir::OptLevel::Development,
+                LlvmBackendMode::CliTest,
+                FrameFormat::Regions,
+            );
+
+            let mut names: Vec<String> = module
+                .get_functions()
+                .map(|function| function.get_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with(&prefix))
+                .collect();
+            names.sort();
+            names
+        };
+
+        let names = clone_function_names();
+
+        assert!(!names.is_empty());
+        assert_eq!(names, clone_function_names());
+    }
+
+    #[test]
+    fn native_layout_values_can_be_cast_back() {
+        use roc_mono::layout::{Builtin, Layout, LayoutInterner};
+
+        // Fields are sorted by alignment, so this is how Roc lays out the record
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Point {
+            b: i64,
+            c: u16,
+            a: u8,
         }
+
+        let mut point = None;
+        let mut points = None;
+        run_expect_test_with_frame(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    flag = 1u8
+                    point = { a: 7u8, b: -12345i64, c: 300u16 }
+                    points = [point, { a: 8u8, b: 99i64, c: 301u16 }]
+
+                    flag + point.a == 0
+                "#
+            ),
+            |memory| memory.set_native_layout(true),
+            |frame| {
+                // The shared memory is aligned, so a value at an aligned offset is aligned too
+                let cast = |offset: usize, len: usize| {
+                    assert_eq!(offset % std::mem::align_of::<Point>(), 0);
+                    assert!(offset + len * std::mem::size_of::<Point>() <= frame.end);
+
+                    unsafe {
+                        let start = frame.buffer.as_ptr().add(offset).cast::<Point>();
+                        std::slice::from_raw_parts(start, len).to_vec()
+                    }
+                };
+                let ptr_size = std::mem::size_of::<usize>();
+                let read_word = |offset: usize| {
+                    usize::from_le_bytes(frame.buffer[offset..][..ptr_size].try_into().unwrap())
+                };
+
+                for lookup in &frame.lookups {
+                    match frame.layout_interner.get(lookup.layout) {
+                        Layout::Struct { .. } => point = Some(cast(lookup.addr, 1)[0]),
+                        // A list's pointer is an offset into the shared memory
+                        Layout::Builtin(Builtin::List(_)) => {
+                            let len = read_word(lookup.addr + ptr_size);
+                            points = Some(cast(read_word(lookup.addr), len))
+                        }
+                        // `flag`, which would leave the record misaligned if values were packed
+                        _ => {}
+                    }
+                }
+            },
+        );
+
+        let first = Point {
+            b: -12345,
+            c: 300,
+            a: 7,
+        };
+        let second = Point {
+            b: 99,
+            c: 301,
+            a: 8,
+        };
+        assert_eq!(point, Some(first));
+        assert_eq!(points, Some(vec![first, second]));
+    }
+
+    #[test]
+    fn with_source_frames_have_the_condition_text() {
+        let source = indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            main = 0
+
+            expect
+                words = ["a", "bc"]
+                answer = 41u8
+
+                List.len words == 2 && answer == 42
+            "#
+        );
+
+        let mut text = None;
+        let mut values = Vec::new();
+        let rendered = run_expect_test_in_format(
+            source,
+            FrameFormat::WithSource,
+            |_| {},
+            |frame| {
+                text = FrameFormat::WithSource
+                    .condition_source(frame.buffer, FRAMES_START)
+                    .map(|text| (frame.region, text.to_string()));
+
+                // the lookups after the text are aligned, and still point at their values
+                let table = FrameFormat::WithSource.lookups_offset(frame.buffer, FRAMES_START);
+                assert_eq!(table % std::mem::size_of::<usize>(), 0);
+
+                for lookup in &frame.lookups {
+                    assert!(lookup.addr < frame.end);
+                    values.push(frame.buffer[lookup.addr]);
+                }
+            },
+        );
+
+        let (region, text) = text.expect("the expect did not fail");
+        let range = region.start().offset as usize..region.end().offset as usize;
+        assert_eq!(text, &source[range]);
+        assert!(text.ends_with("List.len words == 2 && answer == 42"));
+        assert!(values.contains(&41));
+
+        // the values are read back past the text, so the report is the same as without it
+        let without_source = run_expect_test_with_frame(source, |_| {}, |_| {});
+        assert_eq!(rendered, without_source);
     }
 
     #[test]
@@ -359,6 +728,79 @@ mod test {
         );
     }
 
+    #[test]
+    fn lookup_list_of_list_of_bytes() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    a : List (List U8)
+                    a = [[1, 2, 3], [], [4]]
+
+                    List.len a == 2
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      a : List (List U8)
+                7│>      a = [[1, 2, 3], [], [4]]
+                8│>
+                9│>      List.len a == 2
+
+                When it failed, these variables had these values:
+
+                a : List (List U8)
+                a = [[1, 2, 3], [], [4]]
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn lookup_list_of_records_with_strings() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    people : List { name : Str, age : U8 }
+                    people = [{ name: "Astra mortemque praestare gradatim", age: 1 }, { name: "x", age: 2 }]
+
+                    List.len people == 3
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      people : List { name : Str, age : U8 }
+                7│>      people = [{ name: "Astra mortemque praestare gradatim", age: 1 }, { name: "x", age: 2 }]
+                8│>
+                9│>      List.len people == 3
+
+                When it failed, these variables had these values:
+
+                people : List {
+                    age : U8,
+                    name : Str,
+                }
+                people = [{ age: 1, name: "Astra mortemque praestare gradatim" }, { age: 2, name: "x" }]
+                "#
+            ),
+        );
+    }
+
     #[test]
     fn lookup_copy_result() {
         run_expect_test(
@@ -601,7 +1043,7 @@ mod test {
     }
 
     #[test]
-    fn box_with_strings() {
+    fn single_field_record() {
         run_expect_test(
             indoc!(
                 r#"
@@ -610,10 +1052,9 @@ mod test {
                 main = 0
 
                 expect
-                    a = Box.box "Astra mortemque praestare gradatim"
-                    b = Box.box "Profundum et fundamentum"
+                    a = { utopia: "Astra mortemque praestare gradatim" }
 
-                    a == b
+                    a != a
                 "#
             ),
             indoc!(
@@ -621,25 +1062,22 @@ mod test {
                 This expectation failed:
 
                 5│>  expect
-                6│>      a = Box.box "Astra mortemque praestare gradatim"
-                7│>      b = Box.box "Profundum et fundamentum"
-                8│>
-                9│>      a == b
+                6│>      a = { utopia: "Astra mortemque praestare gradatim" }
+                7│>
+                8│>      a != a
 
                 When it failed, these variables had these values:
 
-                a : Box Str
-                a = Box.box "Astra mortemque praestare gradatim"
-
-                b : Box Str
-                b = Box.box "Profundum et fundamentum"
+                a : { utopia : Str }
+                a = { utopia: "Astra mortemque praestare gradatim" }
                 "#
             ),
         );
     }
 
     #[test]
-    fn result_with_strings() {
+    fn small_str() {
+        // small strings keep their bytes (and their length) inline, so they are copied as they are
         run_expect_test(
             indoc!(
                 r#"
@@ -648,10 +1086,10 @@ mod test {
                 main = 0
 
                 expect
-                    a = Ok "Astra mortemque praestare gradatim"
-                    b = Err "Profundum et fundamentum"
+                    short = "Roc"
+                    longest = "Astra mortemque praesta"
 
-                    a == b
+                    short == longest
                 "#
             ),
             indoc!(
@@ -659,25 +1097,27 @@ mod test {
                 This expectation failed:
 
                 5│>  expect
-                6│>      a = Ok "Astra mortemque praestare gradatim"
-                7│>      b = Err "Profundum et fundamentum"
+                6│>      short = "Roc"
+                7│>      longest = "Astra mortemque praesta"
                 8│>
-                9│>      a == b
+                9│>      short == longest
 
                 When it failed, these variables had these values:
 
-                a : [Err Str, Ok Str]
-                a = Ok "Astra mortemque praestare gradatim"
+                short : Str
+                short = "Roc"
 
-                b : [Err Str, Ok Str]
-                b = Err "Profundum et fundamentum"
+                longest : Str
+                longest = "Astra mortemque praesta"
                 "#
             ),
         );
     }
 
     #[test]
-    fn linked_list() {
+    fn nested_record_with_str() {
+        // the Str is only reachable through the inner record, so the outer record cannot be
+        // copied as a whole
         run_expect_test(
             indoc!(
                 r#"
@@ -685,51 +1125,37 @@ mod test {
 
                 main = 0
 
-                ConsList a : [ Nil, Cons a (ConsList a) ]
-
-                cons = \list, x -> Cons x list
-
                 expect
-                    a : ConsList Str
-                    a = Nil
-
-                    b : ConsList Str
-                    b = Nil
-                        |> cons "Astra mortemque praestare gradatim"
-                        |> cons "Profundum et fundamentum"
+                    value : { a : I64, b : { c : Str } }
+                    value = { a: 42, b: { c: "Astra mortemque praestare gradatim" } }
 
-                    a == b
+                    value.b.c == "Profundum et fundamentum"
                 "#
             ),
             indoc!(
                 r#"
                 This expectation failed:
 
-                 9│>  expect
-                10│>      a : ConsList Str
-                11│>      a = Nil
-                12│>
-                13│>      b : ConsList Str
-                14│>      b = Nil
-                15│>          |> cons "Astra mortemque praestare gradatim"
-                16│>          |> cons "Profundum et fundamentum"
-                17│>
-                18│>      a == b
+                5│>  expect
+                6│>      value : { a : I64, b : { c : Str } }
+                7│>      value = { a: 42, b: { c: "Astra mortemque praestare gradatim" } }
+                8│>
+                9│>      value.b.c == "Profundum et fundamentum"
 
                 When it failed, these variables had these values:
 
-                a : ConsList Str
-                a = Nil
-
-                b : ConsList Str
-                b = Cons "Profundum et fundamentum" (Cons "Astra mortemque praestare gradatim" Nil)
+                value : {
+                    a : I64,
+                    b : { c : Str },
+                }
+                value = { a: 42, b: { c: "Astra mortemque praestare gradatim" } }
                 "#
             ),
         );
     }
 
     #[test]
-    fn nullable_tree() {
+    fn list_of_bools() {
         run_expect_test(
             indoc!(
                 r#"
@@ -737,48 +1163,708 @@ mod test {
 
                 main = 0
 
-                Tree a : [ Empty, Leaf a, Node (Tree a) (Tree a) ]
-
-                cons = \list, x -> Cons x list
-
                 expect
-                    a : Tree Str
-                    a = Leaf "Astra mortemque praestare gradatim"
-
-                    b : Tree Str
-                    b = Node Empty Empty
+                    flags = [Bool.true, Bool.false, Bool.false, Bool.true]
 
-                    a == b
+                    flags == [Bool.false]
                 "#
             ),
             indoc!(
                 r#"
                 This expectation failed:
 
-                 9│>  expect
-                10│>      a : Tree Str
-                11│>      a = Leaf "Astra mortemque praestare gradatim"
-                12│>
-                13│>      b : Tree Str
-                14│>      b = Node Empty Empty
-                15│>
-                16│>      a == b
+                5│>  expect
+                6│>      flags = [Bool.true, Bool.false, Bool.false, Bool.true]
+                7│>
+                8│>      flags == [Bool.false]
 
                 When it failed, these variables had these values:
 
-                a : Tree Str
-                a = Leaf "Astra mortemque praestare gradatim"
-
-                b : Tree Str
-                b = Node Empty Empty
+                flags : List Bool
+                flags = [Bool.true, Bool.false, Bool.false, Bool.true]
                 "#
             ),
         );
     }
 
     #[test]
-    fn recursive_tree() {
-        run_expect_test(
+    fn closure_capturing_a_string() {
+        const NAME: &str = "Astra mortemque praestare gradatim";
+
+        let mut frame_has_name = None;
+        let rendered = run_expect_test_with_frame(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                greeter = \name -> \greeting -> Str.concat greeting name
+
+                expect
+                    greet = greeter "Astra mortemque praestare gradatim"
+
+                    greet "Hi " == "Hi"
+                "#
+            ),
+            |_| {},
+            |frame| {
+                // `greet` is the only lookup, so the name can only be in the frame as its capture
+                let values = &frame.buffer[FRAMES_START..frame.end];
+                frame_has_name = Some(
+                    values
+                        .windows(NAME.len())
+                        .any(|window| window == NAME.as_bytes()),
+                );
+            },
+        );
+
+        assert_eq!(
+            rendered,
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 7│>  expect
+                 8│>      greet = greeter "Astra mortemque praestare gradatim"
+                 9│>
+                10│>      greet "Hi " == "Hi"
+
+                When it failed, these variables had these values:
+
+                greet : Str -> Str
+                greet = <function>
+                "#
+            )
+            .trim_end()
+        );
+        assert_eq!(frame_has_name, Some(true));
+    }
+
+    #[test]
+    fn box_with_strings() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    a = Box.box "Astra mortemque praestare gradatim"
+                    b = Box.box "Profundum et fundamentum"
+
+                    a == b
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      a = Box.box "Astra mortemque praestare gradatim"
+                7│>      b = Box.box "Profundum et fundamentum"
+                8│>
+                9│>      a == b
+
+                When it failed, these variables had these values:
+
+                a : Box Str
+                a = Box.box "Astra mortemque praestare gradatim"
+
+                b : Box Str
+                b = Box.box "Profundum et fundamentum"
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn mutually_recursive_unions() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Even : [Zero, SuccE Odd]
+                Odd : [SuccO Even]
+
+                expect
+                    four : Even
+                    four = SuccE (SuccO (SuccE (SuccO Zero)))
+
+                    four == Zero
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 8│>  expect
+                 9│>      four : Even
+                10│>      four = SuccE (SuccO (SuccE (SuccO Zero)))
+                11│>
+                12│>      four == Zero
+
+                When it failed, these variables had these values:
+
+                four : Even
+                four = SuccE (SuccO (SuccE (SuccO Zero)))
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn list_of_empty_records() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    units : List {}
+                    units = List.repeat {} 5
+
+                    List.len units == 4
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      units : List {}
+                7│>      units = List.repeat {} 5
+                8│>
+                9│>      List.len units == 4
+
+                When it failed, these variables had these values:
+
+                units : List {}
+                units = [{}, {}, {}, {}, {}]
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn tuple_in_source_order() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    triple : (Str, I8, Str)
+                    triple = ("Veni, vidi, vici, et cetera", 7, "Ex nihilo nihil fit, ut dicunt")
+
+                    triple.1 == 8
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      triple : (Str, I8, Str)
+                7│>      triple = ("Veni, vidi, vici, et cetera", 7, "Ex nihilo nihil fit, ut dicunt")
+                8│>
+                9│>      triple.1 == 8
+
+                When it failed, these variables had these values:
+
+                triple : (
+                    Str,
+                    I8,
+                    Str,
+                )
+                triple = ("Veni, vidi, vici, et cetera", 7, "Ex nihilo nihil fit, ut dicunt")
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn float_is_shown_like_num_to_str() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    sum : F64
+                    sum = 0.1 + 0.2
+
+                    shown = Num.toStr sum
+
+                    shown == "0.3"
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 5│>  expect
+                 6│>      sum : F64
+                 7│>      sum = 0.1 + 0.2
+                 8│>
+                 9│>      shown = Num.toStr sum
+                10│>
+                11│>      shown == "0.3"
+
+                When it failed, these variables had these values:
+
+                sum : F64
+                sum = 0.30000000000000004
+
+                shown : Str
+                shown = "0.30000000000000004"
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn value_shared_by_a_record() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    shared : Box Str
+                    shared = Box.box "Astra mortemque praestare gradatim"
+
+                    pair : { first : Box Str, second : Box Str }
+                    pair = { first: shared, second: shared }
+
+                    pair.first != pair.second
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 5│>  expect
+                 6│>      shared : Box Str
+                 7│>      shared = Box.box "Astra mortemque praestare gradatim"
+                 8│>
+                 9│>      pair : { first : Box Str, second : Box Str }
+                10│>      pair = { first: shared, second: shared }
+                11│>
+                12│>      pair.first != pair.second
+
+                When it failed, these variables had these values:
+
+                shared : Box Str
+                shared = Box.box "Astra mortemque praestare gradatim"
+
+                pair : {
+                    first : Box Str,
+                    second : Box Str,
+                }
+                pair = { first: Box.box "Astra mortemque praestare gradatim", second: Box.box "Astra mortemque praestare gradatim" }
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn list_of_boxed_strings() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    boxes : List (Box Str)
+                    boxes = [Box.box "short", Box.box "Astra mortemque praestare gradatim"]
+
+                    List.len boxes == 3
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      boxes : List (Box Str)
+                7│>      boxes = [Box.box "short", Box.box "Astra mortemque praestare gradatim"]
+                8│>
+                9│>      List.len boxes == 3
+
+                When it failed, these variables had these values:
+
+                boxes : List (Box Str)
+                boxes = [Box.box "short", Box.box "Astra mortemque praestare gradatim"]
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn list_of_inline_unions() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    a : List [A I64, B Str]
+                    a = [B "Astra mortemque praestare gradatim", A 42, B "short"]
+
+                    b : List [A Str, B I64]
+                    b = [A "Profundum et fundamentum", B 42, A "short"]
+
+                    List.len a == List.len b + 1
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 5│>  expect
+                 6│>      a : List [A I64, B Str]
+                 7│>      a = [B "Astra mortemque praestare gradatim", A 42, B "short"]
+                 8│>
+                 9│>      b : List [A Str, B I64]
+                10│>      b = [A "Profundum et fundamentum", B 42, A "short"]
+                11│>
+                12│>      List.len a == List.len b + 1
+
+                When it failed, these variables had these values:
+
+                a : List [A I64, B Str]
+                a = [B "Astra mortemque praestare gradatim", A 42, B "short"]
+
+                b : List [A Str, B I64]
+                b = [A "Profundum et fundamentum", B 42, A "short"]
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn box_with_integer() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    a : Box I64
+                    a = Box.box 42
+
+                    Box.unbox a == 1337
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      a : Box I64
+                7│>      a = Box.box 42
+                8│>
+                9│>      Box.unbox a == 1337
+
+                When it failed, these variables had these values:
+
+                a : Box I64
+                a = Box.box 42
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn box_with_record_with_string() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    person : Box { name : Str, age : I64 }
+                    person = Box.box { name: "Astra mortemque praestare gradatim", age: 42 }
+
+                    Box.unbox person == { name: "Profundum et fundamentum", age: 1337 }
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      person : Box { name : Str, age : I64 }
+                7│>      person = Box.box { name: "Astra mortemque praestare gradatim", age: 42 }
+                8│>
+                9│>      Box.unbox person == { name: "Profundum et fundamentum", age: 1337 }
+
+                When it failed, these variables had these values:
+
+                person : Box {
+                    age : I64,
+                    name : Str,
+                }
+                person = Box.box { age: 42, name: "Astra mortemque praestare gradatim" }
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn result_with_strings() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                expect
+                    a = Ok "Astra mortemque praestare gradatim"
+                    b = Err "Profundum et fundamentum"
+
+                    a == b
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                5│>  expect
+                6│>      a = Ok "Astra mortemque praestare gradatim"
+                7│>      b = Err "Profundum et fundamentum"
+                8│>
+                9│>      a == b
+
+                When it failed, these variables had these values:
+
+                a : [Err Str, Ok Str]
+                a = Ok "Astra mortemque praestare gradatim"
+
+                b : [Err Str, Ok Str]
+                b = Err "Profundum et fundamentum"
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn linked_list() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                ConsList a : [ Nil, Cons a (ConsList a) ]
+
+                cons = \list, x -> Cons x list
+
+                expect
+                    a : ConsList Str
+                    a = Nil
+
+                    b : ConsList Str
+                    b = Nil
+                        |> cons "Astra mortemque praestare gradatim"
+                        |> cons "Profundum et fundamentum"
+
+                    a == b
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 9│>  expect
+                10│>      a : ConsList Str
+                11│>      a = Nil
+                12│>
+                13│>      b : ConsList Str
+                14│>      b = Nil
+                15│>          |> cons "Astra mortemque praestare gradatim"
+                16│>          |> cons "Profundum et fundamentum"
+                17│>
+                18│>      a == b
+
+                When it failed, these variables had these values:
+
+                a : ConsList Str
+                a = Nil
+
+                b : ConsList Str
+                b = Cons "Profundum et fundamentum" (Cons "Astra mortemque praestare gradatim" Nil)
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn record_with_recursive_field() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Chain : [ End, Link { next : Chain, value : Str } ]
+
+                expect
+                    a : Chain
+                    a = Link {
+                        next: Link { next: End, value: "Profundum et fundamentum" },
+                        value: "Astra mortemque praestare gradatim",
+                    }
+
+                    a == End
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 7│>  expect
+                 8│>      a : Chain
+                 9│>      a = Link {
+                10│>          next: Link { next: End, value: "Profundum et fundamentum" },
+                11│>          value: "Astra mortemque praestare gradatim",
+                12│>      }
+                13│>
+                14│>      a == End
+
+                When it failed, these variables had these values:
+
+                a : Chain
+                a = Link { next: Link { next: End, value: "Profundum et fundamentum" }, value: "Astra mortemque praestare gradatim" }
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn nullable_tree() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Tree a : [ Empty, Leaf a, Node (Tree a) (Tree a) ]
+
+                cons = \list, x -> Cons x list
+
+                expect
+                    a : Tree Str
+                    a = Leaf "Astra mortemque praestare gradatim"
+
+                    b : Tree Str
+                    b = Node Empty Empty
+
+                    a == b
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 9│>  expect
+                10│>      a : Tree Str
+                11│>      a = Leaf "Astra mortemque praestare gradatim"
+                12│>
+                13│>      b : Tree Str
+                14│>      b = Node Empty Empty
+                15│>
+                16│>      a == b
+
+                When it failed, these variables had these values:
+
+                a : Tree Str
+                a = Leaf "Astra mortemque praestare gradatim"
+
+                b : Tree Str
+                b = Node Empty Empty
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn nullable_wrapped_at_the_tag_id_pointer_limit() {
+        // On 64-bit targets, `Narrow` has just few enough tags to keep the tag id in the
+        // pointer, and `Wide` has one too many, so its tag id is stored with the data
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Narrow : [Nil, A Narrow, B Narrow, C Narrow, D Narrow, E Narrow, F Narrow, G Narrow]
+                Wide : [Nil, A Wide, B Wide, C Wide, D Wide, E Wide, F Wide, G Wide, H Wide]
+
+                expect
+                    narrow : Narrow
+                    narrow = G (A (F Nil))
+
+                    wide : Wide
+                    wide = H (A (G Nil))
+
+                    narrow == Nil && wide == Nil
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 8│>  expect
+                 9│>      narrow : Narrow
+                10│>      narrow = G (A (F Nil))
+                11│>
+                12│>      wide : Wide
+                13│>      wide = H (A (G Nil))
+                14│>
+                15│>      narrow == Nil && wide == Nil
+
+                When it failed, these variables had these values:
+
+                narrow : Narrow
+                narrow = G (A (F Nil))
+
+                wide : Wide
+                wide = H (A (G Nil))
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn recursive_tree() {
+        run_expect_test(
             indoc!(
                 r#"
                 app "test" provides [main] to "./platform"
@@ -868,6 +1954,43 @@ mod test {
         );
     }
 
+    #[test]
+    fn single_field_unwrapped_union() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Nested : [Nested (List Nested)]
+
+                expect
+                    a : Nested
+                    a = Nested [Nested [], Nested [Nested []]]
+
+                    a == Nested []
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 7│>  expect
+                 8│>      a : Nested
+                 9│>      a = Nested [Nested [], Nested [Nested []]]
+                10│>
+                11│>      a == Nested []
+
+                When it failed, these variables had these values:
+
+                a : Nested
+                a = Nested [Nested [], Nested [Nested []]]
+                "#
+            ),
+        );
+    }
+
     #[test]
     fn big_recursive_tag_copied_back() {
         run_expect_test(
@@ -914,6 +2037,38 @@ mod test {
         );
     }
 
+    #[test]
+    fn dict_keeps_its_insertion_order() {
+        let rendered = run_expect_test_with_frame(
+            indoc!(
+                r#"
+                interface Test exposes [] imports []
+
+                expect
+                    dict =
+                        Dict.empty {}
+                        |> Dict.insert 30u16 300u16
+                        |> Dict.insert 10u16 100u16
+                        |> Dict.insert 20u16 200u16
+                    order = Dict.keys dict
+
+                    Dict.len dict + List.len order == 0
+                "#
+            ),
+            |_| {},
+            |_| {},
+        );
+
+        // The metadata bytes depend on the hashes, so only the entries are checked. Their
+        // storage order is the order that the program iterates in, which is also `order`.
+        assert!(
+            rendered.contains("data: [T 30 300, T 10 100, T 20 200]"),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("order = [30, 10, 20]"), "{}", rendered);
+    }
+
     #[test]
     fn arg_parser() {
         run_expect_test(
@@ -1099,4 +2254,296 @@ mod test {
             ),
         );
     }
+
+    #[test]
+    fn tag_id_in_pointer() {
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Expr : [Add Expr Expr, Mul Expr Expr, Val I64, Var Str]
+
+                expect
+                    e : Expr
+                    e = Mul (Val 2) (Add (Var "x") (Val 3))
+
+                    e == Val 0
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 7│>  expect
+                 8│>      e : Expr
+                 9│>      e = Mul (Val 2) (Add (Var "x") (Val 3))
+                10│>
+                11│>      e == Val 0
+
+                When it failed, these variables had these values:
+
+                e : Expr
+                e = Mul (Val 2) (Add (Var "x") (Val 3))
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn nullable_tag_id_in_data() {
+        // too many tags for the tag id to fit in the pointer
+        run_expect_test(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Path : [End, A Path, B Path, C Path, D Path, E Path, F Path, G Path, H Path]
+
+                expect
+                    p : Path
+                    p = C (H (A End))
+
+                    p == End
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                 7│>  expect
+                 8│>      p : Path
+                 9│>      p = C (H (A End))
+                10│>
+                11│>      p == End
+
+                When it failed, these variables had these values:
+
+                p : Path
+                p = C (H (A End))
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn deep_recursive_value_is_elided() {
+        run_expect_test_help(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                ConsList : [Nil, Cons I64 ConsList]
+
+                build : I64, ConsList -> ConsList
+                build = \n, acc ->
+                    if n == 0 then acc else build (n - 1) (Cons n acc)
+
+                expect
+                    list : ConsList
+                    list = build 1000 Nil
+
+                    list == Nil
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                11│>  expect
+                12│>      list : ConsList
+                13│>      list = build 1000 Nil
+                14│>
+                15│>      list == Nil
+
+                When it failed, these variables had these values:
+
+                list : ConsList
+                list = Cons 1 (Cons 2 (Cons 3 (Cons 4 (Cons 5 (Cons 6 (Cons 7 (Cons 8 (Cons 9 (Cons 10 (Cons 11 (Cons 12 ...)))))))))))
+                "#
+            ),
+            |memory| memory.set_max_frame_bytes(256),
+        );
+    }
+
+    #[test]
+    fn deep_tree_is_elided_past_the_recursion_budget() {
+        run_expect_test_help(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                Tree : [Leaf, Node Tree I64 Tree]
+
+                build : I64, I64 -> Tree
+                build = \depth, n ->
+                    if depth == 0 then Leaf else Node (build (depth - 1) (2 * n)) n (build (depth - 1) (2 * n + 1))
+
+                expect
+                    tree : Tree
+                    tree = build 12 1
+
+                    tree == Leaf
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                11│>  expect
+                12│>      tree : Tree
+                13│>      tree = build 12 1
+                14│>
+                15│>      tree == Leaf
+
+                When it failed, these variables had these values:
+
+                tree : Tree
+                tree = Node (Node (Node ... 4 ...) 2 (Node ... 5 ...)) 1 (Node (Node ... 6 ...) 3 (Node ... 7 ...))
+                "#
+            ),
+            |memory| memory.set_recursion_budget(2),
+        );
+    }
+
+    #[test]
+    fn shared_value_elided_in_one_lookup_is_shown_in_full_in_another() {
+        run_expect_test_help(
+            indoc!(
+                r#"
+                app "test" provides [main] to "./platform"
+
+                main = 0
+
+                ConsList : [Nil, Cons I64 ConsList]
+
+                dropTwo : ConsList -> ConsList
+                dropTwo = \list ->
+                    when list is
+                        Cons _ (Cons _ rest) -> rest
+                        _ -> Nil
+
+                expect
+                    list : ConsList
+                    list = Cons 0 (Cons 1 (Cons 2 (Cons 3 Nil)))
+
+                    rest : ConsList
+                    rest = dropTwo list
+
+                    list == rest
+                "#
+            ),
+            indoc!(
+                r#"
+                This expectation failed:
+
+                13│>  expect
+                14│>      list : ConsList
+                15│>      list = Cons 0 (Cons 1 (Cons 2 (Cons 3 Nil)))
+                16│>
+                17│>      rest : ConsList
+                18│>      rest = dropTwo list
+                19│>
+                20│>      list == rest
+
+                When it failed, these variables had these values:
+
+                list : ConsList
+                list = Cons 0 (Cons 1 (Cons 2 ...))
+
+                rest : ConsList
+                rest = Cons 2 (Cons 3 Nil)
+                "#
+            ),
+            // `rest` points into `list`, whose clone runs out of budget right where `rest` starts
+            |memory| memory.set_recursion_budget(2),
+        );
+    }
+
+    #[test]
+    fn huge_value_gets_a_frame_size_warning() {
+        let fields: Vec<String> = (0..128).map(|i| format!("f{i}: {i}")).collect();
+        let source = indoc::formatdoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            main = 0
+
+            expect
+                small = 1
+
+                small == 2
+
+            expect
+                huge = {{ {} }}
+
+                huge.f0 == 1
+            "#,
+            fields.join(", ")
+        );
+
+        let arena = bumpalo::Bump::new();
+        let loaded = load_test_module(&arena, &source);
+        let limit = crate::run::ExpectMemory::default_frame_limit();
+
+        let frames = crate::frame_size::large_frames(&loaded, limit);
+
+        assert_eq!(frames.len(), 1);
+        assert!(!frames[0].is_dbg);
+        assert!(frames[0].bytes >= 128 * 8);
+
+        let (path, source) = &loaded.sources[&frames[0].module_id];
+        assert_eq!(
+            frames[0].warning(path, source, limit),
+            format!(
+                "Warning: the expect at {}:10 reports on values that take at least {} bytes, \
+                 but a frame only holds {} bytes. Consider checking a smaller value.",
+                path.display(),
+                frames[0].bytes,
+                limit
+            )
+        );
+    }
+
+    #[test]
+    fn function_lookup_gets_a_warning() {
+        let source = indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            main = 0
+
+            expect
+                name = "Astra mortemque praestare gradatim"
+                greet = \greeting -> Str.concat greeting name
+
+                greet "Hi " == name
+            "#
+        );
+
+        let arena = bumpalo::Bump::new();
+        let loaded = load_test_module(&arena, source);
+
+        let lookups = crate::unshowable::unshowable_lookups(&loaded);
+
+        assert_eq!(lookups.len(), 1);
+        assert!(!lookups[0].is_dbg);
+
+        let (path, source) = &loaded.sources[&lookups[0].module_id];
+        let warning = lookups[0].warning(path, source, &loaded.interns);
+        assert!(warning.contains("expect at"), "{}", warning);
+        assert!(
+            warning.contains("`greet`, which holds a function"),
+            "{}",
+            warning
+        );
+    }
 }
@@ -0,0 +1,216 @@
+//! Renders the values in an expect frame the way `roc test` shows them.
+//!
+//! This is what `render_expect_failure` does for each lookup, without the report around it:
+//! the frame is read back with `get_values`, and each value is formatted as Roc code. Together
+//! with the `serialize` module, this lets the whole way from a value to its rendering be tested
+//! without running any generated code.
+use bumpalo::Bump;
+use roc_fmt::annotation::Formattable;
+use roc_module::symbol::Interns;
+use roc_mono::layout::GlobalLayoutInterner;
+use roc_target::TargetInfo;
+use roc_types::subs::Subs;
+
+use crate::frames::FrameFormat;
+
+/// The values of the `number_of_lookups` lookups in the frame at `offset` in `buffer`, each
+/// rendered like it is after `name = ` in a failure report.
+///
+/// The type of each value is the variable that the frame stores for it, looked up in `subs`.
+#[allow(clippy::too_many_arguments)]
+pub fn render_frame_values<'a>(
+    target_info: TargetInfo,
+    format: FrameFormat,
+    arena: &'a Bump,
+    subs: &Subs,
+    interns: &'a Interns,
+    layout_interner: &GlobalLayoutInterner<'a>,
+    buffer: &[u8],
+    offset: usize,
+    number_of_lookups: usize,
+) -> Vec<String> {
+    let (_, expressions, _variables) = crate::get_values(
+        target_info,
+        arena,
+        subs,
+        interns,
+        layout_interner,
+        buffer.as_ptr(),
+        format.lookups_offset(buffer, offset),
+        number_of_lookups,
+    );
+
+    expressions
+        .iter()
+        .map(|expr| {
+            let mut buf = roc_fmt::Buf::new_in(arena);
+            expr.format(&mut buf, 0);
+
+            buf.into_bump_str().to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use bumpalo::Bump;
+    use roc_module::ident::TagName;
+    use roc_module::symbol::{Interns, ModuleId, Symbol};
+    use roc_mono::layout::{GlobalLayoutInterner, LayoutCache};
+    use roc_region::all::Region;
+    use roc_target::TargetInfo;
+    use roc_types::subs::{
+        Content, Descriptor, FlatType, Subs, TagExt, UnionTags, Variable, VariableSubsSlice,
+    };
+
+    use super::render_frame_values;
+    use crate::frames::FrameFormat;
+    use crate::serialize::{Lookup, Serializer};
+
+    const TARGET_INFO: TargetInfo = TargetInfo::default_x86_64();
+
+    fn words(words: &[usize]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|w| (*w as u64).to_le_bytes())
+            .collect()
+    }
+
+    /// Write a frame with one lookup of type `variable`, whose value is at the start of
+    /// `source`, and render it back.
+    fn render_one(subs: &Subs, variable: Variable, source: &[u8]) -> String {
+        render_one_for(TARGET_INFO, subs, variable, source)
+    }
+
+    fn render_one_for(
+        target_info: TargetInfo,
+        subs: &Subs,
+        variable: Variable,
+        source: &[u8],
+    ) -> String {
+        let arena = Bump::new();
+        let interns = Interns::default();
+
+        let layout_interner = GlobalLayoutInterner::with_capacity(4, target_info);
+        let layout = LayoutCache::new(layout_interner.fork(), target_info)
+            .from_var(&arena, variable, subs)
+            .unwrap();
+        let mut st_interner = layout_interner
+            .unwrap()
+            .unwrap_or_else(|_| panic!("the layout interner is still shared"));
+
+        let lookups = [Lookup {
+            addr: 0,
+            layout,
+            variable,
+        }];
+
+        let mut serializer = Serializer::new(&arena, &mut st_interner, source);
+        serializer.write_frame(0, ModuleId::ATTR, Region::zero(), &lookups);
+        let frame = serializer.into_frame();
+
+        let layout_interner = st_interner.into_global();
+        let mut rendered = render_frame_values(
+            target_info,
+            FrameFormat::Regions,
+            &arena,
+            subs,
+            &interns,
+            &layout_interner,
+            &frame,
+            0,
+            1,
+        );
+
+        rendered.remove(0)
+    }
+
+    fn structure(subs: &mut Subs, flat_type: FlatType) -> Variable {
+        subs.fresh(Descriptor::from(Content::Structure(flat_type)))
+    }
+
+    #[test]
+    fn integer() {
+        let subs = Subs::new();
+
+        assert_eq!(render_one(&subs, Variable::I64, &42i64.to_le_bytes()), "42");
+    }
+
+    #[test]
+    fn big_string() {
+        let subs = Subs::new();
+        let text = b"Profundum et fundamentum";
+
+        let mut source = words(&[24, text.len(), text.len()]);
+        source.extend_from_slice(text);
+
+        assert_eq!(
+            render_one(&subs, Variable::STR, &source),
+            "\"Profundum et fundamentum\""
+        );
+    }
+
+    #[test]
+    fn list_of_integers() {
+        let mut subs = Subs::new();
+        let elements = VariableSubsSlice::insert_into_subs(&mut subs, [Variable::I64]);
+        let list = structure(&mut subs, FlatType::Apply(Symbol::LIST_LIST, elements));
+
+        // the list struct, with a capacity of 4, and its elements at 32
+        let mut source = words(&[32, 3, 4, 0]);
+        source.extend(words(&[7, 8, 9]));
+
+        assert_eq!(render_one(&subs, list, &source), "[7, 8, 9]");
+    }
+
+    #[test]
+    fn boxed_big_string() {
+        let mut subs = Subs::new();
+        let inner = VariableSubsSlice::insert_into_subs(&mut subs, [Variable::STR]);
+        let boxed = structure(&mut subs, FlatType::Apply(Symbol::BOX_BOX_TYPE, inner));
+        let text = b"Astra mortemque praestare gradatim";
+
+        // the box at 0 points to the string at 8, whose bytes are at 32
+        let mut source = words(&[8, 32, text.len(), text.len()]);
+        source.extend_from_slice(text);
+
+        assert_eq!(
+            render_one(&subs, boxed, &source),
+            "Box.box \"Astra mortemque praestare gradatim\""
+        );
+    }
+
+    #[test]
+    fn small_union() {
+        let mut subs = Subs::new();
+        let tags = UnionTags::insert_into_subs(
+            &mut subs,
+            [
+                (TagName("Err".into()), vec![Variable::STR]),
+                (TagName("Ok".into()), vec![Variable::I64]),
+            ],
+        );
+        let result = structure(
+            &mut subs,
+            FlatType::TagUnion(tags, TagExt::Any(Variable::EMPTY_TAG_UNION)),
+        );
+
+        // the payload, then the tag id after the biggest payload (a `Str`)
+        let ok = words(&[42, 0, 0, 1]);
+
+        assert_eq!(render_one(&subs, result, &ok), "Ok 42");
+    }
+
+    #[test]
+    fn nat_of_a_32_bit_target() {
+        let subs = Subs::new();
+
+        // written by a wasm32 program, and read on a 64-bit host
+        let source = 4_000_000_000u32.to_le_bytes();
+
+        assert_eq!(
+            render_one_for(TargetInfo::default_wasm32(), &subs, Variable::NAT, &source),
+            "4000000000"
+        );
+    }
+}
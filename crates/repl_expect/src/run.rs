@@ -9,6 +9,7 @@ use std::{
 use bumpalo::collections::Vec as BumpVec;
 use bumpalo::Bump;
 use inkwell::context::Context;
+use inkwell::module::Module;
 use roc_build::link::llvm_module_to_dylib;
 use roc_can::expr::ExpectLookup;
 use roc_collections::{MutSet, VecMap};
@@ -30,9 +31,14 @@ use roc_target::TargetInfo;
 use roc_types::subs::Subs;
 use target_lexicon::Triple;
 
+use crate::frames::{FrameFormat, FRAME_HEADER_BYTES};
+
 pub struct ExpectMemory<'a> {
     ptr: *mut u8,
     length: usize,
+    max_frame_bytes: usize,
+    native_layout: bool,
+    recursion_budget: usize,
     shm_name: Option<std::ffi::CString>,
     _marker: std::marker::PhantomData<&'a ()>,
 }
@@ -42,10 +48,24 @@ impl<'a> ExpectMemory<'a> {
 
     #[cfg(test)]
     pub(crate) fn from_slice(slice: &mut [u8]) -> Self {
+        Self::from_raw_parts(slice.as_mut_ptr(), slice.len(), None)
+    }
+
+    fn from_raw_parts(ptr: *mut u8, length: usize, shm_name: Option<std::ffi::CString>) -> Self {
+        // `ExpectSequence` and the generated code access the header a word at a time
+        assert_eq!(
+            ptr as usize % std::mem::align_of::<usize>(),
+            0,
+            "the shared memory must be aligned to the pointer size"
+        );
+
         Self {
-            ptr: slice.as_mut_ptr(),
-            length: slice.len(),
-            shm_name: None,
+            ptr,
+            length,
+            max_frame_bytes: Self::default_max_frame_bytes(length),
+            native_layout: false,
+            recursion_budget: usize::MAX,
+            shm_name,
             _marker: std::marker::PhantomData,
         }
     }
@@ -57,7 +77,46 @@ impl<'a> ExpectMemory<'a> {
 
     fn reuse_mmap(&mut self) -> Option<Self> {
         let shm_name = self.shm_name.as_ref()?.clone();
-        Some(Self::mmap_help(shm_name, libc::O_RDWR))
+        let mut memory = Self::mmap_help(shm_name, libc::O_RDWR);
+        memory.set_max_frame_bytes(self.max_frame_bytes);
+        memory.set_native_layout(self.native_layout);
+        memory.set_recursion_budget(self.recursion_budget);
+        Some(memory)
+    }
+
+    /// Recursive values are only written into the shared memory while it holds fewer than
+    /// this many bytes. Deeper parts of such values are elided, and rendered as `...`.
+    ///
+    /// The limit is checked before each recursive step, so it is a soft limit: leave some room
+    /// between it and the end of the buffer for the non-recursive parts of a value.
+    pub fn set_max_frame_bytes(&mut self, max_frame_bytes: usize) {
+        self.max_frame_bytes = max_frame_bytes.min(self.length);
+    }
+
+    /// Write each value at an offset that is a multiple of its alignment, rather than packing
+    /// values one after another. A struct, or the elements of a list, can then be read in
+    /// place, as long as the buffer itself is aligned (the shared memory is page-aligned).
+    ///
+    /// Pointers are still offsets from the start of the buffer, so values that contain them
+    /// must still be read with `get_values`.
+    pub fn set_native_layout(&mut self, native_layout: bool) {
+        self.native_layout = native_layout;
+    }
+
+    /// Each lookup of a frame may follow at most this many recursive pointers, so a value
+    /// like a tree is shown to the same depth however big it is. Deeper parts of the value
+    /// are elided, and rendered as `...`. There is no limit by default.
+    pub fn set_recursion_budget(&mut self, recursion_budget: usize) {
+        self.recursion_budget = recursion_budget;
+    }
+
+    fn default_max_frame_bytes(length: usize) -> usize {
+        length - length / 4
+    }
+
+    /// The `max_frame_bytes` of the shared memory that `roc test` and `roc dev` create
+    pub fn default_frame_limit() -> usize {
+        Self::default_max_frame_bytes(Self::SHM_SIZE)
     }
 
     fn mmap_help(cstring: std::ffi::CString, shm_flags: i32) -> Self {
@@ -97,15 +156,17 @@ impl<'a> ExpectMemory<'a> {
             ptr
         };
 
+        let memory = Self::from_raw_parts(ptr.cast(), Self::SHM_SIZE, Some(cstring));
+
         // puts in the initial header
-        let _ = ExpectSequence::new(ptr as *mut u8);
+        let _ = ExpectSequence::new(
+            memory.ptr,
+            memory.max_frame_bytes,
+            memory.native_layout,
+            memory.recursion_budget,
+        );
 
-        Self {
-            ptr: ptr.cast(),
-            length: Self::SHM_SIZE,
-            shm_name: Some(cstring),
-            _marker: std::marker::PhantomData,
-        }
+        memory
     }
 
     fn set_shared_buffer(&mut self, lib: &libloading::Library) {
@@ -205,6 +266,7 @@ pub(crate) fn run_expects_with_memory<'a, W: std::io::Write>(
             expectations,
             memory,
             expect,
+            expects.frame_format,
         )?;
 
         match result {
@@ -226,6 +288,7 @@ pub(crate) fn run_expects_with_memory<'a, W: std::io::Write>(
             expectations,
             memory,
             expect,
+            expects.frame_format,
         )?;
 
         match result {
@@ -248,10 +311,16 @@ fn run_expect_pure<'a, W: std::io::Write>(
     expectations: &mut VecMap<ModuleId, Expectations>,
     shared_memory: &mut ExpectMemory,
     expect: ToplevelExpect<'_>,
+    frame_format: FrameFormat,
 ) -> std::io::Result<bool> {
     use roc_gen_llvm::try_run_jit_function;
 
-    let sequence = ExpectSequence::new(shared_memory.ptr.cast());
+    let sequence = ExpectSequence::new(
+        shared_memory.ptr.cast(),
+        shared_memory.max_frame_bytes,
+        shared_memory.native_layout,
+        shared_memory.recursion_budget,
+    );
 
     let result: Result<(), (String, _)> = try_run_jit_function!(lib, expect.name, (), |v: ()| v);
 
@@ -283,6 +352,7 @@ fn run_expect_pure<'a, W: std::io::Write>(
                     layout_interner,
                     shared_memory_ptr,
                     offset,
+                    frame_format,
                 )?;
             }
         }
@@ -306,6 +376,7 @@ fn run_expect_fx<'a, W: std::io::Write>(
     expectations: &mut VecMap<ModuleId, Expectations>,
     parent_memory: &mut ExpectMemory,
     expect: ToplevelExpect<'_>,
+    frame_format: FrameFormat,
 ) -> std::io::Result<bool> {
     use signal_hook::{consts::signal::SIGCHLD, consts::signal::SIGUSR1, iterator::Signals};
 
@@ -319,7 +390,12 @@ fn run_expect_fx<'a, W: std::io::Write>(
 
             let mut child_memory = parent_memory.reuse_mmap().unwrap();
 
-            let sequence = ExpectSequence::new(child_memory.ptr);
+            let sequence = ExpectSequence::new(
+                child_memory.ptr,
+                child_memory.max_frame_bytes,
+                child_memory.native_layout,
+                child_memory.recursion_budget,
+            );
 
             child_memory.set_shared_buffer(lib);
 
@@ -357,8 +433,11 @@ fn run_expect_fx<'a, W: std::io::Write>(
                         // this is the signal we use for an expect failure. Let's see what the child told us
                         has_succeeded = false;
 
-                        let frame =
-                            ExpectFrame::at_offset(parent_memory.ptr, ExpectSequence::START_OFFSET);
+                        let frame = ExpectFrame::at_offset(
+                            parent_memory.ptr,
+                            ExpectSequence::START_OFFSET,
+                            frame_format,
+                        );
                         let module_id = frame.module_id;
 
                         let data = expectations.get_mut(&module_id).unwrap();
@@ -384,6 +463,7 @@ fn run_expect_fx<'a, W: std::io::Write>(
                             layout_interner,
                             parent_memory.ptr,
                             ExpectSequence::START_OFFSET,
+                            frame_format,
                         )?;
                     }
                     _ => println!("received signal {}", sig),
@@ -406,7 +486,12 @@ pub fn render_expects_in_memory<'a>(
 ) -> std::io::Result<usize> {
     let shared_ptr = memory.ptr;
 
-    let frame = ExpectFrame::at_offset(shared_ptr, ExpectSequence::START_OFFSET);
+    // `roc dev` builds without `expect_sources`
+    let frame = ExpectFrame::at_offset(
+        shared_ptr,
+        ExpectSequence::START_OFFSET,
+        FrameFormat::Regions,
+    );
     let module_id = frame.module_id;
 
     let data = expectations.get_mut(&module_id).unwrap();
@@ -432,6 +517,7 @@ pub fn render_expects_in_memory<'a>(
         layout_interner,
         shared_ptr,
         ExpectSequence::START_OFFSET,
+        FrameFormat::Regions,
     )
 }
 
@@ -445,7 +531,12 @@ pub fn render_dbgs_in_memory<'a>(
 ) -> std::io::Result<usize> {
     let shared_ptr = memory.ptr;
 
-    let frame = ExpectFrame::at_offset(shared_ptr, ExpectSequence::START_OFFSET);
+    // `roc dev` builds without `expect_sources`
+    let frame = ExpectFrame::at_offset(
+        shared_ptr,
+        ExpectSequence::START_OFFSET,
+        FrameFormat::Regions,
+    );
     let module_id = frame.module_id;
 
     let data = expectations.get_mut(&module_id).unwrap();
@@ -470,6 +561,7 @@ pub fn render_dbgs_in_memory<'a>(
         layout_interner,
         shared_ptr,
         ExpectSequence::START_OFFSET,
+        FrameFormat::Regions,
     )
 }
 
@@ -504,11 +596,12 @@ fn render_dbg_failure<'a>(
     layout_interner: &GlobalLayoutInterner<'a>,
     start: *const u8,
     offset: usize,
+    frame_format: FrameFormat,
 ) -> std::io::Result<usize> {
     // we always run programs as the host
     let target_info = (&target_lexicon::Triple::host()).into();
 
-    let frame = ExpectFrame::at_offset(start, offset);
+    let frame = ExpectFrame::at_offset(start, offset, frame_format);
     let module_id = frame.module_id;
 
     let failure_region = frame.region;
@@ -552,11 +645,12 @@ fn render_expect_failure<'a>(
     layout_interner: &GlobalLayoutInterner<'a>,
     start: *const u8,
     offset: usize,
+    frame_format: FrameFormat,
 ) -> std::io::Result<usize> {
     // we always run programs as the host
     let target_info = (&target_lexicon::Triple::host()).into();
 
-    let frame = ExpectFrame::at_offset(start, offset);
+    let frame = ExpectFrame::at_offset(start, offset, frame_format);
     let module_id = frame.module_id;
 
     let failure_region = frame.region;
@@ -600,18 +694,37 @@ struct ExpectSequence {
 }
 
 impl ExpectSequence {
-    const START_OFFSET: usize = 8 + 8 + 8;
+    const START_OFFSET: usize = crate::frames::FRAMES_START;
 
     const COUNT_INDEX: usize = 0;
     const OFFSET_INDEX: usize = 1;
     const LOCK_INDEX: usize = 2;
-
-    fn new(ptr: *mut u8) -> Self {
+    const MAX_FRAME_BYTES_INDEX: usize = 3;
+    const NATIVE_LAYOUT_INDEX: usize = 4;
+    const RECURSION_BUDGET_INDEX: usize = 5;
+
+    fn new(
+        ptr: *mut u8,
+        max_frame_bytes: usize,
+        native_layout: bool,
+        recursion_budget: usize,
+    ) -> Self {
         unsafe {
             let ptr = ptr as *mut usize;
             std::ptr::write_unaligned(ptr.add(Self::COUNT_INDEX), 0);
             std::ptr::write_unaligned(ptr.add(Self::OFFSET_INDEX), Self::START_OFFSET);
             std::ptr::write_unaligned(ptr.add(Self::LOCK_INDEX), 0);
+
+            // the generated code reads these as 8-byte slots, whatever the pointer size
+            let slots = ptr as *mut u64;
+            let settings = [
+                (Self::MAX_FRAME_BYTES_INDEX, max_frame_bytes as u64),
+                (Self::NATIVE_LAYOUT_INDEX, native_layout as u64),
+                (Self::RECURSION_BUDGET_INDEX, recursion_budget as u64),
+            ];
+            for (index, value) in settings {
+                std::ptr::write_unaligned(slots.add(index), value);
+            }
         }
 
         Self {
@@ -667,15 +780,21 @@ struct ExpectFrame {
 }
 
 impl ExpectFrame {
-    fn at_offset(start: *const u8, offset: usize) -> Self {
+    fn at_offset(start: *const u8, offset: usize, frame_format: FrameFormat) -> Self {
         let region_bytes: [u8; 8] = unsafe { *(start.add(offset).cast()) };
         let region: Region = unsafe { std::mem::transmute(region_bytes) };
 
         let module_id_bytes: [u8; 4] = unsafe { *(start.add(offset + 8).cast()) };
         let module_id: ModuleId = unsafe { std::mem::transmute(module_id_bytes) };
 
-        // skip to frame
-        let start_offset = offset + 8 + 4;
+        // skip to the lookups; the format only reads the header, and the source's length after it
+        let header = unsafe {
+            std::slice::from_raw_parts(
+                start,
+                offset + FRAME_HEADER_BYTES + std::mem::size_of::<u32>(),
+            )
+        };
+        let start_offset = frame_format.lookups_offset(header, offset);
 
         Self {
             region,
@@ -696,6 +815,8 @@ pub struct ToplevelExpect<'a> {
 pub struct ExpectFunctions<'a> {
     pub pure: BumpVec<'a, ToplevelExpect<'a>>,
     pub fx: BumpVec<'a, ToplevelExpect<'a>>,
+    /// What the frames of these expects are written in
+    pub frame_format: FrameFormat,
 }
 
 pub fn expect_mono_module_to_dylib<'a>(
@@ -704,6 +825,7 @@ pub fn expect_mono_module_to_dylib<'a>(
     loaded: MonomorphizedModule<'a>,
     opt_level: OptLevel,
     mode: LlvmBackendMode,
+    frame_format: FrameFormat,
 ) -> Result<
     (
         libloading::Library,
@@ -712,20 +834,59 @@ pub fn expect_mono_module_to_dylib<'a>(
     ),
     libloading::Error,
 > {
-    let target_info = TargetInfo::from(&target);
+    let context = Context::create();
+    let (module, expects, layout_interner) = expect_mono_module_to_llvm(
+        arena,
+        &context,
+        &target,
+        loaded,
+        opt_level,
+        mode,
+        frame_format,
+    );
+
+    llvm_module_to_dylib(module, &target, opt_level).map(|lib| (lib, expects, layout_interner))
+}
+
+/// The optimized and verified LLVM module of `loaded`, with its expects exposed.
+///
+/// With `FrameFormat::WithSource`, every frame has the text of its condition, taken from the
+/// sources of `loaded`.
+pub(crate) fn expect_mono_module_to_llvm<'a, 'ctx>(
+    arena: &'a Bump,
+    context: &'ctx Context,
+    target: &Triple,
+    loaded: MonomorphizedModule<'a>,
+    opt_level: OptLevel,
+    mode: LlvmBackendMode,
+    frame_format: FrameFormat,
+) -> (
+    &'ctx Module<'ctx>,
+    ExpectFunctions<'a>,
+    STLayoutInterner<'a>,
+)
+where
+    'a: 'ctx,
+{
+    let target_info = TargetInfo::from(target);
 
     let MonomorphizedModule {
         toplevel_expects,
         procedures,
         interns,
         mut layout_interner,
+        sources,
         ..
     } = loaded;
 
-    let context = Context::create();
+    let expect_sources = match frame_format {
+        FrameFormat::Regions => None,
+        FrameFormat::WithSource => Some(&*arena.alloc(sources)),
+    };
+
     let builder = context.create_builder();
     let module = arena.alloc(roc_gen_llvm::llvm::build::module_from_builtins(
-        &target, &context, "",
+        target, context, "",
     ));
 
     let module = arena.alloc(module);
@@ -740,13 +901,14 @@ pub fn expect_mono_module_to_dylib<'a>(
         builder: &builder,
         dibuilder: &dibuilder,
         compile_unit: &compile_unit,
-        context: &context,
+        context,
         interns,
         module,
         target_info,
         mode,
         // important! we don't want any procedures to get the C calling convention
         exposed_to_host: MutSet::default(),
+        expect_sources,
     };
 
     // Add roc_alloc, roc_realloc, and roc_dealloc, since the repl has no
@@ -796,6 +958,7 @@ pub fn expect_mono_module_to_dylib<'a>(
     let expects = ExpectFunctions {
         pure: expects_pure,
         fx: expects_fx,
+        frame_format,
     };
 
     env.dibuilder.finalize();
@@ -821,5 +984,5 @@ pub fn expect_mono_module_to_dylib<'a>(
         );
     }
 
-    llvm_module_to_dylib(env.module, &target, opt_level).map(|lib| (lib, expects, layout_interner))
+    (env.module, expects, layout_interner)
 }
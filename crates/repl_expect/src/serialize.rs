@@ -0,0 +1,1011 @@
+//! A pure-Rust reference for the expect frame format.
+//!
+//! `roc_gen_llvm` writes the values of an `expect`'s lookups into shared memory with the
+//! generated code in `llvm/expect.rs` (`clone_to_shared_memory` and `build_clone`), and
+//! `get_values` reads them back. This module produces the same bytes without LLVM, from a
+//! byte view of the values, so the wire format can be tested on its own.
+//!
+//! The source values live in a byte slice, and pointers inside them are offsets into that
+//! same slice. Any code that changes what `build_clone` writes should change this too.
+use bumpalo::Bump;
+use roc_module::symbol::ModuleId;
+use roc_mono::layout::{
+    round_up_to_alignment, Builtin, InLayout, Layout, LayoutInterner, STLayoutInterner, UnionLayout,
+};
+use roc_region::all::Region;
+use roc_target::{PtrWidth, TargetInfo};
+use roc_types::subs::Variable;
+
+use crate::frames::after_condition_source;
+
+/// Where the current value goes in the frame (`offset`), and where the data
+/// that it points to goes (`extra_offset`).
+#[derive(Debug, Clone, Copy)]
+pub struct Cursors {
+    pub offset: usize,
+    pub extra_offset: usize,
+}
+
+/// A lookup to write into a frame: the address of its value in the source,
+/// the layout of that value, and its type variable.
+#[derive(Debug, Clone, Copy)]
+pub struct Lookup<'a> {
+    pub addr: usize,
+    pub layout: InLayout<'a>,
+    pub variable: Variable,
+}
+
+/// How many pointers the generated code remembers per frame (`CLONE_SEEN_CAPACITY` in `expect.zig`)
+const CLONE_SEEN_CAPACITY: usize = 64;
+
+pub struct Serializer<'a, 'r> {
+    arena: &'a Bump,
+    layout_interner: &'r mut STLayoutInterner<'a>,
+    target_info: TargetInfo,
+    source: &'r [u8],
+    max_frame_bytes: usize,
+    /// How many recursive pointers the current lookup may follow, and how many it is inside of
+    recursion_budget: usize,
+    recursion_depth: usize,
+    /// Whether values are placed at offsets that are a multiple of their alignment
+    native_layout: bool,
+    /// The source of the condition's module, when frames are written in the `WithSource` format
+    module_source: Option<&'r str>,
+    frame: Vec<u8>,
+    /// The source pointers written into the current frame, and the word written for each
+    seen: Vec<(usize, u64)>,
+}
+
+impl<'a, 'r> Serializer<'a, 'r> {
+    pub fn new(
+        arena: &'a Bump,
+        layout_interner: &'r mut STLayoutInterner<'a>,
+        source: &'r [u8],
+    ) -> Self {
+        let target_info = layout_interner.target_info();
+
+        Self {
+            arena,
+            layout_interner,
+            target_info,
+            source,
+            max_frame_bytes: usize::MAX,
+            recursion_budget: usize::MAX,
+            recursion_depth: 0,
+            native_layout: false,
+            module_source: None,
+            frame: Vec::new(),
+            seen: Vec::new(),
+        }
+    }
+
+    /// Past this many bytes, recursive values are elided, like the limit
+    /// the host puts in the shared memory header.
+    pub fn with_max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.max_frame_bytes = max_frame_bytes;
+        self
+    }
+
+    /// Past this many recursive pointers in one lookup, the rest of its value is elided, like
+    /// the budget the host puts in the shared memory header.
+    pub fn with_recursion_budget(mut self, recursion_budget: usize) -> Self {
+        self.recursion_budget = recursion_budget;
+        self
+    }
+
+    /// Place each value at an offset that is a multiple of its alignment, like the generated
+    /// code does when the host sets `ExpectMemory::set_native_layout`.
+    pub fn with_native_layout(mut self) -> Self {
+        self.native_layout = true;
+        self
+    }
+
+    /// Write frames in the `WithSource` format, like the generated code does when it is given
+    /// `expect_sources`, with the condition's text taken from `module_source`.
+    pub fn with_module_source(mut self, module_source: &'r str) -> Self {
+        self.module_source = Some(module_source);
+        self
+    }
+
+    /// The bytes written so far. Bytes that were skipped over are zero.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    pub fn into_frame(self) -> Vec<u8> {
+        self.frame
+    }
+
+    /// Write a whole expect frame at `offset`, like `clone_to_shared_memory`,
+    /// and return the offset just past it.
+    pub fn write_frame(
+        &mut self,
+        offset: usize,
+        condition_module: ModuleId,
+        region: Region,
+        lookups: &[Lookup<'a>],
+    ) -> usize {
+        let module_id: u32 = unsafe { std::mem::transmute(condition_module) };
+
+        self.seen.clear();
+
+        let mut offset = offset;
+        offset = self.write_u32(offset, region.start().offset);
+        offset = self.write_u32(offset, region.end().offset);
+        offset = self.write_u32(offset, module_id);
+
+        if let Some(module_source) = self.module_source {
+            let range = region.start().offset as usize..region.end().offset as usize;
+            let text = module_source.get(range).unwrap_or("");
+
+            let len_offset = offset;
+            offset = self.write_u32(offset, text.len() as u32);
+            self.frame_slice(offset, text.len())
+                .copy_from_slice(text.as_bytes());
+            offset = after_condition_source(len_offset, text.len());
+        }
+
+        let after_header = offset;
+
+        offset += lookups.len() * (self.ptr_size() + std::mem::size_of::<u32>());
+
+        let mut lookup_starts = Vec::with_capacity(lookups.len());
+        for lookup in lookups {
+            offset = self.align(offset, self.layout_interner.alignment_bytes(lookup.layout));
+            lookup_starts.push(offset);
+
+            let stack_size = self.layout_interner.stack_size(lookup.layout) as usize;
+            let cursors = Cursors {
+                offset,
+                extra_offset: offset + stack_size,
+            };
+
+            offset = self.write_value(cursors, lookup.addr, lookup.layout);
+        }
+
+        let mut table_offset = after_header;
+        for (lookup_start, lookup) in lookup_starts.into_iter().zip(lookups) {
+            table_offset = self.write_word(table_offset, lookup_start);
+            table_offset = self.write_u32(table_offset, lookup.variable.index());
+        }
+
+        offset
+    }
+
+    /// Write the value at `addr` in the source, like `build_clone`,
+    /// and return the new extra offset.
+    pub fn write_value(&mut self, cursors: Cursors, addr: usize, layout: InLayout<'a>) -> usize {
+        match self.layout_interner.get(layout) {
+            Layout::Builtin(builtin) => self.write_builtin(cursors, addr, layout, builtin),
+
+            Layout::Struct { field_layouts, .. } => self.write_struct(cursors, addr, field_layouts),
+
+            // functions are never displayed, but their captures are written like any other value
+            Layout::LambdaSet(lambda_set) => {
+                self.write_value(cursors, addr, lambda_set.runtime_representation())
+            }
+
+            Layout::Union(union_layout) => {
+                if self.layout_interner.safe_to_memcpy(layout) {
+                    let size = self.layout_interner.stack_size(layout) as usize;
+                    self.copy(cursors.offset, addr, size);
+
+                    cursors.extra_offset
+                } else if let UnionLayout::NonRecursive(_) = union_layout {
+                    self.write_tag(cursors, addr, union_layout)
+                } else {
+                    let tag_ptr = self.read_word(addr);
+                    let word_width = self.union_pointer_width(union_layout);
+
+                    self.write_shared(cursors, tag_ptr, word_width, |this| {
+                        this.write_tag(cursors, tag_ptr, union_layout)
+                    })
+                }
+            }
+
+            Layout::Boxed(inner_layout) => {
+                let inner_addr = self.read_word(addr);
+                let word_width = self.ptr_size();
+
+                self.write_shared(cursors, inner_addr, word_width, |this| {
+                    let alignment = this.layout_interner.alignment_bytes(inner_layout);
+                    let cursors = Cursors {
+                        offset: cursors.offset,
+                        extra_offset: this.align(cursors.extra_offset, alignment),
+                    };
+
+                    this.write_word(cursors.offset, cursors.extra_offset);
+
+                    let inner_width = this.layout_interner.stack_size(inner_layout) as usize;
+
+                    let cursors = Cursors {
+                        offset: cursors.extra_offset,
+                        extra_offset: cursors.extra_offset + inner_width,
+                    };
+
+                    this.write_value(cursors, inner_addr, inner_layout)
+                })
+            }
+
+            Layout::RecursivePointer(rec_layout) => {
+                let union_layout = match self.layout_interner.get(rec_layout) {
+                    Layout::Union(union_layout) => union_layout,
+                    other => unreachable!("recursive pointer to a non-union: {:?}", other),
+                };
+
+                let tag_ptr = self.read_word(addr);
+                let word_width = self.union_pointer_width(union_layout);
+
+                self.write_shared(cursors, tag_ptr, word_width, |this| {
+                    if cursors.extra_offset >= this.max_frame_bytes
+                        || this.recursion_depth >= this.recursion_budget
+                    {
+                        let elided = this.word_mask();
+                        this.write_word(cursors.offset, elided);
+
+                        cursors.extra_offset
+                    } else {
+                        this.recursion_depth += 1;
+                        let extra_offset = this.write_tag(cursors, tag_ptr, union_layout);
+                        this.recursion_depth -= 1;
+
+                        extra_offset
+                    }
+                })
+            }
+        }
+    }
+
+    fn write_builtin(
+        &mut self,
+        cursors: Cursors,
+        addr: usize,
+        layout: InLayout<'a>,
+        builtin: Builtin<'a>,
+    ) -> usize {
+        match builtin {
+            Builtin::Int(_) | Builtin::Float(_) | Builtin::Bool | Builtin::Decimal => {
+                let size = self.layout_interner.stack_size(layout) as usize;
+                self.copy(cursors.offset, addr, size);
+
+                cursors.extra_offset
+            }
+
+            Builtin::Str => {
+                let ptr_size = self.ptr_size();
+                let bytes = self.read_word(addr);
+                let len = self.read_word(addr + ptr_size);
+                let capacity = self.read_word(addr + 2 * ptr_size);
+
+                // small strings have the high bit of their capacity set, and are stored inline
+                let is_small = capacity & (1 << (8 * ptr_size - 1)) != 0;
+
+                if is_small {
+                    self.copy(cursors.offset, addr, 3 * ptr_size);
+
+                    cursors.extra_offset
+                } else {
+                    let mut offset = cursors.offset;
+                    offset = self.write_word(offset, cursors.extra_offset);
+                    offset = self.write_word(offset, len);
+                    self.write_word(offset, capacity);
+
+                    self.copy(cursors.extra_offset, bytes, len);
+
+                    cursors.extra_offset + len
+                }
+            }
+
+            Builtin::List(elem) => {
+                let ptr_size = self.ptr_size();
+                let elements = self.read_word(addr);
+                let len = self.read_word(addr + ptr_size);
+
+                let (element_width, element_align) =
+                    self.layout_interner.stack_size_and_alignment(elem);
+                let element_width = element_width as usize;
+                let elements_start = self.align(cursors.extra_offset, element_align);
+
+                // we only copy the elements we actually have (and skip extra capacity)
+                let mut offset = cursors.offset;
+                offset = self.write_word(offset, elements_start);
+                offset = self.write_word(offset, len);
+                self.write_word(offset, len);
+
+                if self.layout_interner.safe_to_memcpy(elem) {
+                    self.copy(elements_start, elements, len * element_width);
+
+                    elements_start + len * element_width
+                } else {
+                    let mut rest_offset = elements_start + len * element_width;
+
+                    for index in 0..len {
+                        let cursors = Cursors {
+                            offset: elements_start + index * element_width,
+                            extra_offset: rest_offset,
+                        };
+
+                        rest_offset =
+                            self.write_value(cursors, elements + index * element_width, elem);
+                    }
+
+                    rest_offset
+                }
+            }
+        }
+    }
+
+    fn write_struct(
+        &mut self,
+        cursors: Cursors,
+        addr: usize,
+        field_layouts: &'a [InLayout<'a>],
+    ) -> usize {
+        let layout = Layout::struct_no_name_order(field_layouts);
+
+        if layout.safe_to_memcpy(&*self.layout_interner) {
+            let size = layout.stack_size(&*self.layout_interner, self.target_info) as usize;
+            self.copy(cursors.offset, addr, size);
+
+            cursors.extra_offset
+        } else if let [field_layout] = field_layouts {
+            self.write_value(cursors, addr, *field_layout)
+        } else {
+            // In the source, fields sit at their natural alignment (this is the LLVM struct
+            // type); in the frame, they are packed one after another.
+            let mut cursors = cursors;
+            let mut source_offset = 0;
+
+            for field_layout in field_layouts {
+                let (field_width, field_align) =
+                    self.layout_interner.stack_size_and_alignment(*field_layout);
+                source_offset = round_up_to_alignment(source_offset, field_align);
+
+                cursors.extra_offset =
+                    self.write_value(cursors, addr + source_offset as usize, *field_layout);
+                cursors.offset += field_width as usize;
+
+                source_offset += field_width;
+            }
+
+            cursors.extra_offset
+        }
+    }
+
+    /// Like `build_clone_tag`. For a non-recursive union, `value` is the address of the union;
+    /// otherwise it is the (possibly tagged) pointer to the tag's data.
+    fn write_tag(
+        &mut self,
+        cursors: Cursors,
+        value: usize,
+        union_layout: UnionLayout<'a>,
+    ) -> usize {
+        let Cursors {
+            offset,
+            mut extra_offset,
+        } = cursors;
+
+        let (data_width, data_align) =
+            union_layout.data_size_and_alignment(&*self.layout_interner, self.target_info);
+
+        // the data of a recursive union is behind a pointer, and goes at the extra offset
+        if !matches!(union_layout, UnionLayout::NonRecursive(_)) {
+            extra_offset = self.align(extra_offset, data_align);
+        }
+
+        let data_cursors = Cursors {
+            offset: extra_offset,
+            extra_offset: extra_offset + data_width as usize,
+        };
+
+        match union_layout {
+            UnionLayout::NonRecursive(&[]) => {
+                unreachable!("serializing a value of an empty tag union")
+            }
+            UnionLayout::NonRecursive(tags) => {
+                let tag_id = self.read_tag_id(value, union_layout);
+                let layout = self.struct_layout(tags[tag_id]);
+
+                // The tag id goes after the biggest payload, like in the LLVM backend, and not
+                // right after this tag's payload
+                if let Some(tag_id_offset) =
+                    union_layout.tag_id_offset(&*self.layout_interner, self.target_info)
+                {
+                    let tag_id_offset = tag_id_offset as usize;
+                    let tag_id_size = self
+                        .layout_interner
+                        .stack_size(union_layout.tag_id_layout());
+
+                    self.copy(
+                        offset + tag_id_offset,
+                        value + tag_id_offset,
+                        tag_id_size as usize,
+                    );
+                }
+
+                self.write_value(cursors, value, layout)
+            }
+            UnionLayout::Recursive(tags) => {
+                let tag_id = self.read_tag_id(value, union_layout);
+                self.write_pointer_with_tag_id(offset, extra_offset, union_layout, tag_id);
+
+                let layout = if union_layout.stores_tag_id_in_pointer(self.target_info) {
+                    self.struct_layout(tags[tag_id])
+                } else {
+                    self.tag_with_id_layout(tags[tag_id], union_layout)
+                };
+
+                let data = self.clear_tag_id(value);
+                self.write_value(data_cursors, data, layout)
+            }
+            UnionLayout::NonNullableUnwrapped(fields) => {
+                self.write_word(offset, extra_offset);
+
+                let layout = self.struct_layout(fields);
+                self.write_value(data_cursors, value, layout)
+            }
+            UnionLayout::NullableWrapped {
+                nullable_id,
+                other_tags,
+            } => {
+                if value == 0 {
+                    self.write_word(offset, 0);
+
+                    return extra_offset;
+                }
+
+                let tag_id = self.read_tag_id(value, union_layout);
+                self.write_pointer_with_tag_id(offset, extra_offset, union_layout, tag_id);
+
+                let fields = if tag_id >= nullable_id as usize {
+                    other_tags[tag_id - 1]
+                } else {
+                    other_tags[tag_id]
+                };
+
+                let layout = if union_layout.stores_tag_id_in_pointer(self.target_info) {
+                    self.struct_layout(fields)
+                } else {
+                    self.tag_with_id_layout(fields, union_layout)
+                };
+                let data = self.clear_tag_id(value);
+                self.write_value(data_cursors, data, layout)
+            }
+            UnionLayout::NullableUnwrapped { other_fields, .. } => {
+                if value == 0 {
+                    self.write_word(offset, 0);
+
+                    return extra_offset;
+                }
+
+                self.write_word(offset, extra_offset);
+
+                let layout = self.struct_layout(other_fields);
+                let width = self.layout_interner.stack_size(layout) as usize;
+                let cursors = Cursors {
+                    offset: extra_offset,
+                    extra_offset: extra_offset + width,
+                };
+
+                self.write_value(cursors, value, layout)
+            }
+        }
+    }
+
+    /// Like `build_clone_shared`: write the value behind `source_ptr` with `write`, unless the
+    /// same pointer was already written into this frame. Then the `word_width` bytes that were
+    /// written at the offset for it back then are written again.
+    fn write_shared(
+        &mut self,
+        cursors: Cursors,
+        source_ptr: usize,
+        word_width: usize,
+        write: impl FnOnce(&mut Self) -> usize,
+    ) -> usize {
+        let seen = self
+            .seen
+            .iter()
+            .find(|(ptr, _)| source_ptr != 0 && *ptr == source_ptr)
+            .map(|(_, word)| *word);
+
+        if let Some(word) = seen {
+            let bytes = word.to_le_bytes();
+            self.frame_slice(cursors.offset, word_width)
+                .copy_from_slice(&bytes[..word_width]);
+
+            return cursors.extra_offset;
+        }
+
+        let extra_offset = write(self);
+
+        if source_ptr != 0 && self.seen.len() < CLONE_SEEN_CAPACITY {
+            let mut bytes = [0; 8];
+            bytes[..word_width].copy_from_slice(self.frame_slice(cursors.offset, word_width));
+            self.seen.push((source_ptr, u64::from_le_bytes(bytes)));
+        }
+
+        extra_offset
+    }
+
+    /// The width of what `write_pointer_with_tag_id` writes
+    fn union_pointer_width(&self, union_layout: UnionLayout<'a>) -> usize {
+        if union_layout.stores_tag_id_in_pointer(self.target_info) {
+            8
+        } else {
+            self.ptr_size()
+        }
+    }
+
+    fn read_tag_id(&self, value: usize, union_layout: UnionLayout<'a>) -> usize {
+        if union_layout.stores_tag_id_in_pointer(self.target_info) {
+            return value & self.tag_id_mask();
+        }
+
+        match union_layout.tag_id_offset(&*self.layout_interner, self.target_info) {
+            Some(tag_id_offset) => {
+                let addr = value + tag_id_offset as usize;
+
+                match self
+                    .layout_interner
+                    .stack_size(union_layout.tag_id_layout())
+                {
+                    0 => 0,
+                    1 => self.source[addr] as usize,
+                    2 => u16::from_le_bytes([self.source[addr], self.source[addr + 1]]) as usize,
+                    n => unreachable!("tag ids are at most 2 bytes, not {}", n),
+                }
+            }
+            None => 0,
+        }
+    }
+
+    fn write_pointer_with_tag_id(
+        &mut self,
+        offset: usize,
+        extra_offset: usize,
+        union_layout: UnionLayout<'a>,
+        tag_id: usize,
+    ) {
+        if union_layout.stores_tag_id_in_pointer(self.target_info) {
+            let offset = self.write_u32(offset, tag_id as u32);
+            self.write_u32(offset, extra_offset as u32);
+        } else {
+            self.write_word(offset, extra_offset);
+        }
+    }
+
+    fn struct_layout(&mut self, fields: &'a [InLayout<'a>]) -> InLayout<'a> {
+        self.layout_interner
+            .insert(Layout::struct_no_name_order(fields))
+    }
+
+    /// The fields of a tag followed by its tag id, the way the LLVM backend loads them
+    fn tag_with_id_layout(
+        &mut self,
+        fields: &'a [InLayout<'a>],
+        union_layout: UnionLayout<'a>,
+    ) -> InLayout<'a> {
+        let fields_layout = self.struct_layout(fields);
+        let with_id = self
+            .arena
+            .alloc([fields_layout, union_layout.tag_id_layout()]);
+
+        self.struct_layout(with_id)
+    }
+
+    /// Like `align_extra_offset`: round `offset` up to `alignment` for the native layout
+    fn align(&self, offset: usize, alignment: u32) -> usize {
+        if self.native_layout {
+            round_up_to_alignment(offset as u32, alignment) as usize
+        } else {
+            offset
+        }
+    }
+
+    fn ptr_size(&self) -> usize {
+        self.target_info.ptr_size()
+    }
+
+    fn word_mask(&self) -> usize {
+        match self.target_info.ptr_width() {
+            PtrWidth::Bytes8 => u64::MAX as usize,
+            PtrWidth::Bytes4 => u32::MAX as usize,
+        }
+    }
+
+    fn tag_id_mask(&self) -> usize {
+        match self.target_info.ptr_width() {
+            PtrWidth::Bytes8 => 0b111,
+            PtrWidth::Bytes4 => 0b11,
+        }
+    }
+
+    fn clear_tag_id(&self, ptr: usize) -> usize {
+        ptr & !self.tag_id_mask()
+    }
+
+    fn read_word(&self, addr: usize) -> usize {
+        let bytes = &self.source[addr..][..self.ptr_size()];
+
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |word, byte| (word << 8) | *byte as usize)
+    }
+
+    fn copy(&mut self, offset: usize, addr: usize, len: usize) {
+        let bytes = &self.source[addr..][..len];
+        self.frame_slice(offset, len).copy_from_slice(bytes);
+    }
+
+    fn write_word(&mut self, offset: usize, word: usize) -> usize {
+        let ptr_size = self.ptr_size();
+        let bytes = (word as u64).to_le_bytes();
+        self.frame_slice(offset, ptr_size)
+            .copy_from_slice(&bytes[..ptr_size]);
+
+        offset + ptr_size
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) -> usize {
+        self.frame_slice(offset, 4)
+            .copy_from_slice(&value.to_le_bytes());
+
+        offset + 4
+    }
+
+    fn frame_slice(&mut self, offset: usize, len: usize) -> &mut [u8] {
+        if self.frame.len() < offset + len {
+            self.frame.resize(offset + len, 0);
+        }
+
+        &mut self.frame[offset..][..len]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bumpalo::Bump;
+    use roc_module::symbol::ModuleId;
+    use roc_mono::layout::{Builtin, Layout, LayoutInterner, STLayoutInterner, UnionLayout};
+    use roc_region::all::Region;
+    use roc_target::TargetInfo;
+    use roc_types::subs::Variable;
+
+    use super::{Cursors, Lookup, Serializer};
+
+    const TARGET_INFO: TargetInfo = TargetInfo::default_x86_64();
+
+    fn words(words: &[usize]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|w| (*w as u64).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn integer_is_copied() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let source = 42i64.to_le_bytes();
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 8,
+            },
+            0,
+            Layout::I64,
+        );
+
+        assert_eq!(extra, 8);
+        assert_eq!(serializer.frame(), &source);
+    }
+
+    #[test]
+    fn list_of_integers_is_made_relative() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let list_layout = interner.insert(Layout::Builtin(Builtin::List(Layout::I64)));
+
+        // the list struct at 0, with a capacity of 4, and its elements at 32
+        let mut source = words(&[32, 2, 4, 0]);
+        source.extend(words(&[7, 8]));
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 24,
+            },
+            0,
+            list_layout,
+        );
+
+        // the pointer is now an offset into the frame, and the capacity is dropped
+        assert_eq!(extra, 40);
+        assert_eq!(serializer.frame(), words(&[24, 2, 2, 7, 8]).as_slice());
+    }
+
+    #[test]
+    fn list_of_unions_puts_each_tag_id_after_the_biggest_payload() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+
+        // [A I64, B Str], which is 24 bytes of payload, and then the tag id
+        let union_layout = interner.insert(Layout::Union(UnionLayout::NonRecursive(
+            arena.alloc([&[Layout::I64][..], &[Layout::STR][..]]),
+        )));
+        let list_layout = interner.insert(Layout::Builtin(Builtin::List(union_layout)));
+
+        let mut small_str = [0u8; 24];
+        small_str[..2].copy_from_slice(b"hi");
+        small_str[23] = 0x80 | 2;
+
+        // the list struct at 0, and its elements at 32: `A 42`, and then `B "hi"`
+        let mut source = words(&[32, 2, 2, 0]);
+        source.extend(42i64.to_le_bytes());
+        source.extend([0xAA; 16]);
+        source.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        source.extend(small_str);
+        source.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 24,
+            },
+            0,
+            list_layout,
+        );
+
+        // `A` leaves the rest of the payload alone, but its tag id is at 24 all the same
+        let mut expected = words(&[24, 2, 2]);
+        expected.extend(42i64.to_le_bytes());
+        expected.extend([0; 16]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend(small_str);
+        expected.push(1);
+
+        assert_eq!(extra, 88);
+        assert_eq!(serializer.frame(), expected.as_slice());
+    }
+
+    #[test]
+    fn list_of_empty_records_keeps_its_length() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let list_layout = interner.insert(Layout::Builtin(Builtin::List(Layout::UNIT)));
+
+        // five elements of zero bytes each, so there is nothing to point at
+        let source = words(&[0, 5, 5]);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 24,
+            },
+            0,
+            list_layout,
+        );
+
+        // no element bytes, but the length is still there (twice)
+        assert_eq!(extra, 24);
+        assert_eq!(serializer.frame(), words(&[24, 5, 5]).as_slice());
+    }
+
+    #[test]
+    fn big_string_bytes_follow_the_struct() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let text = b"Profundum et fundamentum";
+
+        let mut source = words(&[24, text.len(), text.len()]);
+        source.extend_from_slice(text);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 24,
+            },
+            0,
+            Layout::STR,
+        );
+
+        assert_eq!(extra, 24 + text.len());
+        assert_eq!(serializer.frame(), source.as_slice());
+    }
+
+    #[test]
+    fn box_contents_follow_the_pointer() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let box_layout = interner.insert(Layout::Boxed(Layout::STR));
+        let text = b"Astra mortemque praestare gradatim";
+
+        // the box at 0 points to a big string at 8, whose bytes are at 32
+        let mut source = words(&[8, 32, text.len(), text.len()]);
+        source.extend_from_slice(text);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 8,
+            },
+            0,
+            box_layout,
+        );
+
+        let mut expected = words(&[8, 32, text.len(), text.len()]);
+        expected.extend_from_slice(text);
+
+        assert_eq!(extra, expected.len());
+        assert_eq!(serializer.frame(), expected.as_slice());
+    }
+
+    #[test]
+    fn shared_box_is_written_once() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let box_layout = interner.insert(Layout::Boxed(Layout::STR));
+        let record_layout = interner.insert(Layout::struct_no_name_order(
+            arena.alloc([box_layout, box_layout]),
+        ));
+        let text = b"Astra mortemque praestare gradatim";
+
+        // both fields of the record at 0 point to the same big string at 16
+        let mut source = words(&[16, 16, 40, text.len(), text.len()]);
+        source.extend_from_slice(text);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        let extra = serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 16,
+            },
+            0,
+            record_layout,
+        );
+
+        let mut expected = words(&[16, 16, 40, text.len(), text.len()]);
+        expected.extend_from_slice(text);
+
+        assert_eq!(extra, expected.len());
+        assert_eq!(serializer.frame(), expected.as_slice());
+    }
+
+    #[test]
+    fn dict_entries_keep_their_storage_order() {
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+
+        // `Dict k v := { data, dataIndices, metadata, size }`, with `data : List (T k v)`
+        let entry = interner.insert(Layout::struct_no_name_order(
+            arena.alloc([Layout::I64, Layout::I64]),
+        ));
+        let data = interner.insert(Layout::Builtin(Builtin::List(entry)));
+        let data_indices = interner.insert(Layout::Builtin(Builtin::List(Layout::U64)));
+        let metadata = interner.insert(Layout::Builtin(Builtin::List(Layout::I8)));
+        let dict_layout = interner.insert(Layout::struct_no_name_order(arena.alloc([
+            data,
+            data_indices,
+            metadata,
+            Layout::U64,
+        ])));
+
+        // Inserted as 30, 10, 20. The lists have spare capacity, which is not cloned.
+        let inserted = [(30, 300), (10, 100), (20, 200)];
+        let mut source = words(&[80, 3, 4, 128, 4, 4, 160, 4, 4, 3]);
+        source.extend(words(&[30, 300, 10, 100, 20, 200]));
+        source.extend(words(&[2, 0, 1, 0]));
+        source.extend([17, 0x80, 5, 33]);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        serializer.write_value(
+            Cursors {
+                offset: 0,
+                extra_offset: 80,
+            },
+            0,
+            dict_layout,
+        );
+
+        let frame = serializer.frame();
+        let word = |offset: usize| usize::from_le_bytes(frame[offset..][..8].try_into().unwrap());
+        let list = |offset: usize| (word(offset), word(offset + 8));
+
+        let (entries, len) = list(0);
+        let cloned: Vec<(usize, usize)> = (0..len)
+            .map(|i| (word(entries + 16 * i), word(entries + 16 * i + 8)))
+            .collect();
+        assert_eq!(cloned, inserted);
+
+        let (indices, len) = list(24);
+        let cloned: Vec<usize> = (0..len).map(|i| word(indices + 8 * i)).collect();
+        assert_eq!(cloned, [2, 0, 1, 0]);
+
+        let (metadata, len) = list(48);
+        assert_eq!(&frame[metadata..][..len], [17, 0x80, 5, 33]);
+        assert_eq!(word(72), 3);
+    }
+
+    #[test]
+    fn native_layout_struct_can_be_cast_back() {
+        #[repr(C)]
+        #[derive(Debug, PartialEq)]
+        struct Native {
+            x: i64,
+            y: i32,
+            flag: u8,
+        }
+
+        let arena = Bump::new();
+        let mut interner = STLayoutInterner::with_capacity(4, TARGET_INFO);
+        let struct_layout = interner.insert(Layout::struct_no_name_order(arena.alloc([
+            Layout::I64,
+            Layout::I32,
+            Layout::U8,
+        ])));
+
+        // a `U8` at 0, and the struct at 8
+        let mut source = vec![7, 0, 0, 0, 0, 0, 0, 0];
+        source.extend((-5i64).to_le_bytes());
+        source.extend(1000i32.to_le_bytes());
+        source.extend([1, 0, 0, 0]);
+
+        let lookups = [
+            Lookup {
+                addr: 0,
+                layout: Layout::U8,
+                variable: Variable::U8,
+            },
+            Lookup {
+                addr: 8,
+                layout: struct_layout,
+                variable: Variable::EMPTY_RECORD,
+            },
+        ];
+
+        // the second entry of the lookup table, after the 12 bytes of the frame header and the
+        // 12 bytes of the first entry, says where the struct went
+        let struct_start =
+            |frame: &[u8]| usize::from_le_bytes(frame[24..][..8].try_into().unwrap());
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source);
+        serializer.write_frame(0, ModuleId::ATTR, Region::zero(), &lookups);
+        let packed = serializer.into_frame();
+
+        // right after the `U8` that follows the lookup table
+        assert_eq!(struct_start(&packed), 37);
+
+        let mut serializer = Serializer::new(&arena, &mut interner, &source).with_native_layout();
+        serializer.write_frame(0, ModuleId::ATTR, Region::zero(), &lookups);
+        let frame = serializer.into_frame();
+
+        let start = struct_start(&frame);
+        assert_eq!(start, 40);
+
+        // a buffer with at least the alignment of the shared memory's frames
+        let mut aligned = vec![0u64; (frame.len() + 7) / 8];
+        let native = unsafe {
+            let bytes = aligned.as_mut_ptr().cast::<u8>();
+            std::ptr::copy_nonoverlapping(frame.as_ptr(), bytes, frame.len());
+
+            &*bytes.add(start).cast::<Native>()
+        };
+
+        assert_eq!(
+            native,
+            &Native {
+                x: -5,
+                y: 1000,
+                flag: 1
+            }
+        );
+    }
+}
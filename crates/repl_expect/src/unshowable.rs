@@ -0,0 +1,104 @@
+//! The values that an `expect` or `dbg` reports on, but that a failure cannot fully show.
+//!
+//! A function is cloned into the frame along with its captures, but all that can be shown of it
+//! is `<function>`. A value that is, or holds, a function is found from its layout at compile
+//! time, so that the user learns why before the tests run, rather than from a blank in a report.
+use std::path::Path;
+
+use roc_load::MonomorphizedModule;
+use roc_module::symbol::{Interns, ModuleId, Symbol};
+use roc_mono::layout::{Builtin, InLayout, Layout, LayoutInterner, STLayoutInterner, UnionLayout};
+use roc_region::all::{LineInfo, Region};
+
+use crate::frame_size::visit_frames;
+
+/// A value looked up by an `expect` or `dbg` that holds a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnshowableLookup {
+    pub module_id: ModuleId,
+    pub region: Region,
+    pub is_dbg: bool,
+    /// The value's name. A `dbg` shows an expression, which has none.
+    pub symbol: Option<Symbol>,
+}
+
+impl UnshowableLookup {
+    /// A warning to print before the tests run, pointing at the `expect` or `dbg`.
+    pub fn warning(&self, path: &Path, source: &str, interns: &Interns) -> String {
+        let line = LineInfo::new(source).convert_pos(self.region.start()).line + 1;
+        let value = match self.symbol {
+            Some(symbol) => format!("`{}`", symbol.as_str(interns)),
+            None => "a value".to_string(),
+        };
+
+        format!(
+            "Warning: the {} at {}:{} reports on {}, which holds a function. \
+             A function can't be shown, so it will appear as <function>.",
+            if self.is_dbg { "dbg" } else { "expect" },
+            path.display(),
+            line,
+            value
+        )
+    }
+}
+
+/// The lookups of the `expect`s and `dbg`s in `loaded` whose values hold a function.
+pub fn unshowable_lookups(loaded: &MonomorphizedModule) -> Vec<UnshowableLookup> {
+    let mut unshowable = Vec::new();
+
+    visit_frames(loaded, &mut |module_id,
+                               region,
+                               is_dbg,
+                               lookups,
+                               layouts| {
+        for symbol in lookups.iter() {
+            let holds_function = layouts.get(symbol).map_or(false, |layout| {
+                holds_function(&loaded.layout_interner, *layout)
+            });
+
+            let lookup = UnshowableLookup {
+                module_id,
+                region,
+                is_dbg,
+                symbol: (!is_dbg).then_some(*symbol),
+            };
+
+            // The same expect can end up in several specializations of a function
+            if holds_function && !unshowable.contains(&lookup) {
+                unshowable.push(lookup);
+            }
+        }
+    });
+
+    unshowable.sort_by_key(|lookup| (lookup.region.start().offset, lookup.region.end().offset));
+
+    unshowable
+}
+
+/// Whether a value of this layout is, or holds, a function.
+fn holds_function<'a>(interner: &STLayoutInterner<'a>, layout: InLayout<'a>) -> bool {
+    let any_holds_function =
+        |fields: &[InLayout<'a>]| fields.iter().any(|field| holds_function(interner, *field));
+
+    match interner.get(layout) {
+        Layout::LambdaSet(_) => true,
+        Layout::Struct { field_layouts, .. } => any_holds_function(field_layouts),
+        Layout::Boxed(inner) | Layout::Builtin(Builtin::List(inner)) => {
+            holds_function(interner, inner)
+        }
+        Layout::Union(union_layout) => match union_layout {
+            UnionLayout::NonRecursive(tags)
+            | UnionLayout::Recursive(tags)
+            | UnionLayout::NullableWrapped {
+                other_tags: tags, ..
+            } => tags.iter().any(|fields| any_holds_function(fields)),
+            UnionLayout::NonNullableUnwrapped(fields)
+            | UnionLayout::NullableUnwrapped {
+                other_fields: fields,
+                ..
+            } => any_holds_function(fields),
+        },
+        // the recursive union it points to is already being looked at
+        Layout::Builtin(_) | Layout::RecursivePointer(_) => false,
+    }
+}
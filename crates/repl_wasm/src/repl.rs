@@ -241,6 +241,7 @@ pub async fn entrypoint_from_js(src: String) -> Result<String, String> {
             arena,
             module_id,
             stack_bytes: roc_gen_wasm::Env::DEFAULT_STACK_BYTES,
+            trace_refcounts: false,
             exposed_to_host: exposed_to_host
                 .values
                 .keys()
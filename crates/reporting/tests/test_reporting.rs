@@ -13,7 +13,9 @@ mod test_reporting {
     use indoc::indoc;
     use roc_can::abilities::AbilitiesStore;
     use roc_can::expr::PendingDerives;
-    use roc_load::{self, ExecutionMode, LoadConfig, LoadedModule, LoadingProblem, Threading};
+    use roc_load::{
+        self, ExecutionMode, LoadConfig, LoadedModule, LoadingProblem, MonoPasses, Threading,
+    };
     use roc_module::symbol::{Interns, ModuleId};
     use roc_packaging::cache::RocCacheDir;
     use roc_parse::module::parse_header;
@@ -127,6 +129,7 @@ mod test_reporting {
                 palette: DEFAULT_PALETTE,
                 threading: Threading::Single,
                 exec_mode: ExecutionMode::Check,
+                mono_passes: MonoPasses::All,
             };
             let result = roc_load::load_and_typecheck(
                 arena,
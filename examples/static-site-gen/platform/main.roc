@@ -1,9 +1,9 @@
 platform "static-site-gen"
-    requires {} { transformFileContent : Str, Str -> Str }
+    requires {} { transformFileContent : Str, Str, { readingMinutes : U64, wordCount : U64 }, Str -> { html : Str, data : Str, path : Str } }
     exposes []
     packages {}
     imports []
     provides [transformFileContentForHost]
 
-transformFileContentForHost : Box Str, Box Str -> Str
-transformFileContentForHost = \relPath, htmlContent -> transformFileContent (Box.unbox relPath) (Box.unbox htmlContent)
+transformFileContentForHost : Box Str, Box Str, Box { readingMinutes : U64, wordCount : U64 }, Box Str -> { html : Str, data : Str, path : Str }
+transformFileContentForHost = \relPath, htmlContent, metadata, siteConfig -> transformFileContent (Box.unbox relPath) (Box.unbox htmlContent) (Box.unbox metadata) (Box.unbox siteConfig)
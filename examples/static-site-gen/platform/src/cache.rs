@@ -0,0 +1,155 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::search_index::Section;
+use crate::RenderedMarkdown;
+
+/// Bump this when the cached HTML would change for reasons the key doesn't cover.
+const CACHE_VERSION: u32 = 6;
+
+/// An on-disk cache of the HTML rendered from each page's Markdown (before the Roc
+/// transform), so that a later build can skip parsing and highlighting unchanged pages.
+///
+/// Each entry is the page's word count on the first line and its number of search sections
+/// on the second, followed by a line for each section, and then the HTML.
+///
+/// Entries are keyed by a hash of the Markdown and of `salt`, which should cover
+/// everything else that affects the HTML. `DefaultHasher` is not guaranteed to be
+/// the same across Rust releases, but a changed hash only means a cache miss.
+pub struct HtmlCache {
+    dir: PathBuf,
+    salt: u64,
+    temp_files: AtomicUsize,
+}
+
+impl HtmlCache {
+    pub fn new(dir: PathBuf, salt: impl Hash) -> Result<Self, String> {
+        fs::create_dir_all(&dir).map_err(|e| {
+            format!(
+                "Could not create the cache directory {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+
+        let mut hasher = DefaultHasher::new();
+        CACHE_VERSION.hash(&mut hasher);
+        salt.hash(&mut hasher);
+
+        Ok(HtmlCache {
+            dir,
+            salt: hasher.finish(),
+            temp_files: AtomicUsize::new(0),
+        })
+    }
+
+    /// The cached rendering of `markdown`, or else the result of `render`, which is then cached.
+    /// `page_salt` should cover what the page's own frontmatter changes about its HTML.
+    pub fn get_or_insert_with(
+        &self,
+        markdown: &str,
+        page_salt: impl Hash,
+        render: impl FnOnce() -> RenderedMarkdown,
+    ) -> RenderedMarkdown {
+        let mut hasher = DefaultHasher::new();
+        self.salt.hash(&mut hasher);
+        markdown.hash(&mut hasher);
+        page_salt.hash(&mut hasher);
+        let path = self.dir.join(format!("{:016x}.html", hasher.finish()));
+
+        if let Some(rendered) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|entry| parse_entry(&entry))
+        {
+            return rendered;
+        }
+
+        let rendered = render();
+        let mut entry = format!("{}\n{}\n", rendered.word_count, rendered.sections.len());
+        for section in rendered.sections.iter() {
+            // The words of a section are separated by single spaces, so they have no tabs
+            entry.push_str(&format!(
+                "{}\t{}\t{}\n",
+                section.id, section.title, section.text
+            ));
+        }
+        entry.push_str(&rendered.html);
+
+        // Write to a temporary file first, so that another thread or build
+        // never reads a half-written entry. Failing to cache is not an error.
+        let temp_path = self.dir.join(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            self.temp_files.fetch_add(1, Ordering::Relaxed)
+        ));
+        if fs::write(&temp_path, &entry).is_err() || fs::rename(&temp_path, &path).is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        rendered
+    }
+}
+
+fn parse_entry(entry: &str) -> Option<RenderedMarkdown> {
+    let (word_count, rest) = entry.split_once('\n')?;
+    let (num_sections, mut rest) = rest.split_once('\n')?;
+    let mut sections = Vec::new();
+
+    for _ in 0..num_sections.parse::<usize>().ok()? {
+        let (line, after) = rest.split_once('\n')?;
+        let mut fields = line.splitn(3, '\t');
+
+        sections.push(Section {
+            id: fields.next()?.to_string(),
+            title: fields.next()?.to_string(),
+            text: fields.next()?.to_string(),
+        });
+        rest = after;
+    }
+
+    Some(RenderedMarkdown {
+        html: rest.to_string(),
+        word_count: word_count.parse().ok()?,
+        sections,
+    })
+}
+
+#[test]
+fn unchanged_markdown_is_not_rendered_again() {
+    use crate::test_dir::TestDir;
+
+    let dir = TestDir::new("cache");
+    let markdown = "# Apple\n";
+    let mut renders = 0;
+    let mut render = || {
+        renders += 1;
+        RenderedMarkdown {
+            html: "<h1 id=\"apple\">Apple</h1>\n".to_string(),
+            word_count: 1,
+            sections: vec![
+                Section::default(),
+                Section {
+                    id: "apple".to_string(),
+                    title: "Apple".to_string(),
+                    text: String::new(),
+                },
+            ],
+        }
+    };
+
+    // Separate caches in the same directory, as in separate builds
+    let first = HtmlCache::new(dir.to_path_buf(), "highlighter").unwrap();
+    let first_rendered = first.get_or_insert_with(markdown, false, &mut render);
+    let second = HtmlCache::new(dir.to_path_buf(), "highlighter").unwrap();
+    let second_rendered = second.get_or_insert_with(markdown, false, &mut render);
+    // The same Markdown, but a page whose frontmatter asks for something else
+    second.get_or_insert_with(markdown, true, &mut render);
+    let upgraded = HtmlCache::new(dir.to_path_buf(), "new highlighter").unwrap();
+    upgraded.get_or_insert_with(markdown, false, &mut render);
+
+    assert_eq!(first_rendered, second_rendered);
+    assert_eq!(renders, 3);
+}
@@ -0,0 +1,32 @@
+use pulldown_cmark::escape::escape_html;
+
+/// Wrap a code block's highlighted HTML in a `<figure class="highlight">`, with an
+/// empty copy button for a theme's script to fill in.
+///
+/// This is the code block hook registered by `--code-figures`.
+pub fn wrap_in_figure(block_html: &str, language: &str) -> String {
+    let mut html = String::from("<figure class=\"highlight\"");
+
+    if !language.is_empty() {
+        html.push_str(" data-lang=\"");
+        escape_html(&mut html, language).unwrap();
+        html.push('"');
+    }
+
+    html.push_str("><button class=\"copy\" type=\"button\"></button>");
+    html.push_str(block_html);
+    html.push_str("</figure>");
+
+    html
+}
+
+#[test]
+fn figure_names_the_language() {
+    assert_eq!(
+        wrap_in_figure("<pre><samp>ls\n</samp></pre>", "sh"),
+        "<figure class=\"highlight\" data-lang=\"sh\">\
+         <button class=\"copy\" type=\"button\"></button>\
+         <pre><samp>ls\n</samp></pre></figure>"
+    );
+    assert!(wrap_in_figure("", "").starts_with("<figure class=\"highlight\"><button"));
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::manifest::portable_path;
+
+/// The file in the cache directory with the listings.
+const LISTINGS_FILE: &str = "dir-listings.txt";
+
+/// A directory modified this recently may be modified again without its mtime changing,
+/// on file systems that only keep the time to the second (or two), so it isn't recorded.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// The entries of each directory of the input, and its mtime when they were listed, so that
+/// a later build with `--cache-dir` can reuse them for the directories whose mtime is the same.
+///
+/// A directory's mtime changes when an entry is added, removed or renamed, but not when
+/// something changes further down, so every directory still has its mtime checked. But an
+/// unchanged directory isn't listed again, and its files aren't looked at at all.
+///
+/// Each line of the file is a directory's path relative to the input directory, its mtime
+/// in nanoseconds, and its entries, separated by tabs. Subdirectories end in a `/`.
+pub struct DirListings {
+    path: PathBuf,
+    previous: HashMap<String, Listing>,
+    current: HashMap<String, Listing>,
+    racy_window: Duration,
+    dirs_read: usize,
+}
+
+struct Listing {
+    modified: u128,
+    /// Each entry's name, and whether it is a directory
+    entries: Vec<(String, bool)>,
+}
+
+impl DirListings {
+    /// The listings recorded in `cache_dir` by an earlier build, if there are any.
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(LISTINGS_FILE);
+        let previous = fs::read_to_string(&path)
+            .map(|contents| contents.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+
+        DirListings {
+            path,
+            previous,
+            current: HashMap::new(),
+            racy_window: RACY_WINDOW,
+            dirs_read: 0,
+        }
+    }
+
+    /// The entries of `dir`, which is at `relpath` in the input directory, each with
+    /// whether it is a directory. They are only read if `dir` changed since it was recorded.
+    pub fn entries(
+        &mut self,
+        relpath: &Path,
+        dir: &Path,
+        warnings: &mut Vec<String>,
+    ) -> Vec<(PathBuf, bool)> {
+        let key = portable_path(relpath);
+        let modified = fs::metadata(dir)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let modified_nanos = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_nanos());
+
+        if let Some(listing) = self.previous.remove(&key) {
+            if Some(listing.modified) == modified_nanos {
+                let entries = listing
+                    .entries
+                    .iter()
+                    .map(|(name, is_dir)| (dir.join(name), *is_dir))
+                    .collect();
+                self.current.insert(key, listing);
+
+                return entries;
+            }
+        }
+
+        self.dirs_read += 1;
+        let warnings_before = warnings.len();
+        let entries = read_dir_entries(dir, warnings);
+
+        let settled = matches!(
+            modified.map(|modified| SystemTime::now().duration_since(modified)),
+            Some(Ok(age)) if age >= self.racy_window
+        );
+
+        // Names with tabs or newlines, or that aren't UTF-8, would not survive the file
+        let names: Option<Vec<(String, bool)>> = entries
+            .iter()
+            .map(|(path, is_dir)| {
+                let name = path.file_name()?.to_str()?;
+                (!name.contains(['\t', '\n', '\r'])).then(|| (name.to_string(), *is_dir))
+            })
+            .collect();
+
+        if let (Some(modified), Some(names), true) = (modified_nanos, names, settled) {
+            if warnings.len() == warnings_before {
+                let listing = Listing {
+                    modified,
+                    entries: names,
+                };
+                self.current.insert(key, listing);
+            }
+        }
+
+        entries
+    }
+
+    /// How many directories had to be listed, rather than reusing an earlier listing.
+    pub fn dirs_read(&self) -> usize {
+        self.dirs_read
+    }
+
+    /// Record the listings of the directories that were visited, for the next build.
+    /// Failing to record them is not an error: the next build just lists every directory.
+    pub fn save(&self) {
+        let mut lines: Vec<String> = self
+            .current
+            .iter()
+            .map(|(relpath, listing)| {
+                let mut line = format!("{}\t{}", relpath, listing.modified);
+                for (name, is_dir) in listing.entries.iter() {
+                    line.push('\t');
+                    line.push_str(name);
+                    if *is_dir {
+                        line.push('/');
+                    }
+                }
+                line
+            })
+            .collect();
+        lines.sort();
+
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+
+        let temp_path = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        if fs::write(&temp_path, contents).is_err() || fs::rename(&temp_path, &self.path).is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+    }
+}
+
+/// The entries of `dir`, each with whether it is a directory.
+/// Whatever can't be read is added to `warnings`, and left out.
+pub fn read_dir_entries(dir: &Path, warnings: &mut Vec<String>) -> Vec<(PathBuf, bool)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warnings.push(format!("{}: {}", dir.display(), e));
+            return Vec::new();
+        }
+    };
+
+    let mut dir_entries = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => {
+                let pathbuf = entry.path();
+                let is_dir = pathbuf.is_dir();
+                dir_entries.push((pathbuf, is_dir));
+            }
+            Err(e) => warnings.push(format!("{}: {}", dir.display(), e)),
+        }
+    }
+
+    dir_entries
+}
+
+fn parse_line(line: &str) -> Option<(String, Listing)> {
+    let mut fields = line.split('\t');
+    let relpath = fields.next()?.to_string();
+    let modified = fields.next()?.parse().ok()?;
+    let entries = fields
+        .map(|entry| match entry.strip_suffix('/') {
+            Some(name) => (name.to_string(), true),
+            None => (entry.to_string(), false),
+        })
+        .collect();
+
+    Some((relpath, Listing { modified, entries }))
+}
+
+#[test]
+fn unchanged_directories_are_not_listed_again() {
+    use crate::test_dir::TestDir;
+
+    let dir = TestDir::new("listings");
+    let input_dir = dir.join("input");
+    let cache_dir = dir.join("cache");
+    fs::create_dir_all(input_dir.join("fruit/citrus")).unwrap();
+    fs::create_dir_all(&cache_dir).unwrap();
+    fs::write(input_dir.join("index.md"), "# Home").unwrap();
+    fs::write(input_dir.join("fruit/apple.md"), "# Apple").unwrap();
+    fs::write(input_dir.join("fruit/citrus/lemon.md"), "# Lemon").unwrap();
+
+    let find_files = |added: Option<&str>| {
+        if let Some(added) = added {
+            fs::write(input_dir.join(added), "# New").unwrap();
+        }
+
+        // The directories were all just made, so trust their mtimes anyway
+        let mut listings = DirListings::load(&cache_dir);
+        listings.racy_window = Duration::ZERO;
+
+        let (files, warnings) =
+            crate::find_files(&input_dir, &globset::GlobSet::empty(), Some(&mut listings));
+        listings.save();
+
+        assert!(warnings.is_empty());
+        (files.len(), listings.dirs_read())
+    };
+
+    let first = find_files(None);
+    let unchanged = find_files(None);
+    let added = find_files(Some("fruit/citrus/lime.md"));
+
+    assert_eq!(first, (3, 3));
+    assert_eq!(unchanged, (3, 0));
+    // Only the directory with the new file is listed again
+    assert_eq!(added, (4, 1));
+}
@@ -0,0 +1,286 @@
+/// A page that goes in the feed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedEntry {
+    pub title: String,
+    /// `YYYY-MM-DD`, or a full RFC 3339 date and time, as accepted by `parse_date`
+    pub date: String,
+    pub url: String,
+}
+
+/// The instant that `date` stands for, as seconds and nanoseconds since the Unix epoch, if it
+/// is something `atom_feed` can use: a `YYYY-MM-DD` date, which means midnight UTC, or an
+/// RFC 3339 date and time with an offset, like `2023-03-02T12:30:00+01:00`.
+pub fn parse_date(date: &str) -> Option<(i64, u32)> {
+    let bytes = date.as_bytes();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = bytes.get(range)?;
+        digits.iter().try_fold(0, |n, digit| {
+            digit
+                .is_ascii_digit()
+                .then(|| n * 10 + i64::from(digit - b'0'))
+        })
+    };
+
+    if bytes.get(4) != Some(&b'-') || bytes.get(7) != Some(&b'-') {
+        return None;
+    }
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let midnight = days_since_epoch(year, month, day) * 86400;
+    if bytes.len() == 10 {
+        return Some((midnight, 0));
+    }
+
+    if !matches!(bytes[10], b'T' | b't')
+        || bytes.get(13) != Some(&b':')
+        || bytes.get(16) != Some(&b':')
+    {
+        return None;
+    }
+
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    // a second of 60 is a leap second
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &date[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+
+        // digits past nanoseconds make no difference to the order
+        for (index, digit) in fraction.bytes().take(len).enumerate() {
+            if index < 9 {
+                nanos += u32::from(digit - b'0') * 10u32.pow(8 - index as u32);
+            }
+        }
+        rest = &fraction[len..];
+    }
+
+    let offset = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let start = date.len() - rest.len();
+            let (hours, minutes) = (number(start + 1..start + 3)?, number(start + 4..start + 6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+
+    Some((
+        midnight + hour * 3600 + minute * 60 + second - offset,
+        nanos,
+    ))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The days from 1970-01-01 to a valid date in the proleptic Gregorian calendar.
+fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    let days_in_year = |year| if is_leap_year(year) { 366 } else { 365 };
+    let days_before_year: i64 = if year >= 1970 {
+        (1970..year).map(days_in_year).sum()
+    } else {
+        -(year..1970).map(days_in_year).sum::<i64>()
+    };
+    let days_before_month: i64 = (1..month).map(|month| days_in_month(year, month)).sum();
+
+    days_before_year + days_before_month + day - 1
+}
+
+/// An Atom feed of the entries, newest first.
+pub fn atom_feed(base_url: &str, mut entries: Vec<FeedEntry>) -> String {
+    // Dates with different offsets don't sort as strings, so compare the instants
+    entries.sort_by(|a, b| {
+        parse_date(&b.date)
+            .cmp(&parse_date(&a.date))
+            .then_with(|| a.url.cmp(&b.url))
+    });
+
+    let updated = entries
+        .first()
+        .map(|entry| timestamp(&entry.date))
+        .unwrap_or_else(|| "1970-01-01T00:00:00Z".to_string());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    push_element(&mut xml, "  ", "title", base_url);
+    push_element(&mut xml, "  ", "id", base_url);
+    push_element(&mut xml, "  ", "updated", &updated);
+    xml.push_str("  <link href=\"");
+    push_escaped(&mut xml, base_url);
+    xml.push_str("\"/>\n");
+
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        push_element(&mut xml, "    ", "title", &entry.title);
+        push_element(&mut xml, "    ", "id", &entry.url);
+        push_element(&mut xml, "    ", "updated", &timestamp(&entry.date));
+        xml.push_str("    <link href=\"");
+        push_escaped(&mut xml, &entry.url);
+        xml.push_str("\"/>\n");
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// The URL of the page at `output_relpath` (with `/` separators) on the site at `base_url`.
+pub fn page_url(base_url: &str, output_relpath: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), output_relpath)
+}
+
+/// Atom wants a full date and time, so a plain date means midnight UTC.
+fn timestamp(date: &str) -> String {
+    if date.len() == 10 {
+        format!("{}T00:00:00Z", date)
+    } else {
+        date.to_string()
+    }
+}
+
+fn push_element(xml: &mut String, indent: &str, name: &str, text: &str) {
+    xml.push_str(indent);
+    xml.push('<');
+    xml.push_str(name);
+    xml.push('>');
+    push_escaped(xml, text);
+    xml.push_str("</");
+    xml.push_str(name);
+    xml.push_str(">\n");
+}
+
+fn push_escaped(xml: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            c => xml.push(c),
+        }
+    }
+}
+
+#[test]
+fn feed_of_two_dated_pages() {
+    let base_url = "https://example.com/blog/";
+    let entries = vec![
+        FeedEntry {
+            title: "Hello".to_string(),
+            date: "2023-01-15".to_string(),
+            url: page_url(base_url, "hello.html"),
+        },
+        FeedEntry {
+            title: "Fish & chips".to_string(),
+            date: "2023-03-02T12:30:00Z".to_string(),
+            url: page_url(base_url, "posts/fish.html"),
+        },
+    ];
+
+    assert_eq!(
+        atom_feed(base_url, entries),
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>https://example.com/blog/</title>
+  <id>https://example.com/blog/</id>
+  <updated>2023-03-02T12:30:00Z</updated>
+  <link href="https://example.com/blog/"/>
+  <entry>
+    <title>Fish &amp; chips</title>
+    <id>https://example.com/blog/posts/fish.html</id>
+    <updated>2023-03-02T12:30:00Z</updated>
+    <link href="https://example.com/blog/posts/fish.html"/>
+  </entry>
+  <entry>
+    <title>Hello</title>
+    <id>https://example.com/blog/hello.html</id>
+    <updated>2023-01-15T00:00:00Z</updated>
+    <link href="https://example.com/blog/hello.html"/>
+  </entry>
+</feed>
+"#
+    );
+}
+
+#[test]
+fn dates_are_parsed_strictly() {
+    assert_eq!(parse_date("1970-01-01"), Some((0, 0)));
+    assert_eq!(parse_date("1969-12-31"), Some((-86400, 0)));
+    assert_eq!(parse_date("2024-02-29"), Some((1_709_164_800, 0)));
+    assert_eq!(
+        parse_date("2023-03-02T12:30:00.25+01:00"),
+        Some((1_677_756_600, 250_000_000))
+    );
+    assert_eq!(
+        parse_date("2023-03-02T11:30:00Z"),
+        parse_date("2023-03-02T12:30:00+01:00")
+    );
+
+    for junk in [
+        "2023-01-15junk",
+        "2023-01-15T12:30:00",
+        "2023-02-29",
+        "2023-13-01",
+        "2023-04-31",
+        "2023-01-15T24:00:00Z",
+        "2023-01-15T12:30:00.Z",
+        "2023-01-15T12:30:00+0100",
+        "2023-1-15",
+        "",
+    ] {
+        assert_eq!(parse_date(junk), None, "{}", junk);
+    }
+}
+
+#[test]
+fn entries_are_sorted_by_instant() {
+    let entry = |date: &str| FeedEntry {
+        title: date.to_string(),
+        date: date.to_string(),
+        url: format!("https://example.com/{}.html", date),
+    };
+
+    // The earlier instant, despite sorting later as a string
+    let feed = atom_feed(
+        "https://example.com/",
+        vec![
+            entry("2023-03-02T12:30:00+02:00"),
+            entry("2023-03-02T11:30:00Z"),
+        ],
+    );
+
+    assert!(
+        feed.find("<title>2023-03-02T11:30:00Z").unwrap()
+            < feed.find("<title>2023-03-02T12:30:00+02:00").unwrap()
+    );
+}
@@ -0,0 +1,125 @@
+/// The `key: value` pairs from a `---`-delimited block at the top of a Markdown file.
+///
+/// This is deliberately not full YAML: each line is a single key and a
+/// string value, which covers what the site generator needs.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Frontmatter {
+    fields: Vec<(String, String)>,
+}
+
+impl Frontmatter {
+    /// Split the frontmatter (if any) off the start of `source`,
+    /// returning it along with the rest of the document.
+    pub fn parse(source: &str) -> Result<(Self, &str), String> {
+        let after_open = match strip_delimiter_line(source) {
+            Some(rest) => rest,
+            None => return Ok((Frontmatter::default(), source)),
+        };
+
+        let mut fields = Vec::new();
+        let mut rest = after_open;
+
+        loop {
+            if let Some(body) = strip_delimiter_line(rest) {
+                return Ok((Frontmatter { fields }, body));
+            }
+
+            if rest.is_empty() {
+                return Err("The frontmatter block is missing its closing `---`".into());
+            }
+
+            let (line, next) = match rest.find('\n') {
+                Some(index) => (&rest[..index], &rest[index + 1..]),
+                None => (rest, ""),
+            };
+            let line = line.trim_end_matches('\r');
+            rest = next;
+
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some((key, value)) => {
+                    fields.push((key.trim().to_string(), unquote(value.trim()).to_string()));
+                }
+                None => {
+                    return Err(format!(
+                        "Expected a `key: value` line in the frontmatter, but found `{}`",
+                        line
+                    ));
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The value of a `key: true` or `key: false` line, if there is one.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(other) => Err(format!(
+                "`{}` in the frontmatter should be true or false, but is `{}`",
+                key, other
+            )),
+        }
+    }
+}
+
+fn strip_delimiter_line(source: &str) -> Option<&str> {
+    let rest = source.strip_prefix("---")?;
+    let rest = rest.trim_start_matches([' ', '\t']);
+
+    if let Some(rest) = rest.strip_prefix("\r\n") {
+        Some(rest)
+    } else if let Some(rest) = rest.strip_prefix('\n') {
+        Some(rest)
+    } else if rest.is_empty() {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+
+    value
+}
+
+#[test]
+fn parse_frontmatter() {
+    let source = "---\ntitle: \"Hello, world\"\npermalink: about/\n---\n# Body\n";
+    let (frontmatter, body) = Frontmatter::parse(source).unwrap();
+
+    assert_eq!(frontmatter.get("title"), Some("Hello, world"));
+    assert_eq!(frontmatter.get("permalink"), Some("about/"));
+    assert_eq!(frontmatter.get("slug"), None);
+    assert_eq!(body, "# Body\n");
+
+    let (frontmatter, _) = Frontmatter::parse("---\ndraft: true\nlive: maybe\n---\n").unwrap();
+    assert_eq!(frontmatter.get_bool("draft"), Ok(Some(true)));
+    assert_eq!(frontmatter.get_bool("slug"), Ok(None));
+    assert!(frontmatter.get_bool("live").is_err());
+}
+
+#[test]
+fn no_frontmatter() {
+    let source = "# Body\n---\n";
+    let (frontmatter, body) = Frontmatter::parse(source).unwrap();
+
+    assert_eq!(frontmatter, Frontmatter::default());
+    assert_eq!(body, source);
+}
@@ -0,0 +1,186 @@
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{Event, HeadingLevel, Tag};
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// A heading, with the id `add_heading_anchors` gave it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub id: String,
+    pub text: String,
+    /// Its events in the output, from the one that opens it to the one that closes it.
+    pub events: Range<usize>,
+}
+
+/// Give every heading an `id`, so it can be linked to, and (if `with_links` is set)
+/// an `<a class="anchor">` pointing at itself.
+///
+/// The id comes from the `{#id}` attribute if the heading has one, or else from
+/// its text. Ids made from text start with `id_prefix`, so they can't collide with ids
+/// in the page's template, and get a `-1`, `-2`, ... suffix if they are already taken.
+///
+/// The headings are returned too, in the order they are on the page.
+pub fn add_heading_anchors<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+    with_links: bool,
+    id_prefix: &str,
+) -> (Vec<Event<'a>>, Vec<Heading>) {
+    let mut output = Vec::new();
+    let mut headings = Vec::new();
+    let mut ids = HeadingIds::default();
+
+    // The events inside the heading we're in (if any), and their text
+    let mut in_heading = false;
+    let mut inner = Vec::new();
+    let mut text = String::new();
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading(..)) => {
+                in_heading = true;
+            }
+            Event::End(Tag::Heading(level, explicit_id, classes)) => {
+                let id = match explicit_id {
+                    Some(id) => ids.claim(id.to_string()),
+                    None => ids.claim_unique(&format!("{}{}", id_prefix, slugify(&text))),
+                };
+
+                let start = output.len();
+                output.push(Event::Html(open_heading(level, &id, &classes).into()));
+                output.append(&mut inner);
+
+                if with_links {
+                    let mut link = String::from("<a href=\"#");
+                    escape_html(&mut link, &id).unwrap();
+                    link.push_str("\" class=\"anchor\" aria-hidden=\"true\">#</a>");
+                    output.push(Event::Html(link.into()));
+                }
+
+                output.push(Event::Html(format!("</{}>\n", level).into()));
+
+                headings.push(Heading {
+                    id,
+                    text: std::mem::take(&mut text),
+                    events: start..output.len(),
+                });
+                in_heading = false;
+            }
+            Event::Text(ref t) | Event::Code(ref t) if in_heading => {
+                text.push_str(t);
+                inner.push(event);
+            }
+            e if in_heading => inner.push(e),
+            e => output.push(e),
+        }
+    }
+
+    (output, headings)
+}
+
+fn open_heading(level: HeadingLevel, id: &str, classes: &[&str]) -> String {
+    let mut html = format!("<{} id=\"", level);
+    escape_html(&mut html, id).unwrap();
+    html.push('"');
+
+    if !classes.is_empty() {
+        html.push_str(" class=\"");
+        escape_html(&mut html, &classes.join(" ")).unwrap();
+        html.push('"');
+    }
+
+    html.push('>');
+    html
+}
+
+/// Turn heading text into an id: lowercase words joined by `-`.
+fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_matches('-');
+
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// The ids already used on the page.
+#[derive(Debug, Default)]
+struct HeadingIds {
+    used: HashSet<String>,
+}
+
+impl HeadingIds {
+    /// Use `id` exactly as given, even if it is taken.
+    fn claim(&mut self, id: String) -> String {
+        self.used.insert(id.clone());
+        id
+    }
+
+    /// Use `base`, or the first of `base-1`, `base-2`, ... that isn't taken.
+    fn claim_unique(&mut self, base: &str) -> String {
+        let mut id = base.to_string();
+        let mut suffix = 0;
+
+        while self.used.contains(&id) {
+            suffix += 1;
+            id = format!("{}-{}", base, suffix);
+        }
+
+        self.claim(id)
+    }
+}
+
+#[test]
+fn duplicate_headings_get_unique_ids() {
+    let markdown = "# Intro\n\n## Intro\n\n## Intro 1\n\n### `Str.concat` & friends {#api .code}\n";
+    let (events, headings) = add_heading_anchors(
+        pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::all()),
+        true,
+        "",
+    );
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+
+    assert_eq!(
+        html,
+        "<h1 id=\"intro\">Intro<a href=\"#intro\" class=\"anchor\" aria-hidden=\"true\">#</a></h1>\n\
+         <h2 id=\"intro-1\">Intro<a href=\"#intro-1\" class=\"anchor\" aria-hidden=\"true\">#</a></h2>\n\
+         <h2 id=\"intro-1-1\">Intro 1<a href=\"#intro-1-1\" class=\"anchor\" aria-hidden=\"true\">#</a></h2>\n\
+         <h3 id=\"api\" class=\"code\"><code>Str.concat</code> &amp; friends<a href=\"#api\" class=\"anchor\" aria-hidden=\"true\">#</a></h3>\n"
+    );
+    assert_eq!(headings.len(), 4);
+    assert_eq!(headings[3].id, "api");
+    assert_eq!(headings[3].text, "Str.concat & friends");
+}
+
+#[test]
+fn generated_ids_get_the_prefix() {
+    let markdown = "# Nav\n\n## Nav\n\n## Footer {#footer}\n";
+    let (events, headings) = add_heading_anchors(
+        pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::all()),
+        true,
+        "content-",
+    );
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+
+    assert_eq!(
+        html,
+        "<h1 id=\"content-nav\">Nav<a href=\"#content-nav\" class=\"anchor\" aria-hidden=\"true\">#</a></h1>\n\
+         <h2 id=\"content-nav-1\">Nav<a href=\"#content-nav-1\" class=\"anchor\" aria-hidden=\"true\">#</a></h2>\n\
+         <h2 id=\"footer\">Footer<a href=\"#footer\" class=\"anchor\" aria-hidden=\"true\">#</a></h2>\n"
+    );
+    assert_eq!(headings[0].id, "content-nav");
+}
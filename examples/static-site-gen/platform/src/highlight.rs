@@ -0,0 +1,59 @@
+//! Highlighting of Roc code, with `roc_highlight` when the `highlight` feature is on.
+//!
+//! Without it, Roc code is escaped and rendered like any other code.
+//!
+//! `with_ranges` gives each token's span `data-start` and `data-end` attributes with its byte
+//! range in the code. Code that isn't highlighted has no tokens, so it is left out then.
+
+#[cfg(feature = "highlight")]
+pub fn highlight_block(code: &str, with_ranges: bool) -> String {
+    if with_ranges {
+        roc_highlight::highlight_roc_code_with_ranges(code)
+    } else {
+        roc_highlight::highlight_roc_code(code)
+    }
+}
+
+#[cfg(feature = "highlight")]
+pub fn highlight_inline(code: &str, with_ranges: bool) -> String {
+    if with_ranges {
+        roc_highlight::highlight_roc_code_inline_with_ranges(code)
+    } else {
+        roc_highlight::highlight_roc_code_inline(code)
+    }
+}
+
+/// Something that changes whenever the highlighter's output does,
+/// so that cached HTML from an older highlighter is not reused.
+#[cfg(feature = "highlight")]
+pub fn fingerprint() -> (String, String) {
+    const SAMPLE: &str = "main = \\{} -> \"Hello, \\(name)!\" |> Str.concat \"\" # comment";
+
+    (
+        highlight_block(SAMPLE, false),
+        highlight_inline(SAMPLE, false),
+    )
+}
+
+#[cfg(not(feature = "highlight"))]
+pub fn highlight_block(code: &str, _with_ranges: bool) -> String {
+    let mut html = String::from("<pre><code>");
+    pulldown_cmark::escape::escape_html(&mut html, code).unwrap();
+    html.push_str("</code></pre>");
+
+    html
+}
+
+#[cfg(not(feature = "highlight"))]
+pub fn highlight_inline(code: &str, _with_ranges: bool) -> String {
+    let mut html = String::from("<code>");
+    pulldown_cmark::escape::escape_html(&mut html, code).unwrap();
+    html.push_str("</code>");
+
+    html
+}
+
+#[cfg(not(feature = "highlight"))]
+pub fn fingerprint() -> (String, String) {
+    (String::from("not highlighted"), String::new())
+}
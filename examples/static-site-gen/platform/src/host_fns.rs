@@ -0,0 +1,284 @@
+use core::ffi::c_void;
+
+/// The `roc_*` functions that the compiled Roc app calls into.
+///
+/// Every method has a default implementation, so a crate that builds on this
+/// platform only overrides the ones it cares about, and then exports them all with
+/// `roc_host_fns!(MyHost)` (after turning off the `default-roc-fns` feature, which
+/// exports the defaults).
+///
+/// Required by every Roc app: `alloc`, `realloc`, `dealloc`, `panic`, `memcpy`
+/// and `memset`.
+///
+/// Optional: `getppid`, `mmap` and `shm_open` are only called by `expect`s when the app
+/// runs under `roc dev`, and only exist on Unix.
+///
+/// Functions that are not part of this trait can be defined next to the
+/// `roc_host_fns!` call as ordinary `#[no_mangle]` functions.
+pub trait RocHost {
+    /// # Safety
+    /// Must return memory that is valid for `size` bytes, at the given alignment.
+    unsafe fn alloc(size: usize, alignment: u32) -> *mut c_void {
+        aligned_alloc(size, alignment as usize)
+    }
+
+    /// # Safety
+    /// `c_ptr` must have been returned by `alloc` or `realloc`, with the same alignment.
+    unsafe fn realloc(
+        c_ptr: *mut c_void,
+        new_size: usize,
+        old_size: usize,
+        alignment: u32,
+    ) -> *mut c_void {
+        aligned_realloc(c_ptr, new_size, old_size, alignment as usize)
+    }
+
+    /// # Safety
+    /// `c_ptr` must have been returned by `alloc` or `realloc`, with the same alignment.
+    unsafe fn dealloc(c_ptr: *mut c_void, alignment: u32) {
+        aligned_free(c_ptr, alignment as usize)
+    }
+
+    /// Never returns into the Roc code that called it.
+    ///
+    /// # Safety
+    /// With a `tag_id` of 0, `c_ptr` must point to a nul-terminated message.
+    unsafe fn panic(c_ptr: *mut c_void, tag_id: u32) {
+        crate::report_roc_panic(c_ptr, tag_id)
+    }
+
+    /// # Safety
+    /// Same as `libc::memcpy`.
+    unsafe fn memcpy(dest: *mut c_void, src: *const c_void, bytes: usize) -> *mut c_void {
+        libc::memcpy(dest, src, bytes)
+    }
+
+    /// # Safety
+    /// Same as `libc::memset`.
+    unsafe fn memset(dst: *mut c_void, c: i32, n: usize) -> *mut c_void {
+        libc::memset(dst, c, n)
+    }
+
+    /// # Safety
+    /// Same as `libc::getppid`.
+    #[cfg(unix)]
+    unsafe fn getppid() -> libc::pid_t {
+        libc::getppid()
+    }
+
+    /// # Safety
+    /// Same as `libc::mmap`.
+    #[cfg(unix)]
+    unsafe fn mmap(
+        addr: *mut c_void,
+        len: libc::size_t,
+        prot: libc::c_int,
+        flags: libc::c_int,
+        fd: libc::c_int,
+        offset: libc::off_t,
+    ) -> *mut c_void {
+        libc::mmap(addr, len, prot, flags, fd, offset)
+    }
+
+    /// # Safety
+    /// Same as `libc::shm_open`.
+    #[cfg(unix)]
+    unsafe fn shm_open(
+        name: *const libc::c_char,
+        oflag: libc::c_int,
+        mode: libc::mode_t,
+    ) -> libc::c_int {
+        libc::shm_open(name, oflag, mode as libc::c_uint)
+    }
+}
+
+/// The alignment that `malloc` always gives, for any size.
+const MALLOC_ALIGNMENT: usize = 2 * core::mem::size_of::<usize>();
+
+/// `malloc`, unless `alignment` is more than it guarantees.
+///
+/// # Safety
+/// Free the result with `aligned_free`, passing the same alignment.
+pub unsafe fn aligned_alloc(size: usize, alignment: usize) -> *mut c_void {
+    if alignment <= MALLOC_ALIGNMENT {
+        return libc::malloc(size);
+    }
+
+    #[cfg(unix)]
+    {
+        let mut ptr = core::ptr::null_mut();
+
+        // posix_memalign wants a multiple of the pointer size, which a larger power of two is
+        match libc::posix_memalign(&mut ptr, alignment, size) {
+            0 => ptr,
+            _ => core::ptr::null_mut(),
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        libc::aligned_malloc(size, alignment)
+    }
+}
+
+/// `realloc`, unless `alignment` is more than `malloc` guarantees. `realloc` may move
+/// the block to an address with only that guarantee, so over-aligned blocks are moved by hand.
+///
+/// # Safety
+/// `c_ptr` must have come from `aligned_alloc` or `aligned_realloc` with the same alignment,
+/// and be valid for `old_size` bytes.
+pub unsafe fn aligned_realloc(
+    c_ptr: *mut c_void,
+    new_size: usize,
+    old_size: usize,
+    alignment: usize,
+) -> *mut c_void {
+    if alignment <= MALLOC_ALIGNMENT {
+        return libc::realloc(c_ptr, new_size);
+    }
+
+    let new_ptr = aligned_alloc(new_size, alignment);
+
+    if !new_ptr.is_null() {
+        libc::memcpy(new_ptr, c_ptr, old_size.min(new_size));
+        aligned_free(c_ptr, alignment);
+    }
+
+    new_ptr
+}
+
+/// # Safety
+/// `c_ptr` must have come from `aligned_alloc` or `aligned_realloc` with the same alignment.
+pub unsafe fn aligned_free(c_ptr: *mut c_void, alignment: usize) {
+    // Memory from posix_memalign is freed like any other, but not memory from _aligned_malloc
+    #[cfg(windows)]
+    if alignment > MALLOC_ALIGNMENT {
+        return libc::aligned_free(c_ptr);
+    }
+
+    let _ = alignment;
+    libc::free(c_ptr)
+}
+
+/// Export the `roc_*` functions of a `RocHost` implementation, so that the Roc app links against them.
+///
+/// Use this once per program: the functions are `#[no_mangle]`.
+#[macro_export]
+macro_rules! roc_host_fns {
+    ($host:ty) => {
+        /// # Safety
+        /// Same as `RocHost::alloc`.
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_alloc(size: usize, alignment: u32) -> *mut core::ffi::c_void {
+            <$host as $crate::RocHost>::alloc(size, alignment)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::realloc`.
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_realloc(
+            c_ptr: *mut core::ffi::c_void,
+            new_size: usize,
+            old_size: usize,
+            alignment: u32,
+        ) -> *mut core::ffi::c_void {
+            <$host as $crate::RocHost>::realloc(c_ptr, new_size, old_size, alignment)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::dealloc`.
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_dealloc(c_ptr: *mut core::ffi::c_void, alignment: u32) {
+            <$host as $crate::RocHost>::dealloc(c_ptr, alignment)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::panic`.
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_panic(c_ptr: *mut core::ffi::c_void, tag_id: u32) {
+            <$host as $crate::RocHost>::panic(c_ptr, tag_id)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::memcpy`.
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_memcpy(
+            dest: *mut core::ffi::c_void,
+            src: *const core::ffi::c_void,
+            bytes: usize,
+        ) -> *mut core::ffi::c_void {
+            <$host as $crate::RocHost>::memcpy(dest, src, bytes)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::memset`.
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_memset(
+            dst: *mut core::ffi::c_void,
+            c: i32,
+            n: usize,
+        ) -> *mut core::ffi::c_void {
+            <$host as $crate::RocHost>::memset(dst, c, n)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::getppid`.
+        #[cfg(unix)]
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_getppid() -> $crate::libc::pid_t {
+            <$host as $crate::RocHost>::getppid()
+        }
+
+        /// # Safety
+        /// Same as `RocHost::mmap`.
+        #[cfg(unix)]
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_mmap(
+            addr: *mut core::ffi::c_void,
+            len: $crate::libc::size_t,
+            prot: $crate::libc::c_int,
+            flags: $crate::libc::c_int,
+            fd: $crate::libc::c_int,
+            offset: $crate::libc::off_t,
+        ) -> *mut core::ffi::c_void {
+            <$host as $crate::RocHost>::mmap(addr, len, prot, flags, fd, offset)
+        }
+
+        /// # Safety
+        /// Same as `RocHost::shm_open`.
+        #[cfg(unix)]
+        #[no_mangle]
+        pub unsafe extern "C" fn roc_shm_open(
+            name: *const $crate::libc::c_char,
+            oflag: $crate::libc::c_int,
+            mode: $crate::libc::mode_t,
+        ) -> $crate::libc::c_int {
+            <$host as $crate::RocHost>::shm_open(name, oflag, mode)
+        }
+    };
+}
+
+#[test]
+fn over_aligned_blocks_stay_aligned() {
+    const ALIGNMENT: usize = 32;
+
+    unsafe {
+        let ptr = aligned_alloc(24, ALIGNMENT) as *mut u8;
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % ALIGNMENT, 0);
+
+        for i in 0..24 {
+            *ptr.add(i) = i as u8;
+        }
+
+        let ptr = aligned_realloc(ptr as *mut c_void, 4096, 24, ALIGNMENT) as *mut u8;
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % ALIGNMENT, 0);
+        assert_eq!(
+            core::slice::from_raw_parts(ptr, 24),
+            (0..24).collect::<Vec<u8>>()
+        );
+
+        aligned_free(ptr as *mut c_void, ALIGNMENT);
+    }
+}
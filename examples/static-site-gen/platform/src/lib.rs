@@ -1,128 +1,582 @@
 use core::ffi::c_void;
-use libc;
+use filetime::FileTime;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+pub use libc;
+use pulldown_cmark::escape::escape_html;
 use pulldown_cmark::{html, Options, Parser};
 use roc_std::RocStr;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env;
 use std::ffi::CStr;
 use std::fs;
+use std::net::SocketAddr;
 use std::os::raw::c_char;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod cache;
+mod code_figures;
+mod dir_listings;
+mod feed;
+mod frontmatter;
+mod headings;
+mod highlight;
+mod host_fns;
+mod line_numbers;
+mod log;
+mod manifest;
+mod math;
+mod metrics;
+mod minify;
+mod precompress;
+mod search_index;
+#[cfg(feature = "serve")]
+mod serve;
+mod shortcodes;
+mod site_config;
+#[cfg(test)]
+mod test_dir;
+mod urls;
+
+use cache::HtmlCache;
+use dir_listings::DirListings;
+use frontmatter::Frontmatter;
+pub use host_fns::RocHost;
+use log::{info, Verbosity};
+use search_index::Section;
 
 extern "C" {
     #[link_name = "roc__transformFileContentForHost_1_exposed"]
-    fn roc_transformFileContentForHost(relPath: &RocStr, content: &RocStr) -> RocStr;
+    fn roc_transformFileContentForHost(
+        relPath: &RocStr,
+        content: &RocStr,
+        metadata: &RocPageMetadata,
+        siteConfig: &RocStr,
+    ) -> RocTransformOutput;
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn roc_alloc(size: usize, _alignment: u32) -> *mut c_void {
-    libc::malloc(size)
+/// The `{ readingMinutes : U64, wordCount : U64 }` record passed to the Roc transform.
+/// Fields are in alphabetical order, because they have the same alignment.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct RocPageMetadata {
+    reading_minutes: u64,
+    word_count: u64,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn roc_realloc(
-    c_ptr: *mut c_void,
-    new_size: usize,
-    _old_size: usize,
-    _alignment: u32,
-) -> *mut c_void {
-    libc::realloc(c_ptr, new_size)
+/// The `{ html : Str, data : Str, path : Str }` record returned by the Roc transform.
+/// Fields are in alphabetical order, because they have the same alignment.
+#[repr(C)]
+struct RocTransformOutput {
+    data: RocStr,
+    html: RocStr,
+    path: RocStr,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn roc_dealloc(c_ptr: *mut c_void, _alignment: u32) {
-    libc::free(c_ptr)
+/// What the Roc transform made of a page: the HTML to write, optionally some data
+/// to write alongside it as JSON, and optionally where to write it instead of the
+/// page's usual output path.
+#[derive(Debug)]
+struct TransformOutput {
+    html: String,
+    data: String,
+    path: String,
 }
 
-#[cfg(unix)]
-#[no_mangle]
-pub unsafe extern "C" fn roc_getppid() -> libc::pid_t {
-    libc::getppid()
+/// The HTML rendered from a page's Markdown, how many words of prose
+/// (outside of code blocks) it has, and that prose split up by heading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RenderedMarkdown {
+    html: String,
+    word_count: usize,
+    sections: Vec<Section>,
 }
 
-#[cfg(unix)]
-#[no_mangle]
-pub unsafe extern "C" fn roc_mmap(
-    addr: *mut libc::c_void,
-    len: libc::size_t,
-    prot: libc::c_int,
-    flags: libc::c_int,
-    fd: libc::c_int,
-    offset: libc::off_t,
-) -> *mut libc::c_void {
-    libc::mmap(addr, len, prot, flags, fd, offset)
-}
-
-#[cfg(unix)]
-#[no_mangle]
-pub unsafe extern "C" fn roc_shm_open(
-    name: *const libc::c_char,
-    oflag: libc::c_int,
-    mode: libc::mode_t,
-) -> libc::c_int {
-    libc::shm_open(name, oflag, mode as libc::c_uint)
+/// The `roc_*` functions this platform uses, all of them `RocHost`'s defaults.
+pub struct DefaultHost;
+
+impl RocHost for DefaultHost {}
+
+#[cfg(feature = "default-roc-fns")]
+crate::roc_host_fns!(DefaultHost);
+
+/// Rewrites a page's Markdown before it is parsed, e.g. to support syntax
+/// that pulldown-cmark doesn't know about.
+type Preprocessor = fn(&str) -> String;
+
+/// Turns a code block's highlighted HTML into the markup that goes on the page, e.g. to
+/// wrap it in a `<figure>`. It also gets the block's language, which is `""` if it has none.
+type CodeBlockHook = fn(&str, &str) -> String;
+
+/// Options passed on the command line, after the input and output directories.
+#[derive(Debug, Default)]
+struct Config {
+    /// Collapse insignificant whitespace in the generated HTML.
+    minify: bool,
+    /// Add a link to itself to every heading. Headings get an `id` either way.
+    heading_anchors: bool,
+    /// Put before the ids made from heading text, so they don't collide with ids in the template.
+    heading_id_prefix: String,
+    /// Mark each highlighted token of Roc code with its byte range in the code.
+    token_ranges: bool,
+    /// Run over each page's Markdown in order, before it is parsed.
+    preprocessors: Vec<Preprocessor>,
+    /// Run over each code block after it is highlighted. `None` leaves the highlighted HTML as it is.
+    code_block_hook: Option<CodeBlockHook>,
+    /// How many pages to process at once. `None` means one per CPU.
+    jobs: Option<usize>,
+    /// Write a `manifest.json` listing the pages to the output directory.
+    manifest: bool,
+    /// Use absolute paths in the manifest, rather than paths relative to the input and output directories.
+    absolute_manifest_paths: bool,
+    /// Where to keep the HTML rendered from each page's Markdown between builds.
+    cache_dir: Option<PathBuf>,
+    /// Write an `atom.xml` feed of the dated pages, for the site at this URL.
+    feed_base_url: Option<String>,
+    /// The reading speed used for each page's reading time. `None` means `DEFAULT_WORDS_PER_MINUTE`.
+    words_per_minute: Option<usize>,
+    /// After building, serve the output directory at this address.
+    serve: Option<SocketAddr>,
+    /// Put before the HTML of every page that the Roc transform returns.
+    head_html: String,
+    /// Put after the HTML of every page that the Roc transform returns.
+    footer_html: String,
+    /// Set by `--quiet` or `--verbose`. `None` leaves it to the `SSG_LOG` environment variable.
+    verbosity: Option<Verbosity>,
+    /// Files and directories to leave out, matched against their path relative to the input directory.
+    exclude: GlobSet,
+    /// Stop at the first page that fails, rather than building the rest of the site.
+    fail_fast: bool,
+    /// Empty the output directory before building into it.
+    clean: bool,
+    /// Write a `search-index.json` of each page's headings and prose to the output directory.
+    search_index: bool,
+    /// Write each page without a permalink to an `index.html` in a directory named after it.
+    pretty_urls: bool,
+    /// How pages are linked in the feed and the search index. `None` links them by their output path.
+    url_rewriter: Option<urls::UrlRewriter>,
+    /// Give each page's output files the mtime of its Markdown source.
+    preserve_timestamps: bool,
+    /// After building, write the build's metrics to this file, for Prometheus' textfile collector.
+    metrics_file: Option<PathBuf>,
+    /// Write a compressed copy of each output file next to it, in each of these encodings.
+    precompress: Vec<precompress::Encoding>,
+}
+
+const DEFAULT_WORDS_PER_MINUTE: usize = 200;
+
+const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:8000";
+
+const USAGE_OPTIONS: &str = "\
+Options:
+  --minify            Collapse insignificant whitespace in the generated HTML
+  --shortcodes        Expand shortcodes like {{< youtube VIDEO_ID >}} in the Markdown
+  --heading-anchors   Give each heading a link to itself
+  --heading-id-prefix PREFIX
+                      Start the ids made from heading text with PREFIX, e.g. content-
+  --token-ranges      Give each highlighted token of Roc code data-start and data-end
+                      attributes with its byte range in the code
+  --pretty-urls       Write about.md to about/index.html, and link to it as about/
+  --code-figures      Wrap each code block in a <figure> with a placeholder copy button
+  --jobs N            Process at most N pages at once (default: one per CPU)
+  --manifest          Write a manifest.json listing each page's source and output
+  --search-index      Write a search-index.json of each page's headings and text
+  --absolute-manifest-paths
+                      Use absolute paths in the manifest, instead of relative ones
+  --cache-dir DIR     Reuse the HTML rendered from unchanged Markdown in earlier builds
+  --feed BASE_URL     Write an atom.xml feed of the pages with a `date` in their frontmatter
+  --words-per-minute N
+                      The reading speed for the reading time passed to the transform (default: 200)
+  --serve [ADDR]      After building, serve the output directory (default: 127.0.0.1:8000)
+  --head-file FILE    Put the contents of FILE before the HTML of every page
+  --footer-file FILE  Put the contents of FILE after the HTML of every page
+  --exclude GLOB      Leave out the files and directories that match GLOB, relative to the
+                      input directory, e.g. **/drafts/** (can be given more than once)
+  --fail-fast         Stop at the first page that fails, instead of building the rest
+  --clean             Empty the output directory before building, so that the outputs of
+                      deleted pages don't linger (along with anything else in it)
+  --precompress ENCODINGS
+                      Also write a compressed copy of each output file, for each of
+                      ENCODINGS: gzip, brotli or gzip,brotli (needs the precompress feature)
+  --preserve-timestamps
+                      Give each page's output files the modification time of its source
+  --metrics-file FILE
+                      After building, write the number of files and errors and the build's
+                      duration to FILE, in Prometheus' text format
+  --quiet             Only print errors and warnings
+  --verbose           Also print how long each page and the whole build took
+
+The SSG_LOG environment variable can also be set to quiet, normal or verbose.";
+
+impl Config {
+    /// Split the command line arguments (without the program name)
+    /// into the options and the positional arguments.
+    fn from_args(args: &[String]) -> Result<(Self, Vec<String>), String> {
+        let mut config = Config::default();
+        let mut positional = Vec::new();
+        let mut exclude = GlobSetBuilder::new();
+        let mut args = args.iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--minify" => config.minify = true,
+                "--quiet" => config.verbosity = Some(Verbosity::Quiet),
+                "--verbose" => config.verbosity = Some(Verbosity::Verbose),
+                "--heading-anchors" => config.heading_anchors = true,
+                "--token-ranges" => config.token_ranges = true,
+                "--shortcodes" => config.preprocessors.push(shortcodes::expand_shortcodes),
+                "--code-figures" => config.code_block_hook = Some(code_figures::wrap_in_figure),
+                "--manifest" => config.manifest = true,
+                "--fail-fast" => config.fail_fast = true,
+                "--clean" => config.clean = true,
+                "--search-index" => config.search_index = true,
+                "--preserve-timestamps" => config.preserve_timestamps = true,
+                "--pretty-urls" => {
+                    config.pretty_urls = true;
+                    config.url_rewriter = Some(urls::pretty_url);
+                }
+                "--absolute-manifest-paths" => config.absolute_manifest_paths = true,
+                "--jobs" => {
+                    let value = flag_value(arg, args.next())?;
+                    match value.parse::<usize>() {
+                        Ok(jobs) if jobs > 0 => config.jobs = Some(jobs),
+                        _ => {
+                            return Err(format!(
+                                "--jobs needs a positive number, but got `{}`",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "--heading-id-prefix" => {
+                    config.heading_id_prefix = flag_value(arg, args.next())?.to_string();
+                }
+                "--cache-dir" => {
+                    config.cache_dir = Some(PathBuf::from(flag_value(arg, args.next())?));
+                }
+                "--precompress" => {
+                    if !cfg!(feature = "precompress") {
+                        return Err("--precompress needs the site generator to be built with \
+                             the `precompress` feature"
+                            .into());
+                    }
+
+                    config.precompress =
+                        precompress::parse_encodings(flag_value(arg, args.next())?)?;
+                }
+                "--metrics-file" => {
+                    config.metrics_file = Some(PathBuf::from(flag_value(arg, args.next())?));
+                }
+                "--feed" => {
+                    config.feed_base_url = Some(flag_value(arg, args.next())?.to_string());
+                }
+                "--words-per-minute" => {
+                    let value = flag_value(arg, args.next())?;
+                    match value.parse::<usize>() {
+                        Ok(words) if words > 0 => config.words_per_minute = Some(words),
+                        _ => {
+                            return Err(format!(
+                                "--words-per-minute needs a positive number, but got `{}`",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "--head-file" => {
+                    config.head_html = read_flag_file(flag_value(arg, args.next())?)?;
+                }
+                "--footer-file" => {
+                    config.footer_html = read_flag_file(flag_value(arg, args.next())?)?;
+                }
+                "--exclude" => {
+                    let pattern = flag_value(arg, args.next())?;
+
+                    for glob in exclude_globs(pattern)? {
+                        exclude.add(glob);
+                    }
+                }
+                "--serve" => {
+                    if !cfg!(feature = "serve") {
+                        return Err(
+                            "--serve needs the site generator to be built with the `serve` feature"
+                                .into(),
+                        );
+                    }
+
+                    // The address is optional, so only take the next argument if it is one
+                    config.serve = match args.peek().and_then(|next| serve_addr(next)) {
+                        Some(addr) => {
+                            args.next();
+                            Some(addr)
+                        }
+                        None => serve_addr(DEFAULT_SERVE_ADDR),
+                    };
+                }
+                other if other.starts_with("--") => {
+                    return Err(format!("Unrecognized option: {}", other))
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        config.exclude = exclude
+            .build()
+            .map_err(|e| format!("Invalid --exclude pattern: {}", e))?;
+
+        Ok((config, positional))
+    }
+}
+
+/// The globs for one `--exclude` pattern. A pattern like `**/drafts/**` only matches what is
+/// inside `drafts`, so it also gets a glob for `drafts` itself, which isn't even read then.
+fn exclude_globs(pattern: &str) -> Result<Vec<Glob>, String> {
+    let glob = |pattern| {
+        Glob::new(pattern).map_err(|e| format!("Invalid --exclude pattern `{}`: {}", pattern, e))
+    };
+
+    match pattern.strip_suffix("/**") {
+        Some(dir) if !dir.is_empty() => Ok(vec![glob(pattern)?, glob(dir)?]),
+        _ => Ok(vec![glob(pattern)?]),
+    }
+}
+
+/// The contents of a file named by an option, read once when the options are parsed.
+fn read_flag_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))
+}
+
+/// An address like `127.0.0.1:8080`, or just a port like `8080` on localhost.
+fn serve_addr(arg: &str) -> Option<SocketAddr> {
+    match arg.parse::<u16>() {
+        Ok(port) => Some(SocketAddr::from(([127, 0, 0, 1], port))),
+        Err(_) => arg.parse().ok(),
+    }
+}
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    value
+        .map(|value| value.as_str())
+        .ok_or_else(|| format!("{} needs a value", flag))
 }
 
 #[no_mangle]
 pub extern "C" fn rust_main() -> i32 {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} path/to/input/dir path/to/output/dir", args[0]);
+
+    let (config, positional) = match Config::from_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let verbosity = match config.verbosity {
+        Some(verbosity) => verbosity,
+        None => match Verbosity::from_env() {
+            Ok(verbosity) => verbosity.unwrap_or(Verbosity::Normal),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        },
+    };
+    log::set_verbosity(verbosity);
+
+    if positional.len() != 2 {
+        eprintln!(
+            "Usage: {0} path/to/input/dir path/to/output/dir [options]\n   \
+             or: {0} 'path/to/input/**/*.md' path/to/output/dir [options]\n   \
+             or: {0} path/to/input.md path/to/output.html [options]\n\n{1}",
+            args[0], USAGE_OPTIONS
+        );
         return 1;
     }
 
-    match run(&args[1], &args[2]) {
+    match run(&positional[0], &positional[1], &config) {
         Err(e) => {
             eprintln!("{}", e);
             1
         }
+        #[cfg(feature = "serve")]
+        Ok(()) if config.serve.is_some() => {
+            let addr = config.serve.unwrap();
+
+            match serve::serve(Path::new(&positional[1]), addr) {
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+                Ok(()) => 0,
+            }
+        }
         Ok(()) => 0,
     }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn roc_panic(c_ptr: *mut c_void, tag_id: u32) {
-    match tag_id {
+thread_local! {
+    /// Where `roc_panic` reports a panic on the current thread, if anyone is listening.
+    /// Set by `call_roc_transform` on the worker threads it spawns.
+    static PANIC_SENDER: RefCell<Option<mpsc::Sender<Result<TransformOutput, String>>>> = RefCell::new(None);
+}
+
+/// A Roc panic cannot return into Roc code (the call is followed by `unreachable`),
+/// and we cannot unwind or longjmp through the Roc stack frames either. So instead,
+/// the panic message is sent back to whoever started the Roc call, and the panicking
+/// thread is parked forever.
+///
+/// The limitation is that the parked thread, its stack, and anything Roc had
+/// allocated at the time of the panic are leaked until the process exits.
+/// That is fine for a build that runs once, but a long-running host would
+/// accumulate one parked thread per panic.
+///
+/// This is what `DefaultHost` does for `roc_panic`.
+pub(crate) unsafe fn report_roc_panic(c_ptr: *mut c_void, tag_id: u32) {
+    let message = match tag_id {
         0 => {
             let slice = CStr::from_ptr(c_ptr as *const c_char);
-            let string = slice.to_str().unwrap();
-            eprintln!("Roc hit a panic: {}", string);
-            std::process::exit(1);
+            slice.to_string_lossy().into_owned()
         }
-        _ => todo!(),
+        // Only tag 0 (a message) is defined. Any other is still a panic, just without one.
+        _ => format!("Roc panicked with tag {}", tag_id),
+    };
+
+    let reported = PANIC_SENDER.with(|sender| match sender.borrow().as_ref() {
+        Some(sender) => sender.send(Err(message.clone())).is_ok(),
+        None => false,
+    });
+
+    if !reported {
+        // Nobody is waiting on this thread, so there is nothing to recover to.
+        eprintln!("Roc hit a panic: {}", message);
+        std::process::exit(1);
+    }
+
+    loop {
+        thread::park();
     }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn roc_memcpy(
-    dest: *mut c_void,
-    src: *const c_void,
-    bytes: usize,
-) -> *mut c_void {
-    libc::memcpy(dest, src, bytes)
+/// What a build did with each input file. `build` returns it without printing
+/// anything, so that `run` (or anything else) can report it however it likes.
+#[derive(Debug, Default)]
+struct BuildReport {
+    /// How many files were found in the input directory, Markdown or not.
+    num_files: usize,
+    /// The directories that could not be read, and were skipped.
+    skipped: Vec<String>,
+    /// Each Markdown file, sorted by path.
+    pages: Vec<PageReport>,
+    /// How long the whole build took.
+    duration: Duration,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn roc_memset(dst: *mut c_void, c: i32, n: usize) -> *mut c_void {
-    libc::memset(dst, c, n)
+#[derive(Debug)]
+struct PageReport {
+    input_file: PathBuf,
+    /// The file the page was written to, or why it wasn't.
+    result: Result<PathBuf, String>,
+    duration: Duration,
 }
 
-fn run(input_dirname: &str, output_dirname: &str) -> Result<(), String> {
+impl BuildReport {
+    fn num_successes(&self) -> usize {
+        self.pages.iter().filter(|page| page.result.is_ok()).count()
+    }
+
+    fn num_errors(&self) -> usize {
+        self.pages.len() - self.num_successes()
+    }
+}
+
+/// Build the site, and print what happened.
+fn run(input_dirname: &str, output_dirname: &str, config: &Config) -> Result<(), String> {
+    let report = build(input_dirname, output_dirname, config)?;
+
+    // Skipping an unreadable directory shouldn't stop the rest of the site from building.
+    for warning in report.skipped.iter() {
+        eprintln!("Warning: skipping {}", warning);
+    }
+
+    info!("Processing {} input files...", report.num_files);
+
+    for page in report.pages.iter() {
+        match &page.result {
+            Ok(output_file) => info!(
+                "{} -> {}{}",
+                page.input_file.display(),
+                output_file.display(),
+                log::timing(page.duration)
+            ),
+            Err(e) => eprintln!(
+                "Failed to process file:\n\n  ({:?})with error:\n\n  {}",
+                &page.input_file, e
+            ),
+        }
+    }
+
+    info!(
+        "Processed {} files with {} successes and {} errors{}",
+        report.num_files,
+        report.num_successes(),
+        report.num_errors(),
+        log::timing(report.duration)
+    );
+
+    if let Some(metrics_file) = &config.metrics_file {
+        metrics::write_textfile(metrics_file, &report)?;
+    }
+
+    if report.num_errors() > 0 {
+        Err("Could not process all files".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Build the site, without printing anything. A page that fails doesn't stop the
+/// others from being built, and is reported in the `BuildReport`. Problems with the
+/// site as a whole, like two pages with the same output file, are an `Err`.
+///
+/// If the input is a single file rather than a directory, see `build_file`. If it is a glob
+/// like `content/**/*.md`, only the files that match are built, as if `content` was the input.
+fn build(
+    input_dirname: &str,
+    output_dirname: &str,
+    config: &Config,
+) -> Result<BuildReport, String> {
+    let started = Instant::now();
+
+    if Path::new(input_dirname).is_file() {
+        return build_file(input_dirname, output_dirname, config, started);
+    }
+
+    let (input_dirname, include) = match split_input_glob(input_dirname) {
+        Some((base, pattern)) => (base, Some(input_glob(pattern)?)),
+        None => (input_dirname, None),
+    };
+
     let input_dir = strip_windows_prefix(
         PathBuf::from(input_dirname)
             .canonicalize()
             .map_err(|e| format!("{}: {}", input_dirname, e))?,
     );
 
-    let output_dir = {
-        let dir = PathBuf::from(output_dirname);
-        if !dir.exists() {
-            fs::create_dir_all(&dir).unwrap();
-        }
-        strip_windows_prefix(
-            dir.canonicalize()
-                .map_err(|e| format!("{}: {}", output_dirname, e))?,
-        )
-    };
+    // Checked before the output directory is created, so that a rejected one isn't left behind
+    let output_dir = strip_windows_prefix(
+        canonicalize_uncreated(Path::new(output_dirname))
+            .map_err(|e| format!("{}: {}", output_dirname, e))?,
+    );
+    check_output_dir(&input_dir, &output_dir)?;
+
+    if !output_dir.exists() {
+        fs::create_dir_all(&output_dir)
+            .map_err(|e| format!("Could not create {}: {}", output_dir.display(), e))?;
+    }
 
     if !input_dir.exists() {
         return Err(format!("{} does not exist. The first argument should be the directory where your Markdown files are.", input_dir.display()));
@@ -132,76 +586,727 @@ fn run(input_dirname: &str, output_dirname: &str) -> Result<(), String> {
         return Err(format!("Could not create {}", output_dir.display()));
     }
 
-    let mut input_files: Vec<PathBuf> = vec![];
-    find_files(&input_dir, &mut input_files)
-        .map_err(|e| format!("Error finding input files: {}", e))?;
+    if config.clean {
+        clean_output_dir(&input_dir, &output_dir)?;
+    }
 
-    println!("Processing {} input files...", input_files.len());
+    let mut dir_listings = config.cache_dir.as_deref().map(DirListings::load);
+    let (mut input_files, skipped) = find_files(&input_dir, &config.exclude, dir_listings.as_mut());
+    if let Some(dir_listings) = dir_listings {
+        if log::enabled(Verbosity::Verbose) {
+            info!(
+                "Listed {} new or changed input directories",
+                dir_listings.dirs_read()
+            );
+        }
+        dir_listings.save();
+    }
+
+    if let Some(include) = include {
+        input_files.retain(|input_file| {
+            include.is_match(input_file.strip_prefix(&input_dir).unwrap_or(input_file))
+        });
+    }
 
-    // TODO: process the files asynchronously
-    let num_files = input_files.len();
-    let mut num_errors = 0;
-    let mut num_successes = 0;
+    let mut report = BuildReport {
+        num_files: input_files.len(),
+        skipped,
+        ..BuildReport::default()
+    };
+
+    let mut pages = Vec::with_capacity(input_files.len());
     for input_file in input_files {
         match input_file.extension() {
-            Some(s) if s.eq("md".into()) => {
-                match process_file(&input_dir, &output_dir, &input_file) {
-                    Ok(()) => {
-                        num_successes += 1;
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "Failed to process file:\n\n  ({:?})with error:\n\n  {}",
-                            &input_file, e
-                        );
-                        num_errors += 1;
+            Some(s) if s == "md" => match load_page(&input_dir, input_file.clone(), config) {
+                Ok(page) => pages.push(page),
+                Err(e) => {
+                    report.pages.push(PageReport {
+                        input_file,
+                        result: Err(e),
+                        duration: Duration::ZERO,
+                    });
+
+                    if config.fail_fast {
+                        report.duration = started.elapsed();
+
+                        return Ok(report);
                     }
                 }
-            }
+            },
             _ => {}
         };
     }
 
-    println!(
-        "Processed {} files with {} successes and {} errors",
-        num_files, num_successes, num_errors
-    );
+    check_output_collisions(&pages)?;
+
+    let cache = html_cache(config)?;
+    let site_config = site_config::read_site_config(&input_dir)?;
+
+    report.pages.extend(process_pages(
+        &output_dir,
+        &mut pages,
+        config,
+        cache.as_ref(),
+        &site_config,
+    ));
+    report
+        .pages
+        .sort_by(|page1, page2| page1.input_file.cmp(&page2.input_file));
+
+    // The Roc transform may have moved pages onto each other's output
+    check_output_collisions(&pages)?;
+
+    let manifest = if config.manifest {
+        let paths: Vec<(&Path, &Path)> = pages
+            .iter()
+            .map(|page| {
+                let input_relpath = page.input_file.strip_prefix(&input_dir).unwrap();
+                (input_relpath, page.output_relpath.as_path())
+            })
+            .collect();
+
+        Some(manifest::manifest_json(
+            &input_dir,
+            &output_dir,
+            &paths,
+            config.absolute_manifest_paths,
+        ))
+    } else {
+        None
+    };
+
+    let feed = config
+        .feed_base_url
+        .as_ref()
+        .map(|base_url| feed::atom_feed(base_url, feed_entries(&pages, base_url, config)));
+
+    let search_index = if config.search_index {
+        Some(search_index::search_index_json(&search_pages(
+            &pages, config,
+        )))
+    } else {
+        None
+    };
+
+    // A manifest or feed of a partial build would list pages that were never written
+    if report.num_errors() == 0 {
+        if let Some(manifest) = manifest {
+            write_output_file(
+                &output_dir.join("manifest.json"),
+                &manifest,
+                &config.precompress,
+            )?;
+        }
+
+        if let Some(feed) = feed {
+            write_output_file(&output_dir.join("atom.xml"), &feed, &config.precompress)?;
+        }
+
+        if let Some(search_index) = search_index {
+            write_output_file(
+                &output_dir.join("search-index.json"),
+                &search_index,
+                &config.precompress,
+            )?;
+        }
+    }
+
+    report.duration = started.elapsed();
+
+    Ok(report)
+}
+
+/// The input argument split into the directory before its first component with a glob
+/// metacharacter, and the glob for the rest, like `content` and `**/*.md` for
+/// `content/**/*.md`. `None` if the input isn't a glob.
+fn split_input_glob(input: &str) -> Option<(&str, &str)> {
+    let is_glob = |component: &str| component.contains(['*', '?', '[', '{']);
+
+    let glob_start = input
+        .split('/')
+        .scan(0, |start, component| {
+            let component_start = *start;
+            *start += component.len() + 1;
+            Some((component_start, component))
+        })
+        .find(|(_, component)| is_glob(component))
+        .map(|(start, _)| start)?;
+
+    match input[..glob_start].trim_end_matches('/') {
+        "" if input.starts_with('/') => Some(("/", &input[glob_start..])),
+        "" => Some((".", &input[glob_start..])),
+        base => Some((base, &input[glob_start..])),
+    }
+}
+
+/// The matcher for an input glob's pattern, where `*` doesn't match across directories,
+/// but `**` does, like in a shell.
+fn input_glob(pattern: &str) -> Result<GlobMatcher, String> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("Invalid input glob `{}`: {}", pattern, e))
+}
+
+/// Convert a single Markdown file to `output_filename`, for one-off conversions.
+/// The output is a file too, and there is no manifest or feed for a single page.
+fn build_file(
+    input_filename: &str,
+    output_filename: &str,
+    config: &Config,
+    started: Instant,
+) -> Result<BuildReport, String> {
+    let input_file = strip_windows_prefix(
+        PathBuf::from(input_filename)
+            .canonicalize()
+            .map_err(|e| format!("{}: {}", input_filename, e))?,
+    );
+    let input_dir = input_file.parent().unwrap_or(&input_file).to_path_buf();
+
+    let output_file = PathBuf::from(output_filename);
+    if output_file.is_dir() {
+        return Err(format!(
+            "{} is a directory. When the input is a single file, the output should be a file too.",
+            output_filename
+        ));
+    }
+
+    let output_name = output_file
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file name", output_filename))?;
+    let output_dir = match output_file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| format!("Could not create {}: {}", output_dir.display(), e))?;
+    let output_dir = strip_windows_prefix(
+        output_dir
+            .canonicalize()
+            .map_err(|e| format!("{}: {}", output_dir.display(), e))?,
+    );
+
+    if output_dir.join(output_name) == input_file {
+        return Err(format!(
+            "The output file {} is the input file. Choose a different output file.",
+            output_filename
+        ));
+    }
+
+    let mut report = BuildReport {
+        num_files: 1,
+        ..BuildReport::default()
+    };
+
+    // The page is written where it was asked to go, whatever its frontmatter says
+    match load_page(&input_dir, input_file.clone(), config) {
+        Ok(page) => {
+            let mut pages = [Page {
+                output_relpath: PathBuf::from(output_name),
+                ..page
+            }];
+            let cache = html_cache(config)?;
+            let site_config = site_config::read_site_config(&input_dir)?;
+
+            report.pages.extend(process_pages(
+                &output_dir,
+                &mut pages,
+                config,
+                cache.as_ref(),
+                &site_config,
+            ));
+        }
+        Err(e) => report.pages.push(PageReport {
+            input_file,
+            result: Err(e),
+            duration: Duration::ZERO,
+        }),
+    }
+
+    report.duration = started.elapsed();
+
+    Ok(report)
+}
+
+/// The cache for `--cache-dir`, if it was given.
+fn html_cache(config: &Config) -> Result<Option<HtmlCache>, String> {
+    match &config.cache_dir {
+        Some(cache_dir) => Ok(Some(HtmlCache::new(
+            cache_dir.clone(),
+            (
+                highlight::fingerprint(),
+                config.heading_anchors,
+                config.heading_id_prefix.as_str(),
+                config.token_ranges,
+                // What the hook does to a sample block, like `highlight::fingerprint`
+                config
+                    .code_block_hook
+                    .map(|hook| hook("<pre><samp></samp></pre>", "roc")),
+            ),
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// Process the pages on up to `config.jobs` threads at once, and report on each of them.
+/// Each page's `output_relpath` is updated to where it was actually written, and its
+/// `sections` are filled in.
+///
+/// With `config.fail_fast`, no more pages are started once one has failed, so only the pages
+/// that were started are reported on.
+fn process_pages(
+    output_dir: &Path,
+    pages: &mut [Page],
+    config: &Config,
+    cache: Option<&HtmlCache>,
+    site_config: &str,
+) -> Vec<PageReport> {
+    let jobs = config
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(pages.len())
+        .max(1);
+
+    let reports = Mutex::new(Vec::with_capacity(pages.len()));
+    let pages = Mutex::new(pages.iter_mut());
+    let failed = AtomicBool::new(false);
+
+    let work = || loop {
+        if config.fail_fast && failed.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let page = match pages.lock().unwrap().next() {
+            Some(page) => page,
+            None => break,
+        };
+
+        let started = Instant::now();
+        let result = process_page(output_dir, page, config, cache, site_config).map(
+            |(output_relpath, sections)| {
+                page.output_relpath = output_relpath;
+                page.sections = sections;
+                output_dir.join(&page.output_relpath)
+            },
+        );
+
+        if result.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+
+        reports.lock().unwrap().push(PageReport {
+            input_file: page.input_file.clone(),
+            result,
+            duration: started.elapsed(),
+        });
+    };
+
+    if jobs == 1 {
+        work();
+    } else {
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(work);
+            }
+        });
+    }
+
+    reports.into_inner().unwrap()
+}
+
+/// Canonicalize `path`, which may not exist yet: its closest ancestor that does is
+/// canonicalized, and the rest of the path is appended to that.
+fn canonicalize_uncreated(path: &Path) -> std::io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            // the empty path that's left of a relative path, which is the current directory
+            _ => break,
+        }
+    }
+
+    let existing = if existing.as_os_str().is_empty() {
+        env::current_dir()?
+    } else {
+        existing.canonicalize()?
+    };
+
+    Ok(missing
+        .iter()
+        .rev()
+        .fold(existing, |dir, name| dir.join(name)))
+}
+
+/// Refuse to write the output anywhere inside the input directory,
+/// or the next build would find the previous build's output among its inputs.
+/// Both paths must already be canonicalized.
+fn check_output_dir(input_dir: &Path, output_dir: &Path) -> Result<(), String> {
+    if output_dir.starts_with(input_dir) {
+        Err(format!(
+            "The output directory {} is inside the input directory {}. Choose an output directory outside of it.",
+            output_dir.display(),
+            input_dir.display()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Remove everything in the output directory, for `--clean`. Both paths must already be
+/// canonicalized, and the input directory must not be inside the output directory.
+fn clean_output_dir(input_dir: &Path, output_dir: &Path) -> Result<(), String> {
+    if input_dir.starts_with(output_dir) {
+        return Err(format!(
+            "--clean would delete the input directory {}, which is inside the output directory {}.",
+            input_dir.display(),
+            output_dir.display()
+        ));
+    }
+
+    let entries = fs::read_dir(output_dir)
+        .map_err(|e| format!("Error cleaning {}: {}", output_dir.display(), e))?;
+
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Error cleaning {}: {}", output_dir.display(), e))?
+            .path();
+
+        // A symlink is removed itself, never what it points to
+        let removed = fs::symlink_metadata(&path).and_then(|metadata| {
+            if metadata.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            }
+        });
+
+        removed.map_err(|e| format!("Error removing {}: {}", path.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// A Markdown input file, read and split from its frontmatter,
+/// along with where its output will be written.
+struct Page {
+    input_file: PathBuf,
+    /// The input file, relative to the input directory
+    input_relpath: PathBuf,
+    output_relpath: PathBuf,
+    frontmatter: Frontmatter,
+    content_md: String,
+    /// Its prose by heading, for the search index. Empty until the page is processed.
+    sections: Vec<Section>,
+}
+
+fn load_page(input_dir: &Path, input_file: PathBuf, config: &Config) -> Result<Page, String> {
+    let input_relpath = input_file
+        .strip_prefix(input_dir)
+        .map_err(|e| e.to_string())?
+        .to_path_buf();
+
+    let source = fs::read_to_string(&input_file).map_err(|e| {
+        format!(
+            "Error reading {}: {}",
+            input_file.to_str().unwrap_or("an input file"),
+            e
+        )
+    })?;
+
+    let (frontmatter, content_md) = Frontmatter::parse(&source)?;
+    let output_relpath = output_relpath(&input_relpath, &frontmatter, config.pretty_urls)?;
+    let content_md = content_md.to_string();
+
+    Ok(Page {
+        input_file,
+        input_relpath,
+        output_relpath,
+        frontmatter,
+        content_md,
+        sections: Vec::new(),
+    })
+}
+
+/// The path that a page is linked by, see `Config::url_rewriter`.
+fn page_url_path(config: &Config, output_relpath: &Path) -> String {
+    config.url_rewriter.unwrap_or(urls::plain_url)(output_relpath)
+}
+
+/// The pages for the search index. The text before a page's first heading is titled
+/// with the page's `title`, falling back to the page's path, like in the feed.
+fn search_pages<'a>(pages: &'a [Page], config: &Config) -> Vec<search_index::SearchPage<'a>> {
+    let mut search_pages: Vec<_> = pages
+        .iter()
+        .map(|page| {
+            let path = manifest::portable_path(&page.output_relpath);
+
+            search_index::SearchPage {
+                title: page.frontmatter.get("title").unwrap_or(&path).to_string(),
+                url: page_url_path(config, &page.output_relpath),
+                sections: &page.sections,
+            }
+        })
+        .collect();
+
+    // The input files are found in whatever order the file system lists them
+    search_pages.sort_by(|page1, page2| page1.url.cmp(&page2.url));
+
+    search_pages
+}
+
+/// The pages with a `date` in their frontmatter. Their `title` is used
+/// as the title of the entry, falling back to the page's path.
+fn feed_entries(pages: &[Page], base_url: &str, config: &Config) -> Vec<feed::FeedEntry> {
+    let mut entries = Vec::new();
+
+    for page in pages {
+        let date = match page.frontmatter.get("date") {
+            Some(date) => date,
+            None => continue,
+        };
+
+        if feed::parse_date(date).is_none() {
+            eprintln!(
+                "Warning: leaving {} out of the feed, because its date `{}` is not a YYYY-MM-DD date or an RFC 3339 date and time",
+                page.input_file.display(),
+                date
+            );
+            continue;
+        }
+
+        let path = manifest::portable_path(&page.output_relpath);
+
+        entries.push(feed::FeedEntry {
+            title: page.frontmatter.get("title").unwrap_or(&path).to_string(),
+            date: date.to_string(),
+            url: feed::page_url(base_url, &page_url_path(config, &page.output_relpath)),
+        });
+    }
+
+    entries
+}
+
+/// Where a page's output goes, relative to the output directory.
+///
+/// This is the input path with an `.html` extension (or with `pretty_urls`, an `index.html`
+/// in a directory named after it), unless the frontmatter has a `permalink`. A permalink
+/// ending in `/` names a directory, and the page is written to `index.html` inside it.
+fn output_relpath(
+    input_relpath: &Path,
+    frontmatter: &Frontmatter,
+    pretty_urls: bool,
+) -> Result<PathBuf, String> {
+    let permalink = match frontmatter.get("permalink") {
+        Some(permalink) => permalink,
+        None if pretty_urls => return Ok(urls::pretty_output_relpath(input_relpath)),
+        None => {
+            let mut output_relpath = input_relpath.to_path_buf();
+            output_relpath.set_extension("html");
+
+            return Ok(output_relpath);
+        }
+    };
+
+    let mut output_relpath = PathBuf::new();
+    for component in permalink.split('/').filter(|c| !c.is_empty()) {
+        if component == "." || component == ".." {
+            return Err(format!(
+                "The permalink `{}` must not contain `.` or `..`",
+                permalink
+            ));
+        }
+        output_relpath.push(component);
+    }
+
+    if permalink.ends_with('/') || output_relpath.as_os_str().is_empty() {
+        output_relpath.push("index.html");
+    } else if output_relpath.extension().is_none() {
+        output_relpath.set_extension("html");
+    }
+
+    Ok(output_relpath)
+}
+
+fn check_output_collisions(pages: &[Page]) -> Result<(), String> {
+    let mut claimed: HashMap<&Path, &Path> = HashMap::with_capacity(pages.len());
+
+    for page in pages {
+        if let Some(other) = claimed.insert(&page.output_relpath, &page.input_file) {
+            return Err(format!(
+                "{} and {} would both be written to {}. Give one of them a different permalink or path.",
+                other.display(),
+                page.input_file.display(),
+                page.output_relpath.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Process the page, and return where it was written, relative to `output_dir`,
+/// along with its sections for the search index.
+/// `site_config` is passed to the Roc transform as it is, see `site_config::read_site_config`.
+fn process_page(
+    output_dir: &Path,
+    page: &Page,
+    config: &Config,
+    cache: Option<&HtmlCache>,
+    site_config: &str,
+) -> Result<(PathBuf, Vec<Section>), String> {
+    let Page {
+        input_file,
+        input_relpath,
+        output_relpath,
+        frontmatter,
+        content_md,
+        sections: _,
+    } = page;
+
+    let smart_punctuation = frontmatter.get_bool("smart_punctuation")?.unwrap_or(false);
+
+    let content_md = config
+        .preprocessors
+        .iter()
+        .fold(content_md.clone(), |markdown, preprocess| {
+            preprocess(&markdown)
+        });
+
+    let link_base = urls::LinkBase {
+        input_relpath,
+        output_relpath,
+        pretty_urls: config.pretty_urls,
+    };
+
+    let rendered = match cache {
+        Some(cache) => {
+            cache.get_or_insert_with(&content_md, (smart_punctuation, link_base), || {
+                markdown_to_html(&content_md, smart_punctuation, Some(link_base), config)
+            })
+        }
+        None => markdown_to_html(&content_md, smart_punctuation, Some(link_base), config),
+    };
+
+    let words_per_minute = config.words_per_minute.unwrap_or(DEFAULT_WORDS_PER_MINUTE);
+    let metadata = RocPageMetadata {
+        reading_minutes: reading_minutes(rendered.word_count, words_per_minute) as u64,
+        word_count: rendered.word_count as u64,
+    };
+
+    let mut output = call_roc_transform(
+        output_relpath.to_str().unwrap(),
+        rendered.html,
+        metadata,
+        site_config,
+    )?;
+    let output_relpath = transformed_output_relpath(output_relpath, &output.path)?;
+
+    output.html = wrap_html(config, &output.html);
+
+    if config.minify {
+        output.html = minify::minify_html(&output.html);
+    }
+
+    let mtime_source = config.preserve_timestamps.then_some(input_file.as_path());
+    write_page(
+        output_dir,
+        &output_relpath,
+        &output,
+        mtime_source,
+        &config.precompress,
+    )?;
+
+    Ok((output_relpath, rendered.sections))
+}
+
+/// Put the `--head-file` and `--footer-file` contents around a page's HTML.
+fn wrap_html(config: &Config, html: &str) -> String {
+    [config.head_html.as_str(), html, config.footer_html.as_str()].concat()
+}
+
+/// Where to write a page whose Roc transform returned `path`: the page's usual
+/// `output_relpath` if `path` is empty, or else `path`, relative to the output directory.
+fn transformed_output_relpath(output_relpath: &Path, path: &str) -> Result<PathBuf, String> {
+    if path.is_empty() {
+        return Ok(output_relpath.to_path_buf());
+    }
+
+    let mut relpath = PathBuf::new();
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        if component == "." || component == ".." {
+            return Err(format!(
+                "The transform returned the path `{}`, which must not contain `.` or `..`",
+                path
+            ));
+        }
+        relpath.push(component);
+    }
 
-    if num_errors > 0 {
-        Err("Could not process all files".into())
-    } else {
-        Ok(())
+    if path.ends_with('/') || relpath.as_os_str().is_empty() {
+        return Err(format!(
+            "The transform returned the path `{}`, which does not name a file",
+            path
+        ));
     }
+
+    Ok(relpath)
 }
 
-fn process_file(input_dir: &Path, output_dir: &Path, input_file: &Path) -> Result<(), String> {
-    let input_relpath = input_file
-        .strip_prefix(input_dir)
-        .map_err(|e| e.to_string())?
-        .to_path_buf();
+/// How long it takes to read `word_count` words, in whole minutes (rounded up).
+fn reading_minutes(word_count: usize, words_per_minute: usize) -> usize {
+    checked_div_ceil(word_count, words_per_minute)
+        .expect("--words-per-minute only accepts positive numbers")
+}
 
-    let mut output_relpath = input_relpath.clone();
-    output_relpath.set_extension("html");
+/// `numerator / denominator`, rounded up, or `None` when `denominator` is 0.
+/// Unlike `(numerator + denominator - 1) / denominator`, this cannot overflow.
+/// (`usize::div_ceil` is newer than the MSRV.)
+fn checked_div_ceil(numerator: usize, denominator: usize) -> Option<usize> {
+    let quotient = numerator.checked_div(denominator)?;
 
-    let content_md = fs::read_to_string(input_file).map_err(|e| {
-        format!(
-            "Error reading {}: {}",
-            input_file.to_str().unwrap_or("an input file"),
-            e
-        )
-    })?;
+    if numerator % denominator == 0 {
+        Some(quotient)
+    } else {
+        Some(quotient + 1)
+    }
+}
 
+/// Parse the Markdown and render it as HTML, highlighting any code in it,
+/// and count the words of prose in it. Its prose is collected by heading too.
+///
+/// `smart_punctuation` is the page's own choice, from `smart_punctuation: true` in its frontmatter.
+/// With a `link_base`, relative links and images to `.md` files point at the pages made from them.
+fn markdown_to_html(
+    content_md: &str,
+    smart_punctuation: bool,
+    link_base: Option<urls::LinkBase>,
+    config: &Config,
+) -> RenderedMarkdown {
     let mut content_html = String::new();
     let mut options = Options::all();
 
     // In the tutorial, this messes up string literals in <samp> blocks.
     // Those could be done as markdown code blocks, but the repl ones need
     // a special class, and there's no way to add that class using markdown alone.
-    //
-    // We could make this option user-configurable if people actually want it!
-    options.remove(Options::ENABLE_SMART_PUNCTUATION);
+    // So it is off, unless a page asks for it.
+    if !smart_punctuation {
+        options.remove(Options::ENABLE_SMART_PUNCTUATION);
+    }
 
-    let parser = Parser::new_ext(&content_md, options);
+    let parser = Parser::new_ext(content_md, options);
+    let events = math::render_math(parser);
+    let (events, headings) = headings::add_heading_anchors(
+        events.into_iter(),
+        config.heading_anchors,
+        &config.heading_id_prefix,
+    );
 
     // We'll build a new vector of events since we can only consume the parser once
     let mut parser_with_highlighting = Vec::new();
@@ -210,28 +1315,87 @@ fn process_file(input_dir: &Path, output_dir: &Path, input_file: &Path) -> Resul
     // And track a little bit of state
     let mut in_code_block = false;
     let mut is_roc_code = false;
+    let mut code_language = String::new();
+    let mut has_line_numbers = false;
+    let mut word_count = 0;
+    // The prose before the first heading, and then under each heading
+    let mut sections = vec![Section::default()];
+    let mut headings = headings.into_iter().peekable();
+    let mut heading_events = 0..0;
+
+    for (index, event) in events.into_iter().enumerate() {
+        if let Some(heading) = headings.next_if(|heading| heading.events.start == index) {
+            let mut title = String::new();
+            search_index::push_words(&mut title, &heading.text);
+
+            sections.push(Section {
+                id: heading.id,
+                title,
+                text: String::new(),
+            });
+            heading_events = heading.events;
+        }
+
+        // The heading's own text is its section's title
+        let section_text = if heading_events.contains(&index) {
+            None
+        } else {
+            sections.last_mut().map(|section| &mut section.text)
+        };
 
-    for event in parser {
         match event {
             pulldown_cmark::Event::Code(cow_str) => {
-                let highlighted_html =
-                    roc_highlight::highlight_roc_code_inline(cow_str.to_string().as_str());
+                if let Some(section_text) = section_text {
+                    search_index::push_words(section_text, &cow_str);
+                }
+
+                let highlighted_html = highlight::highlight_inline(&cow_str, config.token_ranges);
                 parser_with_highlighting.push(pulldown_cmark::Event::Html(
                     pulldown_cmark::CowStr::from(highlighted_html),
                 ));
             }
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Link(link_type, dest, title)) => {
+                let dest = rewrite_md_link(link_base, dest);
+                parser_with_highlighting.push(pulldown_cmark::Event::Start(
+                    pulldown_cmark::Tag::Link(link_type, dest, title),
+                ));
+            }
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Image(link_type, dest, title)) => {
+                let dest = rewrite_md_link(link_base, dest);
+                parser_with_highlighting.push(pulldown_cmark::Event::Start(
+                    pulldown_cmark::Tag::Image(link_type, dest, title),
+                ));
+            }
             pulldown_cmark::Event::Start(pulldown_cmark::Tag::CodeBlock(cbk)) => {
                 in_code_block = true;
                 is_roc_code = is_roc_code_block(&cbk);
+                (code_language, has_line_numbers) = match &cbk {
+                    pulldown_cmark::CodeBlockKind::Fenced(info) => (
+                        fence_language(info).to_string(),
+                        line_numbers::wants_line_numbers(info),
+                    ),
+                    pulldown_cmark::CodeBlockKind::Indented => (String::new(), false),
+                };
             }
             pulldown_cmark::Event::End(pulldown_cmark::Tag::CodeBlock(_)) => {
                 if in_code_block {
                     // Format the whole multi-line code block as HTML all at once
-                    let highlighted_html: String;
+                    let mut highlighted_html: String;
                     if is_roc_code {
-                        highlighted_html = roc_highlight::highlight_roc_code(&to_highlight)
+                        highlighted_html =
+                            highlight::highlight_block(&to_highlight, config.token_ranges)
                     } else {
-                        highlighted_html = format!("<pre><samp>{}</pre></samp>", &to_highlight)
+                        highlighted_html = String::from("<pre><samp>");
+                        escape_html(&mut highlighted_html, &to_highlight).unwrap();
+                        highlighted_html.push_str("</samp></pre>");
+                    }
+
+                    if has_line_numbers {
+                        highlighted_html = line_numbers::add_line_numbers(&highlighted_html);
+                    }
+
+                    if let Some(hook) = config.code_block_hook {
+                        highlighted_html = hook(&highlighted_html, &code_language);
                     }
 
                     // And put it into the vector
@@ -247,6 +1411,11 @@ fn process_file(input_dir: &Path, output_dir: &Path, input_file: &Path) -> Resul
                     // If we're in a code block, build up the string of text
                     to_highlight.push_str(&t);
                 } else {
+                    if let Some(section_text) = section_text {
+                        search_index::push_words(section_text, &t);
+                    }
+
+                    word_count += t.split_whitespace().count();
                     parser_with_highlighting.push(pulldown_cmark::Event::Text(t))
                 }
             }
@@ -258,47 +1427,230 @@ fn process_file(input_dir: &Path, output_dir: &Path, input_file: &Path) -> Resul
 
     html::push_html(&mut content_html, parser_with_highlighting.into_iter());
 
-    let roc_relpath = RocStr::from(output_relpath.to_str().unwrap());
-    let roc_content_html = RocStr::from(content_html.as_str());
-    let roc_output_str =
-        unsafe { roc_transformFileContentForHost(&roc_relpath, &roc_content_html) };
+    RenderedMarkdown {
+        html: content_html,
+        word_count,
+        sections,
+    }
+}
+
+fn rewrite_md_link<'a>(
+    link_base: Option<urls::LinkBase>,
+    dest: pulldown_cmark::CowStr<'a>,
+) -> pulldown_cmark::CowStr<'a> {
+    match link_base.and_then(|link_base| link_base.rewrite(&dest)) {
+        Some(rewritten) => pulldown_cmark::CowStr::from(rewritten),
+        None => dest,
+    }
+}
+
+/// Write the page's HTML to `output_relpath`, and its data (if there is any)
+/// next to it with a `.json` extension, along with their `precompress` copies.
+/// With an `mtime_source`, all of them get its mtime.
+fn write_page(
+    output_dir: &Path,
+    output_relpath: &Path,
+    output: &TransformOutput,
+    mtime_source: Option<&Path>,
+    precompress: &[precompress::Encoding],
+) -> Result<(), String> {
+    let mut output_files = vec![(output_dir.join(output_relpath), &output.html)];
+
+    if !output.data.is_empty() {
+        let data_relpath = output_relpath.with_extension("json");
+        output_files.push((output_dir.join(data_relpath), &output.data));
+    }
+
+    for (output_file, contents) in output_files {
+        let written = write_output_file(&output_file, contents, precompress)?;
+
+        if let Some(mtime_source) = mtime_source {
+            for file in written.iter() {
+                copy_mtime(mtime_source, file)?;
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    let output_file = output_dir.join(&output_relpath);
-    let rust_output_str: &str = &roc_output_str;
+/// Set the mtime of `output_file` to that of `input_file`, so that tools which compare
+/// mtimes (like rsync, or a CDN's upload step) can tell an unchanged page from a rebuilt one.
+fn copy_mtime(input_file: &Path, output_file: &Path) -> Result<(), String> {
+    let metadata =
+        fs::metadata(input_file).map_err(|e| format!("{}: {}", input_file.display(), e))?;
+    let mtime = FileTime::from_last_modification_time(&metadata);
 
-    println!("{} -> {}", input_file.display(), output_file.display());
+    filetime::set_file_mtime(output_file, mtime)
+        .map_err(|e| format!("{}: {}", output_file.display(), e))
+}
 
+/// Write `contents` to `output_file`, and a compressed copy next to it in each of `precompress`.
+/// Returns every file that was written, `output_file` first.
+fn write_output_file(
+    output_file: &Path,
+    contents: &str,
+    precompress: &[precompress::Encoding],
+) -> Result<Vec<PathBuf>, String> {
     // Create parent directory if it doesn't exist
-    let parent_dir = output_file.parent().unwrap();
-    if !parent_dir.exists() {
-        fs::create_dir_all(&parent_dir).unwrap();
+    if let Some(parent_dir) = output_file.parent() {
+        if !parent_dir.exists() {
+            fs::create_dir_all(parent_dir)
+                .map_err(|e| format!("Could not create {}: {}", parent_dir.display(), e))?;
+        }
+    }
+
+    fs::write(output_file, contents).map_err(|e| format!("{}: {}", output_file.display(), e))?;
+
+    let mut written = vec![output_file.to_path_buf()];
+    written.extend(precompress::write_variants(
+        output_file,
+        contents.as_bytes(),
+        precompress,
+    )?);
+
+    Ok(written)
+}
+
+/// Run the Roc transform on a worker thread, so that a Roc panic
+/// becomes an `Err` for this file rather than aborting the whole build.
+/// See `roc_panic` for how (and at what cost) that works.
+fn call_roc_transform(
+    relpath: &str,
+    content_html: String,
+    metadata: RocPageMetadata,
+    site_config: &str,
+) -> Result<TransformOutput, String> {
+    // Roc code can recurse deeply, so give the worker the same stack a main thread gets.
+    const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+    let (sender, receiver) = mpsc::channel();
+    let relpath = relpath.to_string();
+    let site_config = site_config.to_string();
+
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            PANIC_SENDER.with(|panic_sender| *panic_sender.borrow_mut() = Some(sender.clone()));
+
+            let roc_relpath = RocStr::from(relpath.as_str());
+            let roc_content_html = RocStr::from(content_html.as_str());
+            let roc_site_config = RocStr::from(site_config.as_str());
+            let roc_output = unsafe {
+                roc_transformFileContentForHost(
+                    &roc_relpath,
+                    &roc_content_html,
+                    &metadata,
+                    &roc_site_config,
+                )
+            };
+
+            let _ = sender.send(Ok(TransformOutput {
+                html: roc_output.html.as_str().to_string(),
+                data: roc_output.data.as_str().to_string(),
+                path: roc_output.path.as_str().to_string(),
+            }));
+        })
+        .map_err(|e| format!("Could not start a thread for the Roc transform: {}", e))?;
+
+    match receiver.recv() {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(message)) => Err(format!("Roc hit a panic: {}", message)),
+        Err(_) => Err("The Roc transform exited without producing any output".into()),
     }
+}
+
+/// Find all the files under `dir`, except the ones `exclude` matches (and the ones in
+/// directories it matches). Entries that can't be read are skipped, and reported in the
+/// second vector instead.
+fn find_files(
+    dir: &Path,
+    exclude: &GlobSet,
+    mut listings: Option<&mut DirListings>,
+) -> (Vec<PathBuf>, Vec<String>) {
+    let mut file_paths = Vec::new();
+    let mut warnings = Vec::new();
+
+    find_files_help(
+        dir,
+        dir,
+        exclude,
+        &mut listings,
+        &mut file_paths,
+        &mut warnings,
+    );
 
-    fs::write(output_file, rust_output_str).map_err(|e| format!("{}", e))
+    (file_paths, warnings)
 }
 
-fn find_files(dir: &Path, file_paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
-    for entry in fs::read_dir(dir)? {
-        let pathbuf = entry?.path();
-        if pathbuf.is_dir() {
-            find_files(&pathbuf, file_paths)?;
+fn find_files_help(
+    root: &Path,
+    dir: &Path,
+    exclude: &GlobSet,
+    listings: &mut Option<&mut DirListings>,
+    file_paths: &mut Vec<PathBuf>,
+    warnings: &mut Vec<String>,
+) {
+    let entries = match listings.as_deref_mut() {
+        Some(listings) => {
+            let relpath = dir.strip_prefix(root).unwrap_or(dir);
+            listings.entries(relpath, dir, warnings)
+        }
+        None => dir_listings::read_dir_entries(dir, warnings),
+    };
+
+    for (pathbuf, is_dir) in entries {
+        let relpath = pathbuf.strip_prefix(root).unwrap_or(&pathbuf);
+
+        if exclude.is_match(relpath) {
+            continue;
+        } else if is_dir {
+            find_files_help(root, &pathbuf, exclude, listings, file_paths, warnings);
         } else {
             file_paths.push(pathbuf);
         }
     }
-    Ok(())
 }
 
 /// On windows, the path is prefixed with `\\?\`, the "verbatim" prefix.
 /// Such a path does not works as an argument to `zig` and other command line tools,
-/// and there seems to be no good way to strip it. So we resort to some string manipulation.
+/// so the prefix is stripped wherever the path means the same without it.
 pub fn strip_windows_prefix(path_buf: PathBuf) -> std::path::PathBuf {
-    #[cfg(not(windows))]
-    return path_buf;
+    // Paths on other platforms never start with a backslash, so they are left as they are
+    match path_buf.to_str().and_then(strip_verbatim_prefix) {
+        Some(stripped) => PathBuf::from(stripped),
+        None => path_buf,
+    }
+}
+
+/// The most UTF-16 units a Windows path can have without the verbatim prefix,
+/// counting the terminating null.
+const WINDOWS_MAX_PATH: usize = 260;
 
-    let path_str = path_buf.display().to_string();
+/// `C:\dir` for a verbatim `\\?\C:\dir`. A path that is too long for `MAX_PATH` keeps the
+/// prefix, since only that lets Windows open it, and so does a share like `\\?\UNC\server\`,
+/// which would mean something else without it.
+fn strip_verbatim_prefix(path: &str) -> Option<&str> {
+    let stripped = path.strip_prefix(r"\\?\")?;
+    let mut chars = stripped.chars();
+    let is_drive_path = matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(drive), Some(':'), Some('\\')) if drive.is_ascii_alphabetic()
+    );
+
+    if is_drive_path && stripped.encode_utf16().count() < WINDOWS_MAX_PATH {
+        Some(stripped)
+    } else {
+        None
+    }
+}
 
-    std::path::Path::new(path_str.trim_start_matches(r"\\?\")).to_path_buf()
+/// The language of a code fence like ```` ```roc,linenos ````, which is its first word.
+fn fence_language(fence_info: &str) -> &str {
+    fence_info
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .next()
+        .unwrap_or("")
 }
 
 fn is_roc_code_block(cbk: &pulldown_cmark::CodeBlockKind) -> bool {
@@ -313,3 +1665,757 @@ fn is_roc_code_block(cbk: &pulldown_cmark::CodeBlockKind) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn permalink_directory_becomes_index_html() {
+        let (frontmatter, _) = Frontmatter::parse("---\npermalink: about/\n---\n").unwrap();
+
+        assert_eq!(
+            output_relpath(Path::new("pages/about-us.md"), &frontmatter, false),
+            Ok(PathBuf::from("about/index.html"))
+        );
+        assert_eq!(
+            output_relpath(
+                Path::new("pages/about-us.md"),
+                &Frontmatter::default(),
+                false
+            ),
+            Ok(PathBuf::from("pages/about-us.html"))
+        );
+    }
+
+    #[test]
+    fn linenos_fence_numbers_the_lines() {
+        let markdown = "```text,linenos\nfirst\n<second>\n```\n\n```text\nplain\n```\n";
+
+        assert_eq!(
+            markdown_to_html(markdown, false, None, &Config::default()).html,
+            "<pre><samp>\
+         <span class=\"line\"><span class=\"line-number\">1</span>first</span>\n\
+         <span class=\"line\"><span class=\"line-number\">2</span>&lt;second&gt;</span>\
+         \n</samp></pre><pre><samp>plain\n</samp></pre>"
+        );
+    }
+
+    #[cfg(not(feature = "highlight"))]
+    #[test]
+    fn roc_code_is_escaped_without_highlighting() {
+        let markdown = "Call `f <| x`:\n\n```roc\nx = \"<b>\"\n```\n";
+
+        assert_eq!(
+            markdown_to_html(markdown, false, None, &Config::default()).html,
+            "<p>Call <code>f &lt;| x</code>:</p>\n\
+         <pre><code>x = &quot;&lt;b&gt;&quot;\n</code></pre>"
+        );
+    }
+
+    #[test]
+    fn code_block_hook_can_wrap_roc_blocks_in_a_figure() {
+        let markdown = "```roc\nx = 1\n```\n\n```text\nplain\n```\n";
+        let config = Config {
+            code_block_hook: Some(|html, language| {
+                if language == "roc" {
+                    format!("<figure class=\"highlight\">{}</figure>", html)
+                } else {
+                    html.to_string()
+                }
+            }),
+            ..Config::default()
+        };
+
+        let html = markdown_to_html(markdown, false, None, &config).html;
+
+        assert!(html.starts_with("<figure class=\"highlight\">"));
+        assert!(html.ends_with("</figure><pre><samp>plain\n</samp></pre>"));
+    }
+
+    #[cfg(feature = "highlight")]
+    #[test]
+    fn token_ranges_mark_each_token_with_its_bytes() {
+        let markdown = "Call `f x`:\n\n```roc\nx = 1\n```\n";
+        let config = Config {
+            token_ranges: true,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            markdown_to_html(markdown, false, None, &config).html,
+            "<p>Call <code>\
+         <span class=\"lowerident\" data-start=\"0\" data-end=\"1\">f</span> \
+         <span class=\"lowerident\" data-start=\"2\" data-end=\"3\">x</span>\
+         </code>:</p>\n<pre><samp>\
+         <span class=\"lowerident\" data-start=\"0\" data-end=\"1\">x</span> \
+         <span class=\"kw\" data-start=\"2\" data-end=\"3\">=</span> \
+         <span class=\"literal\" data-start=\"4\" data-end=\"5\">1</span>\
+         </samp></pre>"
+        );
+        assert!(!markdown_to_html(markdown, false, None, &Config::default())
+            .html
+            .contains("data-start"));
+    }
+
+    #[test]
+    fn pages_can_opt_into_smart_punctuation() {
+        let render = |source| {
+            let (frontmatter, markdown) = Frontmatter::parse(source).unwrap();
+            let smart_punctuation = frontmatter.get_bool("smart_punctuation").unwrap();
+
+            markdown_to_html(
+                markdown,
+                smart_punctuation.unwrap_or(false),
+                None,
+                &Config::default(),
+            )
+            .html
+        };
+
+        let prose = render("---\nsmart_punctuation: true\n---\n\"Quotes\" -- and 'dashes'...\n");
+        let docs = render("---\ntitle: Docs\n---\n\"Quotes\" -- and 'dashes'...\n");
+
+        assert_eq!(prose, "<p>“Quotes” – and ‘dashes’…</p>\n");
+        assert_eq!(docs, "<p>&quot;Quotes&quot; -- and 'dashes'...</p>\n");
+    }
+
+    #[test]
+    fn md_links_point_at_html_pages() {
+        let link_base = urls::LinkBase {
+            input_relpath: Path::new("a.md"),
+            output_relpath: Path::new("a.html"),
+            pretty_urls: false,
+        };
+        let markdown = "[a](b.md) and [elsewhere](https://example.com/b.md)\n";
+
+        assert_eq!(
+        markdown_to_html(markdown, false, Some(link_base), &Config::default()).html,
+        "<p><a href=\"b.html\">a</a> and <a href=\"https://example.com/b.md\">elsewhere</a></p>\n"
+    );
+    }
+
+    #[test]
+    fn word_count_leaves_out_code_blocks() {
+        let markdown = "# Apple pie\n\nBake it *until* golden.\n\n```roc\npie = bake apple sugar\n```\n\n    indented code here\n";
+
+        let rendered = markdown_to_html(markdown, false, None, &Config::default());
+
+        assert_eq!(rendered.word_count, 6);
+        assert_eq!(reading_minutes(rendered.word_count, 200), 1);
+        assert_eq!(reading_minutes(401, 200), 3);
+        assert_eq!(reading_minutes(0, 200), 0);
+        assert_eq!(reading_minutes(usize::MAX, 2), usize::MAX / 2 + 1);
+        assert_eq!(checked_div_ceil(5, 0), None);
+    }
+
+    #[test]
+    fn transform_can_redirect_the_output() {
+        let default = Path::new("posts/news.html");
+
+        assert_eq!(
+            transformed_output_relpath(default, ""),
+            Ok(PathBuf::from("posts/news.html"))
+        );
+        assert_eq!(
+            transformed_output_relpath(default, "/feeds/news.xml"),
+            Ok(PathBuf::from("feeds/news.xml"))
+        );
+        assert!(transformed_output_relpath(default, "../news.html").is_err());
+        assert!(transformed_output_relpath(default, "feeds/").is_err());
+    }
+
+    #[test]
+    fn jobs_flag_takes_a_value() {
+        let args: Vec<String> = ["in/", "--jobs", "4", "out/", "--minify"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        let (config, positional) = Config::from_args(&args).unwrap();
+        assert_eq!(config.jobs, Some(4));
+        assert!(config.minify);
+        assert_eq!(positional, ["in/", "out/"]);
+
+        let missing = Config::from_args(&["in/".to_string(), "--jobs".to_string()]);
+        assert!(missing.is_err());
+
+        let zero = Config::from_args(&["--jobs".to_string(), "0".to_string()]);
+        assert!(zero.is_err());
+    }
+
+    #[cfg(feature = "serve")]
+    #[test]
+    fn serve_flag_takes_an_optional_address() {
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+
+        let (config, positional) = Config::from_args(&args(&["in/", "out/", "--serve"])).unwrap();
+        assert_eq!(config.serve, "127.0.0.1:8000".parse().ok());
+        assert_eq!(positional, ["in/", "out/"]);
+
+        let (config, positional) = Config::from_args(&args(&["--serve", "in/", "out/"])).unwrap();
+        assert_eq!(config.serve, "127.0.0.1:8000".parse().ok());
+        assert_eq!(positional, ["in/", "out/"]);
+
+        let (config, _) =
+            Config::from_args(&args(&["--serve", "0.0.0.0:3000", "in/", "out/"])).unwrap();
+        assert_eq!(config.serve, "0.0.0.0:3000".parse().ok());
+
+        let (config, _) = Config::from_args(&args(&["in/", "out/", "--serve", "9000"])).unwrap();
+        assert_eq!(config.serve, "127.0.0.1:9000".parse().ok());
+    }
+
+    #[test]
+    fn head_and_footer_files_bracket_the_page() {
+        let dir = TestDir::new("wrap");
+        fs::write(
+            dir.join("head.html"),
+            "<link rel=\"stylesheet\" href=\"/site.css\">\n",
+        )
+        .unwrap();
+        fs::write(dir.join("footer.html"), "<footer>Fin</footer>\n").unwrap();
+
+        let args: Vec<String> = [
+            "--head-file",
+            dir.join("head.html").to_str().unwrap(),
+            "--footer-file",
+            dir.join("footer.html").to_str().unwrap(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let parsed = Config::from_args(&args);
+        let missing = Config::from_args(&[
+            "--head-file".to_string(),
+            dir.join("missing.html").to_str().unwrap().to_string(),
+        ]);
+
+        let (config, _) = parsed.unwrap();
+        assert_eq!(
+        wrap_html(&config, "<main>Apple</main>\n"),
+        "<link rel=\"stylesheet\" href=\"/site.css\">\n<main>Apple</main>\n<footer>Fin</footer>\n"
+    );
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn quiet_and_verbose_set_the_verbosity() {
+        let parse = |arg: &str| Config::from_args(&[arg.to_string()]).unwrap().0.verbosity;
+
+        assert_eq!(parse("--quiet"), Some(Verbosity::Quiet));
+        assert_eq!(parse("--verbose"), Some(Verbosity::Verbose));
+        assert_eq!(parse("in/"), None);
+
+        assert_eq!(Verbosity::parse("verbose"), Some(Verbosity::Verbose));
+        assert_eq!(Verbosity::parse("loud"), None);
+        assert!(Verbosity::Verbose > Verbosity::Normal && Verbosity::Normal > Verbosity::Quiet);
+    }
+
+    #[test]
+    fn data_is_written_next_to_the_page() {
+        let output_dir = TestDir::new("data");
+        let output = TransformOutput {
+            html: "<p>Apple</p>".into(),
+            data: r#"{"title":"Apple"}"#.into(),
+            path: String::new(),
+        };
+
+        write_page(
+            &output_dir,
+            Path::new("fruit/apple.html"),
+            &output,
+            None,
+            &[],
+        )
+        .unwrap();
+
+        let html = fs::read_to_string(output_dir.join("fruit/apple.html"));
+        let data = fs::read_to_string(output_dir.join("fruit/apple.json"));
+
+        assert_eq!(html.unwrap(), output.html);
+        assert_eq!(data.unwrap(), output.data);
+    }
+
+    #[test]
+    fn preserved_timestamps_match_the_source() {
+        let dir = TestDir::new("mtime");
+        let input_file = dir.join("apple.md");
+        let output_dir = dir.join("output");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&input_file, "# Apple").unwrap();
+
+        let source_mtime = FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(&input_file, source_mtime).unwrap();
+
+        let output = TransformOutput {
+            html: "<p>Apple</p>".into(),
+            data: r#"{"title":"Apple"}"#.into(),
+            path: String::new(),
+        };
+        let mtime_of = |relpath: &str| {
+            let metadata = fs::metadata(output_dir.join(relpath)).unwrap();
+            FileTime::from_last_modification_time(&metadata)
+        };
+
+        write_page(
+            &output_dir,
+            Path::new("apple.html"),
+            &output,
+            Some(&input_file),
+            &[],
+        )
+        .unwrap();
+        let preserved = (mtime_of("apple.html"), mtime_of("apple.json"));
+
+        write_page(&output_dir, Path::new("apple.html"), &output, None, &[]).unwrap();
+        let rebuilt = mtime_of("apple.html");
+
+        assert_eq!(preserved, (source_mtime, source_mtime));
+        assert_ne!(rebuilt, source_mtime);
+    }
+
+    #[test]
+    fn build_reports_each_page() {
+        let dir = TestDir::new("report");
+        let input_dir = dir.join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("notes.txt"), "not a page").unwrap();
+        fs::write(input_dir.join("broken.md"), "---\ntitle: Broken\n").unwrap();
+
+        let config = Config {
+            manifest: true,
+            ..Config::default()
+        };
+        let report = build(
+            input_dir.to_str().unwrap(),
+            dir.join("output").to_str().unwrap(),
+            &config,
+        );
+        let manifest_written = dir.join("output/manifest.json").exists();
+
+        let report = report.unwrap();
+        assert_eq!(report.num_files, 2);
+        assert_eq!(report.pages.len(), 1);
+        assert!(report.pages[0].input_file.ends_with("broken.md"));
+        assert!(report.pages[0]
+            .result
+            .as_ref()
+            .unwrap_err()
+            .contains("closing `---`"));
+        assert_eq!((report.num_successes(), report.num_errors()), (0, 1));
+        assert!(!manifest_written);
+    }
+
+    #[test]
+    fn input_glob_builds_only_the_files_that_match() {
+        assert_eq!(
+            split_input_glob("site/content/**/*.md"),
+            Some(("site/content", "**/*.md"))
+        );
+        assert_eq!(split_input_glob("*.md"), Some((".", "*.md")));
+        assert_eq!(
+            split_input_glob("/srv/[ab]/*.md"),
+            Some(("/srv", "[ab]/*.md"))
+        );
+        assert_eq!(split_input_glob("site/content"), None);
+
+        let dir = TestDir::new("glob");
+        let site_dir = dir.join("site");
+        fs::create_dir_all(site_dir.join("content/blog")).unwrap();
+        fs::create_dir_all(site_dir.join("templates")).unwrap();
+        // Each page is missing its closing `---`, so it fails before the Roc transform
+        for page in [
+            "content/index.md",
+            "content/blog/post.md",
+            "templates/base.md",
+        ] {
+            fs::write(site_dir.join(page), "---\n").unwrap();
+        }
+        fs::write(site_dir.join("content/notes.txt"), "not a page").unwrap();
+
+        let report = build(
+            &format!("{}/content/**/*.md", site_dir.to_str().unwrap()),
+            dir.join("output").to_str().unwrap(),
+            &Config::default(),
+        );
+
+        let report = report.unwrap();
+        let built: Vec<&Path> = report
+            .pages
+            .iter()
+            .map(|page| page.input_file.strip_prefix(&site_dir).unwrap())
+            .collect();
+        assert_eq!(report.num_files, 2);
+        assert_eq!(
+            built,
+            [
+                Path::new("content/blog/post.md"),
+                Path::new("content/index.md")
+            ]
+        );
+    }
+
+    #[test]
+    fn fail_fast_stops_at_the_first_failure() {
+        let dir = TestDir::new("fail-fast");
+        let output_dir = dir.join("output");
+        // A directory where the first page's HTML should go, so that writing it fails
+        fs::create_dir_all(output_dir.join("first.html")).unwrap();
+
+        let page = |name: &str| Page {
+            input_file: dir.join(name).with_extension("md"),
+            input_relpath: PathBuf::from(name).with_extension("md"),
+            output_relpath: PathBuf::from(name).with_extension("html"),
+            frontmatter: Frontmatter::default(),
+            content_md: String::from("# Hello"),
+            sections: Vec::new(),
+        };
+        let config = |fail_fast| Config {
+            fail_fast,
+            jobs: Some(1),
+            ..Config::default()
+        };
+
+        let all = process_pages(
+            &output_dir,
+            &mut [page("first"), page("second")],
+            &config(false),
+            None,
+            "{}",
+        );
+        fs::remove_file(output_dir.join("second.html")).unwrap();
+        let fast = process_pages(
+            &output_dir,
+            &mut [page("first"), page("second")],
+            &config(true),
+            None,
+            "{}",
+        );
+        let second_written = output_dir.join("second.html").exists();
+
+        assert_eq!(all.len(), 2);
+        assert!(all[0].result.is_err() && all[1].result.is_ok());
+        assert_eq!(fast.len(), 1);
+        assert!(fast[0].result.is_err());
+        assert!(!second_written);
+    }
+
+    #[test]
+    fn search_index_has_an_entry_per_page_and_heading() {
+        let dir = TestDir::new("search");
+        let input_dir = dir.join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(
+            input_dir.join("apple.md"),
+            "---\ntitle: Apple\n---\nCrisp.\n\n## Growing `apple` trees\n\nPlant *in* spring.\n",
+        )
+        .unwrap();
+        fs::write(input_dir.join("banana.md"), "Yellow.\n").unwrap();
+
+        let config = Config {
+            search_index: true,
+            ..Config::default()
+        };
+        let report = build(
+            input_dir.to_str().unwrap(),
+            dir.join("output").to_str().unwrap(),
+            &config,
+        );
+        let index = fs::read_to_string(dir.join("output/search-index.json"));
+
+        assert_eq!(report.unwrap().num_errors(), 0);
+        assert_eq!(
+        index.unwrap(),
+        "[\n  \
+         {\"url\": \"apple.html\", \"title\": \"Apple\", \"text\": \"Crisp.\"},\n  \
+         {\"url\": \"apple.html#growing-apple-trees\", \"title\": \"Growing apple trees\", \"text\": \"Plant in spring.\"},\n  \
+         {\"url\": \"banana.html\", \"title\": \"banana.html\", \"text\": \"Yellow.\"}\n\
+         ]\n"
+    );
+    }
+
+    #[test]
+    fn pretty_urls_write_each_page_to_a_directory() {
+        let dir = TestDir::new("pretty");
+        let input_dir = dir.join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("index.md"), "Home.\n").unwrap();
+        fs::write(input_dir.join("about.md"), "---\ntitle: About\n---\nUs.\n").unwrap();
+
+        let args = ["--pretty-urls", "--search-index"].map(String::from);
+        let (config, _) = Config::from_args(&args).unwrap();
+        let report = build(
+            input_dir.to_str().unwrap(),
+            dir.join("output").to_str().unwrap(),
+            &config,
+        );
+        let about_written = dir.join("output/about/index.html").is_file();
+        let index_written = dir.join("output/index.html").is_file();
+        let index = fs::read_to_string(dir.join("output/search-index.json"));
+
+        assert_eq!(report.unwrap().num_errors(), 0);
+        assert!(about_written && index_written);
+        assert_eq!(
+            index.unwrap(),
+            "[\n  \
+         {\"url\": \"\", \"title\": \"index.html\", \"text\": \"Home.\"},\n  \
+         {\"url\": \"about/\", \"title\": \"About\", \"text\": \"Us.\"}\n\
+         ]\n"
+        );
+    }
+
+    #[test]
+    fn long_windows_paths_keep_the_verbatim_prefix() {
+        let short = PathBuf::from(r"\\?\C:\site\posts");
+        let long = PathBuf::from(format!(r"\\?\C:\site{}\index.html", r"\nested".repeat(40)));
+        let share = PathBuf::from(r"\\?\UNC\server\site");
+
+        assert_eq!(strip_windows_prefix(short), PathBuf::from(r"C:\site\posts"));
+        assert_eq!(strip_windows_prefix(long.clone()), long);
+        assert_eq!(strip_windows_prefix(share.clone()), share);
+        assert_eq!(
+            strip_windows_prefix(PathBuf::from("/home/site")),
+            PathBuf::from("/home/site")
+        );
+    }
+
+    #[test]
+    fn clean_removes_the_output_of_deleted_pages() {
+        let dir = TestDir::new("clean");
+        let input_dir = dir.join("input");
+        let output_dir = dir.join("output");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("kept.md"), "Kept.\n").unwrap();
+        fs::write(input_dir.join("deleted.md"), "Deleted.\n").unwrap();
+
+        let build_with = |config: &Config| {
+            build(
+                input_dir.to_str().unwrap(),
+                output_dir.to_str().unwrap(),
+                config,
+            )
+            .unwrap()
+            .num_errors()
+        };
+
+        assert_eq!(build_with(&Config::default()), 0);
+        fs::remove_file(input_dir.join("deleted.md")).unwrap();
+
+        assert_eq!(build_with(&Config::default()), 0);
+        let lingers = output_dir.join("deleted.html").exists();
+
+        let config = Config {
+            clean: true,
+            ..Config::default()
+        };
+        assert_eq!(build_with(&config), 0);
+        let cleaned = !output_dir.join("deleted.html").exists();
+        let kept = output_dir.join("kept.html").exists();
+
+        assert!(lingers, "without --clean, nothing is removed");
+        assert!(cleaned && kept);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clean_removes_a_symlink_but_not_its_target() {
+        let dir = TestDir::new("clean-symlink");
+        let input_dir = dir.join("input");
+        let output_dir = dir.join("output");
+        let target_dir = dir.join("target");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::create_dir_all(&target_dir).unwrap();
+        fs::write(target_dir.join("precious.txt"), "Precious.\n").unwrap();
+        std::os::unix::fs::symlink(&target_dir, output_dir.join("link")).unwrap();
+
+        clean_output_dir(&input_dir, &output_dir).unwrap();
+
+        assert!(fs::symlink_metadata(output_dir.join("link")).is_err());
+        assert!(target_dir.join("precious.txt").exists());
+    }
+
+    #[test]
+    fn excluded_drafts_are_not_processed() {
+        let dir = TestDir::new("exclude");
+        let input_dir = dir.join("input");
+        fs::create_dir_all(input_dir.join("drafts")).unwrap();
+        fs::create_dir_all(input_dir.join("posts/drafts")).unwrap();
+        fs::write(input_dir.join("notes.txt"), "not a page").unwrap();
+        fs::write(input_dir.join("drafts/wip.md"), "---\ntitle: Unfinished\n").unwrap();
+        fs::write(
+            input_dir.join("posts/drafts/wip.md"),
+            "---\ntitle: Unfinished\n",
+        )
+        .unwrap();
+
+        let args = ["--exclude".to_string(), "**/drafts/**".to_string()];
+        let (config, _) = Config::from_args(&args).unwrap();
+        let report = build(
+            input_dir.to_str().unwrap(),
+            dir.join("output").to_str().unwrap(),
+            &config,
+        );
+
+        let report = report.unwrap();
+        assert_eq!(report.num_files, 1);
+        assert!(report.pages.is_empty());
+        assert!(config.exclude.is_match("drafts"));
+    }
+
+    #[test]
+    fn single_file_is_converted_to_the_output_file() {
+        let dir = TestDir::new("single");
+        fs::write(
+            dir.join("post.md"),
+            "---\npermalink: elsewhere\n---\n# Hello\n",
+        )
+        .unwrap();
+
+        let output_file = dir.join("out/hello.html");
+        let report = build(
+            dir.join("post.md").to_str().unwrap(),
+            output_file.to_str().unwrap(),
+            &Config::default(),
+        );
+        let html = fs::read_to_string(&output_file);
+        let same_file = build(
+            dir.join("post.md").to_str().unwrap(),
+            dir.join("post.md").to_str().unwrap(),
+            &Config::default(),
+        );
+
+        let report = report.unwrap();
+        assert_eq!(report.num_files, 1);
+        assert_eq!((report.num_successes(), report.num_errors()), (1, 0));
+        assert!(html.unwrap().contains("Hello"));
+        assert!(same_file.unwrap_err().contains("is the input file"));
+    }
+
+    #[test]
+    fn output_dir_inside_input_dir_is_rejected() {
+        let input_dir = TestDir::new("nested");
+        let output_dir = input_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+
+        let input_dir = input_dir.canonicalize().unwrap();
+        let output_dir = output_dir.canonicalize().unwrap();
+        let sibling_dir = input_dir.with_file_name("static-site-gen-sibling");
+
+        let nested = check_output_dir(&input_dir, &output_dir);
+        let same = check_output_dir(&input_dir, &input_dir);
+        let sibling = check_output_dir(&input_dir, &sibling_dir);
+
+        assert!(nested.unwrap_err().contains("inside the input directory"));
+        assert!(same.is_err());
+        assert_eq!(sibling, Ok(()));
+    }
+
+    #[test]
+    fn rejected_output_dir_is_not_created() {
+        let input_dir = TestDir::new("uncreated");
+        let output_dir = input_dir.join("new/out");
+
+        let result = build(
+            input_dir.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            &Config::default(),
+        );
+
+        assert!(result.unwrap_err().contains("inside the input directory"));
+        assert!(!input_dir.join("new").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_directory_is_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let input_dir = TestDir::new("find");
+        let locked_dir = input_dir.join("locked");
+        fs::create_dir_all(&locked_dir).unwrap();
+        fs::write(input_dir.join("index.md"), "# Home").unwrap();
+        fs::write(locked_dir.join("secret.md"), "# Secret").unwrap();
+
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o000)).unwrap();
+        // e.g. root can read the directory anyway, so there is nothing to test
+        let can_simulate = fs::read_dir(&locked_dir).is_err();
+
+        let (files, warnings) = find_files(&input_dir, &GlobSet::empty(), None);
+
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        if can_simulate {
+            assert_eq!(files, vec![input_dir.join("index.md")]);
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].contains("locked"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_output_dir_is_a_page_error() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new("read-only");
+        let output_dir = dir.join("output");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::set_permissions(&output_dir, fs::Permissions::from_mode(0o555)).unwrap();
+        // e.g. root can write to the directory anyway, so there is nothing to test
+        let can_simulate = fs::write(output_dir.join("probe"), "").is_err();
+
+        let page = |relpath: &str| Page {
+            input_file: dir.join(relpath).with_extension("md"),
+            input_relpath: PathBuf::from(relpath).with_extension("md"),
+            output_relpath: PathBuf::from(relpath).with_extension("html"),
+            frontmatter: Frontmatter::default(),
+            content_md: String::from("# Hello"),
+            sections: Vec::new(),
+        };
+        let config = Config {
+            jobs: Some(1),
+            ..Config::default()
+        };
+
+        // one page right in the output directory, and one that needs a directory made for it
+        let reports = process_pages(
+            &output_dir,
+            &mut [page("index"), page("fruit/apple")],
+            &config,
+            None,
+            "{}",
+        );
+
+        fs::set_permissions(&output_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        if can_simulate {
+            let errors: Vec<&String> = reports
+                .iter()
+                .filter_map(|report| report.result.as_ref().err())
+                .collect();
+
+            assert_eq!(errors.len(), 2);
+            assert!(errors[0].contains("index.html"));
+            assert!(errors[1].contains("Could not create"));
+        }
+    }
+
+    #[test]
+    fn panic_with_an_unknown_tag_is_reported() {
+        let (sender, receiver) = mpsc::channel();
+
+        // The panicking thread is parked for good, like a worker of `call_roc_transform`
+        thread::spawn(move || {
+            PANIC_SENDER.with(|panic_sender| *panic_sender.borrow_mut() = Some(sender));
+
+            unsafe { report_roc_panic(std::ptr::null_mut(), 7) }
+        });
+
+        assert_eq!(
+            receiver.recv().unwrap().map(|output| output.html),
+            Err("Roc panicked with tag 7".to_string())
+        );
+    }
+}
@@ -0,0 +1,114 @@
+/// Whether a code fence asks for line numbers, like ```` ```roc,linenos ````.
+pub fn wants_line_numbers(fence_info: &str) -> bool {
+    fence_info
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .any(|word| word == "linenos")
+}
+
+/// Wrap each line of a `<pre><samp>...</samp></pre>` (or `<pre><code>...</code></pre>`)
+/// block in a `<span class="line">`, starting with a `<span class="line-number">`.
+///
+/// Highlighting spans that continue onto the next line (like a multi-line string)
+/// are closed at the end of each line and opened again on the next, so that
+/// every line is well-formed on its own.
+pub fn add_line_numbers(block_html: &str) -> String {
+    const WRAPPERS: [(&str, &str); 2] = [
+        ("<pre><samp>", "</samp></pre>"),
+        ("<pre><code>", "</code></pre>"),
+    ];
+
+    let (open, close, code) = match WRAPPERS.iter().find_map(|(open, close)| {
+        block_html
+            .strip_prefix(open)
+            .and_then(|rest| rest.strip_suffix(close))
+            .map(|code| (open, close, code))
+    }) {
+        Some(found) => found,
+        None => return block_html.to_string(),
+    };
+
+    // The last line of a code block ends in a newline, which doesn't start another line
+    let (code, trailing_newline) = match code.strip_suffix('\n') {
+        Some(code) => (code, "\n"),
+        None => (code, ""),
+    };
+
+    let mut html = String::with_capacity(block_html.len() * 2);
+    // The tags that are open at this point, like `<span class="literal">`
+    let mut open_tags: Vec<&str> = Vec::new();
+
+    html.push_str(open);
+
+    for (index, line) in code.split('\n').enumerate() {
+        if index > 0 {
+            html.push('\n');
+        }
+
+        html.push_str(&format!(
+            "<span class=\"line\"><span class=\"line-number\">{}</span>",
+            index + 1
+        ));
+
+        for tag in open_tags.iter() {
+            html.push_str(tag);
+        }
+
+        let mut rest = line;
+
+        while let Some(start) = rest.find('<') {
+            let end = match rest[start..].find('>') {
+                Some(end) => start + end + 1,
+                None => break,
+            };
+            let tag = &rest[start..end];
+
+            if tag.starts_with("</") {
+                open_tags.pop();
+            } else if !tag.ends_with("/>") {
+                open_tags.push(tag);
+            }
+
+            html.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        html.push_str(rest);
+
+        for tag in open_tags.iter().rev() {
+            html.push_str(&closing_tag(tag));
+        }
+
+        html.push_str("</span>");
+    }
+
+    html.push_str(trailing_newline);
+    html.push_str(close);
+
+    html
+}
+
+/// `</span>` for `<span class="kw">`
+fn closing_tag(opening_tag: &str) -> String {
+    let name = opening_tag[1..]
+        .split(|c: char| c.is_whitespace() || c == '>')
+        .next()
+        .unwrap_or("");
+
+    format!("</{}>", name)
+}
+
+#[test]
+fn each_line_gets_a_number() {
+    let block = "<pre><samp><span class=\"kw\">x</span> = <span class=\"literal\">\"\"\"\nmulti\n\"\"\"</span>\n</samp></pre>";
+
+    assert!(wants_line_numbers("roc,linenos"));
+    assert!(!wants_line_numbers("roc"));
+    assert_eq!(
+        add_line_numbers(block),
+        "<pre><samp>\
+         <span class=\"line\"><span class=\"line-number\">1</span><span class=\"kw\">x</span> = <span class=\"literal\">\"\"\"</span></span>\n\
+         <span class=\"line\"><span class=\"line-number\">2</span><span class=\"literal\">multi</span></span>\n\
+         <span class=\"line\"><span class=\"line-number\">3</span><span class=\"literal\">\"\"\"</span></span>\
+         \n</samp></pre>"
+    );
+}
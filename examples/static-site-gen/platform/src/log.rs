@@ -0,0 +1,70 @@
+//! How much the generator prints about its progress.
+//!
+//! Progress goes to stdout with `info!`, and is hidden by `--quiet`. `--verbose` adds
+//! timings to it. Errors and warnings always go to stderr, whatever the verbosity.
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// The environment variable that sets the verbosity when there is no `--quiet` or `--verbose`.
+pub const VERBOSITY_ENV_VAR: &str = "SSG_LOG";
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+impl Verbosity {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "quiet" => Some(Verbosity::Quiet),
+            "normal" => Some(Verbosity::Normal),
+            "verbose" => Some(Verbosity::Verbose),
+            _ => None,
+        }
+    }
+
+    /// The verbosity set by `SSG_LOG`, if it is set.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var(VERBOSITY_ENV_VAR) {
+            Ok(name) => Self::parse(&name).map(Some).ok_or_else(|| {
+                format!(
+                    "{} should be `quiet`, `normal` or `verbose`, but it is `{}`",
+                    VERBOSITY_ENV_VAR, name
+                )
+            }),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(verbosity: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8
+}
+
+/// ` (12.3ms)` when verbose, or else nothing.
+pub fn timing(duration: Duration) -> String {
+    if enabled(Verbosity::Verbose) {
+        format!(" ({:.1?})", duration)
+    } else {
+        String::new()
+    }
+}
+
+/// `println!`, unless the verbosity is `Quiet`.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Verbosity::Normal) {
+            println!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use info;
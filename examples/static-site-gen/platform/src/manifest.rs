@@ -0,0 +1,95 @@
+use std::path::{Component, Path};
+
+/// A `manifest.json` listing every page: the Markdown it came from, and the file it was written to.
+///
+/// Paths are relative to the input and output directories, with `/` separators,
+/// so the manifest is the same whichever machine (or OS) built the site.
+/// With `absolute` set, they are full paths instead.
+pub fn manifest_json(
+    input_dir: &Path,
+    output_dir: &Path,
+    pages: &[(&Path, &Path)],
+    absolute: bool,
+) -> String {
+    let mut entries: Vec<(String, String)> = pages
+        .iter()
+        .map(|(input_relpath, output_relpath)| {
+            if absolute {
+                (
+                    input_dir.join(input_relpath).display().to_string(),
+                    output_dir.join(output_relpath).display().to_string(),
+                )
+            } else {
+                (portable_path(input_relpath), portable_path(output_relpath))
+            }
+        })
+        .collect();
+
+    // The input files are found in whatever order the file system lists them
+    entries.sort();
+
+    let mut json = String::from("[\n");
+
+    for (index, (source, output)) in entries.iter().enumerate() {
+        json.push_str("  {\"source\": ");
+        push_json_string(&mut json, source);
+        json.push_str(", \"output\": ");
+        push_json_string(&mut json, output);
+        json.push('}');
+
+        if index + 1 < entries.len() {
+            json.push(',');
+        }
+        json.push('\n');
+    }
+
+    json.push_str("]\n");
+    json
+}
+
+/// A relative path with `/` between its components, whatever the platform.
+pub fn portable_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+}
+
+#[test]
+fn manifest_paths_are_relative() {
+    let input_dir = Path::new("/home/someone/site/input");
+    let output_dir = Path::new("/home/someone/site/output");
+    let pages = [
+        (Path::new("fruit/banana.md"), Path::new("fruit/banana.html")),
+        (Path::new("about.md"), Path::new("about/index.html")),
+    ];
+
+    assert_eq!(
+        manifest_json(input_dir, output_dir, &pages, false),
+        "[\n  \
+         {\"source\": \"about.md\", \"output\": \"about/index.html\"},\n  \
+         {\"source\": \"fruit/banana.md\", \"output\": \"fruit/banana.html\"}\n\
+         ]\n"
+    );
+}
@@ -0,0 +1,96 @@
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag};
+
+/// Turn the math in the Markdown into the delimiters that MathJax and KaTeX look for.
+///
+/// This version of pulldown-cmark has no math events, so math is written the way GitLab
+/// does it, which the parser already keeps intact: inline math is a code span between dollar
+/// signs, like ``$`e^{i\pi} = -1`$``, and display math is a code block fenced as ```` ```math ````.
+///
+/// Inline math becomes `<span class="math">\(...\)</span>`, and display math becomes
+/// `<div class="math">\[...\]</div>`. Either way, it is HTML, so it isn't highlighted as
+/// code, and doesn't count towards the word count or the search index.
+pub fn render_math<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut output: Vec<Event<'a>> = Vec::new();
+    let mut events = events.peekable();
+    // The TeX of the display math block we are in, if any
+    let mut display_math: Option<String> = None;
+
+    while let Some(event) = events.next() {
+        if let Some(tex) = display_math.as_mut() {
+            match event {
+                Event::Text(text) => tex.push_str(&text),
+                Event::End(Tag::CodeBlock(_)) => {
+                    let html = math_html("div", "\\[", tex.trim_end(), "\\]");
+                    output.push(Event::Html(CowStr::from(html + "\n")));
+                    display_math = None;
+                }
+                event => output.push(event),
+            }
+
+            continue;
+        }
+
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))
+                if crate::fence_language(&info) == "math" =>
+            {
+                display_math = Some(String::new());
+            }
+            Event::Code(tex) if is_inline_math(output.last(), events.peek()) => {
+                if let Some(Event::Text(before)) = output.pop() {
+                    let before = &before[..before.len() - 1];
+                    if !before.is_empty() {
+                        output.push(Event::Text(CowStr::from(before.to_string())));
+                    }
+                }
+
+                output.push(Event::Html(CowStr::from(math_html(
+                    "span", "\\(", &tex, "\\)",
+                ))));
+
+                if let Some(Event::Text(after)) = events.next() {
+                    let after = &after[1..];
+                    if !after.is_empty() {
+                        output.push(Event::Text(CowStr::from(after.to_string())));
+                    }
+                }
+            }
+            event => output.push(event),
+        }
+    }
+
+    output
+}
+
+/// Whether a code span is inline math: right after a `$`, and right before another one.
+fn is_inline_math(before: Option<&Event>, after: Option<&Event>) -> bool {
+    matches!(before, Some(Event::Text(text)) if text.ends_with('$'))
+        && matches!(after, Some(Event::Text(text)) if text.starts_with('$'))
+}
+
+fn math_html(element: &str, open: &str, tex: &str, close: &str) -> String {
+    let mut html = format!("<{} class=\"math\">{}", element, open);
+    escape_html(&mut html, tex).unwrap();
+    html.push_str(close);
+    html.push_str(&format!("</{}>", element));
+
+    html
+}
+
+#[test]
+fn math_gets_mathjax_delimiters() {
+    let markdown = "Euler: $`e^{i\\pi} < 0`$, and `code` is $5.\n\n\
+                    ```math\n\\sum_{n=1}^\\infty \\frac{1}{n^2} = \\frac{\\pi^2}{6}\n```\n";
+
+    let mut html = String::new();
+    let events = render_math(pulldown_cmark::Parser::new(markdown));
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+
+    assert_eq!(
+        html,
+        "<p>Euler: <span class=\"math\">\\(e^{i\\pi} &lt; 0\\)</span>, \
+         and <code>code</code> is $5.</p>\n\
+         <div class=\"math\">\\[\\sum_{n=1}^\\infty \\frac{1}{n^2} = \\frac{\\pi^2}{6}\\]</div>\n"
+    );
+}
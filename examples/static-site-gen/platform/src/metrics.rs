@@ -0,0 +1,91 @@
+//! A build's numbers, for monitoring.
+//!
+//! They are written in Prometheus' text exposition format, to a file that node_exporter's
+//! textfile collector (or anything else that reads the format) can pick up after each build.
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::BuildReport;
+
+/// The metrics of `report`, in the text exposition format.
+pub fn textfile(report: &BuildReport) -> String {
+    let mut text = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+        // Writing to a String can't fail
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(text, "{} {}", name, value);
+    };
+
+    metric(
+        "roc_site_files_total",
+        "counter",
+        "Files found in the input directory, Markdown or not.",
+        &report.num_files,
+    );
+    metric(
+        "roc_site_errors_total",
+        "counter",
+        "Pages that failed to build.",
+        &report.num_errors(),
+    );
+    metric(
+        "roc_site_build_duration_seconds",
+        "gauge",
+        "How long the build took.",
+        &report.duration.as_secs_f64(),
+    );
+
+    text
+}
+
+/// Write the metrics of `report` to `path`. The file is replaced in one go, so that a
+/// collector reading it during a build never sees half of it.
+pub fn write_textfile(path: &Path, report: &BuildReport) -> Result<(), String> {
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+    fs::write(&temp_path, textfile(report))
+        .and_then(|()| fs::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Could not write the metrics to {}: {}", path.display(), e)
+        })
+}
+
+#[test]
+fn metrics_are_written_in_the_text_format() {
+    use crate::test_dir::TestDir;
+    use crate::PageReport;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    let page = |name: &str, result| PageReport {
+        input_file: PathBuf::from(name),
+        result,
+        duration: Duration::ZERO,
+    };
+    let report = BuildReport {
+        num_files: 3,
+        pages: vec![
+            page("good.md", Ok(PathBuf::from("good.html"))),
+            page("bad.md", Err("no closing ---".to_string())),
+        ],
+        duration: Duration::from_millis(1500),
+        ..BuildReport::default()
+    };
+
+    let text = textfile(&report);
+
+    assert!(text.contains("# TYPE roc_site_files_total counter\nroc_site_files_total 3\n"));
+    assert!(text.contains("# TYPE roc_site_errors_total counter\nroc_site_errors_total 1\n"));
+    assert!(text.contains(
+        "# TYPE roc_site_build_duration_seconds gauge\nroc_site_build_duration_seconds 1.5\n"
+    ));
+
+    let dir = TestDir::new("metrics");
+    let path = dir.join("site.prom");
+    write_textfile(&path, &report).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), text);
+}
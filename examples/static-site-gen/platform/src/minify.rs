@@ -0,0 +1,184 @@
+/// Elements whose contents must be copied through untouched, because
+/// whitespace inside them is significant (or is code, not markup).
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Elements that aren't laid out in a line of text, so whitespace next to their
+/// tags isn't shown, along with those that are only in the `<head>`.
+const BLOCK_ELEMENTS: [&str; 50] = [
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "caption",
+    "body",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "script",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Collapse insignificant whitespace in an HTML document.
+///
+/// Runs of whitespace are collapsed to a single space. Whitespace-only text next to
+/// a block-level tag, or at the start or end of the document, is dropped entirely,
+/// but between inline elements it stays a space, so `<a>x</a>\n<b>y</b>` doesn't
+/// run together.
+/// Tags themselves are copied verbatim, as is everything inside
+/// `<pre>`, `<textarea>`, `<script>` and `<style>` elements.
+pub fn minify_html(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    // The document's start counts as a block boundary, like its end
+    let mut after_block = true;
+
+    while !rest.is_empty() {
+        match rest.find('<') {
+            Some(0) => {
+                let tag_len = rest.find('>').map(|i| i + 1).unwrap_or(rest.len());
+                let (tag, after) = rest.split_at(tag_len);
+                output.push_str(tag);
+                rest = after;
+                after_block = is_block_tag(tag);
+
+                if let Some(name) = raw_element_name(tag) {
+                    let raw_len = find_closing_tag(rest, name).unwrap_or(rest.len());
+                    let (raw, after) = rest.split_at(raw_len);
+                    output.push_str(raw);
+                    rest = after;
+                }
+            }
+            Some(text_len) => {
+                let (text, after) = rest.split_at(text_len);
+                push_collapsed(&mut output, text, after_block || is_block_tag(after));
+                rest = after;
+            }
+            None => {
+                push_collapsed(&mut output, rest, true);
+                rest = "";
+            }
+        }
+    }
+
+    output
+}
+
+/// If `tag` opens one of the `RAW_ELEMENTS`, return that element's name.
+fn raw_element_name(tag: &str) -> Option<&'static str> {
+    let name = element_name(tag.trim_start_matches('<'));
+
+    RAW_ELEMENTS.iter().copied().find(|raw| *raw == name)
+}
+
+/// Whether `tag` (or the HTML that starts with it) opens or closes one of the
+/// `BLOCK_ELEMENTS`. A doctype or comment counts too, since it isn't shown.
+fn is_block_tag(tag: &str) -> bool {
+    let inside = tag.trim_start_matches('<');
+
+    if inside.starts_with('!') {
+        return true;
+    }
+
+    let name = element_name(inside.trim_start_matches('/'));
+    BLOCK_ELEMENTS.contains(&name.as_str())
+}
+
+/// The lowercased element name at the start of `tag`, after its `<` (and `/`).
+fn element_name(tag: &str) -> String {
+    tag.chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Byte offset of the `</name` that closes a raw element, ignoring case.
+fn find_closing_tag(html: &str, name: &str) -> Option<usize> {
+    let needle = format!("</{}", name);
+
+    html.to_ascii_lowercase().find(&needle)
+}
+
+/// With `next_to_block`, whitespace-only `text` is dropped rather than collapsed.
+fn push_collapsed(output: &mut String, text: &str, next_to_block: bool) {
+    if text.trim().is_empty() {
+        if !next_to_block && !text.is_empty() {
+            output.push(' ');
+        }
+
+        return;
+    }
+
+    let mut in_whitespace = false;
+
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                output.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            output.push(c);
+            in_whitespace = false;
+        }
+    }
+}
+
+#[test]
+fn pre_whitespace_survives_minification() {
+    let html =
+        "<div>\n    <p>Some   text\n  here</p>\n    <pre><samp>x =\n    1\n</samp></pre>\n</div>\n";
+
+    assert_eq!(
+        minify_html(html),
+        "<div><p>Some text here</p><pre><samp>x =\n    1\n</samp></pre></div>"
+    );
+}
+
+#[test]
+fn newlines_between_inline_elements_become_a_space() {
+    let html = "<p>\n  <a href=\"/\">x</a>\n  <b>y</b>\n</p>\n<ul>\n  <li>z</li>\n</ul>\n";
+
+    assert_eq!(
+        minify_html(html),
+        "<p><a href=\"/\">x</a> <b>y</b></p><ul><li>z</li></ul>"
+    );
+}
@@ -0,0 +1,144 @@
+//! Compressed copies of the output files, like `about.html.gz` next to `about.html`.
+//!
+//! A static host that serves precompressed files can send these to clients that accept them,
+//! rather than compressing every response. The compression needs the `precompress` feature;
+//! without it, `--precompress` is rejected when the options are parsed.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// What is added to the name of the file that a copy is compressed from.
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gz",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// The encodings in a `--precompress` list, like `gzip,brotli`.
+pub fn parse_encodings(list: &str) -> Result<Vec<Encoding>, String> {
+    let mut encodings = Vec::new();
+
+    for name in list.split(',') {
+        let encoding = match name.trim() {
+            "gzip" => Encoding::Gzip,
+            "brotli" => Encoding::Brotli,
+            other => {
+                return Err(format!(
+                    "--precompress takes gzip and brotli, separated by commas, but got `{}`",
+                    other
+                ))
+            }
+        };
+
+        if !encodings.contains(&encoding) {
+            encodings.push(encoding);
+        }
+    }
+
+    Ok(encodings)
+}
+
+/// Write a copy of `contents`, which was just written to `output_file`, next to it in each of
+/// `encodings`. Returns the files that were written.
+pub fn write_variants(
+    output_file: &Path,
+    contents: &[u8],
+    encodings: &[Encoding],
+) -> Result<Vec<PathBuf>, String> {
+    encodings
+        .iter()
+        .map(|encoding| {
+            let mut name = output_file.as_os_str().to_owned();
+            name.push(".");
+            name.push(encoding.extension());
+            let variant_file = PathBuf::from(name);
+
+            compress(*encoding, contents)
+                .and_then(|compressed| fs::write(&variant_file, compressed))
+                .map_err(|e| format!("{}: {}", variant_file.display(), e))?;
+
+            Ok(variant_file)
+        })
+        .collect()
+}
+
+/// Each file is compressed once and served many times, so this uses the smallest settings
+/// rather than the fastest.
+#[cfg(feature = "precompress")]
+fn compress(encoding: Encoding, contents: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(contents)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            // quality 11, with a 4 MiB window
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+            encoder.write_all(contents)?;
+            Ok(encoder.into_inner())
+        }
+    }
+}
+
+#[cfg(not(feature = "precompress"))]
+fn compress(_encoding: Encoding, _contents: &[u8]) -> io::Result<Vec<u8>> {
+    unreachable!("--precompress is rejected without the `precompress` feature")
+}
+
+#[test]
+fn encodings_are_separated_by_commas() {
+    assert_eq!(parse_encodings("gzip"), Ok(vec![Encoding::Gzip]));
+    assert_eq!(
+        parse_encodings("brotli, gzip,brotli"),
+        Ok(vec![Encoding::Brotli, Encoding::Gzip])
+    );
+    assert!(parse_encodings("gzip,zstd").is_err());
+}
+
+#[cfg(feature = "precompress")]
+#[test]
+fn variants_decompress_to_the_original() {
+    use crate::test_dir::TestDir;
+    use std::io::Read;
+
+    let dir = TestDir::new("precompress");
+    let output_file = dir.join("about.html");
+    let html = "<h1>About</h1>\n".repeat(100);
+    fs::write(&output_file, &html).unwrap();
+
+    let variants = write_variants(
+        &output_file,
+        html.as_bytes(),
+        &[Encoding::Gzip, Encoding::Brotli],
+    )
+    .unwrap();
+    assert_eq!(
+        variants,
+        [dir.join("about.html.gz"), dir.join("about.html.br")]
+    );
+
+    let mut gunzipped = String::new();
+    flate2::read::GzDecoder::new(fs::File::open(dir.join("about.html.gz")).unwrap())
+        .read_to_string(&mut gunzipped)
+        .unwrap();
+    assert_eq!(gunzipped, html);
+
+    let mut unbrotlied = String::new();
+    brotli::Decompressor::new(fs::File::open(dir.join("about.html.br")).unwrap(), 4096)
+        .read_to_string(&mut unbrotlied)
+        .unwrap();
+    assert_eq!(unbrotlied, html);
+}
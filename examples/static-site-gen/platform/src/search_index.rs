@@ -0,0 +1,101 @@
+use crate::manifest::push_json_string;
+
+/// The prose of a page under one of its headings, or before the first heading if `id` is empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    pub id: String,
+    pub title: String,
+    pub text: String,
+}
+
+/// A page's sections, and where the page was written.
+pub struct SearchPage<'a> {
+    /// The path the page is linked by, relative to the output directory, with `/` separators.
+    pub url: String,
+    /// The title of the text before the page's first heading.
+    pub title: String,
+    pub sections: &'a [Section],
+}
+
+/// Add the words of `text` to a section's text, with single spaces between them.
+pub fn push_words(section_text: &mut String, text: &str) {
+    for word in text.split_whitespace() {
+        if !section_text.is_empty() {
+            section_text.push(' ');
+        }
+        section_text.push_str(word);
+    }
+}
+
+/// A `search-index.json` for client-side search, e.g. with lunr.js or Fuse.js.
+///
+/// It is a flat array with an entry for each page, and one for each of its headings,
+/// whose `url` ends in the heading's `#id`.
+pub fn search_index_json(pages: &[SearchPage]) -> String {
+    let mut entries = Vec::new();
+
+    for page in pages {
+        for section in page.sections {
+            let (url, title) = if section.id.is_empty() {
+                (page.url.clone(), page.title.as_str())
+            } else {
+                (
+                    format!("{}#{}", page.url, section.id),
+                    section.title.as_str(),
+                )
+            };
+
+            let mut json = String::from("{\"url\": ");
+            push_json_string(&mut json, &url);
+            json.push_str(", \"title\": ");
+            push_json_string(&mut json, title);
+            json.push_str(", \"text\": ");
+            push_json_string(&mut json, &section.text);
+            json.push('}');
+
+            entries.push(json);
+        }
+    }
+
+    if entries.is_empty() {
+        String::from("[]\n")
+    } else {
+        format!("[\n  {}\n]\n", entries.join(",\n  "))
+    }
+}
+
+#[test]
+fn each_page_and_heading_gets_an_entry() {
+    let sections = [
+        Section {
+            text: String::from("Crisp."),
+            ..Section::default()
+        },
+        Section {
+            id: String::from("growing"),
+            title: String::from("Growing"),
+            text: String::from("Plant in \"spring\"."),
+        },
+    ];
+    let pages = [
+        SearchPage {
+            url: String::from("fruit/apple.html"),
+            title: String::from("Apple"),
+            sections: &sections,
+        },
+        SearchPage {
+            url: String::from("index.html"),
+            title: String::from("index.html"),
+            sections: &[Section::default()],
+        },
+    ];
+
+    assert_eq!(
+        search_index_json(&pages),
+        "[\n  \
+         {\"url\": \"fruit/apple.html\", \"title\": \"Apple\", \"text\": \"Crisp.\"},\n  \
+         {\"url\": \"fruit/apple.html#growing\", \"title\": \"Growing\", \"text\": \"Plant in \\\"spring\\\".\"},\n  \
+         {\"url\": \"index.html\", \"title\": \"index.html\", \"text\": \"\"}\n\
+         ]\n"
+    );
+}
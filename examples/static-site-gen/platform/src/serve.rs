@@ -0,0 +1,185 @@
+//! A small static file server for previewing the output directory with `--serve`.
+//!
+//! It only knows `GET` and `HEAD`, serves `index.html` for directories, and never
+//! serves anything outside the directory it was given.
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+use crate::log::info;
+
+/// Serve the files in `dir` at `addr`, until the process is stopped.
+pub fn serve(dir: &Path, addr: SocketAddr) -> Result<(), String> {
+    let listener =
+        TcpListener::bind(addr).map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+
+    info!("Serving {} at http://{}/", dir.display(), addr);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let dir = dir.to_path_buf();
+
+        // A client that never finishes its request shouldn't hold up the others
+        thread::spawn(move || {
+            let _ = handle_connection(&dir, stream);
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(dir: &Path, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or("");
+    let target = words.next().unwrap_or("/");
+
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    match file_for_request(dir, target).and_then(|path| Some((fs::read(&path).ok()?, path))) {
+        Some((contents, path)) => {
+            let body: &[u8] = if method == "HEAD" { b"" } else { &contents };
+            respond(&mut stream, "200 OK", content_type(&path), body)
+        }
+        None => respond(
+            &mut stream,
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            b"Not found",
+        ),
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// The file to serve for a request target like `/posts/?page=2`, if there is one.
+/// Directories are served by their `index.html`.
+fn file_for_request(dir: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or("");
+    let path = percent_decode(path)?;
+
+    let mut file = dir.to_path_buf();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Normal(name) => file.push(name),
+            Component::RootDir | Component::CurDir => {}
+            // Never step outside `dir`
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if file.is_dir() {
+        file.push("index.html");
+    }
+
+    if file.is_file() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+/// `%20` becomes a space. Returns `None` if the result is not UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[test]
+fn directories_are_served_by_their_index() {
+    use crate::test_dir::TestDir;
+
+    let dir = TestDir::new("serve");
+    fs::create_dir_all(dir.join("posts")).unwrap();
+    fs::write(dir.join("index.html"), "home").unwrap();
+    fs::write(dir.join("posts/index.html"), "posts").unwrap();
+    fs::write(dir.join("posts/a b.css"), "").unwrap();
+
+    let root = file_for_request(&dir, "/");
+    let posts = file_for_request(&dir, "/posts/?page=2");
+    let spaced = file_for_request(&dir, "/posts/a%20b.css");
+    let outside = file_for_request(&dir.join("posts"), "/../index.html");
+    let missing = file_for_request(&dir, "/missing.html");
+
+    assert_eq!(root, Some(dir.join("index.html")));
+    assert_eq!(posts, Some(dir.join("posts").join("index.html")));
+    assert_eq!(spaced, Some(dir.join("posts").join("a b.css")));
+    assert_eq!(
+        spaced.as_deref().map(content_type),
+        Some("text/css; charset=utf-8")
+    );
+    assert_eq!(outside, None);
+    assert_eq!(missing, None);
+}
@@ -0,0 +1,112 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use std::ops::Range;
+
+/// Expand shortcodes like `{{< youtube dQw4w9WgXcQ >}}` into HTML.
+///
+/// A shortcode is a name followed by whitespace-separated arguments. Shortcodes
+/// that aren't recognized, or whose arguments don't make sense, are left as they
+/// are, so a typo shows up on the page instead of silently disappearing.
+///
+/// Code spans and code blocks are left alone too, so that a page can show how to
+/// write a shortcode.
+pub fn expand_shortcodes(markdown: &str) -> String {
+    let mut output = String::with_capacity(markdown.len());
+    let mut expanded_up_to = 0;
+
+    for code in code_ranges(markdown) {
+        expand_in(&markdown[expanded_up_to..code.start], &mut output);
+        output.push_str(&markdown[code.clone()]);
+        expanded_up_to = code.end;
+    }
+
+    expand_in(&markdown[expanded_up_to..], &mut output);
+
+    output
+}
+
+/// Where the code spans and code blocks of `markdown` are, in order.
+fn code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, pulldown_cmark::Options::all())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Expand the shortcodes in `text`, which has no code in it, onto `output`.
+fn expand_in(text: &str, output: &mut String) {
+    const OPEN: &str = "{{<";
+    const CLOSE: &str = ">}}";
+
+    let mut rest = text;
+
+    while let Some(start) = rest.find(OPEN) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(CLOSE) {
+            Some(end) => end + CLOSE.len(),
+            None => break,
+        };
+
+        let shortcode = &rest[..end];
+        let mut words = shortcode[OPEN.len()..end - CLOSE.len()].split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+
+        match render_shortcode(name, &args) {
+            Some(html) => output.push_str(&html),
+            None => output.push_str(shortcode),
+        }
+
+        rest = &rest[end..];
+    }
+
+    output.push_str(rest);
+}
+
+fn render_shortcode(name: &str, args: &[&str]) -> Option<String> {
+    match (name, args) {
+        ("youtube", [id]) if is_url_safe(id) => Some(format!(
+            r#"<iframe class="youtube" src="https://www.youtube-nocookie.com/embed/{}" allowfullscreen></iframe>"#,
+            id
+        )),
+        _ => None,
+    }
+}
+
+/// Arguments go straight into the HTML, so only allow characters that need no escaping.
+fn is_url_safe(arg: &str) -> bool {
+    !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[test]
+fn youtube_shortcode_becomes_an_iframe() {
+    let markdown = "Watch this:\n\n{{< youtube dQw4w9WgXcQ >}}\n\n{{< vimeo 123 >}}\n";
+
+    assert_eq!(
+        expand_shortcodes(markdown),
+        "Watch this:\n\n\
+         <iframe class=\"youtube\" src=\"https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ\" allowfullscreen></iframe>\n\n\
+         {{< vimeo 123 >}}\n"
+    );
+}
+
+#[test]
+fn shortcodes_in_code_are_left_alone() {
+    let markdown = "Write `{{< youtube ID >}}` for a video:\n\n\
+                    ```\n{{< youtube dQw4w9WgXcQ >}}\n```\n\n\
+                    {{< youtube dQw4w9WgXcQ >}}\n";
+
+    assert_eq!(
+        expand_shortcodes(markdown),
+        "Write `{{< youtube ID >}}` for a video:\n\n\
+         ```\n{{< youtube dQw4w9WgXcQ >}}\n```\n\n\
+         <iframe class=\"youtube\" src=\"https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ\" allowfullscreen></iframe>\n"
+    );
+}
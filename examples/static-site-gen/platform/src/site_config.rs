@@ -0,0 +1,34 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The file in the input directory with site-wide settings, like the site's title.
+pub const SITE_CONFIG_FILE: &str = "site.json";
+
+/// The contents of the input directory's `site.json`, which are passed to the Roc transform
+/// for every page as they are. Without a `site.json`, the transform gets an empty object.
+///
+/// The JSON isn't checked here: the transform decodes it however it likes.
+pub fn read_site_config(input_dir: &Path) -> Result<String, String> {
+    let path = input_dir.join(SITE_CONFIG_FILE);
+
+    match fs::read_to_string(&path) {
+        Ok(json) => Ok(json),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::from("{}")),
+        Err(e) => Err(format!("Error reading {}: {}", path.display(), e)),
+    }
+}
+
+#[test]
+fn site_config_defaults_to_an_empty_object() {
+    use crate::test_dir::TestDir;
+
+    let dir = TestDir::new("site");
+
+    let missing = read_site_config(&dir);
+    fs::write(dir.join(SITE_CONFIG_FILE), r#"{"title": "Fruit"}"#).unwrap();
+    let present = read_site_config(&dir);
+
+    assert_eq!(missing, Ok(String::from("{}")));
+    assert_eq!(present, Ok(String::from(r#"{"title": "Fruit"}"#)));
+}
@@ -0,0 +1,42 @@
+//! Scratch directories for tests.
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory in the system's temp directory. It is removed when this is dropped, so
+/// also when the test panics.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// `name` keeps apart the directories of tests that run at the same time.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("static-site-gen-{}-{}", name, std::process::id()));
+
+        // left over from a run that was killed before it could clean up
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TestDir(path)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
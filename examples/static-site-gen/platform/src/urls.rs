@@ -0,0 +1,169 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::portable_path;
+
+/// Turns where a page was written (relative to the output directory) into the path it is
+/// linked by, e.g. in the feed and the search index.
+pub type UrlRewriter = fn(&Path) -> String;
+
+/// A page is linked by the path it was written to. This is what happens without a `UrlRewriter`.
+pub fn plain_url(output_relpath: &Path) -> String {
+    portable_path(output_relpath)
+}
+
+/// `about/index.html` is linked as `about/`, and the top `index.html` as the site itself.
+///
+/// This is the `UrlRewriter` registered by `--pretty-urls`.
+pub fn pretty_url(output_relpath: &Path) -> String {
+    let url = portable_path(output_relpath);
+
+    if url == "index.html" {
+        String::new()
+    } else {
+        match url.strip_suffix("/index.html") {
+            Some(dir) => format!("{}/", dir),
+            None => url,
+        }
+    }
+}
+
+/// Where `--pretty-urls` writes a page that has no permalink: `about.md` goes to
+/// `about/index.html`, so that it can be linked as `about/`. An `index.md` stays `index.html`.
+pub fn pretty_output_relpath(input_relpath: &Path) -> PathBuf {
+    let mut output_relpath = input_relpath.to_path_buf();
+
+    if input_relpath.file_stem() != Some(OsStr::new("index")) {
+        output_relpath.set_extension("");
+        output_relpath.push("index.html");
+    } else {
+        output_relpath.set_extension("html");
+    }
+
+    output_relpath
+}
+
+/// Where a page is, so that its relative links to other Markdown files can be pointed at the
+/// pages made from them.
+#[derive(Debug, Clone, Copy, Hash)]
+pub struct LinkBase<'a> {
+    /// The page's Markdown file, relative to the input directory
+    pub input_relpath: &'a Path,
+    /// Where the page is written, relative to the output directory
+    pub output_relpath: &'a Path,
+    pub pretty_urls: bool,
+}
+
+impl<'a> LinkBase<'a> {
+    /// If `dest` is a relative link to a `.md` file, where the page made from that file is,
+    /// relative to this page. A `?query` or `#fragment` is kept.
+    ///
+    /// The other page is linked by where it would be written without a permalink.
+    pub fn rewrite(&self, dest: &str) -> Option<String> {
+        let (path, suffix) = dest.split_at(dest.find(['?', '#']).unwrap_or(dest.len()));
+
+        // An absolute path, or a URL with a scheme like https: or mailto:, is left alone
+        if !path.ends_with(".md") || path.starts_with('/') || path.contains(':') {
+            return None;
+        }
+
+        let linking_dir = portable_path(self.input_relpath.parent()?);
+        let mut target = PathBuf::new();
+        for component in linking_dir.split('/').chain(path.split('/')) {
+            match component {
+                "" | "." => {}
+                // A link out of the input directory isn't to one of its pages
+                ".." => {
+                    if !target.pop() {
+                        return None;
+                    }
+                }
+                component => target.push(component),
+            }
+        }
+
+        let url = if self.pretty_urls {
+            pretty_url(&pretty_output_relpath(&target))
+        } else {
+            plain_url(&target.with_extension("html"))
+        };
+
+        Some(relative_url(self.output_relpath, &url) + suffix)
+    }
+}
+
+/// `url`, which is relative to the output directory, relative to the page at `from_relpath`.
+fn relative_url(from_relpath: &Path, url: &str) -> String {
+    let from_url = portable_path(from_relpath);
+    let mut from_dirs: Vec<&str> = from_url.split('/').collect();
+    from_dirs.pop();
+
+    let mut to_dirs: Vec<&str> = url.split('/').collect();
+    let file = to_dirs.pop().unwrap_or_default();
+
+    let common = from_dirs
+        .iter()
+        .zip(to_dirs.iter())
+        .take_while(|(from, to)| from == to)
+        .count();
+
+    let mut relative = "../".repeat(from_dirs.len() - common);
+    for dir in &to_dirs[common..] {
+        relative.push_str(dir);
+        relative.push('/');
+    }
+    relative.push_str(file);
+
+    if relative.is_empty() {
+        String::from("./")
+    } else {
+        relative
+    }
+}
+
+#[test]
+fn md_links_point_at_the_pages_made_from_them() {
+    let plain = LinkBase {
+        input_relpath: Path::new("guide/intro.md"),
+        output_relpath: Path::new("guide/intro.html"),
+        pretty_urls: false,
+    };
+
+    assert_eq!(plain.rewrite("setup.md").as_deref(), Some("setup.html"));
+    assert_eq!(
+        plain.rewrite("../faq.md#install").as_deref(),
+        Some("../faq.html#install")
+    );
+    assert_eq!(plain.rewrite("https://example.com/a.md"), None);
+    assert_eq!(plain.rewrite("/a.md"), None);
+    assert_eq!(plain.rewrite("../../outside.md"), None);
+    assert_eq!(plain.rewrite("diagram.png"), None);
+
+    // guide/intro.md is written to guide/intro/index.html, a directory further down
+    let pretty = LinkBase {
+        output_relpath: Path::new("guide/intro/index.html"),
+        pretty_urls: true,
+        ..plain
+    };
+
+    assert_eq!(pretty.rewrite("setup.md").as_deref(), Some("../setup/"));
+    assert_eq!(pretty.rewrite("index.md").as_deref(), Some("../"));
+    assert_eq!(pretty.rewrite("../index.md").as_deref(), Some("../../"));
+}
+
+#[test]
+fn pretty_urls_end_in_a_slash() {
+    assert_eq!(
+        pretty_output_relpath(Path::new("about.md")),
+        PathBuf::from("about/index.html")
+    );
+    assert_eq!(
+        pretty_output_relpath(Path::new("posts/index.md")),
+        PathBuf::from("posts/index.html")
+    );
+
+    assert_eq!(pretty_url(Path::new("about/index.html")), "about/");
+    assert_eq!(pretty_url(Path::new("index.html")), "");
+    assert_eq!(pretty_url(Path::new("feeds/news.xml")), "feeds/news.xml");
+    assert_eq!(plain_url(Path::new("about/index.html")), "about/index.html");
+}
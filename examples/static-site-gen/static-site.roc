@@ -19,12 +19,15 @@ navLinks = [
     { url: "cherry.html", title: "Exempli Pagina Cerasus", text: "Cherry" },
 ]
 
-transformFileContent : Str, Str -> Str
-transformFileContent = \currentUrl, htmlContent ->
-    List.findFirst navLinks (\{ url } -> url == currentUrl)
-    |> Result.map (\currentNavLink -> view currentNavLink htmlContent)
-    |> Result.map Html.render
-    |> Result.withDefault ""
+transformFileContent : Str, Str, { readingMinutes : U64, wordCount : U64 }, Str -> { html : Str, data : Str, path : Str }
+transformFileContent = \currentUrl, htmlContent, _, _ ->
+    html =
+        List.findFirst navLinks (\{ url } -> url == currentUrl)
+        |> Result.map (\currentNavLink -> view currentNavLink htmlContent)
+        |> Result.map Html.render
+        |> Result.withDefault ""
+
+    { html, data: "", path: "" }
 
 view : NavLink, Str -> Html.Node
 view = \currentNavLink, htmlContent ->
@@ -6,9 +6,9 @@ app "roc-tutorial"
     ]
     provides [transformFileContent] to pf
 
-transformFileContent : Str, Str -> Str
-transformFileContent = \_, htmlContent ->
-    Html.render (view htmlContent)
+transformFileContent : Str, Str, { readingMinutes : U64, wordCount : U64 }, Str -> { html : Str, data : Str, path : Str }
+transformFileContent = \_, htmlContent, _, _ ->
+    { html: Html.render (view htmlContent), data: "", path: "" }
 
 view : Str -> Html.Node
 view = \htmlContent ->